@@ -0,0 +1,67 @@
+//! Benchmark comparing the JSON and binary encodings of encrypted pickles.
+
+#![allow(clippy::expect_used, missing_docs)]
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use vodozemac::{
+    PickleEncoding,
+    megolm::{GroupSession, InboundGroupSession, InboundGroupSessionPickle},
+    olm::{Account, AccountPickle},
+};
+
+const PICKLE_KEY: [u8; 32] = [0u8; 32];
+const ENCODINGS: [(&str, PickleEncoding); 2] =
+    [("JSON", PickleEncoding::Json), ("binary", PickleEncoding::Binary)];
+
+/// Benchmark how long it takes to pickle and unpickle an account with a full
+/// set of one-time keys.
+pub fn account_pickling(c: &mut Criterion) {
+    let mut account = Account::new();
+
+    account.generate_one_time_keys(50);
+    account.generate_fallback_key();
+
+    for (name, encoding) in ENCODINGS {
+        let pickle = account.pickle().encrypt_with_encoding(&PICKLE_KEY, encoding);
+        println!("Size of an encrypted {name} account pickle: {} bytes", pickle.len());
+
+        c.bench_function(&format!("Pickling an account using {name}"), |b| {
+            b.iter(|| account.pickle().encrypt_with_encoding(&PICKLE_KEY, encoding));
+        });
+
+        c.bench_function(&format!("Unpickling an account using {name}"), |b| {
+            b.iter(|| {
+                AccountPickle::from_encrypted(&pickle, &PICKLE_KEY)
+                    .expect("We should be able to unpickle the account")
+            });
+        });
+    }
+}
+
+/// Benchmark how long it takes to pickle and unpickle an inbound group
+/// session.
+pub fn inbound_group_session_pickling(c: &mut Criterion) {
+    let session = InboundGroupSession::from(&GroupSession::new(Default::default()));
+
+    for (name, encoding) in ENCODINGS {
+        let pickle = session.pickle().encrypt_with_encoding(&PICKLE_KEY, encoding);
+        println!(
+            "Size of an encrypted {name} inbound group session pickle: {} bytes",
+            pickle.len()
+        );
+
+        c.bench_function(&format!("Pickling an inbound group session using {name}"), |b| {
+            b.iter(|| session.pickle().encrypt_with_encoding(&PICKLE_KEY, encoding));
+        });
+
+        c.bench_function(&format!("Unpickling an inbound group session using {name}"), |b| {
+            b.iter(|| {
+                InboundGroupSessionPickle::from_encrypted(&pickle, &PICKLE_KEY)
+                    .expect("We should be able to unpickle the inbound group session")
+            });
+        });
+    }
+}
+
+criterion_group!(benches, account_pickling, inbound_group_session_pickling);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+"""Python bindings for vodozemac.
+
+The bindings themselves are generated by UniFFI, run ``make python-package``
+in the ``bindings`` directory to generate them and to copy the shared library
+next to this file.
+"""
+
+try:
+    from ._vodozemac import *  # noqa: F401,F403
+except ImportError as error:
+    raise ImportError(
+        "The vodozemac bindings haven't been generated, run "
+        "`make python-package` in the bindings directory first"
+    ) from error
@@ -131,6 +131,10 @@ pub enum VodozemacError {
     SessionKeyDecode(String),
     #[error("SAS error: {0}")]
     Sas(String),
+    #[error("PK decryption error: {0}")]
+    PkDecryption(String),
+    #[error("Cipher error: {0}")]
+    Cipher(String),
 }
 
 // Error conversions for ECIES types
@@ -184,6 +188,28 @@ impl From<vodozemac::PickleError> for VodozemacError {
     }
 }
 
+// Error conversions for libolm pickles
+impl From<vodozemac::LibolmPickleError> for VodozemacError {
+    fn from(error: vodozemac::LibolmPickleError) -> Self {
+        VodozemacError::LibolmPickle(error.to_string())
+    }
+}
+
+// Error conversions for PK encryption types
+#[cfg(feature = "insecure-pk-encryption")]
+impl From<vodozemac::pk_encryption::Error> for VodozemacError {
+    fn from(error: vodozemac::pk_encryption::Error) -> Self {
+        VodozemacError::PkDecryption(error.to_string())
+    }
+}
+
+#[cfg(feature = "insecure-pk-encryption")]
+impl From<vodozemac::pk_encryption::MessageDecodeError> for VodozemacError {
+    fn from(error: vodozemac::pk_encryption::MessageDecodeError) -> Self {
+        VodozemacError::Decode(error.to_string())
+    }
+}
+
 // Enums
 #[derive(uniffi::Enum, Debug, Clone, PartialEq)]
 pub enum MessageType {
@@ -219,6 +245,24 @@ impl From<vodozemac::megolm::SessionOrdering> for SessionOrdering {
     }
 }
 
+/// Where the key material of an inbound group session came from
+#[derive(uniffi::Enum, Debug, Clone, PartialEq)]
+pub enum SessionOrigin {
+    SessionKey,
+    ExportedSessionKey,
+    Merged { authenticated: bool },
+}
+
+impl From<vodozemac::megolm::SessionOrigin> for SessionOrigin {
+    fn from(origin: vodozemac::megolm::SessionOrigin) -> Self {
+        match origin {
+            vodozemac::megolm::SessionOrigin::SessionKey => SessionOrigin::SessionKey,
+            vodozemac::megolm::SessionOrigin::ExportedSessionKey => SessionOrigin::ExportedSessionKey,
+            vodozemac::megolm::SessionOrigin::Merged { authenticated } => SessionOrigin::Merged { authenticated },
+        }
+    }
+}
+
 /// Decode a base64 string into bytes with proper error handling
 #[uniffi::export]
 pub fn base64_decode(input: String) -> Result<Vec<u8>, VodozemacError> {
@@ -1146,16 +1190,6 @@ impl EstablishedSas {
         Arc::new(Mac { inner: mac })
     }
 
-    /// Calculate a MAC for the given input using the info string as additional
-    /// data, the MAC is returned as an invalid base64 encoded string.
-    /// 
-    /// **Warning**: This method should never be used unless you require libolm
-    /// compatibility. Libolm used to incorrectly encode their MAC because the
-    /// input buffer was reused as the output buffer.
-    pub fn calculate_mac_invalid_base64(&self, input: String, info: String) -> String {
-        self.inner.calculate_mac_invalid_base64(&input, &info)
-    }
-
     /// Verify a MAC that was previously created using the calculate_mac method.
     /// 
     /// Users should calculate a MAC and send it to the other side, they should
@@ -1183,6 +1217,20 @@ impl EstablishedSas {
     }
 }
 
+#[cfg(feature = "libolm-compat")]
+#[uniffi::export]
+impl EstablishedSas {
+    /// Calculate a MAC for the given input using the info string as additional
+    /// data, the MAC is returned as an invalid base64 encoded string.
+    /// 
+    /// **Warning**: This method should never be used unless you require libolm
+    /// compatibility. Libolm used to incorrectly encode their MAC because the
+    /// input buffer was reused as the output buffer.
+    pub fn calculate_mac_invalid_base64(&self, input: String, info: String) -> String {
+        self.inner.calculate_mac_invalid_base64(&input, &info)
+    }
+}
+
 // ===== OLM (Olm) CRYPTOGRAPHIC STRUCTS =====
 
 /// An Olm Account manages all cryptographic keys used on a device.
@@ -1337,23 +1385,6 @@ impl Account {
         }))
     }
 
-    /// Create an Account object by unpickling an account pickle in libolm legacy pickle format.
-    #[uniffi::constructor]
-    pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Account>, VodozemacError> {
-        let account = vodozemac::olm::Account::from_libolm_pickle(&pickle, &pickle_key)
-            .map_err(|e| VodozemacError::LibolmPickle(e.to_string()))?;
-        Ok(Arc::new(Account {
-            inner: std::sync::RwLock::new(account),
-        }))
-    }
-
-    /// Pickle an Account into a libolm pickle format.
-    pub fn to_libolm_pickle(&self, pickle_key: Vec<u8>) -> Result<String, VodozemacError> {
-        let inner = self.inner.read().unwrap();
-        inner.to_libolm_pickle(&pickle_key)
-            .map_err(|e| VodozemacError::LibolmPickle(e.to_string()))
-    }
-
     /// Create a dehydrated device from the account.
     pub fn to_dehydrated_device(&self, key: Vec<u8>) -> Result<DehydratedDeviceResult, VodozemacError> {
         if key.len() != 32 {
@@ -1387,6 +1418,27 @@ impl Account {
     }
 }
 
+#[cfg(feature = "libolm-compat")]
+#[uniffi::export]
+impl Account {
+    /// Create an Account object by unpickling an account pickle in libolm legacy pickle format.
+    #[uniffi::constructor]
+    pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Account>, VodozemacError> {
+        let account = vodozemac::olm::Account::from_libolm_pickle(&pickle, &pickle_key)
+            .map_err(|e| VodozemacError::LibolmPickle(e.to_string()))?;
+        Ok(Arc::new(Account {
+            inner: std::sync::RwLock::new(account),
+        }))
+    }
+
+    /// Pickle an Account into a libolm pickle format.
+    pub fn to_libolm_pickle(&self, pickle_key: Vec<u8>) -> Result<String, VodozemacError> {
+        let inner = self.inner.read().unwrap();
+        inner.to_libolm_pickle(&pickle_key)
+            .map_err(|e| VodozemacError::LibolmPickle(e.to_string()))
+    }
+}
+
 /// A struct representing the pickled Account.
 #[derive(uniffi::Object)]
 pub struct AccountPickle {
@@ -1556,13 +1608,15 @@ pub struct OlmNormalMessage {
 
 #[uniffi::export]
 impl OlmNormalMessage {
-    /// The ratchet key that was used to encrypt this message.
-    pub fn ratchet_key(&self) -> Arc<Curve25519PublicKey> {
-        Arc::new(Curve25519PublicKey(self.inner.ratchet_key()))
+    /// The ratchet key that was used to encrypt this message, missing if the
+    /// message header is encrypted.
+    pub fn ratchet_key(&self) -> Option<Arc<Curve25519PublicKey>> {
+        self.inner.ratchet_key().map(|key| Arc::new(Curve25519PublicKey(key)))
     }
 
-    /// The index of the chain that was used when the message was encrypted.
-    pub fn chain_index(&self) -> u64 {
+    /// The index of the chain that was used when the message was encrypted,
+    /// missing if the message header is encrypted.
+    pub fn chain_index(&self) -> Option<u64> {
         self.inner.chain_index()
     }
 
@@ -1761,7 +1815,11 @@ impl Session {
             inner: std::sync::RwLock::new(session),
         }))
     }
+}
 
+#[cfg(feature = "libolm-compat")]
+#[uniffi::export]
+impl Session {
     /// Create a Session object by unpickling a session pickle in libolm legacy pickle format.
     #[uniffi::constructor]
     pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Session>, VodozemacError> {
@@ -1931,6 +1989,16 @@ impl DecryptedMessage {
     pub fn message_index(&self) -> u32 {
         self.inner.message_index
     }
+
+    /// Get the origin of the session at the time the message was decrypted
+    pub fn origin(&self) -> SessionOrigin {
+        self.inner.origin.into()
+    }
+
+    /// Check if the message was decrypted by a session that was authenticated by its creator
+    pub fn is_authenticated(&self) -> bool {
+        self.inner.origin.is_authenticated()
+    }
 }
 
 impl From<vodozemac::megolm::DecryptedMessage> for DecryptedMessage {
@@ -2056,6 +2124,19 @@ impl GroupSession {
     }
 }
 
+#[cfg(feature = "libolm-compat")]
+#[uniffi::export]
+impl GroupSession {
+    /// Create a group session by unpickling a session pickle in libolm legacy pickle format.
+    #[uniffi::constructor]
+    pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Self>, VodozemacError> {
+        let session = vodozemac::megolm::GroupSession::from_libolm_pickle(&pickle, &pickle_key)?;
+        Ok(Arc::new(Self {
+            inner: std::sync::Mutex::new(session),
+        }))
+    }
+}
+
 impl From<vodozemac::megolm::GroupSession> for GroupSession {
     fn from(session: vodozemac::megolm::GroupSession) -> Self {
         Self { inner: std::sync::Mutex::new(session) }
@@ -2172,6 +2253,20 @@ impl InboundGroupSession {
         session.first_known_index()
     }
 
+    /// Get the origin of the session's key material
+    pub fn origin(&self) -> SessionOrigin {
+        let session = self.inner.lock().unwrap();
+        session.origin().into()
+    }
+
+    /// Upgrade the session using a signed session key of the same session
+    ///
+    /// Returns false if the session key belongs to another session
+    pub fn upgrade(&self, session_key: Arc<SessionKey>) -> bool {
+        let mut session = self.inner.lock().unwrap();
+        session.upgrade(&session_key.inner)
+    }
+
     /// Export the session at a specific message index
     pub fn export_at(&self, message_index: u32) -> Option<Arc<ExportedSessionKey>> {
         let mut session = self.inner.lock().unwrap();
@@ -2193,6 +2288,19 @@ impl InboundGroupSession {
     }
 }
 
+#[cfg(feature = "libolm-compat")]
+#[uniffi::export]
+impl InboundGroupSession {
+    /// Create an inbound group session by unpickling a session pickle in libolm legacy pickle format.
+    #[uniffi::constructor]
+    pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Self>, VodozemacError> {
+        let session = vodozemac::megolm::InboundGroupSession::from_libolm_pickle(&pickle, &pickle_key)?;
+        Ok(Arc::new(Self {
+            inner: std::sync::Mutex::new(session),
+        }))
+    }
+}
+
 impl From<vodozemac::megolm::InboundGroupSession> for InboundGroupSession {
     fn from(session: vodozemac::megolm::InboundGroupSession) -> Self {
         Self { inner: std::sync::Mutex::new(session) }
@@ -2341,6 +2449,207 @@ impl From<vodozemac::megolm::SessionKey> for SessionKey {
     }
 }
 
+// ========================================================================
+// PK Encryption Bindings
+// ========================================================================
+
+/// A message that was encrypted using a PkEncryption object.
+#[cfg(feature = "insecure-pk-encryption")]
+#[derive(uniffi::Object)]
+pub struct PkMessage {
+    inner: vodozemac::pk_encryption::Message,
+}
+
+#[cfg(feature = "insecure-pk-encryption")]
+#[uniffi::export]
+impl PkMessage {
+    /// Decode a message from its base64 encoded ciphertext, MAC and ephemeral key.
+    #[uniffi::constructor]
+    pub fn from_base64(ciphertext: String, mac: String, ephemeral_key: String) -> Result<Arc<Self>, VodozemacError> {
+        let inner = vodozemac::pk_encryption::Message::from_base64(&ciphertext, &mac, &ephemeral_key)?;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// Get the ciphertext of the message.
+    pub fn ciphertext(&self) -> Vec<u8> {
+        self.inner.ciphertext.clone()
+    }
+
+    /// Get the MAC of the message.
+    ///
+    /// **Warning**: The MAC doesn't authenticate the message.
+    pub fn mac(&self) -> Vec<u8> {
+        self.inner.mac.clone()
+    }
+
+    /// Get the ephemeral key that was used to derive the message key.
+    pub fn ephemeral_key(&self) -> Arc<Curve25519PublicKey> {
+        Arc::new(Curve25519PublicKey(self.inner.ephemeral_key))
+    }
+}
+
+/// The encryption component of the libolm PkEncryption scheme.
+///
+/// **Warning**: This scheme is not authenticated, it is only provided for
+/// compatibility with libolm.
+#[cfg(feature = "insecure-pk-encryption")]
+#[derive(uniffi::Object)]
+pub struct PkEncryption {
+    inner: vodozemac::pk_encryption::PkEncryption,
+}
+
+#[cfg(feature = "insecure-pk-encryption")]
+#[uniffi::export]
+impl PkEncryption {
+    /// Create a PkEncryption object that encrypts messages for the given public key.
+    #[uniffi::constructor]
+    pub fn from_key(public_key: Arc<Curve25519PublicKey>) -> Arc<Self> {
+        Arc::new(Self {
+            inner: vodozemac::pk_encryption::PkEncryption::from_key(public_key.0),
+        })
+    }
+
+    /// Encrypt a plaintext for the recipient.
+    pub fn encrypt(&self, plaintext: Vec<u8>) -> Arc<PkMessage> {
+        Arc::new(PkMessage { inner: self.inner.encrypt(&plaintext) })
+    }
+}
+
+/// The decryption component of the libolm PkEncryption scheme.
+#[cfg(feature = "insecure-pk-encryption")]
+#[derive(uniffi::Object)]
+pub struct PkDecryption {
+    inner: vodozemac::pk_encryption::PkDecryption,
+}
+
+#[cfg(feature = "insecure-pk-encryption")]
+#[uniffi::export]
+impl PkDecryption {
+    /// Create a new PkDecryption object with a random key.
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: vodozemac::pk_encryption::PkDecryption::new(),
+        })
+    }
+
+    /// Create a PkDecryption object from the given secret key.
+    #[uniffi::constructor]
+    pub fn from_key(secret_key: Arc<Curve25519SecretKey>) -> Arc<Self> {
+        let secret_key = vodozemac::Curve25519SecretKey::from_slice(&secret_key.0.to_bytes());
+        Arc::new(Self {
+            inner: vodozemac::pk_encryption::PkDecryption::from_key(secret_key),
+        })
+    }
+
+    /// Create a PkDecryption object by unpickling a libolm pickle.
+    #[uniffi::constructor]
+    pub fn from_libolm_pickle(pickle: String, pickle_key: Vec<u8>) -> Result<Arc<Self>, VodozemacError> {
+        let inner = vodozemac::pk_encryption::PkDecryption::from_libolm_pickle(&pickle, &pickle_key)?;
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// Get the public key messages should be encrypted for.
+    pub fn public_key(&self) -> Arc<Curve25519PublicKey> {
+        Arc::new(Curve25519PublicKey(self.inner.public_key()))
+    }
+
+    /// Get the secret key of this PkDecryption object.
+    pub fn secret_key(&self) -> Arc<Curve25519SecretKey> {
+        Arc::new(Curve25519SecretKey(vodozemac::Curve25519SecretKey::from_slice(
+            &self.inner.secret_key().to_bytes(),
+        )))
+    }
+
+    /// Decrypt a message that was encrypted for our public key.
+    pub fn decrypt(&self, message: Arc<PkMessage>) -> Result<Vec<u8>, VodozemacError> {
+        Ok(self.inner.decrypt(&message.inner)?)
+    }
+
+    /// Pickle the PkDecryption object in the libolm pickle format.
+    pub fn to_libolm_pickle(&self, pickle_key: Vec<u8>) -> Result<String, VodozemacError> {
+        Ok(self.inner.to_libolm_pickle(&pickle_key)?)
+    }
+}
+
+// ========================================================================
+// Hazmat Bindings
+// ========================================================================
+
+/// The low-level AES-256-CBC/HMAC-SHA-256 cipher used by Olm, Megolm and libolm pickles.
+///
+/// **Warning**: This is a low-level primitive, most users should never need it.
+#[cfg(feature = "low-level-api")]
+#[derive(uniffi::Object)]
+pub struct Cipher {
+    inner: vodozemac::hazmat::Cipher,
+}
+
+#[cfg(feature = "low-level-api")]
+#[uniffi::export]
+impl Cipher {
+    /// Create a cipher from a 32 byte key.
+    #[uniffi::constructor]
+    pub fn new(key: Vec<u8>) -> Result<Arc<Self>, VodozemacError> {
+        let key: [u8; 32] = key.try_into().map_err(|_| VodozemacError::Key("Key must be exactly 32 bytes".to_string()))?;
+        Ok(Arc::new(Self { inner: vodozemac::hazmat::Cipher::new(&key) }))
+    }
+
+    /// Create a cipher from a 128 byte Megolm ratchet.
+    #[uniffi::constructor]
+    pub fn new_megolm(key: Vec<u8>) -> Result<Arc<Self>, VodozemacError> {
+        let key: [u8; 128] = key.try_into().map_err(|_| VodozemacError::Key("Key must be exactly 128 bytes".to_string()))?;
+        Ok(Arc::new(Self { inner: vodozemac::hazmat::Cipher::new_megolm(&key) }))
+    }
+
+    /// Create a cipher from a libolm pickle key.
+    #[uniffi::constructor]
+    pub fn new_pickle(key: Vec<u8>) -> Arc<Self> {
+        Arc::new(Self { inner: vodozemac::hazmat::Cipher::new_pickle(&key) })
+    }
+
+    /// Encrypt the plaintext, the ciphertext isn't authenticated.
+    pub fn encrypt(&self, plaintext: Vec<u8>) -> Vec<u8> {
+        self.inner.encrypt(&plaintext)
+    }
+
+    /// Decrypt the ciphertext, the MAC needs to be verified beforehand.
+    pub fn decrypt(&self, ciphertext: Vec<u8>) -> Result<Vec<u8>, VodozemacError> {
+        self.inner.decrypt(&ciphertext).map_err(|e| VodozemacError::Cipher(e.to_string()))
+    }
+
+    /// Calculate the 32 byte MAC of the message.
+    pub fn mac(&self, message: Vec<u8>) -> Vec<u8> {
+        self.inner.mac(&message).as_bytes().to_vec()
+    }
+
+    /// Verify the 32 byte MAC of the message.
+    pub fn verify_mac(&self, message: Vec<u8>, tag: Vec<u8>) -> Result<(), VodozemacError> {
+        if tag.len() != vodozemac::hazmat::Mac::LENGTH {
+            return Err(VodozemacError::Cipher("MAC must be exactly 32 bytes".to_string()));
+        }
+
+        self.verify_truncated_mac(message, tag)
+    }
+
+    /// Verify a MAC of the message that was truncated to its first bytes.
+    pub fn verify_truncated_mac(&self, message: Vec<u8>, tag: Vec<u8>) -> Result<(), VodozemacError> {
+        self.inner
+            .verify_truncated_mac(&message, &tag)
+            .map_err(|e| VodozemacError::Cipher(e.to_string()))
+    }
+
+    /// Encrypt the plaintext and append the truncated MAC, as libolm pickles do.
+    pub fn encrypt_pickle(&self, plaintext: Vec<u8>) -> Vec<u8> {
+        self.inner.encrypt_pickle(&plaintext)
+    }
+
+    /// Verify the truncated MAC appended to the ciphertext and decrypt it, as libolm pickles do.
+    pub fn decrypt_pickle(&self, ciphertext: Vec<u8>) -> Result<Vec<u8>, VodozemacError> {
+        self.inner.decrypt_pickle(&ciphertext).map_err(|e| VodozemacError::Cipher(e.to_string()))
+    }
+}
+
 // Macro-only UniFFI: generate FFI scaffolding and metadata for the `vodozemac` namespace.
 uniffi::setup_scaffolding!("vodozemac");
 
@@ -181,4 +181,131 @@ mod test_bindings {
         
         assert_eq!(public_key_from_secret.to_bytes(), public_key_from_recovered.to_bytes());
     }
+
+    // Tests for libolm pickle import
+    #[cfg(feature = "libolm-compat")]
+    #[test]
+    fn test_group_sessions_from_invalid_libolm_pickle() {
+        use crate::{GroupSession, InboundGroupSession, VodozemacError};
+
+        let pickle = "not a libolm pickle".to_string();
+        let key = b"DEFAULT_PICKLE_KEY".to_vec();
+
+        assert!(matches!(
+            GroupSession::from_libolm_pickle(pickle.clone(), key.clone()),
+            Err(VodozemacError::LibolmPickle(_))
+        ));
+        assert!(matches!(
+            InboundGroupSession::from_libolm_pickle(pickle, key),
+            Err(VodozemacError::LibolmPickle(_))
+        ));
+    }
+
+    // Tests for Megolm session authenticity
+    #[test]
+    fn test_inbound_group_session_origin() {
+        use crate::{GroupSession, InboundGroupSession, MegolmSessionConfig, SessionOrigin};
+
+        let outbound = GroupSession::with_config(MegolmSessionConfig::version_1());
+        let inbound = InboundGroupSession::new(outbound.session_key(), MegolmSessionConfig::version_1());
+        let imported = InboundGroupSession::import(inbound.export_at(0).unwrap(), MegolmSessionConfig::version_1());
+
+        let message = outbound.encrypt(b"Hello".to_vec());
+        let decrypted = imported.decrypt(message.clone()).unwrap();
+        assert_eq!(decrypted.origin(), SessionOrigin::ExportedSessionKey);
+        assert!(!decrypted.is_authenticated());
+
+        assert!(!imported.upgrade(GroupSession::new().session_key()));
+        assert!(imported.upgrade(outbound.session_key()));
+        assert_eq!(imported.origin(), SessionOrigin::Merged { authenticated: true });
+        assert!(imported.decrypt(message).unwrap().is_authenticated());
+    }
+
+    // Tests for PK encryption
+    #[cfg(feature = "insecure-pk-encryption")]
+    #[test]
+    fn test_pk_encryption_roundtrip() {
+        use crate::{PkDecryption, PkEncryption, PkMessage};
+
+        let decryption = PkDecryption::new();
+        let encryption = PkEncryption::from_key(decryption.public_key());
+
+        let message = encryption.encrypt(b"It's a secret to everybody".to_vec());
+        let message = PkMessage::from_base64(
+            base64_encode(message.ciphertext()),
+            base64_encode(message.mac()),
+            message.ephemeral_key().to_base64(),
+        )
+        .unwrap();
+
+        assert_eq!(decryption.decrypt(message).unwrap(), b"It's a secret to everybody");
+    }
+
+    #[cfg(feature = "insecure-pk-encryption")]
+    #[test]
+    fn test_pk_decryption_libolm_pickle() {
+        use crate::{PkDecryption, PkEncryption, VodozemacError};
+
+        let secret_key = Curve25519SecretKey::from_slice(vec![4u8; 32]);
+        let decryption = PkDecryption::from_key(secret_key);
+        assert_eq!(decryption.secret_key().to_bytes(), vec![4u8; 32]);
+
+        let key = b"DEFAULT_PICKLE_KEY".to_vec();
+        let pickle = decryption.to_libolm_pickle(key.clone()).unwrap();
+        let unpickled = PkDecryption::from_libolm_pickle(pickle.clone(), key).unwrap();
+        assert_eq!(unpickled.public_key().to_base64(), decryption.public_key().to_base64());
+
+        let message = PkEncryption::from_key(unpickled.public_key()).encrypt(b"Hello".to_vec());
+        assert_eq!(decryption.decrypt(message).unwrap(), b"Hello");
+
+        assert!(matches!(
+            PkDecryption::from_libolm_pickle(pickle, b"WRONG_KEY".to_vec()),
+            Err(VodozemacError::LibolmPickle(_))
+        ));
+    }
+
+    #[cfg(feature = "insecure-pk-encryption")]
+    #[test]
+    fn test_pk_decryption_wrong_key() {
+        use crate::{PkDecryption, PkEncryption, VodozemacError};
+
+        let message = PkEncryption::from_key(PkDecryption::new().public_key()).encrypt(b"Hello".to_vec());
+
+        assert!(matches!(PkDecryption::new().decrypt(message), Err(VodozemacError::PkDecryption(_))));
+    }
+
+    // Tests for the hazmat cipher
+    #[cfg(feature = "low-level-api")]
+    #[test]
+    fn test_cipher_roundtrip() {
+        use crate::Cipher;
+
+        let cipher = Cipher::new(vec![1u8; 32]).unwrap();
+        let ciphertext = cipher.encrypt(b"It's a secret to everybody".to_vec());
+        let mac = cipher.mac(ciphertext.clone());
+
+        cipher.verify_mac(ciphertext.clone(), mac.clone()).unwrap();
+        cipher.verify_truncated_mac(ciphertext.clone(), mac[..8].to_vec()).unwrap();
+        assert!(cipher.verify_mac(ciphertext.clone(), mac[..8].to_vec()).is_err());
+        assert!(cipher.verify_mac(b"Something else".to_vec(), mac).is_err());
+
+        assert_eq!(cipher.decrypt(ciphertext).unwrap(), b"It's a secret to everybody");
+    }
+
+    #[cfg(feature = "low-level-api")]
+    #[test]
+    fn test_cipher_pickle() {
+        use crate::{Cipher, VodozemacError};
+
+        let cipher = Cipher::new_pickle(b"DEFAULT_PICKLE_KEY".to_vec());
+        let ciphertext = cipher.encrypt_pickle(b"Pickled".to_vec());
+
+        assert_eq!(cipher.decrypt_pickle(ciphertext.clone()).unwrap(), b"Pickled");
+        assert!(matches!(
+            Cipher::new_pickle(b"WRONG_KEY".to_vec()).decrypt_pickle(ciphertext),
+            Err(VodozemacError::Cipher(_))
+        ));
+        assert!(matches!(Cipher::new(vec![0u8; 16]), Err(VodozemacError::Key(_))));
+        assert!(Cipher::new_megolm(vec![0u8; 128]).is_ok());
+    }
 }
@@ -0,0 +1,18 @@
+import importlib.util
+import json
+import sys
+from pathlib import Path
+
+import pytest
+
+BINDINGS_DIR = Path(__file__).resolve().parents[2]
+
+# Use the package in `bindings/python` unless vodozemac has been installed.
+if importlib.util.find_spec("vodozemac") is None:
+    sys.path.append(str(BINDINGS_DIR / "python"))
+
+
+@pytest.fixture(scope="session")
+def test_vectors():
+    with open(BINDINGS_DIR / "tests" / "test_vectors.json") as f:
+        return json.load(f)
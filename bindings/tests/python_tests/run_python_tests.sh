@@ -0,0 +1,38 @@
+#!/bin/bash
+set -e
+
+echo "🔧 Python Test Runner for Vodozemac Bindings"
+echo "============================================"
+
+# Determine script location and project paths
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+BINDINGS_ROOT="$(cd "$SCRIPT_DIR/../../" && pwd)"
+PYTHON="${PYTHON:-python3}"
+
+echo "📁 Project paths:"
+echo "  Python tests: $SCRIPT_DIR"
+echo "  Python package: $BINDINGS_ROOT/python"
+
+# Verify required tools exist
+echo "🔍 Verifying prerequisites..."
+if ! command -v "$PYTHON" >/dev/null 2>&1; then
+    echo "❌ Error: $PYTHON not found"
+    exit 1
+fi
+
+if ! "$PYTHON" -m pytest --version >/dev/null 2>&1; then
+    echo "❌ Error: pytest not found, install it with: $PYTHON -m pip install pytest"
+    exit 1
+fi
+
+# Build the library, generate the bindings and copy both into the package
+echo "🏗️  Generating Python bindings..."
+make -C "$BINDINGS_ROOT" python-package
+
+# Run tests
+echo "🧪 Running Python tests..."
+cd "$SCRIPT_DIR"
+"$PYTHON" -m pytest -v "$@"
+
+echo ""
+echo "🎉 Python test execution completed!"
@@ -0,0 +1,219 @@
+"""Tests for the Python bindings, mirroring the Swift test suite in
+`tests/swift_tests`."""
+
+import pytest
+
+import vodozemac
+from vodozemac import (
+    Account,
+    Curve25519PublicKey,
+    Curve25519SecretKey,
+    Ecies,
+    Ed25519PublicKey,
+    Ed25519SecretKey,
+    Ed25519Signature,
+    GroupSession,
+    InboundGroupSession,
+    MegolmMessage,
+    MegolmSessionConfig,
+    PreKeyMessage,
+    Sas,
+    SessionConfig,
+    SessionKey,
+    VodozemacError,
+)
+
+
+def test_utility_functions(test_vectors):
+    utility_tests = test_vectors["utility_tests"]
+
+    assert vodozemac.get_version() == utility_tests["version"]
+
+    for test in utility_tests["base64_encode_tests"]:
+        encoded = vodozemac.base64_encode(test["input"].encode())
+        assert encoded == test["output"], f"Base64 encoding failed for input: {test['input']}"
+
+    for test in utility_tests["base64_decode_tests"]:
+        if test["input"]:
+            decoded = vodozemac.base64_decode(test["input"])
+            assert decoded.decode() == test["output"], (
+                f"Base64 decoding failed for input: {test['input']}"
+            )
+
+
+def test_basic_cryptography():
+    secret_key = Curve25519SecretKey()
+    assert len(secret_key.public_key().as_bytes()) == 32
+
+    ed25519_secret = Ed25519SecretKey()
+    ed25519_public = ed25519_secret.public_key()
+    assert len(ed25519_public.as_bytes()) == 32
+
+    message = b"Test message for signing"
+    signature = ed25519_secret.sign(message)
+    ed25519_public.verify(message, signature)
+
+    with pytest.raises(VodozemacError):
+        ed25519_public.verify(b"Another message", signature)
+
+
+def test_diffie_hellman():
+    alice = Curve25519SecretKey()
+    bob = Curve25519SecretKey()
+
+    alice_secret = alice.diffie_hellman(bob.public_key())
+    bob_secret = bob.diffie_hellman(alice.public_key())
+
+    assert alice_secret.was_contributory()
+    assert alice_secret.to_bytes() == bob_secret.to_bytes()
+
+
+def test_megolm_basics():
+    group_session = GroupSession.with_config(MegolmSessionConfig.version_1())
+    assert group_session.session_id()
+
+    session_key = group_session.session_key()
+    assert session_key.to_base64()
+
+    plaintext = b"Hello, group!"
+    encrypted = group_session.encrypt(plaintext)
+    assert encrypted.to_base64()
+    assert group_session.message_index() == 1
+
+    inbound_session = InboundGroupSession(session_key, MegolmSessionConfig.version_1())
+    assert inbound_session.session_id() == group_session.session_id()
+
+    decrypted = inbound_session.decrypt(encrypted)
+    assert decrypted.plaintext() == plaintext
+    assert decrypted.message_index() == 0
+
+
+def test_megolm_pickling():
+    group_session = GroupSession()
+    pickle_key = bytes(32)
+
+    pickle = group_session.pickle().encrypt(pickle_key)
+    restored = GroupSession.from_pickle(
+        vodozemac.GroupSessionPickle.from_encrypted(pickle, pickle_key)
+    )
+
+    assert restored.session_id() == group_session.session_id()
+    assert restored.message_index() == group_session.message_index()
+
+
+def test_olm_session():
+    alice = Account()
+    bob = Account()
+    bob.generate_one_time_keys(1)
+    one_time_key = bob.one_time_keys()[0].key()
+    bob.mark_keys_as_published()
+
+    alice_session = alice.create_outbound_session(
+        SessionConfig.version_2(), bob.curve25519_key(), one_time_key
+    )
+    message = alice_session.encrypt(b"Hello from Alice to Bob!")
+    assert message.message_type() == vodozemac.MessageType.PRE_KEY
+
+    result = bob.create_inbound_session(
+        alice.curve25519_key(), PreKeyMessage.from_base64(message.to_base64())
+    )
+    assert result.plaintext() == b"Hello from Alice to Bob!"
+
+    bob_session = result.session()
+    assert bob_session.session_id() == alice_session.session_id()
+
+    reply = bob_session.encrypt(b"Hello from Bob to Alice!")
+    assert reply.message_type() == vodozemac.MessageType.NORMAL
+    assert alice_session.decrypt(reply) == b"Hello from Bob to Alice!"
+
+
+def test_account_pickling():
+    account = Account()
+    pickle_key = bytes(32)
+
+    pickle = account.pickle().encrypt(pickle_key)
+    restored = Account.from_pickle(vodozemac.AccountPickle.from_encrypted(pickle, pickle_key))
+
+    assert restored.curve25519_key().to_base64() == account.curve25519_key().to_base64()
+    assert restored.ed25519_key().to_base64() == account.ed25519_key().to_base64()
+
+
+def test_sas():
+    alice = Sas()
+    bob = Sas()
+
+    alice_key = alice.public_key()
+    bob_key = bob.public_key()
+
+    alice_established = alice.diffie_hellman(bob_key)
+    bob_established = bob.diffie_hellman(alice_key)
+
+    alice_bytes = alice_established.bytes("AGREED_INFO")
+    bob_bytes = bob_established.bytes("AGREED_INFO")
+    assert alice_bytes.emoji_indices() == bob_bytes.emoji_indices()
+    assert alice_bytes.decimals() == bob_bytes.decimals()
+
+    mac = alice_established.calculate_mac("ID_KEY", "MAC_INFO")
+    bob_established.verify_mac("ID_KEY", "MAC_INFO", vodozemac.Mac.from_base64(mac.to_base64()))
+
+    with pytest.raises(VodozemacError):
+        bob_established.verify_mac("OTHER_KEY", "MAC_INFO", mac)
+
+    # The SAS object is consumed once the shared secret is established.
+    with pytest.raises(VodozemacError):
+        alice.diffie_hellman(bob_key)
+
+
+def test_ecies():
+    alice = Ecies()
+    bob = Ecies()
+
+    outbound = alice.establish_outbound_channel(bob.public_key(), b"It's a secret to everybody")
+    inbound = bob.establish_inbound_channel(outbound.message())
+
+    assert inbound.message() == b"It's a secret to everybody"
+    assert outbound.ecies().check_code().to_digit() == inbound.ecies().check_code().to_digit()
+
+    message = inbound.ecies().encrypt(b"Hello back")
+    assert outbound.ecies().decrypt(message) == b"Hello back"
+
+
+def test_error_handling():
+    with pytest.raises(VodozemacError):
+        vodozemac.base64_decode("invalid base64!")
+
+    with pytest.raises(VodozemacError):
+        Curve25519PublicKey.from_slice(bytes(16))
+
+    with pytest.raises(VodozemacError):
+        MegolmMessage.from_base64("not a megolm message")
+
+
+def test_account_operations_when_vectors_available(test_vectors):
+    if not test_vectors["account_tests"]:
+        pytest.skip("No account test vectors available")
+
+    for test in test_vectors["account_tests"]:
+        public_key = Ed25519PublicKey.from_base64(test["identity_keys"]["ed25519"])
+        signature = Ed25519Signature.from_base64(test["signature"])
+
+        public_key.verify(test["signature_message"].encode(), signature)
+        Curve25519PublicKey.from_base64(test["identity_keys"]["curve25519"])
+
+
+def test_group_session_operations_when_vectors_available(test_vectors):
+    if not test_vectors["group_session_tests"]:
+        pytest.skip("No group session test vectors available")
+
+    for test in test_vectors["group_session_tests"]:
+        config = (
+            MegolmSessionConfig.version_2()
+            if "v2" in test["name"]
+            else MegolmSessionConfig.version_1()
+        )
+        session = InboundGroupSession(SessionKey.from_base64(test["session_key"]), config)
+        assert session.session_id() == test["session_id"]
+
+        decrypted = session.decrypt(MegolmMessage.from_base64(test["encrypted_message"]))
+        assert decrypted.plaintext().decode() == test["decrypted_message"], test["name"]
+        assert decrypted.message_index() == test["decrypted_index"], test["name"]
@@ -0,0 +1,187 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Context;
+use clap::Subcommand;
+use serde_json::{Value, json};
+use vodozemac::{
+    base64_decode, base64_encode,
+    megolm::MegolmMessage,
+    olm::{self, OlmMessage, PreKeyMessage},
+};
+
+use crate::argument_or_stdin;
+
+/// The kinds of messages that can be decoded.
+#[derive(Subcommand)]
+pub(crate) enum Message {
+    /// An Olm message, as found in the `body` of an `m.olm.v1` ciphertext.
+    Olm {
+        /// The message type, as found in the `type` of an `m.olm.v1`
+        /// ciphertext, 0 for pre-key messages and 1 for normal messages.
+        #[arg(long = "type", value_name = "TYPE")]
+        message_type: usize,
+        /// The base64 encoded message, read from stdin if missing.
+        message: Option<String>,
+    },
+    /// An Olm pre-key message.
+    PreKey {
+        /// The base64 encoded message, read from stdin if missing.
+        message: Option<String>,
+    },
+    /// A Megolm message, as found in the `ciphertext` of an
+    /// `m.megolm.v1.aes-sha2` event.
+    Megolm {
+        /// The base64 encoded message, read from stdin if missing.
+        message: Option<String>,
+    },
+}
+
+impl Message {
+    pub(crate) fn decode(self) -> anyhow::Result<Value> {
+        match self {
+            Message::Olm { message_type, message } => {
+                let message = base64_decode(argument_or_stdin(message)?)
+                    .context("The message isn't valid base64")?;
+
+                match OlmMessage::from_parts(message_type, &message)? {
+                    OlmMessage::Normal(m) => Ok(normal_message(&m)),
+                    OlmMessage::PreKey(m) => Ok(pre_key_message(&m)),
+                }
+            }
+            Message::PreKey { message } => {
+                Ok(pre_key_message(&PreKeyMessage::from_base64(&argument_or_stdin(message)?)?))
+            }
+            Message::Megolm { message } => {
+                Ok(megolm_message(&MegolmMessage::from_base64(&argument_or_stdin(message)?)?))
+            }
+        }
+    }
+}
+
+fn normal_message(message: &olm::Message) -> Value {
+    json!({
+        "type": "normal",
+        "version": message.version(),
+        "header_encrypted": message.is_header_encrypted(),
+        "ratchet_key": message.ratchet_key().map(|key| key.to_base64()),
+        "chain_index": message.chain_index(),
+        "ciphertext_length": message.ciphertext().len(),
+        "padded": message.is_padded(),
+        "mac_truncated": message.mac_truncated(),
+        "mac": base64_encode(message.mac()),
+    })
+}
+
+fn pre_key_message(message: &PreKeyMessage) -> Value {
+    json!({
+        "type": "pre_key",
+        "session_id": message.session_id(),
+        "identity_key": message.identity_key().to_base64(),
+        "base_key": message.base_key().to_base64(),
+        "one_time_key": message.one_time_key().to_base64(),
+        "kem_key_hash": message.kem_key_hash().map(base64_encode),
+        "message": normal_message(message.message()),
+    })
+}
+
+fn megolm_message(message: &MegolmMessage) -> Value {
+    json!({
+        "version": message.version(),
+        "message_index": message.message_index(),
+        "ciphertext_length": message.ciphertext().len(),
+        "padded": message.is_padded(),
+        "mac": base64_encode(message.mac()),
+        "signature": message.signature().to_base64(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use vodozemac::{
+        Curve25519PublicKey,
+        megolm::{GroupSession, SessionConfig},
+        olm::Account,
+    };
+
+    use super::*;
+
+    #[test]
+    fn decode_olm_messages() {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+        let one_time_key = *bob.one_time_keys().values().next().expect("Bob should have a key");
+
+        let mut session = alice.create_outbound_session(
+            olm::SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+        let (message_type, message) = session.encrypt("Hello").to_parts();
+
+        let decoded = Message::Olm { message_type, message: Some(base64_encode(&message)) }
+            .decode()
+            .expect("The pre-key message should be decodable");
+
+        assert_eq!(decoded["type"], "pre_key");
+        assert_eq!(decoded["session_id"], session.session_id());
+        assert_eq!(decoded["identity_key"], alice.curve25519_key().to_base64());
+        assert_eq!(decoded["one_time_key"], one_time_key.to_base64());
+        assert!(decoded["kem_key_hash"].is_null());
+        assert_eq!(decoded["message"]["version"], 4);
+        assert_eq!(decoded["message"]["chain_index"], 0);
+        assert_eq!(decoded["message"]["header_encrypted"], false);
+        assert_eq!(decoded["message"]["mac_truncated"], false);
+        assert_eq!(
+            base64_decode(decoded["message"]["mac"].as_str().expect("The MAC should be a string"))
+                .expect("The MAC should be valid base64")
+                .len(),
+            32
+        );
+        Curve25519PublicKey::from_base64(
+            decoded["message"]["ratchet_key"].as_str().expect("The key should be a string"),
+        )
+        .expect("The ratchet key should be valid");
+
+        let pre_key = Message::PreKey { message: Some(base64_encode(&message)) }
+            .decode()
+            .expect("The pre-key message should be decodable");
+        assert_eq!(pre_key, decoded);
+
+        assert!(
+            Message::Olm { message_type: 1, message: Some(base64_encode(&message)) }
+                .decode()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn decode_megolm_message() {
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        session.encrypt("First");
+        let message = session.encrypt("Second");
+
+        let decoded = Message::Megolm { message: Some(message.to_base64()) }
+            .decode()
+            .expect("The Megolm message should be decodable");
+
+        assert_eq!(decoded["version"], 3);
+        assert_eq!(decoded["message_index"], 1);
+        assert_eq!(decoded["signature"], message.signature().to_base64());
+        assert_eq!(decoded["mac"], base64_encode(message.mac()));
+
+        assert!(Message::Megolm { message: Some("not a message".to_owned()) }.decode().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A tool to inspect the artifacts vodozemac produces, for debugging
+//! "unable to decrypt" reports.
+//!
+//! It decodes Olm and Megolm messages, prints the non-secret metadata of
+//! pickles and verifies the signature of Megolm messages. Everything is printed
+//! as JSON so the output can be attached to bug reports or piped into `jq`.
+//!
+//! Message and pickle arguments can be left out, they are then read from
+//! standard input.
+
+mod decode;
+mod pickle;
+mod verify;
+
+use std::io::Read;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+
+#[derive(Parser)]
+#[command(version, about = "Inspect vodozemac messages and pickles")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decode a base64 encoded message.
+    #[command(subcommand)]
+    Decode(decode::Message),
+    /// Decrypt a pickle and print its non-secret metadata.
+    Pickle(pickle::Arguments),
+    /// Verify the signature of a Megolm message against a session key.
+    VerifyMegolm(verify::Arguments),
+}
+
+/// Use the argument if it was given, read it from standard input otherwise.
+fn argument_or_stdin(argument: Option<String>) -> anyhow::Result<String> {
+    match argument {
+        Some(argument) => Ok(argument),
+        None => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Couldn't read the input from stdin")?;
+
+            Ok(input.trim().to_owned())
+        }
+    }
+}
+
+fn print(value: &Value) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::Decode(message) => print(&message.decode()?),
+        Command::Pickle(arguments) => print(&arguments.inspect()?),
+        Command::VerifyMegolm(arguments) => {
+            let result = arguments.verify()?;
+            print(&result)?;
+
+            if result["valid"] != Value::Bool(true) {
+                std::process::exit(1);
+            }
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::{Args, ValueEnum};
+use serde_json::{Value, json};
+use vodozemac::{
+    base64_decode,
+    megolm::{GroupSession, GroupSessionPickle, InboundGroupSession, InboundGroupSessionPickle},
+    olm::{Account, AccountPickle, Session, SessionPickle},
+};
+
+use crate::argument_or_stdin;
+
+/// The kinds of objects that can be pickled.
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum Kind {
+    /// An Olm account.
+    Account,
+    /// An Olm session.
+    Session,
+    /// An outbound Megolm session.
+    GroupSession,
+    /// An inbound Megolm session.
+    InboundGroupSession,
+}
+
+/// The environment variable the pickle key is read from if no key file was
+/// given.
+const PICKLE_KEY_VARIABLE: &str = "VODOZEMAC_PICKLE_KEY";
+
+#[derive(Args)]
+pub(crate) struct Arguments {
+    /// The kind of object the pickle contains.
+    #[arg(value_enum)]
+    kind: Kind,
+    /// A file containing the key the pickle was encrypted with.
+    ///
+    /// For vodozemac pickles this is the base64 encoded 32 byte key, for
+    /// libolm pickles the key is used as is. If missing, the key is read from
+    /// the `VODOZEMAC_PICKLE_KEY` environment variable. The key can't be
+    /// passed as an argument, since it would end up in the shell history and
+    /// be visible to other users of the system.
+    #[arg(long, value_name = "PATH")]
+    pickle_key_file: Option<PathBuf>,
+    /// The pickle uses the libolm pickle format.
+    #[arg(long)]
+    libolm: bool,
+    /// The pickle, read from stdin if missing.
+    pickle: Option<String>,
+}
+
+impl Arguments {
+    /// Decrypt the pickle and collect the metadata that is safe to share.
+    pub(crate) fn inspect(self) -> anyhow::Result<Value> {
+        let pickle_key = self.pickle_key()?;
+        self.inspect_with_key(&pickle_key)
+    }
+
+    /// Read the pickle key from the key file, or from the environment if no
+    /// file was given.
+    fn pickle_key(&self) -> anyhow::Result<String> {
+        let key = match &self.pickle_key_file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Couldn't read the pickle key from {}", path.display()))?,
+            None => std::env::var(PICKLE_KEY_VARIABLE).with_context(|| {
+                format!("Either pass --pickle-key-file or set {PICKLE_KEY_VARIABLE}")
+            })?,
+        };
+
+        Ok(key.trim_end_matches(['\r', '\n']).to_owned())
+    }
+
+    fn inspect_with_key(self, pickle_key: &str) -> anyhow::Result<Value> {
+        let pickle = argument_or_stdin(self.pickle)?;
+
+        if self.libolm {
+            let key = pickle_key.as_bytes();
+
+            Ok(match self.kind {
+                Kind::Account => account(&Account::from_libolm_pickle(&pickle, key)?),
+                Kind::Session => session(&Session::from_libolm_pickle(&pickle, key)?),
+                Kind::GroupSession => {
+                    group_session(&GroupSession::from_libolm_pickle(&pickle, key)?)
+                }
+                Kind::InboundGroupSession => {
+                    inbound_group_session(&InboundGroupSession::from_libolm_pickle(&pickle, key)?)
+                }
+            })
+        } else {
+            let key = decode_pickle_key(pickle_key)?;
+
+            Ok(match self.kind {
+                Kind::Account => {
+                    account(&Account::from_pickle(AccountPickle::from_encrypted(&pickle, &key)?))
+                }
+                Kind::Session => {
+                    session(&Session::from_pickle(SessionPickle::from_encrypted(&pickle, &key)?))
+                }
+                Kind::GroupSession => group_session(&GroupSession::from_pickle(
+                    GroupSessionPickle::from_encrypted(&pickle, &key)?,
+                )),
+                Kind::InboundGroupSession => {
+                    inbound_group_session(&InboundGroupSession::from_pickle(
+                        InboundGroupSessionPickle::from_encrypted(&pickle, &key)?,
+                    ))
+                }
+            })
+        }
+    }
+}
+
+fn decode_pickle_key(key: &str) -> anyhow::Result<[u8; 32]> {
+    let key = base64_decode(key).context("The pickle key isn't valid base64")?;
+
+    match key.try_into() {
+        Ok(key) => Ok(key),
+        Err(key) => bail!("The pickle key needs to be 32 bytes long, got {} bytes", key.len()),
+    }
+}
+
+fn account(account: &Account) -> Value {
+    json!({
+        "curve25519_key": account.curve25519_key().to_base64(),
+        "ed25519_key": account.ed25519_key().to_base64(),
+        "one_time_key_count": account.stored_one_time_key_count(),
+        "unpublished_one_time_key_count": account.one_time_keys().len(),
+        "has_fallback_key": !account.fallback_key().is_empty(),
+    })
+}
+
+fn session(session: &Session) -> Value {
+    json!({
+        "session_id": session.session_id(),
+        "version": session.session_config().version(),
+        "has_received_message": session.has_received_message(),
+        "receiving_chain_count": session.receiving_chain_count(),
+    })
+}
+
+fn group_session(session: &GroupSession) -> Value {
+    json!({
+        "session_id": session.session_id(),
+        "version": session.session_config().version(),
+        "message_index": session.message_index(),
+        "creation_time": session.creation_time(),
+    })
+}
+
+fn inbound_group_session(session: &InboundGroupSession) -> Value {
+    json!({
+        "session_id": session.session_id(),
+        "first_known_index": session.first_known_index(),
+        "authenticated": session.origin().is_authenticated(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use vodozemac::{base64_encode, megolm::SessionConfig};
+
+    use super::*;
+
+    const PICKLE_KEY: [u8; 32] = [1u8; 32];
+
+    fn arguments(kind: Kind, pickle: String) -> Arguments {
+        Arguments { kind, pickle_key_file: None, libolm: false, pickle: Some(pickle) }
+    }
+
+    #[test]
+    fn inspect_account() {
+        let mut account = Account::new();
+        account.generate_one_time_keys(3);
+        account.generate_fallback_key();
+
+        let pickle = account.pickle().encrypt(&PICKLE_KEY);
+        let metadata = arguments(Kind::Account, pickle)
+            .inspect_with_key(&base64_encode(PICKLE_KEY))
+            .expect("Should inspect the pickle");
+
+        assert_eq!(metadata["curve25519_key"], account.curve25519_key().to_base64());
+        assert_eq!(metadata["one_time_key_count"], 3);
+        assert_eq!(metadata["has_fallback_key"], true);
+    }
+
+    #[test]
+    fn inspect_inbound_group_session() {
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        session.encrypt("Hello");
+
+        let inbound = InboundGroupSession::new(&session.session_key(), SessionConfig::version_1());
+        let pickle = inbound.pickle().encrypt(&PICKLE_KEY);
+
+        let metadata = arguments(Kind::InboundGroupSession, pickle)
+            .inspect_with_key(&base64_encode(PICKLE_KEY))
+            .expect("Should inspect the pickle");
+
+        assert_eq!(metadata["session_id"], session.session_id());
+        assert_eq!(metadata["first_known_index"], 1);
+        assert_eq!(metadata["authenticated"], true);
+    }
+
+    #[test]
+    fn inspect_libolm_account() {
+        let account = Account::new();
+        let pickle = account.to_libolm_pickle(b"It's a secret").expect("Should pickle the account");
+
+        let metadata = Arguments {
+            kind: Kind::Account,
+            pickle_key_file: None,
+            libolm: true,
+            pickle: Some(pickle),
+        }
+        .inspect_with_key("It's a secret")
+        .expect("Should inspect the libolm pickle");
+
+        assert_eq!(metadata["ed25519_key"], account.ed25519_key().to_base64());
+        assert_eq!(metadata["has_fallback_key"], false);
+    }
+
+    #[test]
+    fn pickle_key_from_file() {
+        let path = std::env::temp_dir().join("vodozemac-cli-pickle-key");
+        std::fs::write(&path, "It's a secret\n").expect("Should write the key file");
+
+        let arguments = Arguments {
+            kind: Kind::Account,
+            pickle_key_file: Some(path.clone()),
+            libolm: true,
+            pickle: None,
+        };
+        let key = arguments.pickle_key();
+        std::fs::remove_file(path).expect("Should remove the key file");
+
+        assert_eq!(key.expect("Should read the pickle key"), "It's a secret");
+    }
+
+    #[test]
+    fn inspect_with_invalid_key() {
+        let session = GroupSession::new(SessionConfig::version_1());
+        let pickle = session.pickle().encrypt(&PICKLE_KEY);
+
+        let wrong_key = arguments(Kind::GroupSession, pickle.clone());
+        assert!(wrong_key.inspect_with_key(&base64_encode([2u8; 32])).is_err());
+
+        let short_key = arguments(Kind::GroupSession, pickle);
+        assert!(short_key.inspect_with_key(&base64_encode([1u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Context;
+use clap::Args;
+use serde_json::{Value, json};
+use vodozemac::{
+    Ed25519PublicKey,
+    megolm::{ExportedSessionKey, MegolmMessage, SessionKey},
+};
+
+use crate::argument_or_stdin;
+
+#[derive(Args)]
+pub(crate) struct Arguments {
+    /// The base64 encoded session key, either in the signed format found in
+    /// `m.room_key` events or in the exported format used by key backups and
+    /// forwarded room keys.
+    #[arg(long)]
+    session_key: String,
+    /// The base64 encoded Megolm message, read from stdin if missing.
+    message: Option<String>,
+}
+
+impl Arguments {
+    /// Check if the message was signed by the signing key of the session.
+    pub(crate) fn verify(self) -> anyhow::Result<Value> {
+        let signing_key = signing_key(&self.session_key)?;
+        let message = MegolmMessage::from_base64(&argument_or_stdin(self.message)?)?;
+
+        let result = message.verify_signature(&signing_key);
+
+        Ok(json!({
+            "valid": result.is_ok(),
+            "error": result.err().map(|e| e.to_string()),
+            "signing_key": signing_key.to_base64(),
+            "message_index": message.message_index(),
+        }))
+    }
+}
+
+fn signing_key(session_key: &str) -> anyhow::Result<Ed25519PublicKey> {
+    match SessionKey::from_base64(session_key) {
+        Ok(key) => Ok(key.signing_key()),
+        Err(_) => Ok(ExportedSessionKey::from_base64(session_key)
+            .context("The session key is neither a valid session key nor an exported one")?
+            .signing_key()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vodozemac::megolm::{GroupSession, InboundGroupSession, SessionConfig};
+
+    use super::*;
+
+    #[test]
+    fn verify_signature() {
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        let message = session.encrypt("Hello").to_base64();
+
+        let result = Arguments {
+            session_key: session.session_key().to_base64(),
+            message: Some(message.clone()),
+        }
+        .verify()
+        .expect("Should verify the message");
+
+        assert_eq!(result["valid"], true);
+        assert_eq!(result["message_index"], 0);
+
+        let inbound = InboundGroupSession::new(&session.session_key(), SessionConfig::version_1());
+        let exported = inbound.export_at_first_known_index();
+
+        let result = Arguments { session_key: exported.to_base64(), message: Some(message) }
+            .verify()
+            .expect("Should verify the message");
+
+        assert_eq!(result["valid"], true);
+    }
+
+    #[test]
+    fn verify_signature_of_another_session() {
+        let session = GroupSession::new(SessionConfig::version_1());
+        let mut other = GroupSession::new(SessionConfig::version_1());
+
+        let result = Arguments {
+            session_key: session.session_key().to_base64(),
+            message: Some(other.encrypt("Hello").to_base64()),
+        }
+        .verify()
+        .expect("Should check the message");
+
+        assert_eq!(result["valid"], false);
+        assert!(result["error"].is_string());
+
+        assert!(Arguments { session_key: "invalid".to_owned(), message: None }.verify().is_err());
+    }
+}
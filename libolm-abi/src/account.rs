@@ -0,0 +1,589 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::BTreeMap, ffi::c_void};
+
+use serde_json::json;
+use vodozemac::olm::{Account, AccountPickle};
+
+use crate::{
+    OlmErrorCode, OlmObject, OlmSession, OlmState, RandomBuffer, input, object, object_functions,
+    output, pickle_key, unpickle, write_output,
+};
+
+/// The random bytes an account needs, one Ed25519 and one Curve25519 key.
+const ACCOUNT_RANDOM_LENGTH: usize = 64;
+/// The random bytes a single one-time or fallback key needs.
+const KEY_RANDOM_LENGTH: usize = 32;
+
+/// An Olm account, see [`Account`].
+pub type OlmAccount = OlmObject<Account>;
+
+impl OlmState for Account {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::BadAccountKey;
+}
+
+object_functions!(
+    OlmAccount, "an account",
+    size: olm_account_size,
+    init: olm_account,
+    last_error: olm_account_last_error,
+    last_error_code: olm_account_last_error_code,
+    clear: olm_clear_account,
+);
+
+fn identity_keys(account: &Account) -> String {
+    let keys = account.identity_keys();
+
+    json!({
+        "curve25519": keys.curve25519.to_base64(),
+        "ed25519": keys.ed25519.to_base64(),
+    })
+    .to_string()
+}
+
+fn one_time_keys(account: &Account) -> String {
+    let keys: BTreeMap<_, _> = account
+        .one_time_keys()
+        .into_iter()
+        .map(|(key_id, key)| (key_id.to_base64(), key.to_base64()))
+        .collect();
+
+    json!({ "curve25519": keys }).to_string()
+}
+
+fn unpublished_fallback_key(account: &Account) -> String {
+    let keys: BTreeMap<_, _> = account
+        .fallback_key()
+        .into_iter()
+        .map(|(key_id, key)| (key_id.to_base64(), key.to_base64()))
+        .collect();
+
+    json!({ "curve25519": keys }).to_string()
+}
+
+/// The number of random bytes [`olm_create_account()`] needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_account_random_length(_account: *const OlmAccount) -> usize {
+    ACCOUNT_RANDOM_LENGTH
+}
+
+/// Create new identity keys for the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_account(
+    account: *mut OlmAccount,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = RandomBuffer::new(random, ACCOUNT_RANDOM_LENGTH).map(|mut rng| {
+        account.inner = Some(Account::new_with_rng(&mut rng));
+        0
+    });
+
+    account.finish(result)
+}
+
+/// The length of the pickled account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_account_length(account: *mut OlmAccount) -> usize {
+    let account = unsafe { object(account) };
+
+    // The length of a libolm pickle doesn't depend on the key.
+    let result = account
+        .inner()
+        .and_then(|a| a.to_libolm_pickle(&[]).map(|pickle| pickle.len()).map_err(Into::into));
+
+    account.finish(result)
+}
+
+/// Pickle the account in the libolm format, encrypting it with the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_account(
+    account: *mut OlmAccount,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *mut c_void,
+    pickled_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { output(pickled, pickled_length) };
+
+    let result = account.inner().and_then(|a| {
+        let pickle = a.to_libolm_pickle(key)?;
+        write_output(pickled, pickle.as_bytes())
+    });
+
+    account.finish(result)
+}
+
+/// Restore the account from a libolm or vodozemac pickle, decrypting it with
+/// the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_unpickle_account(
+    account: *mut OlmAccount,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *const c_void,
+    pickled_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { input(pickled, pickled_length) };
+
+    let result = unpickle(
+        pickled,
+        |pickle| Account::from_libolm_pickle(pickle, key),
+        |pickle| AccountPickle::from_encrypted(pickle, &pickle_key(key)).map(Account::from_pickle),
+    )
+    .map(|unpickled| {
+        account.inner = Some(unpickled);
+        pickled_length
+    });
+
+    account.finish(result)
+}
+
+/// The length of the JSON encoded identity keys of the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_identity_keys_length(account: *mut OlmAccount) -> usize {
+    let account = unsafe { object(account) };
+    let result = account.inner().map(|a| identity_keys(a).len());
+
+    account.finish(result)
+}
+
+/// Write the public identity keys of the account, JSON encoded, into the
+/// output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_identity_keys(
+    account: *mut OlmAccount,
+    identity_keys_buffer: *mut c_void,
+    identity_keys_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let identity_keys_buffer = unsafe { output(identity_keys_buffer, identity_keys_length) };
+
+    let result = account
+        .inner()
+        .and_then(|a| write_output(identity_keys_buffer, identity_keys(a).as_bytes()));
+
+    account.finish(result)
+}
+
+/// The length of a base64 encoded Ed25519 signature.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_signature_length(_account: *const OlmAccount) -> usize {
+    86
+}
+
+/// Sign the message using the Ed25519 key of the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_sign(
+    account: *mut OlmAccount,
+    message: *const c_void,
+    message_length: usize,
+    signature: *mut c_void,
+    signature_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let message = unsafe { input(message, message_length) };
+    let signature = unsafe { output(signature, signature_length) };
+
+    let result = account
+        .inner()
+        .and_then(|a| write_output(signature, a.sign(message).to_base64().as_bytes()));
+
+    account.finish(result)
+}
+
+/// The length of the JSON encoded unpublished one-time keys of the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_one_time_keys_length(account: *mut OlmAccount) -> usize {
+    let account = unsafe { object(account) };
+    let result = account.inner().map(|a| one_time_keys(a).len());
+
+    account.finish(result)
+}
+
+/// Write the unpublished one-time keys of the account, JSON encoded, into the
+/// output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_one_time_keys(
+    account: *mut OlmAccount,
+    one_time_keys_buffer: *mut c_void,
+    one_time_keys_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let one_time_keys_buffer = unsafe { output(one_time_keys_buffer, one_time_keys_length) };
+
+    let result = account
+        .inner()
+        .and_then(|a| write_output(one_time_keys_buffer, one_time_keys(a).as_bytes()));
+
+    account.finish(result)
+}
+
+/// Mark the one-time and fallback keys of the account as published, returns
+/// the number of one-time keys that were marked.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_mark_keys_as_published(account: *mut OlmAccount) -> usize {
+    let account = unsafe { object(account) };
+
+    let result = account.inner().map(|a| {
+        let count = a.one_time_keys().len();
+        a.mark_keys_as_published();
+        count
+    });
+
+    account.finish(result)
+}
+
+/// The maximum number of one-time keys the account can store.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_max_number_of_one_time_keys(
+    account: *mut OlmAccount,
+) -> usize {
+    let account = unsafe { object(account) };
+    let result = account.inner().map(|a| a.max_number_of_one_time_keys());
+
+    account.finish(result)
+}
+
+/// The number of random bytes [`olm_account_generate_one_time_keys()`] needs to
+/// generate the given number of keys.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_generate_one_time_keys_random_length(
+    _account: *const OlmAccount,
+    number_of_keys: usize,
+) -> usize {
+    number_of_keys.saturating_mul(KEY_RANDOM_LENGTH)
+}
+
+/// Generate new one-time keys, returns the number of generated keys.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_generate_one_time_keys(
+    account: *mut OlmAccount,
+    number_of_keys: usize,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = account.inner().and_then(|a| {
+        let mut rng = RandomBuffer::new(random, number_of_keys.saturating_mul(KEY_RANDOM_LENGTH))?;
+        Ok(a.generate_one_time_keys_with_rng(number_of_keys, &mut rng).created.len())
+    });
+
+    account.finish(result)
+}
+
+/// The number of random bytes [`olm_account_generate_fallback_key()`] needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_generate_fallback_key_random_length(
+    _account: *const OlmAccount,
+) -> usize {
+    KEY_RANDOM_LENGTH
+}
+
+/// Generate a new fallback key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_generate_fallback_key(
+    account: *mut OlmAccount,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = account.inner().and_then(|a| {
+        let mut rng = RandomBuffer::new(random, KEY_RANDOM_LENGTH)?;
+        a.generate_fallback_key_with_rng(&mut rng);
+        Ok(1)
+    });
+
+    account.finish(result)
+}
+
+/// The length of the JSON encoded unpublished fallback key of the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_unpublished_fallback_key_length(
+    account: *mut OlmAccount,
+) -> usize {
+    let account = unsafe { object(account) };
+    let result = account.inner().map(|a| unpublished_fallback_key(a).len());
+
+    account.finish(result)
+}
+
+/// Write the unpublished fallback key of the account, JSON encoded, into the
+/// output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_unpublished_fallback_key(
+    account: *mut OlmAccount,
+    fallback_key: *mut c_void,
+    fallback_key_length: usize,
+) -> usize {
+    let account = unsafe { object(account) };
+    let fallback_key = unsafe { output(fallback_key, fallback_key_length) };
+
+    let result = account
+        .inner()
+        .and_then(|a| write_output(fallback_key, unpublished_fallback_key(a).as_bytes()));
+
+    account.finish(result)
+}
+
+/// Forget the previous fallback key of the account.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_account_forget_old_fallback_key(account: *mut OlmAccount) {
+    let account = unsafe { object(account) };
+
+    if let Some(account) = account.inner.as_mut() {
+        account.forget_fallback_key();
+    }
+}
+
+/// Remove the one-time key the session used from the account.
+///
+/// vodozemac already removes the one-time key when the inbound session is
+/// created, so this doesn't need to do anything.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_remove_one_time_keys(
+    _account: *mut OlmAccount,
+    _session: *mut OlmSession,
+) -> usize {
+    0
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::ptr;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{olm_error, test::Object};
+
+    pub(crate) fn create_account() -> Object<Account> {
+        let account = Object::new(olm_account_size(), olm_account);
+        let random = [7u8; ACCOUNT_RANDOM_LENGTH];
+
+        assert_eq!(
+            unsafe { olm_create_account(account.ptr(), random.as_ptr().cast(), random.len()) },
+            0
+        );
+
+        account
+    }
+
+    fn json(
+        account: &Object<Account>,
+        length: usize,
+        write: unsafe extern "C" fn(*mut OlmAccount, *mut c_void, usize) -> usize,
+    ) -> Value {
+        let mut buffer = vec![0u8; length];
+        assert_eq!(
+            unsafe { write(account.ptr(), buffer.as_mut_ptr().cast(), buffer.len()) },
+            length
+        );
+
+        serde_json::from_slice(&buffer).expect("The output should be valid JSON")
+    }
+
+    #[test]
+    fn identity_keys_and_signing() {
+        let account = create_account();
+        let length = unsafe { olm_account_identity_keys_length(account.ptr()) };
+        let keys = json(&account, length, olm_account_identity_keys);
+
+        let inner = account.inner();
+        assert_eq!(keys["curve25519"], inner.curve25519_key().to_base64());
+        assert_eq!(keys["ed25519"], inner.ed25519_key().to_base64());
+
+        let message = b"It's a secret to everybody";
+        let mut signature = vec![0u8; unsafe { olm_account_signature_length(account.ptr()) }];
+
+        unsafe {
+            olm_account_sign(
+                account.ptr(),
+                message.as_ptr().cast(),
+                message.len(),
+                signature.as_mut_ptr().cast(),
+                signature.len(),
+            )
+        };
+
+        let signature = vodozemac::Ed25519Signature::from_base64(
+            std::str::from_utf8(&signature).expect("The signature should be valid UTF-8"),
+        )
+        .expect("The signature should be valid base64");
+
+        inner.ed25519_key().verify(message, &signature).expect("The signature should be valid");
+    }
+
+    #[test]
+    fn one_time_keys() {
+        let account = create_account();
+        let random = [1u8; 2 * KEY_RANDOM_LENGTH];
+
+        assert_eq!(
+            unsafe { olm_account_generate_one_time_keys_random_length(account.ptr(), 2) },
+            random.len()
+        );
+        assert_eq!(
+            unsafe {
+                olm_account_generate_one_time_keys(
+                    account.ptr(),
+                    3,
+                    random.as_ptr().cast(),
+                    random.len(),
+                )
+            },
+            olm_error(),
+            "Generating keys without enough random bytes should fail"
+        );
+        assert_eq!(account.last_error(), c"NOT_ENOUGH_RANDOM");
+        assert_eq!(
+            unsafe {
+                olm_account_generate_one_time_keys(
+                    account.ptr(),
+                    2,
+                    random.as_ptr().cast(),
+                    random.len(),
+                )
+            },
+            2
+        );
+
+        let length = unsafe { olm_account_one_time_keys_length(account.ptr()) };
+        let keys = json(&account, length, olm_account_one_time_keys);
+        assert_eq!(keys["curve25519"].as_object().map(|keys| keys.len()), Some(2));
+
+        assert_eq!(unsafe { olm_account_mark_keys_as_published(account.ptr()) }, 2);
+
+        let length = unsafe { olm_account_one_time_keys_length(account.ptr()) };
+        let keys = json(&account, length, olm_account_one_time_keys);
+        assert_eq!(keys, json!({ "curve25519": {} }));
+    }
+
+    #[test]
+    fn fallback_key() {
+        let account = create_account();
+        let random = [2u8; KEY_RANDOM_LENGTH];
+
+        unsafe {
+            olm_account_generate_fallback_key(account.ptr(), random.as_ptr().cast(), random.len())
+        };
+
+        let length = unsafe { olm_account_unpublished_fallback_key_length(account.ptr()) };
+        let key = json(&account, length, olm_account_unpublished_fallback_key);
+        let expected = account.inner().fallback_key().into_values().next().map(|k| k.to_base64());
+
+        assert_eq!(
+            key["curve25519"].as_object().and_then(|k| k.values().next()).and_then(Value::as_str),
+            expected.as_deref()
+        );
+    }
+
+    #[test]
+    fn pickling() {
+        let account = create_account();
+        let key = b"DEFAULT_PICKLE_KEY";
+
+        let mut pickle = vec![0u8; unsafe { olm_pickle_account_length(account.ptr()) }];
+        unsafe {
+            olm_pickle_account(
+                account.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_mut_ptr().cast(),
+                pickle.len(),
+            )
+        };
+
+        let unpickled = Account::from_libolm_pickle(
+            std::str::from_utf8(&pickle).expect("The pickle should be valid UTF-8"),
+            key,
+        )
+        .expect("The pickle should be a valid libolm pickle");
+        assert_eq!(unpickled.identity_keys(), account.inner().identity_keys());
+
+        let restored = Object::new(olm_account_size(), olm_account);
+        let wrong_key = b"WRONG_KEY";
+
+        assert_eq!(
+            unsafe {
+                olm_unpickle_account(
+                    restored.ptr(),
+                    wrong_key.as_ptr().cast(),
+                    wrong_key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(restored.last_error(), c"BAD_ACCOUNT_KEY");
+
+        unsafe {
+            olm_unpickle_account(
+                restored.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_ptr().cast(),
+                pickle.len(),
+            )
+        };
+        assert_eq!(restored.inner().identity_keys(), account.inner().identity_keys());
+
+        // Pickles in the vodozemac format are accepted as well.
+        let pickle = account.inner().pickle().encrypt(&pickle_key(key));
+        let restored = Object::new(olm_account_size(), olm_account);
+
+        assert_eq!(
+            unsafe {
+                olm_unpickle_account(
+                    restored.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            pickle.len()
+        );
+        assert_eq!(restored.inner().identity_keys(), account.inner().identity_keys());
+    }
+
+    #[test]
+    fn uninitialized_account() {
+        let account = Object::new(olm_account_size(), olm_account);
+
+        assert_eq!(unsafe { olm_account_identity_keys_length(account.ptr()) }, olm_error());
+        assert_eq!(account.last_error(), c"BAD_ACCOUNT_KEY");
+        assert_eq!(
+            unsafe { olm_account_last_error_code(account.ptr()) },
+            OlmErrorCode::BadAccountKey
+        );
+
+        assert_eq!(unsafe { olm_clear_account(account.ptr()) }, olm_account_size());
+        assert_eq!(unsafe { olm_create_account(account.ptr(), ptr::null(), 0) }, olm_error());
+        assert_eq!(account.last_error(), c"NOT_ENOUGH_RANDOM");
+    }
+}
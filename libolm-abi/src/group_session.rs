@@ -0,0 +1,368 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::c_void;
+
+use vodozemac::megolm::{GroupSession, GroupSessionPickle, SessionConfig};
+
+use crate::{
+    OlmErrorCode, OlmObject, OlmState, RandomBuffer, input, object, object_functions, output,
+    pickle_key, unpickle, write_output,
+};
+
+/// The random bytes an outbound group session needs, the Ed25519 signing key
+/// and the Megolm ratchet.
+const GROUP_SESSION_RANDOM_LENGTH: usize = 160;
+/// The length of a base64 encoded group session ID.
+const GROUP_SESSION_ID_LENGTH: usize = 43;
+
+/// An outbound Megolm group session, see [`GroupSession`].
+pub type OlmOutboundGroupSession = OlmObject<GroupSession>;
+
+impl OlmState for GroupSession {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::BadSessionKey;
+}
+
+object_functions!(
+    OlmOutboundGroupSession, "an outbound group session",
+    size: olm_outbound_group_session_size,
+    init: olm_outbound_group_session,
+    last_error: olm_outbound_group_session_last_error,
+    last_error_code: olm_outbound_group_session_last_error_code,
+    clear: olm_clear_outbound_group_session,
+);
+
+/// The number of random bytes [`olm_init_outbound_group_session()`] needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_init_outbound_group_session_random_length(
+    _session: *const OlmOutboundGroupSession,
+) -> usize {
+    GROUP_SESSION_RANDOM_LENGTH
+}
+
+/// Create a new outbound group session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_init_outbound_group_session(
+    session: *mut OlmOutboundGroupSession,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = RandomBuffer::new(random, GROUP_SESSION_RANDOM_LENGTH).map(|mut rng| {
+        session.inner = Some(GroupSession::new_with_rng(SessionConfig::version_1(), &mut rng));
+        0
+    });
+
+    session.finish(result)
+}
+
+/// The length of the message [`olm_group_encrypt()`] produces for a plaintext
+/// of the given length.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_group_encrypt_message_length(
+    session: *mut OlmOutboundGroupSession,
+    plaintext_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+
+    let result = session.inner().map(|s| {
+        let mut copy = GroupSession::from_pickle(s.pickle());
+        copy.encrypt(vec![0u8; plaintext_length]).to_base64().len()
+    });
+
+    session.finish(result)
+}
+
+/// Encrypt the plaintext, writing the base64 encoded message into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_group_encrypt(
+    session: *mut OlmOutboundGroupSession,
+    plaintext: *const c_void,
+    plaintext_length: usize,
+    message: *mut c_void,
+    max_message_length: usize,
+) -> usize {
+    let required_length = unsafe { olm_group_encrypt_message_length(session, plaintext_length) };
+
+    let session = unsafe { object(session) };
+    let plaintext = unsafe { input(plaintext, plaintext_length) };
+    let message = unsafe { output(message, max_message_length) };
+
+    let result = session.inner().and_then(|s| {
+        // Don't advance the ratchet if the message can't be returned.
+        if message.len() < required_length {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        write_output(message, s.encrypt(plaintext).to_base64().as_bytes())
+    });
+
+    session.finish(result)
+}
+
+/// The length of the group session ID.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_outbound_group_session_id_length(
+    _session: *const OlmOutboundGroupSession,
+) -> usize {
+    GROUP_SESSION_ID_LENGTH
+}
+
+/// Write the ID of the group session into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_outbound_group_session_id(
+    session: *mut OlmOutboundGroupSession,
+    id: *mut u8,
+    id_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let id = unsafe { output(id.cast(), id_length) };
+
+    let result = session.inner().and_then(|s| write_output(id, s.session_id().as_bytes()));
+
+    session.finish(result)
+}
+
+/// The index the next message will be encrypted with.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_outbound_group_session_message_index(
+    session: *const OlmOutboundGroupSession,
+) -> u32 {
+    let session = unsafe { &*session };
+
+    session.inner.as_ref().map_or(0, GroupSession::message_index)
+}
+
+/// The length of the base64 encoded session key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_outbound_group_session_key_length(
+    session: *mut OlmOutboundGroupSession,
+) -> usize {
+    let session = unsafe { object(session) };
+    let result = session.inner().map(|s| s.session_key().to_base64().len());
+
+    session.finish(result)
+}
+
+/// Write the base64 encoded session key, which can be shared with the members
+/// of the group, into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_outbound_group_session_key(
+    session: *mut OlmOutboundGroupSession,
+    key: *mut u8,
+    key_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { output(key.cast(), key_length) };
+
+    let result =
+        session.inner().and_then(|s| write_output(key, s.session_key().to_base64().as_bytes()));
+
+    session.finish(result)
+}
+
+/// The length of the pickled group session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_outbound_group_session_length(
+    session: *mut OlmOutboundGroupSession,
+) -> usize {
+    let session = unsafe { object(session) };
+
+    // The length of a vodozemac pickle doesn't depend on the key.
+    let result = session.inner().map(|s| s.pickle().encrypt(&[0u8; 32]).len());
+
+    session.finish(result)
+}
+
+/// Pickle the group session in the vodozemac format, encrypting it with the
+/// SHA-256 hash of the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_outbound_group_session(
+    session: *mut OlmOutboundGroupSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *mut c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { output(pickled, pickled_length) };
+
+    let result = session
+        .inner()
+        .and_then(|s| write_output(pickled, s.pickle().encrypt(&pickle_key(key)).as_bytes()));
+
+    session.finish(result)
+}
+
+/// Restore the group session from a libolm or vodozemac pickle, decrypting it
+/// with the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_unpickle_outbound_group_session(
+    session: *mut OlmOutboundGroupSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *const c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { input(pickled, pickled_length) };
+
+    let result = unpickle(
+        pickled,
+        |pickle| GroupSession::from_libolm_pickle(pickle, key),
+        |pickle| {
+            GroupSessionPickle::from_encrypted(pickle, &pickle_key(key))
+                .map(GroupSession::from_pickle)
+        },
+    )
+    .map(|unpickled| {
+        session.inner = Some(unpickled);
+        pickled_length
+    });
+
+    session.finish(result)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use vodozemac::megolm::{InboundGroupSession, MegolmMessage};
+
+    use super::*;
+    use crate::{
+        olm_error,
+        test::{Object, RandomCounter},
+    };
+
+    pub(crate) fn create_group_session() -> Object<GroupSession> {
+        let session = Object::new(olm_outbound_group_session_size(), olm_outbound_group_session);
+        let random = [9u8; GROUP_SESSION_RANDOM_LENGTH];
+
+        assert_eq!(
+            unsafe {
+                olm_init_outbound_group_session(session.ptr(), random.as_ptr().cast(), random.len())
+            },
+            0
+        );
+
+        session
+    }
+
+    pub(crate) fn group_encrypt(session: &Object<GroupSession>, plaintext: &[u8]) -> String {
+        let mut message =
+            vec![0u8; unsafe { olm_group_encrypt_message_length(session.ptr(), plaintext.len()) }];
+
+        assert_eq!(
+            unsafe {
+                olm_group_encrypt(
+                    session.ptr(),
+                    plaintext.as_ptr().cast(),
+                    plaintext.len(),
+                    message.as_mut_ptr().cast(),
+                    message.len(),
+                )
+            },
+            message.len(),
+            "The precomputed message length should be exact"
+        );
+
+        String::from_utf8(message).expect("The message should be valid UTF-8")
+    }
+
+    #[test]
+    fn random_length_matches() {
+        let mut counter = RandomCounter::default();
+        GroupSession::new_with_rng(SessionConfig::version_1(), &mut counter);
+
+        assert_eq!(counter.0, GROUP_SESSION_RANDOM_LENGTH);
+    }
+
+    #[test]
+    fn encryption_interop() {
+        let session = create_group_session();
+        let mut inbound =
+            InboundGroupSession::new(&session.inner().session_key(), SessionConfig::version_1());
+
+        assert_eq!(unsafe { olm_outbound_group_session_message_index(session.ptr()) }, 0);
+
+        let message = group_encrypt(&session, b"It's a secret to everybody");
+        let message = MegolmMessage::from_base64(&message).expect("The message should be valid");
+        let decrypted = inbound.decrypt(&message).expect("The message should be decryptable");
+
+        assert_eq!(decrypted.plaintext, b"It's a secret to everybody");
+        assert_eq!(decrypted.message_index, 0);
+        assert_eq!(unsafe { olm_outbound_group_session_message_index(session.ptr()) }, 1);
+
+        let mut id = vec![0u8; unsafe { olm_outbound_group_session_id_length(session.ptr()) }];
+        unsafe { olm_outbound_group_session_id(session.ptr(), id.as_mut_ptr(), id.len()) };
+        assert_eq!(id, inbound.session_id().as_bytes());
+
+        let mut message = [0u8; 16];
+        assert_eq!(
+            unsafe {
+                olm_group_encrypt(
+                    session.ptr(),
+                    b"Hi".as_ptr().cast(),
+                    2,
+                    message.as_mut_ptr().cast(),
+                    16,
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(session.last_error(), c"OUTPUT_BUFFER_TOO_SMALL");
+        assert_eq!(
+            unsafe { olm_outbound_group_session_message_index(session.ptr()) },
+            1,
+            "A failed encryption shouldn't advance the ratchet"
+        );
+    }
+
+    #[test]
+    fn pickling() {
+        let session = create_group_session();
+        let key = b"DEFAULT_PICKLE_KEY";
+        let mut pickle =
+            vec![0u8; unsafe { olm_pickle_outbound_group_session_length(session.ptr()) }];
+
+        unsafe {
+            olm_pickle_outbound_group_session(
+                session.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_mut_ptr().cast(),
+                pickle.len(),
+            )
+        };
+
+        let restored = Object::new(olm_outbound_group_session_size(), olm_outbound_group_session);
+        assert_eq!(
+            unsafe {
+                olm_unpickle_outbound_group_session(
+                    restored.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            pickle.len()
+        );
+        assert_eq!(restored.inner().session_id(), session.inner().session_id());
+    }
+}
@@ -0,0 +1,446 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::c_void;
+
+use vodozemac::megolm::{
+    DecryptionError, ExportedSessionKey, InboundGroupSession, InboundGroupSessionPickle,
+    MegolmMessage, SessionConfig, SessionKey, SessionKeyDecodeError,
+};
+
+use crate::{
+    OlmErrorCode, OlmObject, OlmState, base64_input, input, object, object_functions, output,
+    pickle_key, unpickle, write_output,
+};
+
+/// The length of a base64 encoded group session ID.
+const GROUP_SESSION_ID_LENGTH: usize = 43;
+
+/// An inbound Megolm group session, see [`InboundGroupSession`].
+pub type OlmInboundGroupSession = OlmObject<InboundGroupSession>;
+
+impl OlmState for InboundGroupSession {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::BadSessionKey;
+}
+
+object_functions!(
+    OlmInboundGroupSession, "an inbound group session",
+    size: olm_inbound_group_session_size,
+    init: olm_inbound_group_session,
+    last_error: olm_inbound_group_session_last_error,
+    last_error_code: olm_inbound_group_session_last_error_code,
+    clear: olm_clear_inbound_group_session,
+);
+
+impl From<DecryptionError> for OlmErrorCode {
+    fn from(error: DecryptionError) -> Self {
+        match error {
+            DecryptionError::Signature(_) => Self::BadSignature,
+            DecryptionError::UnknownMessageIndex(..) => Self::UnknownMessageIndex,
+            DecryptionError::InvalidMessageVersion(..) => Self::BadMessageVersion,
+            _ => Self::BadMessageMac,
+        }
+    }
+}
+
+impl From<SessionKeyDecodeError> for OlmErrorCode {
+    fn from(error: SessionKeyDecodeError) -> Self {
+        match error {
+            SessionKeyDecodeError::Base64(_) => Self::InvalidBase64,
+            SessionKeyDecodeError::Signature(_) => Self::BadSignature,
+            _ => Self::BadSessionKey,
+        }
+    }
+}
+
+fn megolm_message(message: &[u8]) -> Result<MegolmMessage, OlmErrorCode> {
+    Ok(MegolmMessage::from_base64(base64_input(message)?)?)
+}
+
+/// Create an inbound group session from the session key of an outbound group
+/// session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_init_inbound_group_session(
+    session: *mut OlmInboundGroupSession,
+    session_key: *const u8,
+    session_key_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let session_key = unsafe { input(session_key.cast(), session_key_length) };
+
+    let result = (|| {
+        let key = SessionKey::from_base64(base64_input(session_key)?)?;
+        session.inner = Some(InboundGroupSession::new(&key, SessionConfig::version_1()));
+
+        Ok(0)
+    })();
+
+    session.finish(result)
+}
+
+/// Import an inbound group session from a previously exported session key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_import_inbound_group_session(
+    session: *mut OlmInboundGroupSession,
+    session_key: *const u8,
+    session_key_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let session_key = unsafe { input(session_key.cast(), session_key_length) };
+
+    let result = (|| {
+        let key = ExportedSessionKey::from_base64(base64_input(session_key)?)?;
+        session.inner = Some(InboundGroupSession::import(&key, SessionConfig::version_1()));
+
+        Ok(0)
+    })();
+
+    session.finish(result)
+}
+
+/// The maximal length of the plaintext of the given message.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_group_decrypt_max_plaintext_length(
+    session: *mut OlmInboundGroupSession,
+    message: *const u8,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let message = unsafe { input(message.cast(), message_length) };
+
+    let result = megolm_message(message).map(|message| message.ciphertext().len());
+
+    session.finish(result)
+}
+
+/// Decrypt the base64 encoded message, writing the plaintext into the output
+/// buffer and the index of the message into `message_index`, if it isn't null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_group_decrypt(
+    session: *mut OlmInboundGroupSession,
+    message: *const u8,
+    message_length: usize,
+    plaintext: *mut u8,
+    max_plaintext_length: usize,
+    message_index: *mut u32,
+) -> usize {
+    let session = unsafe { object(session) };
+    let message = unsafe { input(message.cast(), message_length) };
+    let plaintext = unsafe { output(plaintext.cast(), max_plaintext_length) };
+
+    let result = session.inner().and_then(|s| {
+        let message = megolm_message(message)?;
+
+        if plaintext.len() < message.ciphertext().len() {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        let decrypted = s.decrypt(&message)?;
+
+        if !message_index.is_null() {
+            unsafe { message_index.write(decrypted.message_index) };
+        }
+
+        write_output(plaintext, &decrypted.plaintext)
+    });
+
+    session.finish(result)
+}
+
+/// The length of the group session ID.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_inbound_group_session_id_length(
+    _session: *const OlmInboundGroupSession,
+) -> usize {
+    GROUP_SESSION_ID_LENGTH
+}
+
+/// Write the ID of the group session into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_inbound_group_session_id(
+    session: *mut OlmInboundGroupSession,
+    id: *mut u8,
+    id_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let id = unsafe { output(id.cast(), id_length) };
+
+    let result = session.inner().and_then(|s| write_output(id, s.session_id().as_bytes()));
+
+    session.finish(result)
+}
+
+/// The first message index the group session can decrypt.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_inbound_group_session_first_known_index(
+    session: *const OlmInboundGroupSession,
+) -> u32 {
+    let session = unsafe { &*session };
+
+    session.inner.as_ref().map_or(0, InboundGroupSession::first_known_index)
+}
+
+/// Returns 1 if the session was created from a signed session key, or merged
+/// with one, and 0 if it was only ever imported.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_inbound_group_session_is_verified(
+    session: *const OlmInboundGroupSession,
+) -> i32 {
+    let session = unsafe { &*session };
+
+    session.inner.as_ref().map_or(0, |s| s.origin().is_authenticated().into())
+}
+
+/// The length of the base64 encoded exported session key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_export_inbound_group_session_length(
+    session: *mut OlmInboundGroupSession,
+) -> usize {
+    let session = unsafe { object(session) };
+    let result = session.inner().map(|s| s.export_at_first_known_index().to_base64().len());
+
+    session.finish(result)
+}
+
+/// Export the group session at the given message index, writing the base64
+/// encoded session key into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_export_inbound_group_session(
+    session: *mut OlmInboundGroupSession,
+    key: *mut u8,
+    key_length: usize,
+    message_index: u32,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { output(key.cast(), key_length) };
+
+    let result = session.inner().and_then(|s| {
+        let exported = s.export_at(message_index).ok_or(OlmErrorCode::UnknownMessageIndex)?;
+        write_output(key, exported.to_base64().as_bytes())
+    });
+
+    session.finish(result)
+}
+
+/// The length of the pickled group session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_inbound_group_session_length(
+    session: *mut OlmInboundGroupSession,
+) -> usize {
+    let session = unsafe { object(session) };
+
+    // The length of a vodozemac pickle doesn't depend on the key.
+    let result = session.inner().map(|s| s.pickle().encrypt(&[0u8; 32]).len());
+
+    session.finish(result)
+}
+
+/// Pickle the group session in the vodozemac format, encrypting it with the
+/// SHA-256 hash of the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_inbound_group_session(
+    session: *mut OlmInboundGroupSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *mut c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { output(pickled, pickled_length) };
+
+    let result = session
+        .inner()
+        .and_then(|s| write_output(pickled, s.pickle().encrypt(&pickle_key(key)).as_bytes()));
+
+    session.finish(result)
+}
+
+/// Restore the group session from a libolm or vodozemac pickle, decrypting it
+/// with the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_unpickle_inbound_group_session(
+    session: *mut OlmInboundGroupSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *const c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { input(pickled, pickled_length) };
+
+    let result = unpickle(
+        pickled,
+        |pickle| InboundGroupSession::from_libolm_pickle(pickle, key),
+        |pickle| {
+            InboundGroupSessionPickle::from_encrypted(pickle, &pickle_key(key))
+                .map(InboundGroupSession::from_pickle)
+        },
+    )
+    .map(|unpickled| {
+        session.inner = Some(unpickled);
+        pickled_length
+    });
+
+    session.finish(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        group_session::test::{create_group_session, group_encrypt},
+        olm_error,
+        test::Object,
+    };
+
+    fn inbound_session(key: &str) -> (Object<InboundGroupSession>, usize) {
+        let session = Object::new(olm_inbound_group_session_size(), olm_inbound_group_session);
+        let result =
+            unsafe { olm_init_inbound_group_session(session.ptr(), key.as_ptr(), key.len()) };
+
+        (session, result)
+    }
+
+    fn group_decrypt(
+        session: &Object<InboundGroupSession>,
+        message: &str,
+    ) -> (usize, Vec<u8>, u32) {
+        let mut plaintext = vec![
+            0u8;
+            unsafe {
+                olm_group_decrypt_max_plaintext_length(
+                    session.ptr(),
+                    message.as_ptr(),
+                    message.len(),
+                )
+            }
+        ];
+        let mut message_index = u32::MAX;
+
+        let result = unsafe {
+            olm_group_decrypt(
+                session.ptr(),
+                message.as_ptr(),
+                message.len(),
+                plaintext.as_mut_ptr(),
+                plaintext.len(),
+                &mut message_index,
+            )
+        };
+
+        plaintext.truncate(result.min(plaintext.len()));
+
+        (result, plaintext, message_index)
+    }
+
+    #[test]
+    fn decryption() {
+        let outbound = create_group_session();
+        let (session, result) = inbound_session(&outbound.inner().session_key().to_base64());
+        assert_eq!(result, 0);
+
+        let first = group_encrypt(&outbound, b"First");
+        let second = group_encrypt(&outbound, b"Second");
+
+        let (_, plaintext, message_index) = group_decrypt(&session, &second);
+        assert_eq!(plaintext, b"Second");
+        assert_eq!(message_index, 1);
+
+        let (_, plaintext, message_index) = group_decrypt(&session, &first);
+        assert_eq!(plaintext, b"First");
+        assert_eq!(message_index, 0);
+
+        let mut id = vec![0u8; unsafe { olm_inbound_group_session_id_length(session.ptr()) }];
+        unsafe { olm_inbound_group_session_id(session.ptr(), id.as_mut_ptr(), id.len()) };
+        assert_eq!(id, outbound.inner().session_id().as_bytes());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let outbound = create_group_session();
+        let (session, _) = inbound_session(&outbound.inner().session_key().to_base64());
+        let first = group_encrypt(&outbound, b"First");
+        let second = group_encrypt(&outbound, b"Second");
+
+        let mut exported =
+            vec![0u8; unsafe { olm_export_inbound_group_session_length(session.ptr()) }];
+        assert_eq!(
+            unsafe {
+                olm_export_inbound_group_session(
+                    session.ptr(),
+                    exported.as_mut_ptr(),
+                    exported.len(),
+                    1,
+                )
+            },
+            exported.len()
+        );
+
+        let imported = Object::new(olm_inbound_group_session_size(), olm_inbound_group_session);
+        unsafe {
+            olm_import_inbound_group_session(imported.ptr(), exported.as_ptr(), exported.len())
+        };
+        assert_eq!(unsafe { olm_inbound_group_session_first_known_index(imported.ptr()) }, 1);
+        assert_eq!(unsafe { olm_inbound_group_session_is_verified(imported.ptr()) }, 0);
+        assert_eq!(unsafe { olm_inbound_group_session_is_verified(session.ptr()) }, 1);
+
+        let (result, _, _) = group_decrypt(&imported, &first);
+        assert_eq!(result, olm_error());
+        assert_eq!(imported.last_error(), c"UNKNOWN_MESSAGE_INDEX");
+
+        let (_, plaintext, _) = group_decrypt(&imported, &second);
+        assert_eq!(plaintext, b"Second");
+    }
+
+    #[test]
+    fn invalid_session_key() {
+        let (session, result) = inbound_session("Not a session key!");
+
+        assert_eq!(result, olm_error());
+        assert_eq!(session.last_error(), c"INVALID_BASE64");
+    }
+
+    #[test]
+    fn pickling() {
+        let outbound = create_group_session();
+        let (session, _) = inbound_session(&outbound.inner().session_key().to_base64());
+        let key = b"DEFAULT_PICKLE_KEY";
+        let mut pickle =
+            vec![0u8; unsafe { olm_pickle_inbound_group_session_length(session.ptr()) }];
+
+        unsafe {
+            olm_pickle_inbound_group_session(
+                session.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_mut_ptr().cast(),
+                pickle.len(),
+            )
+        };
+
+        let restored = Object::new(olm_inbound_group_session_size(), olm_inbound_group_session);
+        unsafe {
+            olm_unpickle_inbound_group_session(
+                restored.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_ptr().cast(),
+                pickle.len(),
+            )
+        };
+        assert_eq!(restored.inner().session_id(), outbound.inner().session_id());
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A libolm compatible C ABI implemented on top of vodozemac.
+//!
+//! This crate builds a `libolm` shared library which exports the `olm_*`
+//! functions declared in libolm's `olm.h`, `outbound_group_session.h`,
+//! `inbound_group_session.h`, `pk.h` and `sas.h` headers. C and C++ code
+//! which links against libolm can link against this library instead, without
+//! any source changes.
+//!
+//! # Conventions
+//!
+//! The functions follow the conventions of libolm:
+//!
+//! * Objects live in memory allocated by the caller. The caller allocates
+//!   `olm_*_size()` bytes, initializes them using the matching constructor,
+//!   e.g. [`olm_account()`], and calls the matching `olm_clear_*()` function
+//!   before the memory is freed.
+//! * Functions which can fail return [`olm_error()`] on failure. The reason for
+//!   the failure is available through the `*_last_error()` and
+//!   `*_last_error_code()` functions of the object.
+//! * Functions which produce output take an output buffer and its length, the
+//!   required length is returned by the matching `*_length()` function. The
+//!   `OUTPUT_BUFFER_TOO_SMALL` error is returned if the buffer is too small.
+//! * Randomness is supplied by the caller, the required amount of random bytes
+//!   is returned by the matching `*_random_length()` function. The
+//!   `NOT_ENOUGH_RANDOM` error is returned if not enough random bytes were
+//!   supplied.
+//!
+//! # Differences to libolm
+//!
+//! * Olm and Megolm sessions are pickled using the vodozemac pickle format,
+//!   encrypted using the SHA-256 hash of the pickle key. Unpickling accepts
+//!   pickles in both the libolm and the vodozemac format. Accounts and PK
+//!   decryption objects are pickled in the libolm format.
+//! * vodozemac creates a new ratchet key when a message is received instead of
+//!   when the next message is sent. Randomness which is needed while decrypting
+//!   is taken from the operating system.
+//! * Creating an inbound Olm session already decrypts the pre-key message and
+//!   removes the used one-time key from the account. The plaintext is returned
+//!   by the next [`olm_decrypt()`] call for the same message, and
+//!   [`olm_remove_one_time_keys()`] doesn't need to do anything.
+//! * Megolm sessions use [`SessionConfig::version_1()`], which is the only
+//!   version libolm supports.
+//!
+//! # Safety
+//!
+//! All functions which take pointers are `unsafe`. Object pointers need to
+//! point to memory which was initialized using the matching constructor and
+//! which wasn't cleared since. Buffers need to be valid for the given length.
+//! Objects must not be used from multiple threads at the same time.
+//!
+//! [`SessionConfig::version_1()`]: vodozemac::megolm::SessionConfig::version_1
+
+#![allow(clippy::missing_safety_doc)]
+
+mod account;
+mod group_session;
+mod inbound_group_session;
+mod pk;
+mod sas;
+mod session;
+mod utility;
+
+use std::{
+    ffi::{CStr, c_void},
+    slice,
+};
+
+pub use account::*;
+pub use group_session::*;
+pub use inbound_group_session::*;
+pub use pk::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+pub use sas::*;
+pub use session::*;
+use sha2::{Digest, Sha256};
+pub use utility::*;
+use vodozemac::{DecodeError, KeyError, LibolmPickleError, PickleError, SignatureError};
+
+/// The libolm version this library is compatible with.
+const LIBOLM_VERSION: (u8, u8, u8) = (3, 2, 16);
+
+/// The error codes which are reported by the `*_last_error_code()` functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OlmErrorCode {
+    /// There wasn't an error.
+    Success = 0,
+    /// Not enough random bytes were supplied.
+    NotEnoughRandom = 1,
+    /// The supplied output buffer is too small.
+    OutputBufferTooSmall = 2,
+    /// The message version is unsupported.
+    BadMessageVersion = 3,
+    /// The message couldn't be decoded.
+    BadMessageFormat = 4,
+    /// The message couldn't be decrypted.
+    BadMessageMac = 5,
+    /// The message references an unknown key.
+    BadMessageKeyId = 6,
+    /// The input wasn't valid base64.
+    InvalidBase64 = 7,
+    /// The key used to decrypt a pickle is wrong, or the object wasn't set up.
+    BadAccountKey = 8,
+    /// The pickle version is unsupported.
+    UnknownPickleVersion = 9,
+    /// The pickle couldn't be decoded.
+    CorruptedPickle = 10,
+    /// The Megolm session key is invalid.
+    BadSessionKey = 11,
+    /// The message was encrypted using a message index the session can't
+    /// decrypt.
+    UnknownMessageIndex = 12,
+    /// The pickle of an account is an old, unsupported, libolm pickle.
+    BadLegacyAccountPickle = 13,
+    /// A signature couldn't be verified.
+    BadSignature = 14,
+    /// The supplied input buffer is too small.
+    InputBufferTooSmall = 15,
+    /// The SAS object doesn't have the other side's public key yet.
+    SasTheirKeyNotSet = 16,
+    /// The pickle contains data after the end of the object.
+    PickleExtraData = 17,
+}
+
+impl OlmErrorCode {
+    /// The error string libolm uses for this error code.
+    pub const fn as_c_str(self) -> &'static CStr {
+        match self {
+            Self::Success => c"SUCCESS",
+            Self::NotEnoughRandom => c"NOT_ENOUGH_RANDOM",
+            Self::OutputBufferTooSmall => c"OUTPUT_BUFFER_TOO_SMALL",
+            Self::BadMessageVersion => c"BAD_MESSAGE_VERSION",
+            Self::BadMessageFormat => c"BAD_MESSAGE_FORMAT",
+            Self::BadMessageMac => c"BAD_MESSAGE_MAC",
+            Self::BadMessageKeyId => c"BAD_MESSAGE_KEY_ID",
+            Self::InvalidBase64 => c"INVALID_BASE64",
+            Self::BadAccountKey => c"BAD_ACCOUNT_KEY",
+            Self::UnknownPickleVersion => c"UNKNOWN_PICKLE_VERSION",
+            Self::CorruptedPickle => c"CORRUPTED_PICKLE",
+            Self::BadSessionKey => c"BAD_SESSION_KEY",
+            Self::UnknownMessageIndex => c"UNKNOWN_MESSAGE_INDEX",
+            Self::BadLegacyAccountPickle => c"BAD_LEGACY_ACCOUNT_PICKLE",
+            Self::BadSignature => c"BAD_SIGNATURE",
+            Self::InputBufferTooSmall => c"OLM_INPUT_BUFFER_TOO_SMALL",
+            Self::SasTheirKeyNotSet => c"OLM_SAS_THEIR_KEY_NOT_SET",
+            Self::PickleExtraData => c"OLM_PICKLE_EXTRA_DATA",
+        }
+    }
+}
+
+impl From<DecodeError> for OlmErrorCode {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::InvalidVersion(..) => Self::BadMessageVersion,
+            DecodeError::Base64(_) => Self::InvalidBase64,
+            _ => Self::BadMessageFormat,
+        }
+    }
+}
+
+impl From<KeyError> for OlmErrorCode {
+    fn from(error: KeyError) -> Self {
+        match error {
+            KeyError::Base64Error(_) | KeyError::Base64PrivateKey(_) => Self::InvalidBase64,
+            KeyError::InvalidKeyLength { .. } => Self::InputBufferTooSmall,
+            _ => Self::BadMessageKeyId,
+        }
+    }
+}
+
+impl From<SignatureError> for OlmErrorCode {
+    fn from(error: SignatureError) -> Self {
+        match error {
+            SignatureError::Base64(_) => Self::InvalidBase64,
+            SignatureError::Signature(_) => Self::BadMessageMac,
+        }
+    }
+}
+
+impl From<LibolmPickleError> for OlmErrorCode {
+    fn from(error: LibolmPickleError) -> Self {
+        match error {
+            LibolmPickleError::MissingVersion | LibolmPickleError::Version(..) => {
+                Self::UnknownPickleVersion
+            }
+            LibolmPickleError::Base64(_) => Self::InvalidBase64,
+            LibolmPickleError::Decryption(_) => Self::BadAccountKey,
+            _ => Self::CorruptedPickle,
+        }
+    }
+}
+
+impl From<vodozemac::Base64DecodeError> for OlmErrorCode {
+    fn from(_: vodozemac::Base64DecodeError) -> Self {
+        Self::InvalidBase64
+    }
+}
+
+/// Types which can be stored inside of an [`OlmObject`].
+pub trait OlmState {
+    /// The error which is reported if the object is used before it was set
+    /// up, e.g. an account before `olm_create_account()` was called.
+    const UNINITIALIZED: OlmErrorCode;
+}
+
+/// A libolm object, living in memory which was allocated by the caller.
+pub struct OlmObject<T> {
+    last_error: OlmErrorCode,
+    inner: Option<T>,
+}
+
+impl<T> OlmObject<T> {
+    const fn new() -> Self {
+        Self { last_error: OlmErrorCode::Success, inner: None }
+    }
+
+    /// Remember the given error and return the libolm error value.
+    fn fail(&mut self, error: OlmErrorCode) -> usize {
+        self.last_error = error;
+        olm_error()
+    }
+
+    /// Convert the result of an operation into the value libolm returns,
+    /// remembering the error if the operation failed.
+    fn finish(&mut self, result: Result<usize, OlmErrorCode>) -> usize {
+        result.unwrap_or_else(|error| self.fail(error))
+    }
+}
+
+impl<T: OlmState> OlmObject<T> {
+    fn inner(&mut self) -> Result<&mut T, OlmErrorCode> {
+        self.inner.as_mut().ok_or(T::UNINITIALIZED)
+    }
+}
+
+/// Generate the size, constructor, error and destructor functions every libolm
+/// object has.
+macro_rules! object_functions {
+    (
+        $object:ty, $description:literal,
+        size: $size:ident,
+        init: $init:ident,
+        last_error: $last_error:ident,
+        last_error_code: $last_error_code:ident,
+        clear: $clear:ident $(,)?
+    ) => {
+        #[doc = concat!("The size of ", $description, " object in bytes.")]
+        #[unsafe(no_mangle)]
+        pub extern "C" fn $size() -> usize {
+            size_of::<$object>()
+        }
+
+        #[doc = concat!(
+                    "Initialize ", $description, " object using the supplied memory.\n\n",
+                    "The memory needs to be at least [`", stringify!($size), "()`] bytes large."
+                )]
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn $init(memory: *mut std::ffi::c_void) -> *mut $object {
+            unsafe { $crate::init(memory) }
+        }
+
+        /// A null terminated string describing the most recent error that
+        /// happened to the object.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn $last_error(object: *const $object) -> *const std::ffi::c_char {
+            unsafe { $crate::last_error(object).as_c_str().as_ptr() }
+        }
+
+        /// The code of the most recent error that happened to the object.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn $last_error_code(object: *const $object) -> $crate::OlmErrorCode {
+            unsafe { $crate::last_error(object) }
+        }
+
+        /// Clear the object, destroying all the secrets it contains, and return
+        /// its size in bytes.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn $clear(object: *mut $object) -> usize {
+            unsafe { $crate::clear(object) }
+        }
+    };
+}
+
+pub(crate) use object_functions;
+
+unsafe fn init<T>(memory: *mut c_void) -> *mut OlmObject<T> {
+    let object = memory.cast::<OlmObject<T>>();
+    unsafe { object.write(OlmObject::new()) };
+
+    object
+}
+
+unsafe fn last_error<T>(object: *const OlmObject<T>) -> OlmErrorCode {
+    unsafe { (*object).last_error }
+}
+
+unsafe fn clear<T>(object: *mut OlmObject<T>) -> usize {
+    // Dropping the inner object zeroizes the secrets it contains, leave a valid
+    // empty object behind so clearing the object twice is harmless.
+    unsafe {
+        object.drop_in_place();
+        object.write(OlmObject::new());
+    }
+
+    size_of::<OlmObject<T>>()
+}
+
+unsafe fn object<'a, T>(object: *mut OlmObject<T>) -> &'a mut OlmObject<T> {
+    unsafe { &mut *object }
+}
+
+unsafe fn input<'a>(buffer: *const c_void, length: usize) -> &'a [u8] {
+    if buffer.is_null() || length == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(buffer.cast(), length) }
+    }
+}
+
+unsafe fn output<'a>(buffer: *mut c_void, length: usize) -> &'a mut [u8] {
+    if buffer.is_null() || length == 0 {
+        &mut []
+    } else {
+        unsafe { slice::from_raw_parts_mut(buffer.cast(), length) }
+    }
+}
+
+/// Copy `data` into the start of the `output` buffer.
+fn write_output(output: &mut [u8], data: &[u8]) -> Result<usize, OlmErrorCode> {
+    let output = output.get_mut(..data.len()).ok_or(OlmErrorCode::OutputBufferTooSmall)?;
+    output.copy_from_slice(data);
+
+    Ok(data.len())
+}
+
+/// Interpret the input as a base64 encoded string.
+fn base64_input(input: &[u8]) -> Result<&str, OlmErrorCode> {
+    std::str::from_utf8(input).map_err(|_| OlmErrorCode::InvalidBase64)
+}
+
+/// The maximal length of the data a base64 encoded string of the given length
+/// decodes to.
+const fn base64_decoded_length(length: usize) -> usize {
+    length * 3 / 4
+}
+
+/// Derive the key which encrypts pickles in the vodozemac format from the
+/// arbitrary length key libolm users supply.
+fn pickle_key(key: &[u8]) -> [u8; 32] {
+    Sha256::digest(key).into()
+}
+
+/// Restore an object from a pickle, which can either be in the libolm or in
+/// the vodozemac format.
+fn unpickle<T>(
+    pickle: &[u8],
+    libolm: impl FnOnce(&str) -> Result<T, LibolmPickleError>,
+    vodozemac: impl FnOnce(&str) -> Result<T, PickleError>,
+) -> Result<T, OlmErrorCode> {
+    let pickle = base64_input(pickle)?;
+
+    // Most pickles will have been created by libolm, report why the libolm
+    // format didn't work if neither format does.
+    libolm(pickle).or_else(|error| vodozemac(pickle).map_err(|_| error.into()))
+}
+
+/// A random number generator handing out the random bytes the caller
+/// supplied.
+///
+/// Once the supplied bytes run out, random bytes are taken from the operating
+/// system.
+struct RandomBuffer<'a>(&'a [u8]);
+
+impl<'a> RandomBuffer<'a> {
+    /// Create a new [`RandomBuffer`], checking that the caller supplied at
+    /// least `required` random bytes.
+    const fn new(random: &'a [u8], required: usize) -> Result<Self, OlmErrorCode> {
+        if random.len() < required { Err(OlmErrorCode::NotEnoughRandom) } else { Ok(Self(random)) }
+    }
+}
+
+impl RngCore for RandomBuffer<'_> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let (supplied, rest) = self.0.split_at(dest.len().min(self.0.len()));
+        let (from_buffer, from_os) = dest.split_at_mut(supplied.len());
+
+        from_buffer.copy_from_slice(supplied);
+        OsRng.fill_bytes(from_os);
+
+        self.0 = rest;
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for RandomBuffer<'_> {}
+
+/// The value functions return if they fail.
+#[unsafe(no_mangle)]
+pub extern "C" fn olm_error() -> usize {
+    usize::MAX
+}
+
+/// Get the version of libolm this library is compatible with.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_get_library_version(major: *mut u8, minor: *mut u8, patch: *mut u8) {
+    let (major_version, minor_version, patch_version) = LIBOLM_VERSION;
+
+    for (pointer, version) in
+        [(major, major_version), (minor, minor_version), (patch, patch_version)]
+    {
+        if !pointer.is_null() {
+            unsafe { pointer.write(version) };
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// A random number generator which counts how many random bytes an
+    /// operation needs, the generated bytes are all zeroes.
+    #[derive(Default)]
+    pub(crate) struct RandomCounter(pub(crate) usize);
+
+    impl RngCore for RandomCounter {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0);
+            self.0 += dest.len();
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for RandomCounter {}
+
+    /// An object living in memory allocated the same way a C caller would.
+    pub(crate) struct Object<T> {
+        _memory: Vec<u128>,
+        object: *mut OlmObject<T>,
+    }
+
+    impl<T> Object<T> {
+        pub(crate) fn new(
+            size: usize,
+            init: unsafe extern "C" fn(*mut c_void) -> *mut OlmObject<T>,
+        ) -> Self {
+            let mut memory = vec![0u128; size.div_ceil(size_of::<u128>())];
+            let object = unsafe { init(memory.as_mut_ptr().cast()) };
+
+            Self { _memory: memory, object }
+        }
+
+        pub(crate) const fn ptr(&self) -> *mut OlmObject<T> {
+            self.object
+        }
+
+        pub(crate) fn last_error(&self) -> &'static CStr {
+            unsafe { last_error(self.object) }.as_c_str()
+        }
+
+        pub(crate) fn inner(&self) -> &T {
+            unsafe { &*self.object }.inner.as_ref().expect("The object should be initialized")
+        }
+    }
+
+    impl<T> Drop for Object<T> {
+        fn drop(&mut self) {
+            unsafe { clear(self.object) };
+        }
+    }
+
+    #[test]
+    fn error_strings_match_libolm() {
+        assert_eq!(OlmErrorCode::Success.as_c_str(), c"SUCCESS");
+        assert_eq!(OlmErrorCode::BadMessageMac as usize, 5);
+        assert_eq!(OlmErrorCode::BadMessageMac.as_c_str(), c"BAD_MESSAGE_MAC");
+        assert_eq!(OlmErrorCode::PickleExtraData as usize, 17);
+        assert_eq!(OlmErrorCode::PickleExtraData.as_c_str(), c"OLM_PICKLE_EXTRA_DATA");
+    }
+
+    #[test]
+    fn random_buffer_hands_out_supplied_bytes() {
+        let random = [1u8, 2, 3, 4];
+
+        assert_eq!(
+            RandomBuffer::new(&random, 5).err(),
+            Some(OlmErrorCode::NotEnoughRandom),
+            "Supplying fewer random bytes than required should fail"
+        );
+
+        let mut rng = RandomBuffer::new(&random, 4).expect("Enough random bytes were supplied");
+        let mut bytes = [0u8; 3];
+
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(bytes, [1, 2, 3]);
+
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(bytes[0], 4, "The remaining supplied byte should be used first");
+    }
+
+    #[test]
+    fn library_version() {
+        let (mut major, mut minor, mut patch) = (0, 0, 0);
+        unsafe { olm_get_library_version(&mut major, &mut minor, &mut patch) };
+
+        assert_eq!((major, minor, patch), LIBOLM_VERSION);
+    }
+}
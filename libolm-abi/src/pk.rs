@@ -0,0 +1,366 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::c_void;
+
+use vodozemac::{
+    Curve25519SecretKey,
+    pk_encryption::{Error, Message, MessageDecodeError, PkDecryption},
+};
+
+use crate::{
+    OlmErrorCode, OlmObject, OlmState, base64_decoded_length, base64_input, input, object,
+    object_functions, output, write_output,
+};
+
+/// The length of a Curve25519 private key.
+const PRIVATE_KEY_LENGTH: usize = 32;
+/// The length of a base64 encoded Curve25519 public key.
+const PUBLIC_KEY_LENGTH: usize = 43;
+
+/// A PK decryption object, see [`PkDecryption`].
+pub type OlmPkDecryption = OlmObject<PkDecryption>;
+
+impl OlmState for PkDecryption {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::BadAccountKey;
+}
+
+object_functions!(
+    OlmPkDecryption, "a PK decryption",
+    size: olm_pk_decryption_size,
+    init: olm_pk_decryption,
+    last_error: olm_pk_decryption_last_error,
+    last_error_code: olm_pk_decryption_last_error_code,
+    clear: olm_clear_pk_decryption,
+);
+
+impl From<Error> for OlmErrorCode {
+    fn from(_: Error) -> Self {
+        Self::BadMessageMac
+    }
+}
+
+impl From<MessageDecodeError> for OlmErrorCode {
+    fn from(error: MessageDecodeError) -> Self {
+        match error {
+            MessageDecodeError::Base64(_) => Self::InvalidBase64,
+            MessageDecodeError::Key(error) => error.into(),
+        }
+    }
+}
+
+/// Write the public key of the PK decryption object into the output buffer, if
+/// the buffer isn't null.
+fn write_public_key(decryption: &PkDecryption, public_key: &mut [u8]) -> Result<(), OlmErrorCode> {
+    if !public_key.is_empty() {
+        write_output(public_key, decryption.public_key().to_base64().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// The length of a private key.
+#[unsafe(no_mangle)]
+pub extern "C" fn olm_pk_private_key_length() -> usize {
+    PRIVATE_KEY_LENGTH
+}
+
+/// The length of a base64 encoded public key.
+#[unsafe(no_mangle)]
+pub extern "C" fn olm_pk_key_length() -> usize {
+    PUBLIC_KEY_LENGTH
+}
+
+/// Initialize the PK decryption object using the given private key, writing the
+/// base64 encoded public key into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pk_key_from_private(
+    decryption: *mut OlmPkDecryption,
+    pubkey: *mut c_void,
+    pubkey_length: usize,
+    privkey: *const c_void,
+    privkey_length: usize,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+    let pubkey = unsafe { output(pubkey, pubkey_length) };
+    let privkey = unsafe { input(privkey, privkey_length) };
+
+    let result = (|| {
+        if pubkey.len() < PUBLIC_KEY_LENGTH {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        let private_key: &[u8; PRIVATE_KEY_LENGTH] = privkey
+            .get(..PRIVATE_KEY_LENGTH)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(OlmErrorCode::InputBufferTooSmall)?;
+        let inner = PkDecryption::from_key(Curve25519SecretKey::from_slice(private_key));
+
+        write_public_key(&inner, pubkey)?;
+        decryption.inner = Some(inner);
+
+        Ok(0)
+    })();
+
+    decryption.finish(result)
+}
+
+/// Write the private key of the PK decryption object into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pk_get_private_key(
+    decryption: *mut OlmPkDecryption,
+    private_key: *mut c_void,
+    private_key_length: usize,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+    let private_key = unsafe { output(private_key, private_key_length) };
+
+    let result = decryption
+        .inner()
+        .and_then(|d| write_output(private_key, d.secret_key().to_bytes().as_slice()));
+
+    decryption.finish(result)
+}
+
+/// The maximal length of the plaintext of a base64 encoded ciphertext of the
+/// given length.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pk_max_plaintext_length(
+    _decryption: *const OlmPkDecryption,
+    ciphertext_length: usize,
+) -> usize {
+    base64_decoded_length(ciphertext_length)
+}
+
+/// Decrypt the base64 encoded message, writing the plaintext into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pk_decrypt(
+    decryption: *mut OlmPkDecryption,
+    ephemeral_key: *const c_void,
+    ephemeral_key_length: usize,
+    mac: *const c_void,
+    mac_length: usize,
+    ciphertext: *const c_void,
+    ciphertext_length: usize,
+    plaintext: *mut c_void,
+    max_plaintext_length: usize,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+    let ephemeral_key = unsafe { input(ephemeral_key, ephemeral_key_length) };
+    let mac = unsafe { input(mac, mac_length) };
+    let ciphertext = unsafe { input(ciphertext, ciphertext_length) };
+    let plaintext = unsafe { output(plaintext, max_plaintext_length) };
+
+    let result = decryption.inner().and_then(|d| {
+        if plaintext.len() < base64_decoded_length(ciphertext.len()) {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        let message = Message::from_base64(
+            base64_input(ciphertext)?,
+            base64_input(mac)?,
+            base64_input(ephemeral_key)?,
+        )?;
+
+        write_output(plaintext, &d.decrypt(&message)?)
+    });
+
+    decryption.finish(result)
+}
+
+/// The length of the pickled PK decryption object.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_pk_decryption_length(
+    decryption: *mut OlmPkDecryption,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+
+    // The length of a libolm pickle doesn't depend on the key.
+    let result = decryption
+        .inner()
+        .and_then(|d| d.to_libolm_pickle(&[]).map(|pickle| pickle.len()).map_err(Into::into));
+
+    decryption.finish(result)
+}
+
+/// Pickle the PK decryption object in the libolm format, encrypting it with the
+/// given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_pk_decryption(
+    decryption: *mut OlmPkDecryption,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *mut c_void,
+    pickled_length: usize,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { output(pickled, pickled_length) };
+
+    let result = decryption.inner().and_then(|d| {
+        let pickle = d.to_libolm_pickle(key)?;
+        write_output(pickled, pickle.as_bytes())
+    });
+
+    decryption.finish(result)
+}
+
+/// Restore the PK decryption object from a libolm pickle, writing the base64
+/// encoded public key into the output buffer if it isn't null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_unpickle_pk_decryption(
+    decryption: *mut OlmPkDecryption,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *const c_void,
+    pickled_length: usize,
+    pubkey: *mut c_void,
+    pubkey_length: usize,
+) -> usize {
+    let decryption = unsafe { object(decryption) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { input(pickled, pickled_length) };
+    let pubkey = unsafe { output(pubkey, pubkey_length) };
+
+    let result = (|| {
+        let inner = PkDecryption::from_libolm_pickle(base64_input(pickled)?, key)?;
+
+        write_public_key(&inner, pubkey)?;
+        decryption.inner = Some(inner);
+
+        Ok(pickled_length)
+    })();
+
+    decryption.finish(result)
+}
+
+#[cfg(test)]
+mod test {
+    use vodozemac::{Curve25519PublicKey, base64_encode, pk_encryption::PkEncryption};
+
+    use super::*;
+    use crate::{olm_error, test::Object};
+
+    fn decryption(private_key: &[u8; 32]) -> (Object<PkDecryption>, String) {
+        let decryption = Object::new(olm_pk_decryption_size(), olm_pk_decryption);
+        let mut public_key = vec![0u8; olm_pk_key_length()];
+
+        assert_eq!(
+            unsafe {
+                olm_pk_key_from_private(
+                    decryption.ptr(),
+                    public_key.as_mut_ptr().cast(),
+                    public_key.len(),
+                    private_key.as_ptr().cast(),
+                    private_key.len(),
+                )
+            },
+            0
+        );
+
+        (decryption, String::from_utf8(public_key).expect("The public key should be valid UTF-8"))
+    }
+
+    #[test]
+    fn decryption_interop() {
+        let (decryption, public_key) = decryption(&[4u8; 32]);
+        let public_key =
+            Curve25519PublicKey::from_base64(&public_key).expect("The public key should be valid");
+
+        let message = PkEncryption::from_key(public_key).encrypt(b"It's a secret to everybody");
+        let ephemeral_key = message.ephemeral_key.to_base64();
+        let mac = base64_encode(&message.mac);
+        let ciphertext = base64_encode(&message.ciphertext);
+
+        let mut plaintext =
+            vec![0u8; unsafe { olm_pk_max_plaintext_length(decryption.ptr(), ciphertext.len()) }];
+        let length = unsafe {
+            olm_pk_decrypt(
+                decryption.ptr(),
+                ephemeral_key.as_ptr().cast(),
+                ephemeral_key.len(),
+                mac.as_ptr().cast(),
+                mac.len(),
+                ciphertext.as_ptr().cast(),
+                ciphertext.len(),
+                plaintext.as_mut_ptr().cast(),
+                plaintext.len(),
+            )
+        };
+
+        assert_eq!(&plaintext[..length], b"It's a secret to everybody");
+
+        let mut private_key = [0u8; PRIVATE_KEY_LENGTH];
+        unsafe { olm_pk_get_private_key(decryption.ptr(), private_key.as_mut_ptr().cast(), 32) };
+        assert_eq!(private_key, [4u8; 32]);
+    }
+
+    #[test]
+    fn short_private_key() {
+        let decryption = Object::new(olm_pk_decryption_size(), olm_pk_decryption);
+        let mut public_key = [0u8; PUBLIC_KEY_LENGTH];
+        let private_key = [0u8; 16];
+
+        assert_eq!(
+            unsafe {
+                olm_pk_key_from_private(
+                    decryption.ptr(),
+                    public_key.as_mut_ptr().cast(),
+                    public_key.len(),
+                    private_key.as_ptr().cast(),
+                    private_key.len(),
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(decryption.last_error(), c"OLM_INPUT_BUFFER_TOO_SMALL");
+    }
+
+    #[test]
+    fn pickling() {
+        let (decryption, public_key) = decryption(&[4u8; 32]);
+        let key = b"DEFAULT_PICKLE_KEY";
+        let mut pickle = vec![0u8; unsafe { olm_pickle_pk_decryption_length(decryption.ptr()) }];
+
+        unsafe {
+            olm_pickle_pk_decryption(
+                decryption.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_mut_ptr().cast(),
+                pickle.len(),
+            )
+        };
+
+        let restored = Object::new(olm_pk_decryption_size(), olm_pk_decryption);
+        let mut restored_key = vec![0u8; PUBLIC_KEY_LENGTH];
+
+        assert_eq!(
+            unsafe {
+                olm_unpickle_pk_decryption(
+                    restored.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                    restored_key.as_mut_ptr().cast(),
+                    restored_key.len(),
+                )
+            },
+            pickle.len()
+        );
+        assert_eq!(restored_key, public_key.as_bytes());
+    }
+}
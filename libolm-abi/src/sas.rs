@@ -0,0 +1,355 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::{c_int, c_void};
+
+use vodozemac::{
+    Curve25519PublicKey,
+    sas::{EstablishedSas, Sas},
+};
+
+use crate::{
+    OlmErrorCode, OlmObject, OlmState, RandomBuffer, base64_input, input, object, object_functions,
+    output, write_output,
+};
+
+/// The random bytes a SAS object needs, one Curve25519 key.
+const SAS_RANDOM_LENGTH: usize = 32;
+/// The length of a base64 encoded Curve25519 public key.
+const PUBLIC_KEY_LENGTH: usize = 43;
+/// The length of a base64 encoded SAS MAC.
+const MAC_LENGTH: usize = 43;
+
+/// The state of an [`OlmSas`].
+pub enum SasState {
+    /// The other side's public key isn't known yet.
+    Created(Sas),
+    /// The shared secret with the other side has been established.
+    Established(EstablishedSas),
+}
+
+impl SasState {
+    const fn public_key(&self) -> Curve25519PublicKey {
+        match self {
+            SasState::Created(sas) => sas.public_key(),
+            SasState::Established(sas) => sas.our_public_key(),
+        }
+    }
+
+    const fn established(&self) -> Result<&EstablishedSas, OlmErrorCode> {
+        match self {
+            SasState::Created(_) => Err(OlmErrorCode::SasTheirKeyNotSet),
+            SasState::Established(sas) => Ok(sas),
+        }
+    }
+}
+
+/// A SAS object, see [`Sas`] and [`EstablishedSas`].
+pub type OlmSas = OlmObject<SasState>;
+
+impl OlmState for SasState {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::NotEnoughRandom;
+}
+
+object_functions!(
+    OlmSas, "a SAS",
+    size: olm_sas_size,
+    init: olm_sas,
+    last_error: olm_sas_last_error,
+    last_error_code: olm_sas_last_error_code,
+    clear: olm_clear_sas,
+);
+
+/// Interpret the input as a string, the info strings and MAC inputs of SAS are
+/// free-form text.
+fn text_input(input: &[u8]) -> Result<&str, OlmErrorCode> {
+    std::str::from_utf8(input).map_err(|_| OlmErrorCode::BadMessageFormat)
+}
+
+/// The number of random bytes [`olm_create_sas()`] needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_sas_random_length(_sas: *const OlmSas) -> usize {
+    SAS_RANDOM_LENGTH
+}
+
+/// Create a new SAS object.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_sas(
+    sas: *mut OlmSas,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = RandomBuffer::new(random, SAS_RANDOM_LENGTH).map(|mut rng| {
+        sas.inner = Some(SasState::Created(Sas::new_with_rng(&mut rng)));
+        0
+    });
+
+    sas.finish(result)
+}
+
+/// The length of the base64 encoded public key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_pubkey_length(_sas: *const OlmSas) -> usize {
+    PUBLIC_KEY_LENGTH
+}
+
+/// Write the base64 encoded public key of the SAS object into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_get_pubkey(
+    sas: *mut OlmSas,
+    pubkey: *mut c_void,
+    pubkey_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let pubkey = unsafe { output(pubkey, pubkey_length) };
+
+    let result =
+        sas.inner().and_then(|s| write_output(pubkey, s.public_key().to_base64().as_bytes()));
+
+    sas.finish(result)
+}
+
+/// Establish the shared secret using the other side's base64 encoded public
+/// key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_set_their_key(
+    sas: *mut OlmSas,
+    their_key: *const c_void,
+    their_key_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let their_key = unsafe { input(their_key, their_key_length) };
+
+    let result = (|| {
+        let their_key = Curve25519PublicKey::from_base64(base64_input(their_key)?)?;
+
+        match sas.inner.take() {
+            Some(SasState::Created(created)) => {
+                sas.inner = Some(SasState::Established(created.diffie_hellman(their_key)?));
+                Ok(0)
+            }
+            // The key of the other side can only be set once.
+            established @ Some(SasState::Established(_)) => {
+                sas.inner = established;
+                Err(OlmErrorCode::BadMessageKeyId)
+            }
+            None => Err(SasState::UNINITIALIZED),
+        }
+    })();
+
+    sas.finish(result)
+}
+
+/// Whether the other side's public key has been set.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_is_their_key_set(sas: *const OlmSas) -> c_int {
+    let sas = unsafe { &*sas };
+
+    matches!(sas.inner, Some(SasState::Established(_))).into()
+}
+
+/// Generate bytes to use for the short authentication string.
+///
+/// At most 8160 bytes can be generated, `BAD_MESSAGE_FORMAT` is returned if
+/// more are requested.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_generate_bytes(
+    sas: *mut OlmSas,
+    info: *const c_void,
+    info_length: usize,
+    output_buffer: *mut c_void,
+    output_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let info = unsafe { input(info, info_length) };
+    let output_buffer = unsafe { output(output_buffer, output_length) };
+
+    let result = sas.inner().and_then(|s| {
+        let bytes = s
+            .established()?
+            .bytes_raw(text_input(info)?, output_buffer.len())
+            .map_err(|_| OlmErrorCode::BadMessageFormat)?;
+
+        write_output(output_buffer, &bytes).map(|_| 0)
+    });
+
+    sas.finish(result)
+}
+
+/// The length of a base64 encoded MAC.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_mac_length(_sas: *const OlmSas) -> usize {
+    MAC_LENGTH
+}
+
+fn calculate_mac(
+    sas: &mut OlmSas,
+    input: &[u8],
+    info: &[u8],
+    mac: &mut [u8],
+    invalid_base64: bool,
+) -> Result<usize, OlmErrorCode> {
+    let established = sas.inner()?.established()?;
+    let (input, info) = (text_input(input)?, text_input(info)?);
+
+    let calculated = if invalid_base64 {
+        established.calculate_mac_invalid_base64(input, info)
+    } else {
+        established.calculate_mac(input, info).to_base64()
+    };
+
+    write_output(mac, calculated.as_bytes()).map(|_| 0)
+}
+
+/// Calculate a MAC of the input, writing it base64 encoded into the output
+/// buffer.
+///
+/// This uses the same, incorrect, base64 encoding libolm's
+/// `olm_sas_calculate_mac()` uses, new code should use
+/// [`olm_sas_calculate_mac_fixed_base64()`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_calculate_mac(
+    sas: *mut OlmSas,
+    input_buffer: *const c_void,
+    input_length: usize,
+    info: *const c_void,
+    info_length: usize,
+    mac: *mut c_void,
+    mac_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let input_buffer = unsafe { input(input_buffer, input_length) };
+    let info = unsafe { input(info, info_length) };
+    let mac = unsafe { output(mac, mac_length) };
+
+    let result = calculate_mac(sas, input_buffer, info, mac, true);
+
+    sas.finish(result)
+}
+
+/// Calculate a MAC of the input, writing it base64 encoded into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sas_calculate_mac_fixed_base64(
+    sas: *mut OlmSas,
+    input_buffer: *const c_void,
+    input_length: usize,
+    info: *const c_void,
+    info_length: usize,
+    mac: *mut c_void,
+    mac_length: usize,
+) -> usize {
+    let sas = unsafe { object(sas) };
+    let input_buffer = unsafe { input(input_buffer, input_length) };
+    let info = unsafe { input(info, info_length) };
+    let mac = unsafe { output(mac, mac_length) };
+
+    let result = calculate_mac(sas, input_buffer, info, mac, false);
+
+    sas.finish(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{olm_error, test::Object};
+
+    fn create_sas() -> Object<SasState> {
+        let sas = Object::new(olm_sas_size(), olm_sas);
+        let random = [6u8; SAS_RANDOM_LENGTH];
+
+        assert_eq!(unsafe { olm_create_sas(sas.ptr(), random.as_ptr().cast(), random.len()) }, 0);
+
+        sas
+    }
+
+    #[test]
+    fn sas_interop() {
+        let sas = create_sas();
+        let bob = Sas::new();
+        let mut alice_key = vec![0u8; PUBLIC_KEY_LENGTH];
+
+        unsafe { olm_sas_get_pubkey(sas.ptr(), alice_key.as_mut_ptr().cast(), alice_key.len()) };
+        let alice_key = Curve25519PublicKey::from_base64(
+            std::str::from_utf8(&alice_key).expect("The public key should be valid UTF-8"),
+        )
+        .expect("The public key should be valid");
+
+        let mut bytes = [0u8; 6];
+        assert_eq!(
+            unsafe {
+                olm_sas_generate_bytes(
+                    sas.ptr(),
+                    b"info".as_ptr().cast(),
+                    4,
+                    bytes.as_mut_ptr().cast(),
+                    6,
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(sas.last_error(), c"OLM_SAS_THEIR_KEY_NOT_SET");
+
+        let bob_key = bob.public_key().to_base64();
+        assert_eq!(unsafe { olm_sas_is_their_key_set(sas.ptr()) }, 0);
+        unsafe { olm_sas_set_their_key(sas.ptr(), bob_key.as_ptr().cast(), bob_key.len()) };
+        assert_eq!(unsafe { olm_sas_is_their_key_set(sas.ptr()) }, 1);
+
+        let bob = bob.diffie_hellman(alice_key).expect("The public key should be contributory");
+
+        unsafe {
+            olm_sas_generate_bytes(
+                sas.ptr(),
+                b"info".as_ptr().cast(),
+                4,
+                bytes.as_mut_ptr().cast(),
+                6,
+            )
+        };
+        assert_eq!(&bytes, bob.bytes("info").as_bytes());
+
+        let mut mac = vec![0u8; MAC_LENGTH];
+        unsafe {
+            olm_sas_calculate_mac_fixed_base64(
+                sas.ptr(),
+                b"input".as_ptr().cast(),
+                5,
+                b"info".as_ptr().cast(),
+                4,
+                mac.as_mut_ptr().cast(),
+                mac.len(),
+            )
+        };
+        assert_eq!(mac, bob.calculate_mac("input", "info").to_base64().as_bytes());
+
+        let legacy_mac = bob.calculate_mac_invalid_base64("input", "info");
+        let mut mac = vec![0u8; legacy_mac.len()];
+        unsafe {
+            olm_sas_calculate_mac(
+                sas.ptr(),
+                b"input".as_ptr().cast(),
+                5,
+                b"info".as_ptr().cast(),
+                4,
+                mac.as_mut_ptr().cast(),
+                mac.len(),
+            )
+        };
+        assert_eq!(mac, legacy_mac.as_bytes());
+    }
+}
@@ -0,0 +1,1029 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::{c_int, c_void};
+
+use serde::{Deserialize, Serialize};
+use vodozemac::{
+    Curve25519PublicKey, PickleError, base64_decode, base64_encode, decrypt_pickle, encrypt_pickle,
+    olm::{
+        DecryptionError, OlmMessage, PreKeyMessage, Session, SessionConfig, SessionCreationError,
+        SessionPickle,
+    },
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    OlmAccount, OlmErrorCode, OlmObject, OlmState, RandomBuffer, base64_input, input, object,
+    object_functions, output, pickle_key, unpickle, write_output,
+};
+
+/// The random bytes an outbound session needs, the base key and the first
+/// ratchet key.
+const OUTBOUND_SESSION_RANDOM_LENGTH: usize = 64;
+/// The length of a base64 encoded session ID.
+const SESSION_ID_LENGTH: usize = 43;
+/// The pickle type of a [`SessionStatePickle`].
+const SESSION_STATE_PICKLE_TYPE: &str = "LibolmSessionState";
+
+/// The pre-key message which was decrypted while an inbound session was
+/// created, and its plaintext.
+///
+/// libolm decrypts the pre-key message only once [`olm_decrypt()`] is called,
+/// vodozemac already decrypts it while creating the session. The plaintext is
+/// kept around, and pickled alongside the session, until [`olm_decrypt()`] is
+/// called with the pre-key message.
+#[derive(Clone, Serialize, Deserialize)]
+struct PendingMessage {
+    message: Vec<u8>,
+    plaintext: Zeroizing<Vec<u8>>,
+}
+
+/// The pickled form of a [`SessionState`] with a [`PendingMessage`].
+///
+/// Sessions without a pending message are pickled as a plain
+/// [`SessionPickle`].
+#[derive(Serialize, Deserialize)]
+struct SessionStatePickle {
+    session: SessionPickle,
+    pending: PendingMessage,
+}
+
+/// The state of an [`OlmSession`].
+pub struct SessionState {
+    session: Session,
+    pending: Option<PendingMessage>,
+}
+
+impl SessionState {
+    const fn new(session: Session) -> Self {
+        Self { session, pending: None }
+    }
+
+    /// Pickle the session and, if there is one, the pending pre-key message.
+    fn pickle(&self, pickle_key: &[u8; 32]) -> String {
+        let session = self.session.pickle();
+
+        match &self.pending {
+            Some(pending) => encrypt_pickle(
+                &SessionStatePickle { session, pending: pending.clone() },
+                pickle_key,
+                SESSION_STATE_PICKLE_TYPE,
+            ),
+            None => session.encrypt(pickle_key),
+        }
+    }
+
+    /// Restore the state from a pickle created by [`SessionState::pickle()`].
+    fn from_pickle(pickle: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
+        match SessionPickle::from_encrypted(pickle, pickle_key) {
+            Ok(session) => Ok(Self::new(Session::from_pickle(session))),
+            Err(_) => {
+                let pickle: SessionStatePickle =
+                    decrypt_pickle(pickle, pickle_key, SESSION_STATE_PICKLE_TYPE)?;
+
+                Ok(Self {
+                    session: Session::from_pickle(pickle.session),
+                    pending: Some(pickle.pending),
+                })
+            }
+        }
+    }
+}
+
+/// An Olm session, see [`Session`].
+pub type OlmSession = OlmObject<SessionState>;
+
+impl OlmState for SessionState {
+    const UNINITIALIZED: OlmErrorCode = OlmErrorCode::BadSessionKey;
+}
+
+object_functions!(
+    OlmSession, "a session",
+    size: olm_session_size,
+    init: olm_session,
+    last_error: olm_session_last_error,
+    last_error_code: olm_session_last_error_code,
+    clear: olm_clear_session,
+);
+
+impl From<DecryptionError> for OlmErrorCode {
+    fn from(error: DecryptionError) -> Self {
+        match error {
+            DecryptionError::MissingMessageKey(_) | DecryptionError::TooBigMessageGap(..) => {
+                Self::BadMessageKeyId
+            }
+            _ => Self::BadMessageMac,
+        }
+    }
+}
+
+impl From<SessionCreationError> for OlmErrorCode {
+    fn from(error: SessionCreationError) -> Self {
+        match error {
+            SessionCreationError::Decryption(error) => error.into(),
+            _ => Self::BadMessageKeyId,
+        }
+    }
+}
+
+fn curve25519_key(key: &[u8]) -> Result<Curve25519PublicKey, OlmErrorCode> {
+    Ok(Curve25519PublicKey::from_base64(base64_input(key)?)?)
+}
+
+fn pre_key_message(message: &[u8]) -> Result<PreKeyMessage, OlmErrorCode> {
+    Ok(PreKeyMessage::from_base64(base64_input(message)?)?)
+}
+
+fn decode_message(
+    message_type: usize,
+    message: &[u8],
+) -> Result<(Vec<u8>, OlmMessage), OlmErrorCode> {
+    let message = base64_decode(message)?;
+    let decoded = OlmMessage::from_parts(message_type, &message)?;
+
+    Ok((message, decoded))
+}
+
+/// The number of random bytes [`olm_create_outbound_session()`] needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_outbound_session_random_length(
+    _session: *const OlmSession,
+) -> usize {
+    OUTBOUND_SESSION_RANDOM_LENGTH
+}
+
+/// Create a new outbound session for sending messages to the given identity and
+/// one-time key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_outbound_session(
+    session: *mut OlmSession,
+    account: *mut OlmAccount,
+    their_identity_key: *const c_void,
+    their_identity_key_length: usize,
+    their_one_time_key: *const c_void,
+    their_one_time_key_length: usize,
+    random: *const c_void,
+    random_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let account = unsafe { object(account) };
+    let their_identity_key = unsafe { input(their_identity_key, their_identity_key_length) };
+    let their_one_time_key = unsafe { input(their_one_time_key, their_one_time_key_length) };
+    let random = unsafe { input(random, random_length) };
+
+    let result = (|| {
+        let mut rng = RandomBuffer::new(random, OUTBOUND_SESSION_RANDOM_LENGTH)?;
+        let identity_key = curve25519_key(their_identity_key)?;
+        let one_time_key = curve25519_key(their_one_time_key)?;
+
+        let outbound = account.inner()?.create_outbound_session_with_rng(
+            SessionConfig::version_1(),
+            identity_key,
+            one_time_key,
+            &mut rng,
+        );
+        session.inner = Some(SessionState::new(outbound));
+
+        Ok(0)
+    })();
+
+    session.finish(result)
+}
+
+fn create_inbound_session(
+    session: &mut OlmSession,
+    account: &mut OlmAccount,
+    their_identity_key: Option<&[u8]>,
+    one_time_key_message: &[u8],
+) -> Result<usize, OlmErrorCode> {
+    let message = pre_key_message(one_time_key_message)?;
+    let identity_key = match their_identity_key {
+        Some(key) => curve25519_key(key)?,
+        None => message.identity_key(),
+    };
+
+    let result = account.inner()?.create_inbound_session(identity_key, &message)?;
+    let mut state = SessionState::new(result.session);
+    state.pending = Some(PendingMessage {
+        message: message.to_bytes(),
+        plaintext: Zeroizing::new(result.plaintext),
+    });
+    session.inner = Some(state);
+
+    Ok(0)
+}
+
+/// Create a new inbound session from the given pre-key message.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_inbound_session(
+    session: *mut OlmSession,
+    account: *mut OlmAccount,
+    one_time_key_message: *const c_void,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let account = unsafe { object(account) };
+    let one_time_key_message = unsafe { input(one_time_key_message, message_length) };
+
+    let result = create_inbound_session(session, account, None, one_time_key_message);
+
+    session.finish(result)
+}
+
+/// Create a new inbound session from the given pre-key message, checking that
+/// it was sent using the given identity key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_create_inbound_session_from(
+    session: *mut OlmSession,
+    account: *mut OlmAccount,
+    their_identity_key: *const c_void,
+    their_identity_key_length: usize,
+    one_time_key_message: *const c_void,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let account = unsafe { object(account) };
+    let their_identity_key = unsafe { input(their_identity_key, their_identity_key_length) };
+    let one_time_key_message = unsafe { input(one_time_key_message, message_length) };
+
+    let result =
+        create_inbound_session(session, account, Some(their_identity_key), one_time_key_message);
+
+    session.finish(result)
+}
+
+/// The length of the session ID.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_session_id_length(_session: *const OlmSession) -> usize {
+    SESSION_ID_LENGTH
+}
+
+/// Write the ID of the session into the output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_session_id(
+    session: *mut OlmSession,
+    id: *mut c_void,
+    id_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let id = unsafe { output(id, id_length) };
+
+    let result = session.inner().and_then(|s| write_output(id, s.session.session_id().as_bytes()));
+
+    session.finish(result)
+}
+
+/// Whether the session has received a message from the other side.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_session_has_received_message(session: *const OlmSession) -> c_int {
+    let session = unsafe { &*session };
+
+    session.inner.as_ref().is_some_and(|s| s.session.has_received_message()).into()
+}
+
+fn matches_inbound_session(
+    session: &mut OlmSession,
+    their_identity_key: Option<&[u8]>,
+    one_time_key_message: &[u8],
+) -> Result<usize, OlmErrorCode> {
+    let message = pre_key_message(one_time_key_message)?;
+    let identity_key = their_identity_key.map(curve25519_key).transpose()?;
+
+    let matches = session.inner()?.session.session_keys() == message.session_keys()
+        && identity_key.is_none_or(|key| key == message.identity_key());
+
+    Ok(matches.into())
+}
+
+/// Check whether the pre-key message was sent using this session, returns 1
+/// if it was and 0 if it wasn't.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_matches_inbound_session(
+    session: *mut OlmSession,
+    one_time_key_message: *const c_void,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let one_time_key_message = unsafe { input(one_time_key_message, message_length) };
+
+    let result = matches_inbound_session(session, None, one_time_key_message);
+
+    session.finish(result)
+}
+
+/// Check whether the pre-key message was sent using this session and the given
+/// identity key, returns 1 if it was and 0 if it wasn't.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_matches_inbound_session_from(
+    session: *mut OlmSession,
+    their_identity_key: *const c_void,
+    their_identity_key_length: usize,
+    one_time_key_message: *const c_void,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let their_identity_key = unsafe { input(their_identity_key, their_identity_key_length) };
+    let one_time_key_message = unsafe { input(one_time_key_message, message_length) };
+
+    let result = matches_inbound_session(session, Some(their_identity_key), one_time_key_message);
+
+    session.finish(result)
+}
+
+/// The type of the next message [`olm_encrypt()`] will produce, 0 for a pre-key
+/// message and 1 for a normal message.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_encrypt_message_type(session: *mut OlmSession) -> usize {
+    let session = unsafe { object(session) };
+    let result = session.inner().map(|s| s.session.has_received_message().into());
+
+    session.finish(result)
+}
+
+/// The number of random bytes the next [`olm_encrypt()`] call needs.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_encrypt_random_length(session: *mut OlmSession) -> usize {
+    let session = unsafe { object(session) };
+
+    let result = session.inner().map(|s| s.session.encrypt_random_length());
+
+    session.finish(result)
+}
+
+/// The length of the message [`olm_encrypt()`] produces for a plaintext of the
+/// given length.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_encrypt_message_length(
+    session: *mut OlmSession,
+    plaintext_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+
+    let result = session.inner().map(|s| {
+        // vodozemac encodes messages as unpadded base64.
+        (s.session.encrypted_message_length(plaintext_length) * 4).div_ceil(3)
+    });
+
+    session.finish(result)
+}
+
+/// Encrypt the plaintext, writing the base64 encoded message into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_encrypt(
+    session: *mut OlmSession,
+    plaintext: *const c_void,
+    plaintext_length: usize,
+    random: *const c_void,
+    random_length: usize,
+    message: *mut c_void,
+    message_length: usize,
+) -> usize {
+    let required_random = unsafe { olm_encrypt_random_length(session) };
+    let required_length = unsafe { olm_encrypt_message_length(session, plaintext_length) };
+
+    let session = unsafe { object(session) };
+    let plaintext = unsafe { input(plaintext, plaintext_length) };
+    let random = unsafe { input(random, random_length) };
+    let message = unsafe { output(message, message_length) };
+
+    let result = session.inner().and_then(|s| {
+        // Check everything up front, the session should stay untouched if
+        // encryption fails.
+        let mut rng = RandomBuffer::new(random, required_random)?;
+
+        if message.len() < required_length {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        let encrypted = s.session.encrypt_with_rng(plaintext, &mut rng);
+        write_output(message, base64_encode(encrypted.to_parts().1).as_bytes())
+    });
+
+    session.finish(result)
+}
+
+/// The maximal length of the plaintext of the given message.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_decrypt_max_plaintext_length(
+    session: *mut OlmSession,
+    message_type: usize,
+    message: *const c_void,
+    message_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let message = unsafe { input(message, message_length) };
+
+    let result = decode_message(message_type, message).map(|(_, message)| message.message().len());
+
+    session.finish(result)
+}
+
+/// Decrypt the base64 encoded message, writing the plaintext into the output
+/// buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_decrypt(
+    session: *mut OlmSession,
+    message_type: usize,
+    message: *const c_void,
+    message_length: usize,
+    plaintext: *mut c_void,
+    max_plaintext_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let message = unsafe { input(message, message_length) };
+    let plaintext = unsafe { output(plaintext, max_plaintext_length) };
+
+    let result = session.inner().and_then(|s| {
+        let (bytes, message) = decode_message(message_type, message)?;
+
+        if plaintext.len() < message.message().len() {
+            return Err(OlmErrorCode::OutputBufferTooSmall);
+        }
+
+        let decrypted = match s.pending.take_if(|pending| pending.message == bytes) {
+            Some(pending) => pending.plaintext,
+            None => Zeroizing::new(s.session.decrypt(&message)?),
+        };
+
+        write_output(plaintext, &decrypted)
+    });
+
+    session.finish(result)
+}
+
+/// The length of the pickled session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_session_length(session: *mut OlmSession) -> usize {
+    let session = unsafe { object(session) };
+
+    // The length of a vodozemac pickle doesn't depend on the key.
+    let result = session.inner().map(|s| s.pickle(&[0u8; 32]).len());
+
+    session.finish(result)
+}
+
+/// Pickle the session in the vodozemac format, encrypting it with the SHA-256
+/// hash of the given key.
+///
+/// If the session was created from a pre-key message which wasn't passed to
+/// [`olm_decrypt()`] yet, the plaintext of the message is pickled alongside
+/// the session, so it can still be decrypted after unpickling.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_pickle_session(
+    session: *mut OlmSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *mut c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { output(pickled, pickled_length) };
+
+    let result =
+        session.inner().and_then(|s| write_output(pickled, s.pickle(&pickle_key(key)).as_bytes()));
+
+    session.finish(result)
+}
+
+/// Restore the session from a libolm or vodozemac pickle, decrypting it with
+/// the given key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_unpickle_session(
+    session: *mut OlmSession,
+    key: *const c_void,
+    key_length: usize,
+    pickled: *const c_void,
+    pickled_length: usize,
+) -> usize {
+    let session = unsafe { object(session) };
+    let key = unsafe { input(key, key_length) };
+    let pickled = unsafe { input(pickled, pickled_length) };
+
+    let result = unpickle(
+        pickled,
+        |pickle| Session::from_libolm_pickle(pickle, key).map(SessionState::new),
+        |pickle| SessionState::from_pickle(pickle, &pickle_key(key)),
+    )
+    .map(|unpickled| {
+        session.inner = Some(unpickled);
+        pickled_length
+    });
+
+    session.finish(result)
+}
+
+#[cfg(test)]
+mod test {
+    use olm_rs::{PicklingMode, account::OlmAccount as LibolmAccount};
+    use vodozemac::olm::{Account, MessageType};
+
+    use super::*;
+    use crate::{account::test::create_account, olm_error, test::Object};
+
+    fn session() -> Object<SessionState> {
+        Object::new(olm_session_size(), olm_session)
+    }
+
+    fn outbound_session(account: &Object<Account>, bob: &mut Account) -> Object<SessionState> {
+        let session = session();
+
+        bob.generate_one_time_keys(1);
+        let identity_key = bob.curve25519_key().to_base64();
+        let one_time_key = bob
+            .one_time_keys()
+            .values()
+            .next()
+            .expect("Bob should have a one-time key")
+            .to_base64();
+        let random = [3u8; OUTBOUND_SESSION_RANDOM_LENGTH];
+
+        assert_eq!(
+            unsafe {
+                olm_create_outbound_session(
+                    session.ptr(),
+                    account.ptr(),
+                    identity_key.as_ptr().cast(),
+                    identity_key.len(),
+                    one_time_key.as_ptr().cast(),
+                    one_time_key.len(),
+                    random.as_ptr().cast(),
+                    random.len(),
+                )
+            },
+            0
+        );
+
+        session
+    }
+
+    fn encrypt(session: &Object<SessionState>, plaintext: &[u8]) -> (usize, String) {
+        let message_type = unsafe { olm_encrypt_message_type(session.ptr()) };
+        let random = vec![5u8; unsafe { olm_encrypt_random_length(session.ptr()) }];
+        let mut message =
+            vec![0u8; unsafe { olm_encrypt_message_length(session.ptr(), plaintext.len()) }];
+
+        let written = unsafe {
+            olm_encrypt(
+                session.ptr(),
+                plaintext.as_ptr().cast(),
+                plaintext.len(),
+                random.as_ptr().cast(),
+                random.len(),
+                message.as_mut_ptr().cast(),
+                message.len(),
+            )
+        };
+        assert_eq!(written, message.len(), "The precomputed message length should be exact");
+
+        (message_type, String::from_utf8(message).expect("The message should be valid UTF-8"))
+    }
+
+    fn pickle(session: &Object<SessionState>, key: &[u8]) -> Vec<u8> {
+        let mut pickle = vec![0u8; unsafe { olm_pickle_session_length(session.ptr()) }];
+        assert_eq!(
+            unsafe {
+                olm_pickle_session(
+                    session.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_mut_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            pickle.len()
+        );
+
+        pickle
+    }
+
+    fn unpickle(key: &[u8], pickle: &[u8]) -> Object<SessionState> {
+        let session = session();
+        assert_eq!(
+            unsafe {
+                olm_unpickle_session(
+                    session.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            pickle.len()
+        );
+
+        session
+    }
+
+    /// Create an inbound session from the pre-key message, using the given
+    /// account.
+    fn create_inbound(account: &Object<Account>, message: &str) -> Object<SessionState> {
+        let session = session();
+        assert_eq!(
+            unsafe {
+                olm_create_inbound_session(
+                    session.ptr(),
+                    account.ptr(),
+                    message.as_ptr().cast(),
+                    message.len(),
+                )
+            },
+            0
+        );
+
+        session
+    }
+
+    /// Decrypt the message, returning the plaintext.
+    fn decrypt_plaintext(
+        session: &Object<SessionState>,
+        message_type: usize,
+        message: &str,
+    ) -> Option<Vec<u8>> {
+        let mut plaintext = vec![0u8; message.len()];
+        let length = unsafe {
+            olm_decrypt(
+                session.ptr(),
+                message_type,
+                message.as_ptr().cast(),
+                message.len(),
+                plaintext.as_mut_ptr().cast(),
+                plaintext.len(),
+            )
+        };
+
+        (length != olm_error()).then(|| plaintext[..length].to_vec())
+    }
+
+    fn decrypt(session: &Object<SessionState>, message_type: usize, message: &str) -> usize {
+        let mut plaintext = vec![
+            0u8;
+            unsafe {
+                olm_decrypt_max_plaintext_length(
+                    session.ptr(),
+                    message_type,
+                    message.as_ptr().cast(),
+                    message.len(),
+                )
+            }
+        ];
+
+        unsafe {
+            olm_decrypt(
+                session.ptr(),
+                message_type,
+                message.as_ptr().cast(),
+                message.len(),
+                plaintext.as_mut_ptr().cast(),
+                plaintext.len(),
+            )
+        }
+    }
+
+    #[test]
+    fn outbound_session_interop() {
+        let alice = create_account();
+        let mut bob = Account::new();
+        let session = outbound_session(&alice, &mut bob);
+
+        let (message_type, message) = encrypt(&session, b"It's a secret to everybody");
+        assert_eq!(message_type, 0);
+
+        let message = OlmMessage::from_parts(
+            message_type,
+            &base64_decode(message).expect("The message should be valid base64"),
+        )
+        .expect("The message should be a valid Olm message");
+        let OlmMessage::PreKey(message) = message else {
+            panic!("The first message should be a pre-key message");
+        };
+
+        let mut result = bob
+            .create_inbound_session(alice.inner().curve25519_key(), &message)
+            .expect("Bob should be able to create an inbound session");
+        assert_eq!(result.plaintext, b"It's a secret to everybody");
+
+        let mut id = vec![0u8; unsafe { olm_session_id_length(session.ptr()) }];
+        unsafe { olm_session_id(session.ptr(), id.as_mut_ptr().cast(), id.len()) };
+        assert_eq!(id, result.session.session_id().as_bytes());
+
+        let reply = result.session.encrypt("Hello Alice");
+        assert_eq!(reply.message_type(), MessageType::Normal);
+
+        let reply = base64_encode(reply.to_parts().1);
+        assert_eq!(unsafe { olm_session_has_received_message(session.ptr()) }, 0);
+        assert_eq!(decrypt(&session, 1, &reply), "Hello Alice".len());
+        assert_eq!(unsafe { olm_session_has_received_message(session.ptr()) }, 1);
+        assert_eq!(unsafe { olm_encrypt_message_type(session.ptr()) }, 1);
+
+        let (_, message) = encrypt(&session, b"Hello Bob");
+        assert_eq!(
+            decrypt(&session, 1, &message),
+            olm_error(),
+            "Alice can't decrypt her own message"
+        );
+        assert_eq!(session.last_error(), c"BAD_MESSAGE_MAC");
+    }
+
+    #[test]
+    fn inbound_session() {
+        let alice = Account::new();
+        let bob = create_account();
+
+        let random = [1u8; 32];
+        unsafe {
+            crate::olm_account_generate_one_time_keys(bob.ptr(), 1, random.as_ptr().cast(), 32)
+        };
+        let one_time_key =
+            *bob.inner().one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let mut outbound = alice.create_outbound_session(
+            SessionConfig::version_1(),
+            bob.inner().curve25519_key(),
+            one_time_key,
+        );
+        let message = base64_encode(outbound.encrypt("It's a secret to everybody").to_parts().1);
+
+        let session = session();
+        let identity_key = alice.curve25519_key().to_base64();
+
+        assert_eq!(
+            unsafe {
+                olm_create_inbound_session_from(
+                    session.ptr(),
+                    bob.ptr(),
+                    identity_key.as_ptr().cast(),
+                    identity_key.len(),
+                    message.as_ptr().cast(),
+                    message.len(),
+                )
+            },
+            0
+        );
+        assert_eq!(
+            unsafe {
+                olm_matches_inbound_session(session.ptr(), message.as_ptr().cast(), message.len())
+            },
+            1
+        );
+
+        let mut plaintext = vec![0u8; message.len()];
+        let length = unsafe {
+            olm_decrypt(
+                session.ptr(),
+                0,
+                message.as_ptr().cast(),
+                message.len(),
+                plaintext.as_mut_ptr().cast(),
+                plaintext.len(),
+            )
+        };
+        assert_eq!(&plaintext[..length], b"It's a secret to everybody");
+
+        // Creating a second session fails, the one-time key has been used up.
+        let session = self::session();
+
+        assert_eq!(
+            unsafe {
+                olm_create_inbound_session(
+                    session.ptr(),
+                    bob.ptr(),
+                    message.as_ptr().cast(),
+                    message.len(),
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(session.last_error(), c"BAD_MESSAGE_KEY_ID");
+    }
+
+    #[test]
+    fn pickling_pending_pre_key_message() {
+        let alice = Account::new();
+        let bob = create_account();
+        let key = b"DEFAULT_PICKLE_KEY";
+
+        let random = [1u8; 32];
+        unsafe {
+            crate::olm_account_generate_one_time_keys(bob.ptr(), 1, random.as_ptr().cast(), 32)
+        };
+        let one_time_key =
+            *bob.inner().one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let mut outbound = alice.create_outbound_session(
+            SessionConfig::version_1(),
+            bob.inner().curve25519_key(),
+            one_time_key,
+        );
+        let message = base64_encode(outbound.encrypt("It's a secret to everybody").to_parts().1);
+
+        // Like libolm, the pre-key message can be decrypted after the session
+        // has been pickled and unpickled.
+        let session = create_inbound(&bob, &message);
+        let pickle = pickle(&session, key);
+        let is_session_pickle = |pickle: &[u8]| {
+            let pickle = std::str::from_utf8(pickle).expect("The pickle should be valid UTF-8");
+            SessionPickle::from_encrypted(pickle, &pickle_key(key)).is_ok()
+        };
+        assert!(!is_session_pickle(&pickle));
+
+        let restored = unpickle(key, &pickle);
+        assert_eq!(
+            decrypt_plaintext(&restored, 0, &message).as_deref(),
+            Some(b"It's a secret to everybody".as_slice())
+        );
+        assert_eq!(decrypt_plaintext(&restored, 0, &message), None);
+
+        // Once the pre-key message has been decrypted, it isn't pickled anymore.
+        assert!(is_session_pickle(&self::pickle(&restored, key)));
+
+        let wrong_key = b"WRONG_KEY";
+        let restored = self::session();
+        assert_eq!(
+            unsafe {
+                olm_unpickle_session(
+                    restored.ptr(),
+                    wrong_key.as_ptr().cast(),
+                    wrong_key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            olm_error()
+        );
+    }
+
+    #[test]
+    fn pre_key_message_matches_libolm() {
+        let alice = LibolmAccount::new();
+        let bob = create_account();
+        let libolm_bob = LibolmAccount::new();
+        let key = b"DEFAULT_PICKLE_KEY";
+        let plaintext = "It's a secret to everybody";
+
+        let random = [1u8; 32];
+        unsafe {
+            crate::olm_account_generate_one_time_keys(bob.ptr(), 1, random.as_ptr().cast(), 32)
+        };
+        let one_time_key = bob
+            .inner()
+            .one_time_keys()
+            .values()
+            .next()
+            .expect("Bob should have a one-time key")
+            .to_base64();
+        libolm_bob.generate_one_time_keys(1);
+        let libolm_one_time_key = libolm_bob
+            .parsed_one_time_keys()
+            .curve25519()
+            .values()
+            .next()
+            .cloned()
+            .expect("libolm Bob should have a one-time key");
+
+        let outbound = alice
+            .create_outbound_session(&bob.inner().curve25519_key().to_base64(), &one_time_key)
+            .expect("Alice should be able to create a session with Bob");
+        let libolm_outbound = alice
+            .create_outbound_session(
+                libolm_bob.parsed_identity_keys().curve25519(),
+                &libolm_one_time_key,
+            )
+            .expect("Alice should be able to create a session with libolm Bob");
+
+        let olm_rs::session::OlmMessage::PreKey(message) = outbound.encrypt(plaintext) else {
+            panic!("The first message should be a pre-key message");
+        };
+        let olm_rs::session::OlmMessage::PreKey(libolm_message) =
+            libolm_outbound.encrypt(plaintext)
+        else {
+            panic!("The first message should be a pre-key message");
+        };
+
+        // Pickle both inbound sessions before the pre-key message is decrypted.
+        let (_, message) = message.to_tuple();
+        let session = unpickle(key, &pickle(&create_inbound(&bob, &message), key));
+
+        let libolm_session = libolm_bob
+            .create_inbound_session(libolm_message.clone())
+            .expect("libolm Bob should be able to create an inbound session");
+        let libolm_session = olm_rs::session::OlmSession::unpickle(
+            libolm_session.pickle(PicklingMode::Encrypted { key: key.to_vec() }),
+            PicklingMode::Encrypted { key: key.to_vec() },
+        )
+        .expect("libolm Bob should be able to unpickle the session");
+
+        let (_, libolm_message) = libolm_message.to_tuple();
+        let libolm_plaintext = libolm_session
+            .decrypt(
+                olm_rs::session::OlmMessage::from_type_and_ciphertext(0, libolm_message)
+                    .expect("The message should be a valid pre-key message"),
+            )
+            .expect("libolm Bob should be able to decrypt the pre-key message");
+
+        assert_eq!(libolm_plaintext, plaintext);
+        assert_eq!(
+            decrypt_plaintext(&session, 0, &message).as_deref(),
+            Some(libolm_plaintext.as_bytes())
+        );
+
+        // Both sessions can reply to Alice.
+        let (message_type, reply) = encrypt(&session, b"Hello Alice");
+        assert_eq!(message_type, 1);
+        let reply = outbound
+            .decrypt(
+                olm_rs::session::OlmMessage::from_type_and_ciphertext(1, reply)
+                    .expect("The reply should be a valid Olm message"),
+            )
+            .expect("Alice should be able to decrypt the reply");
+        assert_eq!(reply, "Hello Alice");
+    }
+
+    #[test]
+    fn encryption_checks_buffers() {
+        let alice = create_account();
+        let mut bob = Account::new();
+        let session = outbound_session(&alice, &mut bob);
+        let mut message = [0u8; 8];
+
+        let written = unsafe {
+            olm_encrypt(
+                session.ptr(),
+                b"Hello".as_ptr().cast(),
+                5,
+                [0u8; 0].as_ptr().cast(),
+                0,
+                message.as_mut_ptr().cast(),
+                message.len(),
+            )
+        };
+
+        // A fresh outbound session already has a ratchet key, no random
+        // bytes are needed.
+        assert_eq!(unsafe { olm_encrypt_random_length(session.ptr()) }, 0);
+        assert_eq!(written, olm_error());
+        assert_eq!(session.last_error(), c"OUTPUT_BUFFER_TOO_SMALL");
+    }
+
+    #[test]
+    fn pickling() {
+        let alice = create_account();
+        let mut bob = Account::new();
+        let session = outbound_session(&alice, &mut bob);
+        let key = b"DEFAULT_PICKLE_KEY";
+
+        let mut pickle = vec![0u8; unsafe { olm_pickle_session_length(session.ptr()) }];
+        assert_eq!(
+            unsafe {
+                olm_pickle_session(
+                    session.ptr(),
+                    key.as_ptr().cast(),
+                    key.len(),
+                    pickle.as_mut_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            pickle.len()
+        );
+
+        let restored = self::session();
+        unsafe {
+            olm_unpickle_session(
+                restored.ptr(),
+                key.as_ptr().cast(),
+                key.len(),
+                pickle.as_ptr().cast(),
+                pickle.len(),
+            )
+        };
+        assert_eq!(restored.inner().session.session_id(), session.inner().session.session_id());
+
+        let wrong_key = b"WRONG_KEY";
+        assert_eq!(
+            unsafe {
+                olm_unpickle_session(
+                    restored.ptr(),
+                    wrong_key.as_ptr().cast(),
+                    wrong_key.len(),
+                    pickle.as_ptr().cast(),
+                    pickle.len(),
+                )
+            },
+            olm_error()
+        );
+        assert_eq!(restored.last_error(), c"BAD_ACCOUNT_KEY");
+    }
+}
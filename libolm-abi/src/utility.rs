@@ -0,0 +1,144 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::c_void;
+
+use sha2::{Digest, Sha256};
+use vodozemac::{Ed25519PublicKey, Ed25519Signature, base64_encode};
+
+use crate::{OlmObject, base64_input, input, object, object_functions, output, write_output};
+
+/// The length of a base64 encoded SHA-256 hash.
+const SHA256_LENGTH: usize = 43;
+
+/// A utility object, it doesn't hold any state.
+pub type OlmUtility = OlmObject<()>;
+
+object_functions!(
+    OlmUtility, "a utility",
+    size: olm_utility_size,
+    init: olm_utility,
+    last_error: olm_utility_last_error,
+    last_error_code: olm_utility_last_error_code,
+    clear: olm_clear_utility,
+);
+
+/// The length of a base64 encoded SHA-256 hash.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sha256_length(_utility: *const OlmUtility) -> usize {
+    SHA256_LENGTH
+}
+
+/// Calculate the SHA-256 hash of the input, writing it base64 encoded into the
+/// output buffer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_sha256(
+    utility: *mut OlmUtility,
+    input_buffer: *const c_void,
+    input_length: usize,
+    output_buffer: *mut c_void,
+    output_length: usize,
+) -> usize {
+    let utility = unsafe { object(utility) };
+    let input_buffer = unsafe { input(input_buffer, input_length) };
+    let output_buffer = unsafe { output(output_buffer, output_length) };
+
+    let result =
+        write_output(output_buffer, base64_encode(Sha256::digest(input_buffer)).as_bytes());
+
+    utility.finish(result)
+}
+
+/// Verify the base64 encoded Ed25519 signature of the message, returns 0 if the
+/// signature is valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn olm_ed25519_verify(
+    utility: *mut OlmUtility,
+    key: *const c_void,
+    key_length: usize,
+    message: *const c_void,
+    message_length: usize,
+    signature: *const c_void,
+    signature_length: usize,
+) -> usize {
+    let utility = unsafe { object(utility) };
+    let key = unsafe { input(key, key_length) };
+    let message = unsafe { input(message, message_length) };
+    let signature = unsafe { input(signature, signature_length) };
+
+    let result = (|| {
+        let key = Ed25519PublicKey::from_base64(base64_input(key)?)?;
+        let signature = Ed25519Signature::from_base64(base64_input(signature)?)?;
+
+        key.verify(message, &signature)?;
+
+        Ok(0)
+    })();
+
+    utility.finish(result)
+}
+
+#[cfg(test)]
+mod test {
+    use vodozemac::Ed25519SecretKey;
+
+    use super::*;
+    use crate::{olm_error, test::Object};
+
+    fn utility() -> Object<()> {
+        Object::new(olm_utility_size(), olm_utility)
+    }
+
+    #[test]
+    fn sha256() {
+        let utility = utility();
+        let mut hash = vec![0u8; unsafe { olm_sha256_length(utility.ptr()) }];
+
+        unsafe {
+            olm_sha256(
+                utility.ptr(),
+                b"Hello".as_ptr().cast(),
+                5,
+                hash.as_mut_ptr().cast(),
+                hash.len(),
+            )
+        };
+
+        assert_eq!(hash, b"GF+NsyJx/iX1Yab8k4suJkMG7DBO2lGAB9F2SCY4GWk");
+    }
+
+    #[test]
+    fn ed25519_verify() {
+        let utility = utility();
+        let key = Ed25519SecretKey::new();
+        let public_key = key.public_key().to_base64();
+        let signature = key.sign(b"Hello").to_base64();
+
+        let verify = |message: &[u8]| unsafe {
+            olm_ed25519_verify(
+                utility.ptr(),
+                public_key.as_ptr().cast(),
+                public_key.len(),
+                message.as_ptr().cast(),
+                message.len(),
+                signature.as_ptr().cast(),
+                signature.len(),
+            )
+        };
+
+        assert_eq!(verify(b"Hello"), 0);
+        assert_eq!(verify(b"Goodbye"), olm_error());
+        assert_eq!(utility.last_error(), c"BAD_MESSAGE_MAC");
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The key and nonce for a single ChaCha20-Poly1305 encryption.
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct ExpandedAeadKeys {
+    key: Box<[u8; 32]>,
+    nonce: Box<[u8; 12]>,
+}
+
+impl ExpandedAeadKeys {
+    const MEGOLM_HKDF_INFO: &'static [u8] = b"MEGOLM_AEAD_KEYS";
+    const PICKLE_HKDF_INFO: &'static [u8] = b"PICKLE_AEAD_KEYS";
+    const OLM_HEADER_HKDF_INFO: &'static [u8] = b"OLM_HEADER_KEYS";
+    const ROOM_KEY_BUNDLE_HKDF_INFO: &'static [u8] = b"ROOM_KEY_BUNDLE_KEYS";
+
+    fn new_helper(message_key: &[u8], salt: &[u8], info: &[u8]) -> Self {
+        let mut expanded_keys = Box::new([0u8; 44]);
+
+        let hkdf: Hkdf<Sha256> = Hkdf::new(Some(salt), message_key);
+
+        #[allow(clippy::expect_used)]
+        hkdf.expand(info, expanded_keys.as_mut_slice())
+            .expect("HKDF should be able to expand a key into 44 bytes");
+
+        let mut key = Box::new([0u8; 32]);
+        let mut nonce = Box::new([0u8; 12]);
+
+        key.copy_from_slice(&expanded_keys[0..32]);
+        nonce.copy_from_slice(&expanded_keys[32..44]);
+
+        expanded_keys.zeroize();
+
+        Self { key, nonce }
+    }
+}
+
+/// A ChaCha20-Poly1305 cipher whose key and nonce are deterministically
+/// derived from a message key.
+///
+/// Since the nonce is derived from the message key, every message key must
+/// only ever be used to encrypt a single message.
+pub(crate) struct AeadCipher {
+    keys: ExpandedAeadKeys,
+}
+
+impl AeadCipher {
+    /// Create a new [`AeadCipher`] from the 128-byte Megolm ratchet value,
+    /// using the byte string "MEGOLM_AEAD_KEYS" as the HKDF info.
+    pub fn new_megolm(message_key: &[u8; 128]) -> Self {
+        Self {
+            keys: ExpandedAeadKeys::new_helper(
+                message_key,
+                &[0],
+                ExpandedAeadKeys::MEGOLM_HKDF_INFO,
+            ),
+        }
+    }
+
+    /// Create a new [`AeadCipher`] from a pickle key and a salt, using the byte
+    /// string "PICKLE_AEAD_KEYS" as the HKDF info.
+    ///
+    /// The salt needs to be random and unique for every pickle, it ensures that
+    /// the same key and nonce are never used twice.
+    pub fn new_pickle(pickle_key: &[u8; 32], salt: &[u8]) -> Self {
+        Self {
+            keys: ExpandedAeadKeys::new_helper(
+                pickle_key,
+                salt,
+                ExpandedAeadKeys::PICKLE_HKDF_INFO,
+            ),
+        }
+    }
+
+    /// Create a new [`AeadCipher`] from an Olm header key and a salt, using the
+    /// byte string "OLM_HEADER_KEYS" as the HKDF info.
+    ///
+    /// A header key encrypts the headers of all messages in a chain, so the
+    /// salt needs to be random and unique for every header.
+    pub fn new_olm_header(header_key: &[u8; 32], salt: &[u8]) -> Self {
+        Self {
+            keys: ExpandedAeadKeys::new_helper(
+                header_key,
+                salt,
+                ExpandedAeadKeys::OLM_HEADER_HKDF_INFO,
+            ),
+        }
+    }
+
+    /// Create a new [`AeadCipher`] from a room key bundle key, using the byte
+    /// string "ROOM_KEY_BUNDLE_KEYS" as the HKDF info.
+    ///
+    /// A fresh bundle key is generated for every bundle, so no salt is needed.
+    pub fn new_room_key_bundle(bundle_key: &[u8; 32]) -> Self {
+        Self {
+            keys: ExpandedAeadKeys::new_helper(
+                bundle_key,
+                &[0],
+                ExpandedAeadKeys::ROOM_KEY_BUNDLE_HKDF_INFO,
+            ),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(self.keys.key.as_ref().into())
+    }
+
+    /// Encrypt and authenticate the plaintext, additionally authenticating the
+    /// associated data. The authentication tag is appended to the ciphertext.
+    pub fn encrypt(&self, plaintext: &[u8], associated_data: &[u8]) -> Vec<u8> {
+        let nonce = Nonce::from_slice(self.keys.nonce.as_ref());
+
+        #[allow(clippy::expect_used)]
+        self.cipher()
+            .encrypt(nonce, Payload { msg: plaintext, aad: associated_data })
+            .expect("We should be able to encrypt a message using ChaCha20-Poly1305")
+    }
+
+    /// Verify the authentication tag of the ciphertext and the associated data
+    /// and decrypt the ciphertext.
+    pub fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, chacha20poly1305::aead::Error> {
+        let nonce = Nonce::from_slice(self.keys.nonce.as_ref());
+
+        self.cipher().decrypt(nonce, Payload { msg: ciphertext, aad: associated_data })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AeadCipher;
+
+    #[test]
+    fn roundtrip() {
+        let cipher = AeadCipher::new_megolm(&[1u8; 128]);
+
+        let ciphertext = cipher.encrypt(b"It's a secret to everybody", b"room");
+        // ChaCha20 is a stream cipher, the ciphertext is only extended by the tag.
+        assert_eq!(ciphertext.len(), 26 + 16);
+
+        let plaintext = cipher.decrypt(&ciphertext, b"room").expect("We should be able to decrypt");
+        assert_eq!(plaintext, b"It's a secret to everybody");
+
+        cipher
+            .decrypt(&ciphertext, b"other room")
+            .expect_err("Decryption with different associated data should fail");
+        AeadCipher::new_megolm(&[2u8; 128])
+            .decrypt(&ciphertext, b"room")
+            .expect_err("Decryption with a different key should fail");
+    }
+}
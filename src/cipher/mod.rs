@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub(crate) mod aead;
 pub(crate) mod key;
 
 use aes::{
@@ -60,6 +61,9 @@ impl Mac {
 pub(crate) enum MessageMac {
     Truncated([u8; Mac::TRUNCATED_LEN]),
     Full(Mac),
+    /// The message was encrypted using an AEAD, the authentication tag is part
+    /// of the ciphertext.
+    Aead,
 }
 
 impl MessageMac {
@@ -67,6 +71,7 @@ impl MessageMac {
         match self {
             MessageMac::Truncated(m) => m.as_ref(),
             MessageMac::Full(m) => m.as_bytes(),
+            MessageMac::Aead => &[],
         }
     }
 }
@@ -91,6 +96,8 @@ pub enum DecryptionError {
     Mac(#[from] MacError),
     #[error("The ciphertext didn't contain a valid MAC")]
     MacMissing,
+    #[error("The authentication tag or the associated data of the ciphertext didn't match")]
+    Aead,
 }
 
 /// A cipher used for encrypting and decrypting messages.
@@ -81,6 +81,7 @@
 use chacha20poly1305::{ChaCha20Poly1305, Key as Chacha20Key, KeyInit, Nonce, aead::Aead};
 use hkdf::Hkdf;
 use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use sha2::Sha512;
 use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, SharedSecret};
@@ -232,13 +233,27 @@ impl Ecies {
         Self::with_info(MATRIX_QR_LOGIN_INFO_PREFIX)
     }
 
+    /// Create a new, random, unestablished ECIES session using the given
+    /// random number generator.
+    ///
+    /// This method will use the `MATRIX_QR_CODE_LOGIN` info, like
+    /// [`Ecies::new()`] does.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        Self::with_info_and_rng(MATRIX_QR_LOGIN_INFO_PREFIX, rng)
+    }
+
     /// Create a new, random, unestablished ECIES session with the given
     /// application info.
     ///
     /// The application info will be used to derive the various secrets and
     /// provide domain separation.
     pub fn with_info(info: &str) -> Self {
-        let rng = thread_rng();
+        Self::with_info_and_rng(info, &mut thread_rng())
+    }
+
+    /// Create a new, random, unestablished ECIES session with the given
+    /// application info, using the given random number generator.
+    pub fn with_info_and_rng(info: &str, rng: &mut impl CryptoRngCore) -> Self {
         let secret_key = EphemeralSecret::random_from_rng(rng);
         let application_info_prefix = info.to_owned();
 
@@ -703,8 +718,8 @@ mod test {
         use crate::types::Curve25519Keypair;
 
         let app_info = "foobar";
-        let our_public_key = Curve25519Keypair::new().public_key;
-        let their_public_key = Curve25519Keypair::new().public_key;
+        let our_public_key = Curve25519Keypair::new(&mut thread_rng()).public_key;
+        let their_public_key = Curve25519Keypair::new(&mut thread_rng()).public_key;
 
         let check_code_info1 = EstablishedEcies::get_check_code_info(
             app_info,
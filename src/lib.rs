@@ -69,6 +69,7 @@
 //! - [libolm pickle format](#legacy-pickles) (read-only)
 //! - [Modern pickle format](#modern-pickles)
 //! - [SAS (Short Authentication Strings)](https://matrix-org.github.io/vodozemac/vodozemac/sas/index.html)
+//! - [QR code verification](https://matrix-org.github.io/vodozemac/vodozemac/qr_verification/index.html)
 //!
 //! ## Unsupported
 //!
@@ -102,6 +103,34 @@
 //! Extreme care must be taken when using such APIs, as incorrect usage can lead
 //! to broken sessions.
 //!
+//! ## SAS emoji translations
+//!
+//! Feature: `sas-emoji-translations` (default: off)
+//!
+//! Bundles the German, Spanish, French and Dutch translations of the SAS emoji
+//! descriptions, which can then be retrieved using
+//! [`sas::Emoji::translated_description()`].
+//!
+//! ## Test vectors
+//!
+//! Feature: `test-vectors` (default: off)
+//!
+//! Generates JSON test vectors of scripted Olm, Megolm, SAS and ECIES
+//! scenarios, which other implementations can be checked against, and replays
+//! such vectors, see the `test_vectors` module.
+//!
+//! # Randomness
+//!
+//! By default, all keys, nonces and salts are generated using the thread-local
+//! random number generator of the `rand` crate, which is seeded by the
+//! operating system.
+//!
+//! Every method which consumes randomness has a `_with_rng()` variant, e.g.
+//! [`olm::Account::new_with_rng()`] or [`olm::Session::encrypt_with_rng()`],
+//! which accepts any random number generator implementing [`CryptoRngCore`].
+//! This allows plugging in a hardware random number generator, or, in tests,
+//! a seeded one to produce reproducible transcripts.
+//!
 //! # Pickling
 //!
 //! vodozemac supports serializing its entire internal state into a form
@@ -155,6 +184,24 @@
 //! }
 //! ```
 //!
+//! Encrypted pickles are serialized to JSON and encrypted using
+//! ChaCha20-Poly1305. The name of the pickled type is authenticated as well,
+//! so restoring e.g. an encrypted `SessionPickle` as an `AccountPickle` fails.
+//! Pickles which were encrypted by older versions of vodozemac, using
+//! AES-256-CBC and HMAC-SHA-256, are still accepted by the `from_encrypted()`
+//! methods.
+//!
+//! The encrypted pickles of the structs above can also be encoded using a
+//! compact binary format, see [`PickleEncoding`].
+//!
+//! The pickle key can be derived from a passphrase using Argon2id, see
+//! [`PickleKey`].
+//!
+//! Encrypted pickles can be moved to a new pickle key, without restoring the
+//! objects they contain, using [`rekey_pickle()`] and [`rekey_pickles()`].
+//! State of your own can be pickled in the same format using
+//! [`encrypt_pickle()`].
+//!
 //! You can unpickle a pickle-able struct directly from its serialized form:
 //!
 //! ```rust
@@ -196,6 +243,8 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 
 mod cipher;
+mod padding;
+mod pickle_key;
 mod types;
 mod utilities;
 
@@ -205,15 +254,24 @@ pub mod megolm;
 pub mod olm;
 #[cfg(feature = "insecure-pk-encryption")]
 pub mod pk_encryption;
+pub mod qr_verification;
 pub mod sas;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
 
 pub use base64::DecodeError as Base64DecodeError;
+pub use padding::PaddingPolicy;
+pub use pickle_key::{Argon2Params, PickleKey, PickleKeyError, PickleKeyHeader};
 pub use prost::DecodeError as ProtoBufDecodeError;
+pub use rand_core::CryptoRngCore;
 pub use types::{
     Curve25519PublicKey, Curve25519SecretKey, Ed25519Keypair, Ed25519PublicKey, Ed25519SecretKey,
-    Ed25519Signature, KeyError, KeyId, SharedSecret, SignatureError,
+    Ed25519Signature, KeyError, KeyId, MlKem768PublicKey, SharedSecret, SignatureError,
+};
+pub use utilities::{
+    BinaryPickleError, PickleEncoding, base64_decode, base64_encode, decrypt_pickle,
+    encrypt_pickle, rekey_pickle, rekey_pickle_with_rng, rekey_pickles,
 };
-pub use utilities::{base64_decode, base64_encode};
 
 /// Error type describing the various ways Vodozemac pickles can fail to be
 /// decoded.
@@ -228,6 +286,9 @@ pub enum PickleError {
     /// The serialized Vodozemac object couldn't be deserialized.
     #[error("The pickle couldn't be deserialized: {0}")]
     Serialization(#[from] serde_json::Error),
+    /// The binary encoded Vodozemac object couldn't be deserialized.
+    #[error("The binary pickle couldn't be deserialized: {0}")]
+    BinarySerialization(#[from] BinaryPickleError),
 }
 
 /// Error type describing the various ways libolm pickles can fail to be
@@ -310,6 +371,12 @@ pub enum DecodeError {
     /// The embedded message authentication code couldn't be decoded.
     #[error("The message contained a MAC with an invalid size, expected {0}, got {1}")]
     InvalidMacLength(usize, usize),
+    /// The embedded ML-KEM-768 key hash or ciphertext couldn't be decoded.
+    #[error("The message contained an invalid ML-KEM-768 key hash or ciphertext")]
+    InvalidKemEncapsulation,
+    /// The header-encrypted message didn't contain a valid encrypted header.
+    #[error("The message didn't contain a valid encrypted header")]
+    InvalidEncryptedHeader,
     /// An embedded signature couldn't be decoded.
     #[error("The message contained an invalid Signature: {0}")]
     Signature(#[from] SignatureError),
@@ -0,0 +1,363 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The Matrix `m.megolm.*` layer of Megolm encrypted room events.
+//!
+//! The plaintext of a Megolm encrypted room event is a JSON object containing
+//! the event type, the content and the ID of the room the event was sent to.
+//! The ciphertext is sent alongside the ID of the session and, for older
+//! clients, the Curve25519 key of the sender.
+//!
+//! A room key is not bound to a room, the room ID in the plaintext needs to be
+//! checked, otherwise a member of one room can replay events into another
+//! room. [`MegolmEnvelope::decrypt()`] checks the room ID, the session ID and
+//! the sender key. Sessions using Megolm version 3 additionally bind the
+//! ciphertext to the room ID, as associated data, so it can't be decrypted in
+//! another room at all.
+//!
+//! The [`InboundGroupSession`] doesn't know which device it belongs to, the
+//! sender key is checked against the key the caller passes in. This needs to
+//! be the Curve25519 key of the device the session was received from, as
+//! stored alongside the session, never a key taken from the event itself.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::{DecryptionError, GroupSession, InboundGroupSession, MegolmMessage, SessionOrigin};
+use crate::{Curve25519PublicKey, DecodeError, KeyError};
+
+/// Error type describing failures to decrypt or validate a Megolm encrypted
+/// room event.
+#[derive(Debug, Error)]
+pub enum MegolmEnvelopeError {
+    /// The event, or its decrypted plaintext, wasn't valid JSON or was missing
+    /// fields.
+    #[error("The Megolm encrypted event couldn't be deserialized: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The event is encrypted using an algorithm other than the one of the
+    /// session, e.g. with Olm or another version of Megolm.
+    #[error("The event uses an unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    /// The sender key of the event wasn't a valid Curve25519 key.
+    #[error("The Megolm encrypted event contained an invalid sender key: {0}")]
+    InvalidKey(#[from] KeyError),
+    /// The ciphertext wasn't a valid Megolm message.
+    #[error("The ciphertext of the event isn't a valid Megolm message: {0}")]
+    Message(#[from] DecodeError),
+    /// The event was encrypted using another session.
+    #[error("The event was encrypted using the session {0}")]
+    MismatchedSessionId(String),
+    /// The event claims to be sent by another device than the one the session
+    /// belongs to.
+    #[error("The sender key of the event doesn't match the sender of the session")]
+    MismatchedSenderKey,
+    /// The ciphertext couldn't be decrypted.
+    #[error(transparent)]
+    Decryption(#[from] DecryptionError),
+    /// The plaintext claims the event was sent to another room.
+    #[error("The event was meant for the room {claimed}, but was received in {expected}")]
+    MismatchedRoomId {
+        /// The ID of the room the event was received in.
+        expected: String,
+        /// The ID of the room the plaintext claims the event was sent to.
+        claimed: String,
+    },
+}
+
+/// The plaintext of a Megolm encrypted event.
+#[derive(Serialize, Deserialize)]
+struct PlaintextContent {
+    #[serde(rename = "type")]
+    event_type: String,
+    content: Map<String, Value>,
+    room_id: String,
+}
+
+/// The content of an `m.room.encrypted` room event using Megolm.
+#[derive(Serialize, Deserialize)]
+struct EncryptedContent {
+    algorithm: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender_key: Option<String>,
+    session_id: String,
+    ciphertext: String,
+}
+
+/// The plaintext of a Megolm encrypted room event.
+#[derive(Debug, Clone)]
+pub struct MegolmEnvelope {
+    event_type: String,
+    content: Map<String, Value>,
+    room_id: String,
+}
+
+/// A room event successfully decrypted by [`MegolmEnvelope::decrypt()`].
+#[derive(Debug, Clone)]
+pub struct DecryptedMegolmEnvelope {
+    /// The decrypted and validated event.
+    pub envelope: MegolmEnvelope,
+    /// The message index of the event, used to detect replay attacks.
+    pub message_index: u32,
+    /// The origin of the session at the time the event was decrypted.
+    ///
+    /// The event should only be considered to be authentic if
+    /// [`SessionOrigin::is_authenticated()`] returns `true`.
+    pub origin: SessionOrigin,
+}
+
+impl MegolmEnvelope {
+    /// Create a new envelope for an event sent to the given room. The
+    /// `content` needs to be a JSON object.
+    pub fn new(
+        room_id: &str,
+        event_type: &str,
+        content: &str,
+    ) -> Result<Self, MegolmEnvelopeError> {
+        Ok(Self {
+            event_type: event_type.to_owned(),
+            content: serde_json::from_str(content)?,
+            room_id: room_id.to_owned(),
+        })
+    }
+
+    /// The type of the wrapped event.
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    /// The content of the wrapped event, serialized as JSON.
+    pub fn content(&self) -> String {
+        #[allow(clippy::expect_used)]
+        serde_json::to_string(&self.content)
+            .expect("A JSON object should always be serializable into JSON")
+    }
+
+    /// The ID of the room the event was sent to.
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    /// Encrypt the envelope using the given [`GroupSession`].
+    ///
+    /// The `sender_key` is our own Curve25519 identity key. Returns the JSON
+    /// content of the `m.room.encrypted` room event, its algorithm depends on
+    /// the [`SessionConfig`] of the session.
+    ///
+    /// If the session supports associated data, the ciphertext is bound to the
+    /// room ID of the envelope.
+    ///
+    /// [`SessionConfig`]: super::SessionConfig
+    pub fn encrypt(&self, session: &mut GroupSession, sender_key: Curve25519PublicKey) -> String {
+        let plaintext = PlaintextContent {
+            event_type: self.event_type.clone(),
+            content: self.content.clone(),
+            room_id: self.room_id.clone(),
+        };
+
+        #[allow(clippy::expect_used)]
+        let plaintext = serde_json::to_vec(&plaintext)
+            .expect("The plaintext of a Megolm envelope should always be serializable into JSON");
+
+        let message = if session.session_config().supports_associated_data() {
+            #[allow(clippy::expect_used)]
+            session.encrypt_with_associated_data(plaintext, &self.room_id).expect(
+                "A session supporting associated data should always be able to encrypt with it",
+            )
+        } else {
+            session.encrypt(plaintext)
+        };
+
+        let content = EncryptedContent {
+            algorithm: session.session_config().algorithm().to_owned(),
+            sender_key: Some(sender_key.to_base64()),
+            session_id: session.session_id(),
+            ciphertext: message.to_base64(),
+        };
+
+        #[allow(clippy::expect_used)]
+        serde_json::to_string(&content).expect(
+            "The content of a Megolm encrypted event should always be serializable into JSON",
+        )
+    }
+
+    /// Decrypt the content of an `m.room.encrypted` room event, which was
+    /// received in the given room, and validate the envelope.
+    ///
+    /// The `sender_key` is the Curve25519 key of the device which shared the
+    /// session with us. The [`InboundGroupSession`] doesn't record this key,
+    /// the caller needs to supply the key it stored alongside the session,
+    /// otherwise the sender of the event isn't checked at all.
+    ///
+    /// The event is rejected if it was encrypted using another session or
+    /// another version of Megolm, claims to be sent by another device, or if
+    /// the room ID in the plaintext doesn't match the `room_id`. Since the
+    /// sender key has been deprecated, events which don't include it are
+    /// accepted.
+    pub fn decrypt(
+        session: &mut InboundGroupSession,
+        room_id: &str,
+        sender_key: Curve25519PublicKey,
+        encrypted_content: &str,
+    ) -> Result<DecryptedMegolmEnvelope, MegolmEnvelopeError> {
+        let encrypted: EncryptedContent = serde_json::from_str(encrypted_content)?;
+
+        if encrypted.algorithm != session.session_config().algorithm() {
+            return Err(MegolmEnvelopeError::UnsupportedAlgorithm(encrypted.algorithm));
+        }
+
+        if encrypted.session_id != session.session_id() {
+            return Err(MegolmEnvelopeError::MismatchedSessionId(encrypted.session_id));
+        }
+
+        if let Some(claimed_sender_key) = encrypted.sender_key {
+            if Curve25519PublicKey::from_base64(&claimed_sender_key)? != sender_key {
+                return Err(MegolmEnvelopeError::MismatchedSenderKey);
+            }
+        }
+
+        let message = MegolmMessage::from_base64(&encrypted.ciphertext)?;
+        let decrypted = if session.session_config().supports_associated_data() {
+            session.decrypt_with_associated_data(&message, room_id)?
+        } else {
+            session.decrypt(&message)?
+        };
+        let plaintext: PlaintextContent = serde_json::from_slice(&decrypted.plaintext)?;
+
+        if plaintext.room_id != room_id {
+            return Err(MegolmEnvelopeError::MismatchedRoomId {
+                expected: room_id.to_owned(),
+                claimed: plaintext.room_id,
+            });
+        }
+
+        Ok(DecryptedMegolmEnvelope {
+            envelope: Self {
+                event_type: plaintext.event_type,
+                content: plaintext.content,
+                room_id: plaintext.room_id,
+            },
+            message_index: decrypted.message_index,
+            origin: decrypted.origin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{MegolmEnvelope, MegolmEnvelopeError};
+    use crate::{
+        Curve25519PublicKey,
+        megolm::{DecryptionError, GroupSession, InboundGroupSession, SessionConfig},
+    };
+
+    const ROOM_ID: &str = "!room:example.org";
+
+    #[test]
+    fn envelope_roundtrip() {
+        let sender_key = Curve25519PublicKey::from_bytes([1u8; 32]);
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        let mut inbound = InboundGroupSession::from(&session);
+
+        let envelope =
+            MegolmEnvelope::new(ROOM_ID, "m.room.message", r#"{"body": "Hello"}"#).unwrap();
+        let encrypted = envelope.encrypt(&mut session, sender_key);
+
+        let content: serde_json::Value = serde_json::from_str(&encrypted).unwrap();
+        assert_eq!(content["algorithm"], "m.megolm.v1.aes-sha2");
+
+        let decrypted =
+            MegolmEnvelope::decrypt(&mut inbound, ROOM_ID, sender_key, &encrypted).unwrap();
+
+        assert_eq!(decrypted.envelope.event_type(), "m.room.message");
+        assert_eq!(decrypted.envelope.content(), r#"{"body":"Hello"}"#);
+        assert_eq!(decrypted.envelope.room_id(), ROOM_ID);
+        assert_eq!(decrypted.message_index, 0);
+        assert!(decrypted.origin.is_authenticated());
+
+        // The sender key is deprecated and may be missing.
+        let mut encrypted: serde_json::Value =
+            serde_json::from_str(&envelope.encrypt(&mut session, sender_key)).unwrap();
+        encrypted.as_object_mut().unwrap().remove("sender_key");
+
+        let decrypted =
+            MegolmEnvelope::decrypt(&mut inbound, ROOM_ID, sender_key, &encrypted.to_string())
+                .unwrap();
+        assert_eq!(decrypted.message_index, 1);
+    }
+
+    #[test]
+    fn envelope_bindings_are_checked() {
+        let sender_key = Curve25519PublicKey::from_bytes([1u8; 32]);
+        let mut session = GroupSession::new(SessionConfig::version_2());
+        let mut inbound = InboundGroupSession::from(&session);
+        let mut other_inbound =
+            InboundGroupSession::from(&GroupSession::new(SessionConfig::version_2()));
+
+        let envelope = MegolmEnvelope::new(ROOM_ID, "m.room.message", "{}").unwrap();
+        let encrypted = envelope.encrypt(&mut session, sender_key);
+
+        assert_matches!(
+            MegolmEnvelope::decrypt(&mut inbound, "!other:example.org", sender_key, &encrypted),
+            Err(MegolmEnvelopeError::MismatchedRoomId { .. })
+        );
+        assert_matches!(
+            MegolmEnvelope::decrypt(
+                &mut inbound,
+                ROOM_ID,
+                Curve25519PublicKey::from_bytes([2u8; 32]),
+                &encrypted
+            ),
+            Err(MegolmEnvelopeError::MismatchedSenderKey)
+        );
+        assert_matches!(
+            MegolmEnvelope::decrypt(&mut other_inbound, ROOM_ID, sender_key, &encrypted),
+            Err(MegolmEnvelopeError::MismatchedSessionId(_))
+        );
+
+        let olm = encrypted.replace("m.megolm.v2.aes-sha2", "m.olm.v1.curve25519-aes-sha2");
+        assert_matches!(
+            MegolmEnvelope::decrypt(&mut inbound, ROOM_ID, sender_key, &olm),
+            Err(MegolmEnvelopeError::UnsupportedAlgorithm(_))
+        );
+
+        // The algorithm needs to match the version of the session.
+        let downgraded = encrypted.replace("m.megolm.v2.aes-sha2", "m.megolm.v1.aes-sha2");
+        assert_matches!(
+            MegolmEnvelope::decrypt(&mut inbound, ROOM_ID, sender_key, &downgraded),
+            Err(MegolmEnvelopeError::UnsupportedAlgorithm(_))
+        );
+    }
+
+    #[test]
+    fn version_3_envelopes_are_bound_to_the_room() {
+        let sender_key = Curve25519PublicKey::from_bytes([1u8; 32]);
+        let mut session = GroupSession::new(SessionConfig::version_3());
+        let mut inbound = InboundGroupSession::from(&session);
+
+        let envelope = MegolmEnvelope::new(ROOM_ID, "m.room.message", "{}").unwrap();
+        let encrypted = envelope.encrypt(&mut session, sender_key);
+
+        assert_matches!(
+            MegolmEnvelope::decrypt(&mut inbound, "!other:example.org", sender_key, &encrypted),
+            Err(MegolmEnvelopeError::Decryption(DecryptionError::Aead))
+        );
+
+        let decrypted =
+            MegolmEnvelope::decrypt(&mut inbound, ROOM_ID, sender_key, &encrypted).unwrap();
+        assert_eq!(decrypted.envelope.room_id(), ROOM_ID);
+    }
+}
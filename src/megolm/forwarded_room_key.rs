@@ -0,0 +1,357 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{ExportedSessionKey, SessionConfig};
+use crate::{Curve25519PublicKey, Ed25519PublicKey, KeyError};
+
+/// Error type describing failures to parse a [`ForwardedRoomKey`].
+#[derive(Debug, Error)]
+pub enum ForwardedRoomKeyError {
+    /// The forwarded room key wasn't valid JSON or was missing fields.
+    #[error("The forwarded room key couldn't be deserialized: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The forwarded room key is for an algorithm other than a supported
+    /// version of Megolm.
+    #[error("The forwarded room key uses an unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    /// One of the keys of the forwarded room key was invalid.
+    #[error("The forwarded room key contained an invalid key: {0}")]
+    InvalidKey(#[from] KeyError),
+    /// The session ID doesn't match the session the key belongs to.
+    #[error("The session ID of the forwarded room key doesn't match its session key")]
+    MismatchedSessionId,
+}
+
+/// A Megolm room key which was forwarded by another device, as it is sent in
+/// the Matrix `m.forwarded_room_key` event.
+///
+/// Besides the [`ExportedSessionKey`], a forwarded room key describes where
+/// the session originally came from, and the chain of Curve25519 keys of the
+/// devices which forwarded it. Every device which forwards the key appends its
+/// own key to the chain, see [`ForwardedRoomKey::forward()`].
+///
+/// The algorithm of the room key is derived from the [`SessionConfig`] of the
+/// session, every version of Megolm vodozemac supports can be forwarded.
+///
+/// Since the [`ExportedSessionKey`] isn't signed, the original sender can't be
+/// verified, the sender keys are only as trustworthy as the devices in the
+/// forwarding chain.
+pub struct ForwardedRoomKey {
+    room_id: String,
+    sender_key: Curve25519PublicKey,
+    sender_claimed_ed25519_key: Ed25519PublicKey,
+    forwarding_curve25519_key_chain: Vec<Curve25519PublicKey>,
+    session_key: ExportedSessionKey,
+    session_config: SessionConfig,
+}
+
+impl ForwardedRoomKey {
+    /// Create a new [`ForwardedRoomKey`], with an empty forwarding chain.
+    ///
+    /// The `sender_key` and the `sender_claimed_ed25519_key` are the keys of
+    /// the device which created the session, the `session_config` is the
+    /// [`SessionConfig`] of the session.
+    pub const fn new(
+        room_id: String,
+        sender_key: Curve25519PublicKey,
+        sender_claimed_ed25519_key: Ed25519PublicKey,
+        session_key: ExportedSessionKey,
+        session_config: SessionConfig,
+    ) -> Self {
+        Self {
+            room_id,
+            sender_key,
+            sender_claimed_ed25519_key,
+            forwarding_curve25519_key_chain: Vec::new(),
+            session_key,
+            session_config,
+        }
+    }
+
+    /// Prepare the room key to be forwarded by us, appending our own
+    /// Curve25519 identity key to the forwarding chain.
+    pub fn forward(mut self, our_identity_key: Curve25519PublicKey) -> Self {
+        self.forwarding_curve25519_key_chain.push(our_identity_key);
+        self
+    }
+
+    /// Replace the forwarding chain of the room key, used when a room key is
+    /// built from a session which was itself forwarded to us.
+    pub(super) fn with_forwarding_chain(
+        mut self,
+        forwarding_curve25519_key_chain: Vec<Curve25519PublicKey>,
+    ) -> Self {
+        self.forwarding_curve25519_key_chain = forwarding_curve25519_key_chain;
+        self
+    }
+
+    /// The ID of the room the session is used in.
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    /// The Curve25519 key of the device which created the session.
+    pub const fn sender_key(&self) -> Curve25519PublicKey {
+        self.sender_key
+    }
+
+    /// The Ed25519 key the device which created the session claims to own.
+    pub const fn sender_claimed_ed25519_key(&self) -> Ed25519PublicKey {
+        self.sender_claimed_ed25519_key
+    }
+
+    /// The Curve25519 keys of the devices which forwarded the key, in the
+    /// order they forwarded it.
+    pub fn forwarding_curve25519_key_chain(&self) -> &[Curve25519PublicKey] {
+        &self.forwarding_curve25519_key_chain
+    }
+
+    /// The ID of the session the key belongs to.
+    pub fn session_id(&self) -> String {
+        self.session_key.signing_key().to_base64()
+    }
+
+    /// The [`ExportedSessionKey`] of the session.
+    pub const fn session_key(&self) -> &ExportedSessionKey {
+        &self.session_key
+    }
+
+    /// The [`SessionConfig`] of the session, as derived from the algorithm of
+    /// the room key.
+    ///
+    /// The algorithm doesn't include the padding, room keys which were parsed
+    /// from JSON use a config without padding.
+    pub const fn session_config(&self) -> SessionConfig {
+        self.session_config
+    }
+
+    /// Serialize the forwarded room key into the JSON content of an
+    /// `m.forwarded_room_key` event.
+    pub fn to_json(&self) -> String {
+        #[allow(clippy::expect_used)]
+        serde_json::to_string(&ForwardedRoomKeyContent::from(self))
+            .expect("A forwarded room key should always be serializable into JSON")
+    }
+
+    /// Parse and validate the JSON content of an `m.forwarded_room_key` event.
+    ///
+    /// Besides the format of the keys, this checks that the room key is a key
+    /// of a supported version of Megolm and that the session ID belongs to the
+    /// session key.
+    pub fn from_json(json: &str) -> Result<Self, ForwardedRoomKeyError> {
+        serde_json::from_str::<ForwardedRoomKeyContent>(json)?.try_into()
+    }
+}
+
+impl std::fmt::Debug for ForwardedRoomKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ForwardedRoomKey")
+            .field("room_id", &self.room_id)
+            .field("session_id", &self.session_id())
+            .field("session_config", &self.session_config)
+            .field("sender_key", &self.sender_key)
+            .field("forwarding_curve25519_key_chain", &self.forwarding_curve25519_key_chain)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The JSON form of a [`ForwardedRoomKey`], following the Matrix spec.
+#[derive(Serialize, Deserialize)]
+pub(super) struct ForwardedRoomKeyContent {
+    algorithm: String,
+    room_id: String,
+    sender_key: String,
+    sender_claimed_ed25519_key: String,
+    pub(super) forwarding_curve25519_key_chain: Vec<String>,
+    session_id: String,
+    session_key: ExportedSessionKey,
+}
+
+impl From<&ForwardedRoomKey> for ForwardedRoomKeyContent {
+    fn from(key: &ForwardedRoomKey) -> Self {
+        Self {
+            algorithm: key.session_config.algorithm().to_owned(),
+            room_id: key.room_id.clone(),
+            sender_key: key.sender_key.to_base64(),
+            sender_claimed_ed25519_key: key.sender_claimed_ed25519_key.to_base64(),
+            forwarding_curve25519_key_chain: key
+                .forwarding_curve25519_key_chain
+                .iter()
+                .map(Curve25519PublicKey::to_base64)
+                .collect(),
+            session_id: key.session_id(),
+            session_key: ExportedSessionKey {
+                ratchet_index: key.session_key.ratchet_index,
+                ratchet: key.session_key.ratchet.clone(),
+                signing_key: key.session_key.signing_key,
+            },
+        }
+    }
+}
+
+impl TryFrom<ForwardedRoomKeyContent> for ForwardedRoomKey {
+    type Error = ForwardedRoomKeyError;
+
+    fn try_from(content: ForwardedRoomKeyContent) -> Result<Self, Self::Error> {
+        let Some(session_config) = SessionConfig::from_algorithm(&content.algorithm) else {
+            return Err(ForwardedRoomKeyError::UnsupportedAlgorithm(content.algorithm));
+        };
+
+        if Ed25519PublicKey::from_base64(&content.session_id)? != content.session_key.signing_key()
+        {
+            return Err(ForwardedRoomKeyError::MismatchedSessionId);
+        }
+
+        let forwarding_curve25519_key_chain = content
+            .forwarding_curve25519_key_chain
+            .iter()
+            .map(|key| Curve25519PublicKey::from_base64(key))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            room_id: content.room_id,
+            sender_key: Curve25519PublicKey::from_base64(&content.sender_key)?,
+            sender_claimed_ed25519_key: Ed25519PublicKey::from_base64(
+                &content.sender_claimed_ed25519_key,
+            )?,
+            forwarding_curve25519_key_chain,
+            session_key: content.session_key,
+            session_config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+    use serde_json::json;
+
+    use super::{ForwardedRoomKey, ForwardedRoomKeyError};
+    use crate::{
+        Curve25519PublicKey, Ed25519Keypair, PaddingPolicy,
+        megolm::{GroupSession, InboundGroupSession, SessionConfig},
+    };
+
+    fn forwarded_room_key(config: SessionConfig) -> (GroupSession, ForwardedRoomKey) {
+        let session = GroupSession::new(config);
+        let mut inbound = InboundGroupSession::from(&session);
+
+        let key = ForwardedRoomKey::new(
+            "!room:example.org".to_owned(),
+            Curve25519PublicKey::from_bytes([1u8; 32]),
+            Ed25519Keypair::new().public_key(),
+            inbound.export_at(0).unwrap(),
+            config,
+        );
+
+        (session, key)
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let (session, key) = forwarded_room_key(SessionConfig::version_1());
+        let key = key
+            .forward(Curve25519PublicKey::from_bytes([2u8; 32]))
+            .forward(Curve25519PublicKey::from_bytes([3u8; 32]));
+
+        let json: serde_json::Value = serde_json::from_str(&key.to_json()).unwrap();
+        assert_eq!(json["algorithm"], "m.megolm.v1.aes-sha2");
+        assert_eq!(json["session_id"], session.session_id());
+        assert_eq!(json["forwarding_curve25519_key_chain"].as_array().unwrap().len(), 2);
+
+        let parsed = ForwardedRoomKey::from_json(&json.to_string()).unwrap();
+        assert_eq!(parsed.room_id(), "!room:example.org");
+        assert_eq!(parsed.sender_key(), key.sender_key());
+        assert_eq!(parsed.sender_claimed_ed25519_key(), key.sender_claimed_ed25519_key());
+        assert_eq!(parsed.forwarding_curve25519_key_chain(), key.forwarding_curve25519_key_chain());
+        assert_eq!(parsed.session_id(), session.session_id());
+        assert_eq!(parsed.session_config(), SessionConfig::version_1());
+    }
+
+    #[test]
+    fn algorithm_follows_the_session_config() {
+        for (config, algorithm) in [
+            (SessionConfig::version_1(), "m.megolm.v1.aes-sha2"),
+            (SessionConfig::version_2(), "m.megolm.v2.aes-sha2"),
+            (SessionConfig::version_3(), "org.matrix.vodozemac.megolm.v3.chacha20-poly1305"),
+        ] {
+            let (_, key) = forwarded_room_key(config.with_padding(PaddingPolicy::Padme));
+
+            let json: serde_json::Value = serde_json::from_str(&key.to_json()).unwrap();
+            assert_eq!(json["algorithm"], algorithm);
+
+            let parsed = ForwardedRoomKey::from_json(&json.to_string()).unwrap();
+            assert_eq!(parsed.session_config(), config);
+        }
+    }
+
+    #[test]
+    fn invalid_json() {
+        let (_, key) = forwarded_room_key(SessionConfig::version_1());
+        let json: serde_json::Value = serde_json::from_str(&key.to_json()).unwrap();
+
+        let mut invalid = json.clone();
+        invalid["algorithm"] = json!("m.olm.v1.curve25519-aes-sha2");
+        assert_matches!(
+            ForwardedRoomKey::from_json(&invalid.to_string()),
+            Err(ForwardedRoomKeyError::UnsupportedAlgorithm(_))
+        );
+
+        let mut invalid = json.clone();
+        invalid["session_id"] = json!(Ed25519Keypair::new().public_key().to_base64());
+        assert_matches!(
+            ForwardedRoomKey::from_json(&invalid.to_string()),
+            Err(ForwardedRoomKeyError::MismatchedSessionId)
+        );
+
+        let mut invalid = json.clone();
+        invalid["forwarding_curve25519_key_chain"] = json!(["not a key"]);
+        assert_matches!(
+            ForwardedRoomKey::from_json(&invalid.to_string()),
+            Err(ForwardedRoomKeyError::InvalidKey(_))
+        );
+
+        let mut invalid = json;
+        invalid.as_object_mut().unwrap().remove("room_id");
+        assert_matches!(
+            ForwardedRoomKey::from_json(&invalid.to_string()),
+            Err(ForwardedRoomKeyError::Json(_))
+        );
+    }
+
+    #[test]
+    fn import_records_the_forwarding_chain() {
+        let (mut session, key) = forwarded_room_key(SessionConfig::version_1());
+        let forwarder = Curve25519PublicKey::from_bytes([2u8; 32]);
+        let key = key.forward(forwarder);
+
+        let mut inbound = InboundGroupSession::import_forwarded(&key);
+        assert_eq!(inbound.forwarding_curve25519_key_chain(), [forwarder]);
+        assert!(!inbound.origin().is_authenticated());
+
+        let message = session.encrypt("Hello");
+        assert_eq!(inbound.decrypt(&message).unwrap().plaintext, b"Hello");
+
+        let unpickled = InboundGroupSession::from_pickle(inbound.pickle());
+        assert_eq!(unpickled.forwarding_curve25519_key_chain(), [forwarder]);
+
+        // Merging with the original room key leaves us with the shorter chain.
+        let mut original = InboundGroupSession::from(&GroupSession::from_pickle(session.pickle()));
+        let merged = inbound.merge(&mut original).unwrap();
+        assert!(merged.forwarding_curve25519_key_chain().is_empty());
+    }
+}
@@ -12,19 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use super::{
-    SessionConfig, default_config, message::MegolmMessage, ratchet::Ratchet,
-    session_config::Version, session_keys::SessionKey,
+    RotationPolicy, SessionConfig, default_config, message::MegolmMessage, ratchet::Ratchet,
+    session_keys::SessionKey,
 };
 use crate::{
-    PickleError,
-    cipher::Cipher,
+    Curve25519PublicKey, PickleEncoding, PickleError,
     types::Ed25519Keypair,
-    utilities::{pickle, unpickle},
+    utilities::{pickle, pickle_with_encoding, pickle_with_rng, unpickle},
 };
 
+/// Error type for Megolm-based encryption failures.
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    /// Associated data was supplied, but the session uses a Megolm version
+    /// which can't authenticate it.
+    #[error(
+        "The session uses Megolm version {0}, which doesn't support associated data, \
+        version 3 is required"
+    )]
+    AssociatedDataUnsupported(u8),
+}
+
 /// A Megolm group session represents a single sending participant in an
 /// encrypted group communication context containing multiple receiving parties.
 ///
@@ -39,10 +55,21 @@ use crate::{
 /// Such an inbound group session is typically sent by the outbound group
 /// session owner to each of the receiving parties via a secure peer-to-peer
 /// channel (e.g. an Olm channel).
+///
+/// The group session keeps track of the recipients it was shared with and of
+/// when it should be rotated, see [`GroupSession::share_with()`] and
+/// [`GroupSession::needs_rotation()`].
 pub struct GroupSession {
     ratchet: Ratchet,
     signing_key: Ed25519Keypair,
     config: SessionConfig,
+    /// The time the session was created at, in milliseconds since the Unix
+    /// epoch.
+    creation_time: Option<u64>,
+    rotation_policy: RotationPolicy,
+    /// The message index at which the session was first shared with each of
+    /// the recipients, keyed by their Curve25519 identity key.
+    shared_with: HashMap<Curve25519PublicKey, u32>,
 }
 
 impl Default for GroupSession {
@@ -55,8 +82,40 @@ impl GroupSession {
     /// Construct a new group session, with a random ratchet state and signing
     /// key pair.
     pub fn new(config: SessionConfig) -> Self {
-        let signing_key = Ed25519Keypair::new();
-        Self { signing_key, ratchet: Ratchet::new(), config }
+        Self::new_with_rng(config, &mut thread_rng())
+    }
+
+    /// Construct a new group session, with a ratchet state and signing key
+    /// pair generated by the given random number generator.
+    pub fn new_with_rng(config: SessionConfig, rng: &mut impl CryptoRngCore) -> Self {
+        let signing_key = Ed25519Keypair::new_with_rng(rng);
+
+        Self {
+            signing_key,
+            ratchet: Ratchet::new(rng),
+            config,
+            creation_time: None,
+            rotation_policy: Default::default(),
+            shared_with: Default::default(),
+        }
+    }
+
+    /// Construct a new group session, recording `now`, in milliseconds since
+    /// the Unix epoch, as its creation time.
+    ///
+    /// The library doesn't have access to a clock on all platforms, the
+    /// creation time needs to be known for [`GroupSession::needs_rotation()`]
+    /// to take the age of the session into account.
+    pub fn new_at(config: SessionConfig, now: u64) -> Self {
+        Self::new_at_with_rng(config, now, &mut thread_rng())
+    }
+
+    /// Construct a new group session created at `now`, with a ratchet state
+    /// and signing key pair generated by the given random number generator.
+    ///
+    /// See [`GroupSession::new_at()`] for more details.
+    pub fn new_at_with_rng(config: SessionConfig, now: u64, rng: &mut impl CryptoRngCore) -> Self {
+        Self { creation_time: Some(now), ..Self::new_with_rng(config, rng) }
     }
 
     /// Returns the globally unique session ID, in base64-encoded form.
@@ -82,27 +141,126 @@ impl GroupSession {
         self.config
     }
 
+    /// The time the session was created at, in milliseconds since the Unix
+    /// epoch, if the session was constructed using [`GroupSession::new_at()`].
+    pub const fn creation_time(&self) -> Option<u64> {
+        self.creation_time
+    }
+
+    /// Get the [`RotationPolicy`] of this [`GroupSession`].
+    pub const fn rotation_policy(&self) -> RotationPolicy {
+        self.rotation_policy
+    }
+
+    /// Change the [`RotationPolicy`] of this [`GroupSession`].
+    pub const fn set_rotation_policy(&mut self, rotation_policy: RotationPolicy) {
+        self.rotation_policy = rotation_policy;
+    }
+
+    /// Should the session be replaced by a new one, according to its
+    /// [`RotationPolicy`].
+    ///
+    /// The session needs to be rotated once it has encrypted the maximal number
+    /// of messages, or once it's older than the maximal age at the given time
+    /// `now`, in milliseconds since the Unix epoch. The age is only taken into
+    /// account if the creation time of the session is known.
+    pub fn needs_rotation(&self, now: u64) -> bool {
+        let max_age = u64::try_from(self.rotation_policy.max_age().as_millis()).unwrap_or(u64::MAX);
+
+        let too_many_messages =
+            u64::from(self.message_index()) >= self.rotation_policy.max_messages();
+        let too_old = self
+            .creation_time
+            .is_some_and(|creation_time| now.saturating_sub(creation_time) >= max_age);
+
+        too_many_messages || too_old
+    }
+
+    /// Export the group session into a [`SessionKey`] for the given recipient,
+    /// recording the current message index as the index the session was shared
+    /// with the recipient at.
+    ///
+    /// See [`GroupSession::session_key()`] for more details.
+    pub fn share_with(&mut self, recipient: Curve25519PublicKey) -> SessionKey {
+        self.mark_as_shared(recipient, self.message_index());
+        self.session_key()
+    }
+
+    /// Record that the session was shared with the given recipient, identified
+    /// by its Curve25519 identity key, at the given message index.
+    ///
+    /// If the session was already shared with the recipient, the lower of the
+    /// two message indices is kept.
+    pub fn mark_as_shared(&mut self, recipient: Curve25519PublicKey, message_index: u32) {
+        self.shared_with
+            .entry(recipient)
+            .and_modify(|index| *index = (*index).min(message_index))
+            .or_insert(message_index);
+    }
+
+    /// The message index the session was first shared with the given recipient
+    /// at, `None` if it wasn't shared with the recipient yet.
+    ///
+    /// When the recipient asks for the session again, it should only be given
+    /// the session starting from this index, e.g. using
+    /// [`InboundGroupSession::export_at()`].
+    ///
+    /// [`InboundGroupSession::export_at()`]: super::InboundGroupSession::export_at
+    pub fn shared_message_index(&self, recipient: &Curve25519PublicKey) -> Option<u32> {
+        self.shared_with.get(recipient).copied()
+    }
+
+    /// The recipients the session was shared with, along with the message
+    /// index it was first shared at.
+    pub fn recipients(&self) -> impl Iterator<Item = (&Curve25519PublicKey, u32)> {
+        self.shared_with.iter().map(|(recipient, index)| (recipient, *index))
+    }
+
     /// Encrypt the given `plaintext` with the group session.
     ///
     /// The resulting ciphertext is MAC-ed, then signed with the group session's
     /// Ed25519 key pair and finally base64-encoded.
     pub fn encrypt(&mut self, plaintext: impl AsRef<[u8]>) -> MegolmMessage {
-        let cipher = Cipher::new_megolm(self.ratchet.as_bytes());
-
-        let message = match self.config.version {
-            Version::V1 => MegolmMessage::encrypt_truncated_mac(
-                self.message_index(),
-                &cipher,
-                &self.signing_key,
-                plaintext.as_ref(),
-            ),
-            Version::V2 => MegolmMessage::encrypt_full_mac(
-                self.message_index(),
-                &cipher,
-                &self.signing_key,
-                plaintext.as_ref(),
-            ),
-        };
+        self.encrypt_helper(plaintext.as_ref(), &[])
+    }
+
+    /// Encrypt the given `plaintext` with the group session, binding the
+    /// message to the given `associated_data`.
+    ///
+    /// The associated data isn't part of the message, the receiving side needs
+    /// to supply the same associated data to
+    /// [`InboundGroupSession::decrypt_with_associated_data()`], otherwise
+    /// decryption fails. Binding a message to e.g. the room ID and the sender
+    /// prevents it from being replayed in another room or by another sender.
+    ///
+    /// Only sessions using [`SessionConfig::version_3()`] can authenticate
+    /// associated data, an error is returned for other versions unless the
+    /// associated data is empty.
+    ///
+    /// [`InboundGroupSession::decrypt_with_associated_data()`]: super::InboundGroupSession::decrypt_with_associated_data
+    pub fn encrypt_with_associated_data(
+        &mut self,
+        plaintext: impl AsRef<[u8]>,
+        associated_data: impl AsRef<[u8]>,
+    ) -> Result<MegolmMessage, EncryptionError> {
+        let associated_data = associated_data.as_ref();
+
+        if associated_data.is_empty() || self.config.supports_associated_data() {
+            Ok(self.encrypt_helper(plaintext.as_ref(), associated_data))
+        } else {
+            Err(EncryptionError::AssociatedDataUnsupported(self.config.version()))
+        }
+    }
+
+    fn encrypt_helper(&mut self, plaintext: &[u8], associated_data: &[u8]) -> MegolmMessage {
+        let message = MegolmMessage::encrypt_with_config(
+            self.message_index(),
+            self.ratchet.as_bytes(),
+            &self.signing_key,
+            plaintext,
+            associated_data,
+            &self.config,
+        );
 
         self.ratchet.advance();
 
@@ -130,10 +288,26 @@ impl GroupSession {
     /// Convert the group session into a struct which implements
     /// [`serde::Serialize`] and [`serde::Deserialize`].
     pub fn pickle(&self) -> GroupSessionPickle {
+        let mut shared_with: Vec<_> = self
+            .shared_with
+            .iter()
+            .map(|(recipient, message_index)| SharedWithPickle {
+                recipient: *recipient,
+                message_index: *message_index,
+            })
+            .collect();
+        // Sort the recipients so pickling the same session always produces the
+        // same pickle.
+        shared_with.sort_by_key(|s| *s.recipient.as_bytes());
+
         GroupSessionPickle {
             ratchet: self.ratchet.clone(),
             signing_key: self.signing_key.clone(),
             config: self.config,
+            creation_time: self.creation_time,
+            rotation_policy: (self.rotation_policy != RotationPolicy::default())
+                .then_some(self.rotation_policy),
+            shared_with,
         }
     }
 
@@ -190,7 +364,14 @@ mod libolm_compat {
             let signing_key =
                 Ed25519Keypair::from_expanded_key(&pickle.ed25519_keypair.private_key)?;
 
-            Ok(Self { ratchet, signing_key, config: SessionConfig::version_1() })
+            Ok(Self {
+                ratchet,
+                signing_key,
+                config: SessionConfig::version_1(),
+                creation_time: None,
+                rotation_policy: Default::default(),
+                shared_with: Default::default(),
+            })
         }
     }
 }
@@ -203,14 +384,59 @@ pub struct GroupSessionPickle {
     signing_key: Ed25519Keypair,
     #[serde(default = "default_config")]
     config: SessionConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    creation_time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotation_policy: Option<RotationPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shared_with: Vec<SharedWithPickle>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedWithPickle {
+    recipient: Curve25519PublicKey,
+    message_index: u32,
 }
 
 impl GroupSessionPickle {
+    const PICKLE_TYPE: &'static str = "GroupSession";
+
     /// Serialize and encrypt the pickle using the given key.
     ///
     /// This is the inverse of [`GroupSessionPickle::from_encrypted`].
     pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
-        pickle(&self, pickle_key)
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`GroupSessionPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, encoding it using
+    /// the given [`PickleEncoding`].
+    ///
+    /// This is the inverse of [`GroupSessionPickle::from_encrypted`], which
+    /// detects the encoding automatically.
+    pub fn encrypt_with_encoding(self, pickle_key: &[u8; 32], encoding: PickleEncoding) -> String {
+        pickle_with_encoding(&self, pickle_key, Self::PICKLE_TYPE, encoding)
+    }
+
+    /// Serialize and encrypt the pickle using the given key and
+    /// [`PickleEncoding`], generating the salt of the encrypted pickle using
+    /// the given random number generator.
+    ///
+    /// See [`GroupSessionPickle::encrypt_with_encoding`] for more details.
+    pub fn encrypt_with_encoding_and_rng(
+        self,
+        pickle_key: &[u8; 32],
+        encoding: PickleEncoding,
+        rng: &mut impl CryptoRngCore,
+    ) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, encoding, rng)
     }
 
     /// Obtain a pickle from a ciphertext by decrypting and deserializing using
@@ -218,19 +444,38 @@ impl GroupSessionPickle {
     ///
     /// This is the inverse of [`GroupSessionPickle::encrypt`].
     pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
-        unpickle(ciphertext, pickle_key)
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
     }
 }
 
 impl From<GroupSessionPickle> for GroupSession {
     fn from(pickle: GroupSessionPickle) -> Self {
-        Self { ratchet: pickle.ratchet, signing_key: pickle.signing_key, config: pickle.config }
+        Self {
+            ratchet: pickle.ratchet,
+            signing_key: pickle.signing_key,
+            config: pickle.config,
+            creation_time: pickle.creation_time,
+            rotation_policy: pickle.rotation_policy.unwrap_or_default(),
+            shared_with: pickle
+                .shared_with
+                .into_iter()
+                .map(|s| (s.recipient, s.message_index))
+                .collect(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::megolm::{GroupSession, SessionConfig};
+    use std::time::Duration;
+
+    use assert_matches2::assert_matches;
+
+    use super::{EncryptionError, GroupSessionPickle};
+    use crate::{
+        Curve25519PublicKey,
+        megolm::{GroupSession, RotationPolicy, SessionConfig},
+    };
 
     #[test]
     fn create_with_session_config() {
@@ -242,5 +487,94 @@ mod test {
             GroupSession::new(SessionConfig::version_2()).session_config(),
             SessionConfig::version_2()
         );
+        assert_eq!(
+            GroupSession::new(SessionConfig::version_3()).session_config(),
+            SessionConfig::version_3()
+        );
+    }
+
+    #[test]
+    fn associated_data_requires_version_3() {
+        let mut session = GroupSession::new(SessionConfig::version_2());
+
+        assert_matches!(
+            session.encrypt_with_associated_data("It's a secret to everybody", "!room:server"),
+            Err(EncryptionError::AssociatedDataUnsupported(2))
+        );
+        session
+            .encrypt_with_associated_data("It's a secret to everybody", "")
+            .expect("Empty associated data should be accepted by any version");
+
+        GroupSession::new(SessionConfig::version_3())
+            .encrypt_with_associated_data("It's a secret to everybody", "!room:server")
+            .expect("Version 3 should accept associated data");
+    }
+
+    #[test]
+    fn rotation() {
+        let policy = RotationPolicy::new(Duration::from_secs(60), 2);
+
+        // Without a creation time only the message count is taken into account.
+        let mut session = GroupSession::new(SessionConfig::version_2());
+        session.set_rotation_policy(policy);
+        assert!(!session.needs_rotation(u64::MAX));
+
+        let mut session = GroupSession::new_at(SessionConfig::version_2(), 1_000);
+        session.set_rotation_policy(policy);
+        assert_eq!(session.creation_time(), Some(1_000));
+        assert!(!session.needs_rotation(60_999));
+        assert!(session.needs_rotation(61_000));
+
+        session.encrypt("First");
+        assert!(!session.needs_rotation(1_000));
+        session.encrypt("Second");
+        assert!(session.needs_rotation(1_000));
+    }
+
+    #[test]
+    fn shared_message_indices() {
+        let mut session = GroupSession::new(SessionConfig::version_2());
+        let alice = Curve25519PublicKey::from_bytes([1u8; 32]);
+        let bob = Curve25519PublicKey::from_bytes([2u8; 32]);
+
+        let session_key = session.share_with(alice);
+        assert_eq!(session_key.signing_key().to_base64(), session.session_id());
+
+        session.encrypt("Hello");
+        session.share_with(bob);
+        session.share_with(alice);
+
+        assert_eq!(session.shared_message_index(&alice), Some(0));
+        assert_eq!(session.shared_message_index(&bob), Some(1));
+        assert_eq!(session.recipients().count(), 2);
+
+        session.mark_as_shared(bob, 0);
+        assert_eq!(session.shared_message_index(&bob), Some(0));
+    }
+
+    #[test]
+    fn pickle_roundtrip() {
+        // Sessions without any rotation or sharing state pickle like they did
+        // before these existed.
+        let session = GroupSession::new(SessionConfig::version_2());
+        let pickle = serde_json::to_value(session.pickle()).unwrap();
+        assert!(pickle.get("creation_time").is_none());
+        assert!(pickle.get("rotation_policy").is_none());
+        assert!(pickle.get("shared_with").is_none());
+
+        let mut session = GroupSession::new_at(SessionConfig::version_2(), 1_000);
+        session.set_rotation_policy(RotationPolicy::new(Duration::from_secs(60), 2));
+        session.share_with(Curve25519PublicKey::from_bytes([1u8; 32]));
+
+        let pickle = serde_json::to_string(&session.pickle()).unwrap();
+        let pickle: GroupSessionPickle = serde_json::from_str(&pickle).unwrap();
+        let unpickled = GroupSession::from_pickle(pickle);
+
+        assert_eq!(unpickled.creation_time(), Some(1_000));
+        assert_eq!(unpickled.rotation_policy(), session.rotation_policy());
+        assert_eq!(
+            unpickled.shared_message_index(&Curve25519PublicKey::from_bytes([1u8; 32])),
+            Some(0)
+        );
     }
 }
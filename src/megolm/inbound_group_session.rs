@@ -16,22 +16,24 @@ use std::cmp::Ordering;
 
 use aes::cipher::block_padding::UnpadError;
 use hmac::digest::MacError;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use subtle::ConstantTimeEq;
 use thiserror::Error;
 
 use super::{
-    GroupSession, SessionConfig, default_config,
-    message::MegolmMessage,
+    ForwardedRoomKey, GroupSession, SessionConfig, default_config,
+    message::{MegolmMessage, message_version},
     ratchet::Ratchet,
     session_config::Version,
     session_keys::{ExportedSessionKey, SessionKey},
 };
 use crate::{
-    PickleError,
-    cipher::{Cipher, Mac, MessageMac},
-    types::{Ed25519PublicKey, SignatureError},
-    utilities::{base64_encode, pickle, unpickle},
+    PickleEncoding, PickleError,
+    cipher::{Cipher, Mac, MessageMac, aead::AeadCipher},
+    padding::unpad,
+    types::{Curve25519PublicKey, Ed25519PublicKey, SignatureError},
+    utilities::{base64_encode, pickle, pickle_with_encoding, pickle_with_rng, unpickle},
 };
 
 /// The result of a comparison between two [`InboundGroupSession`] types.
@@ -50,6 +52,51 @@ pub enum SessionOrdering {
     Unconnected,
 }
 
+/// Where the key material of an [`InboundGroupSession`] came from.
+///
+/// Only a [`SessionKey`] is signed by the creator of the [`GroupSession`], an
+/// [`ExportedSessionKey`] could have been created by anybody who knows the
+/// ratchet. Messages decrypted by sessions that were only ever imported are
+/// therefore not authenticated, even if their signature is valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionOrigin {
+    /// The session was created from a signed [`SessionKey`] using
+    /// [`InboundGroupSession::new()`].
+    SessionKey,
+    /// The session was created from an [`ExportedSessionKey`] using
+    /// [`InboundGroupSession::import()`].
+    ExportedSessionKey,
+    /// The session was created by [`InboundGroupSession::merge()`] or
+    /// upgraded using [`InboundGroupSession::upgrade()`].
+    Merged {
+        /// At least one of the merged sessions was created from a signed
+        /// [`SessionKey`].
+        authenticated: bool,
+    },
+}
+
+impl SessionOrigin {
+    /// Was the key material of the session, directly or through a merge,
+    /// received in a [`SessionKey`] signed by the creator of the session.
+    pub const fn is_authenticated(&self) -> bool {
+        match self {
+            SessionOrigin::SessionKey => true,
+            SessionOrigin::ExportedSessionKey => false,
+            SessionOrigin::Merged { authenticated } => *authenticated,
+        }
+    }
+
+    /// The origin of a session whose only record is the `signing_key_verified`
+    /// flag, as is the case for libolm pickles and older vodozemac pickles.
+    const fn from_verified_flag(signing_key_verified: bool) -> Self {
+        if signing_key_verified {
+            SessionOrigin::SessionKey
+        } else {
+            SessionOrigin::ExportedSessionKey
+        }
+    }
+}
+
 /// Error type for Megolm-based decryption failures.
 #[derive(Debug, Error)]
 pub enum DecryptionError {
@@ -78,6 +125,29 @@ pub enum DecryptionError {
         first known index {0}, index of the message {1}"
     )]
     UnknownMessageIndex(u32, u32),
+
+    /// The message was marked as padded, but the plaintext didn't contain
+    /// valid length-hiding padding.
+    #[error("Failed decrypting Megolm message, invalid length-hiding padding")]
+    InvalidLengthHidingPadding,
+
+    /// The version of the message doesn't match the Megolm version the session
+    /// is configured to use.
+    #[error("Failed decrypting Megolm message, invalid message version: expected {0}, got {1}")]
+    InvalidMessageVersion(u8, u8),
+
+    /// The message was encrypted using an AEAD and either its authentication
+    /// tag was invalid or the associated data didn't match.
+    #[error("Failed decrypting Megolm message, invalid authentication tag or associated data")]
+    Aead,
+
+    /// Associated data was supplied, but the session uses a Megolm version
+    /// which can't authenticate it.
+    #[error(
+        "The session uses Megolm version {0}, which doesn't support associated data, \
+        version 3 is required"
+    )]
+    AssociatedDataUnsupported(u8),
 }
 
 /// A Megolm inbound group session represents a single receiving participant in
@@ -91,7 +161,9 @@ pub struct InboundGroupSession {
     initial_ratchet: Ratchet,
     latest_ratchet: Ratchet,
     signing_key: Ed25519PublicKey,
-    signing_key_verified: bool,
+    origin: SessionOrigin,
+    forwarding_chain: Vec<Curve25519PublicKey>,
+    shareable: bool,
     config: SessionConfig,
 }
 
@@ -103,6 +175,11 @@ pub struct DecryptedMessage {
     /// The message index, used to detect replay attacks. Each plaintext message
     /// should be encrypted with a unique message index per session.
     pub message_index: u32,
+    /// The origin of the session at the time the message was decrypted.
+    ///
+    /// The message should only be considered to be authentic if
+    /// [`SessionOrigin::is_authenticated()`] returns `true`.
+    pub origin: SessionOrigin,
 }
 
 impl InboundGroupSession {
@@ -120,7 +197,9 @@ impl InboundGroupSession {
             initial_ratchet,
             latest_ratchet,
             signing_key: key.session_key.signing_key,
-            signing_key_verified: true,
+            origin: SessionOrigin::SessionKey,
+            forwarding_chain: Vec::new(),
+            shareable: false,
             config: session_config,
         }
     }
@@ -144,11 +223,28 @@ impl InboundGroupSession {
             initial_ratchet,
             latest_ratchet,
             signing_key: session_key.signing_key,
-            signing_key_verified: false,
+            origin: SessionOrigin::ExportedSessionKey,
+            forwarding_chain: Vec::new(),
+            shareable: false,
             config: session_config,
         }
     }
 
+    /// Creates a new [`InboundGroupSession`] from a [`ForwardedRoomKey`]
+    /// received over an authenticated channel.
+    ///
+    /// This behaves like [`InboundGroupSession::import()`], but additionally
+    /// records the forwarding chain of the room key, which can later be
+    /// retrieved using
+    /// [`InboundGroupSession::forwarding_curve25519_key_chain()`]. The session
+    /// uses the [`SessionConfig`] of the room key.
+    pub fn import_forwarded(room_key: &ForwardedRoomKey) -> Self {
+        Self {
+            forwarding_chain: room_key.forwarding_curve25519_key_chain().to_vec(),
+            ..Self::import(room_key.session_key(), room_key.session_config())
+        }
+    }
+
     /// Retrieves the unique ID of this session.
     ///
     /// This ID is the [`Ed25519PublicKey`] encoded in Base64 format.
@@ -156,6 +252,11 @@ impl InboundGroupSession {
         base64_encode(self.signing_key.as_bytes())
     }
 
+    /// The public key messages of this session are signed with.
+    pub(super) const fn signing_key(&self) -> &Ed25519PublicKey {
+        &self.signing_key
+    }
+
     /// Check if two [`InboundGroupSession`]s are the same.
     ///
     /// An [`InboundGroupSession`] could be received multiple times with varying
@@ -182,9 +283,10 @@ impl InboundGroupSession {
         // After that we compare the raw ratchet bytes in constant time.
 
         #[allow(clippy::unreachable)]
-        if self.config != other.config || self.signing_key != other.signing_key {
-            // Short circuit if session configs differ or the signing keys
-            // differ. This is comparing public key material.
+        if self.config.version != other.config.version || self.signing_key != other.signing_key {
+            // Short circuit if session versions differ or the signing keys
+            // differ. This is comparing public key material. The padding policy
+            // only matters to the sender, so it's ignored.
             false
         } else if let Some(ratchet) = self.find_ratchet(other.first_known_index()) {
             ratchet.ct_eq(&other.initial_ratchet).into()
@@ -230,6 +332,9 @@ impl InboundGroupSession {
     /// with ratchet state B, provided S1 and S2 connect with each other
     /// (meaning they are the same session, just at different ratchet indices).
     ///
+    /// The merged session keeps the [shareable] flag of this session, the
+    /// flag of the other session is ignored.
+    ///
     /// Returns `Some(session)` if the sessions could be merged, i.e. they are
     /// considered to be connected and `None` otherwise.
     ///
@@ -251,6 +356,8 @@ impl InboundGroupSession {
     /// assert_eq!(merged.compare(&mut second_session), SessionOrdering::Better);
     /// assert_eq!(merged.compare(&mut first_session), SessionOrdering::Equal);
     /// ```
+    ///
+    /// [shareable]: InboundGroupSession::is_shareable
     pub fn merge(&mut self, other: &mut InboundGroupSession) -> Option<InboundGroupSession> {
         let best_ratchet = match self.compare(other) {
             SessionOrdering::Equal | SessionOrdering::Better => Some(self.initial_ratchet.clone()),
@@ -262,11 +369,90 @@ impl InboundGroupSession {
             initial_ratchet: best_ratchet.clone(),
             latest_ratchet: best_ratchet,
             signing_key: self.signing_key,
-            signing_key_verified: self.signing_key_verified || other.signing_key_verified,
+            origin: SessionOrigin::Merged {
+                authenticated: self.origin.is_authenticated() || other.origin.is_authenticated(),
+            },
+            // Every forwarding device could have tampered with the sender
+            // information of the room key, keep the chain with fewer hops.
+            forwarding_chain: if other.forwarding_chain.len() < self.forwarding_chain.len() {
+                other.forwarding_chain.clone()
+            } else {
+                self.forwarding_chain.clone()
+            },
+            // Whether a session may be shared is decided by the owner of this
+            // session, a copy received from elsewhere can't change it.
+            shareable: self.shareable,
             config: self.config,
         })
     }
 
+    /// Upgrade the session using a signed [`SessionKey`] of the same session.
+    ///
+    /// This is useful if the session was imported from an
+    /// [`ExportedSessionKey`], e.g. from a key backup, and the `m.room_key`
+    /// event of the sender arrives later on. The session is [merged] with the
+    /// session the [`SessionKey`] describes and is authenticated afterwards,
+    /// keeping the lowest first known index of the two.
+    ///
+    /// Returns `false`, leaving the session untouched, if the [`SessionKey`]
+    /// doesn't belong to this session.
+    ///
+    /// [merged]: InboundGroupSession::merge
+    pub fn upgrade(&mut self, session_key: &SessionKey) -> bool {
+        let mut other = InboundGroupSession::new(session_key, self.config);
+
+        if let Some(merged) = self.merge(&mut other) {
+            *self = merged;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Get the [`SessionConfig`] that this [`InboundGroupSession`] is
+    /// configured to use.
+    pub const fn session_config(&self) -> SessionConfig {
+        self.config
+    }
+
+    /// Where the key material of the session came from.
+    ///
+    /// Messages decrypted by this session are only authenticated if
+    /// [`SessionOrigin::is_authenticated()`] returns `true`.
+    pub const fn origin(&self) -> SessionOrigin {
+        self.origin
+    }
+
+    /// The Curve25519 keys of the devices which forwarded the key material of
+    /// the session to us, in the order they forwarded it.
+    ///
+    /// The chain is empty unless the session was created using
+    /// [`InboundGroupSession::import_forwarded()`].
+    pub fn forwarding_curve25519_key_chain(&self) -> &[Curve25519PublicKey] {
+        &self.forwarding_chain
+    }
+
+    /// Whether the session may be shared with users joining the room later
+    /// on, as part of a room key bundle.
+    ///
+    /// Sessions aren't shareable unless they were marked as such using
+    /// [`InboundGroupSession::set_shareable()`], this includes sessions which
+    /// were imported from a [`RoomKeyBundle`].
+    ///
+    /// [`RoomKeyBundle`]: super::RoomKeyBundle
+    pub const fn is_shareable(&self) -> bool {
+        self.shareable
+    }
+
+    /// Mark the session as shareable, or not shareable, with users joining the
+    /// room later on.
+    ///
+    /// This usually mirrors the history visibility of the room at the time
+    /// the session was received.
+    pub const fn set_shareable(&mut self, shareable: bool) {
+        self.shareable = shareable;
+    }
+
     /// Retrieves the first known message index for this
     /// [`InboundGroupSession`].
     ///
@@ -332,22 +518,44 @@ impl InboundGroupSession {
         }
     }
 
-    fn verify_mac(&self, cipher: &Cipher, message: &MegolmMessage) -> Result<(), DecryptionError> {
-        match self.config.version {
-            Version::V1 => {
-                if let MessageMac::Truncated(m) = &message.mac {
-                    Ok(cipher.verify_truncated_mac(&message.to_mac_bytes(), m)?)
-                } else {
-                    Err(DecryptionError::InvalidMACLength(Mac::TRUNCATED_LEN, Mac::LENGTH))
-                }
+    fn decrypt_ciphertext(
+        ratchet: &Ratchet,
+        config: &SessionConfig,
+        message: &MegolmMessage,
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>, DecryptionError> {
+        match (config.version, &message.mac) {
+            (Version::V1, MessageMac::Truncated(m)) => {
+                let cipher = Cipher::new_megolm(ratchet.as_bytes());
+                cipher.verify_truncated_mac(&message.to_mac_bytes(), m)?;
+
+                Ok(cipher.decrypt(&message.ciphertext)?)
+            }
+            (Version::V2, MessageMac::Full(m)) => {
+                let cipher = Cipher::new_megolm(ratchet.as_bytes());
+                cipher.verify_mac(&message.to_mac_bytes(), m)?;
+
+                Ok(cipher.decrypt(&message.ciphertext)?)
             }
-            Version::V2 => {
-                if let MessageMac::Full(m) = &message.mac {
-                    Ok(cipher.verify_mac(&message.to_mac_bytes(), m)?)
-                } else {
-                    Err(DecryptionError::InvalidMACLength(Mac::LENGTH, Mac::TRUNCATED_LEN))
-                }
+            (Version::V3, MessageMac::Aead) => {
+                let cipher = AeadCipher::new_megolm(ratchet.as_bytes());
+
+                cipher
+                    .decrypt(&message.ciphertext, &message.to_associated_data(associated_data))
+                    .map_err(|_| DecryptionError::Aead)
+            }
+            (Version::V1, MessageMac::Full(_)) => {
+                Err(DecryptionError::InvalidMACLength(Mac::TRUNCATED_LEN, Mac::LENGTH))
             }
+            (Version::V2, MessageMac::Truncated(_)) => {
+                Err(DecryptionError::InvalidMACLength(Mac::LENGTH, Mac::TRUNCATED_LEN))
+            }
+            // Mixing the MAC based versions with the AEAD based one is never
+            // allowed, this prevents downgrades in both directions.
+            (version, _) => Err(DecryptionError::InvalidMessageVersion(
+                message_version(version),
+                message.version,
+            )),
         }
     }
 
@@ -357,20 +565,66 @@ impl InboundGroupSession {
     /// Returns a [`DecryptedMessage`] containing the plaintext and the message
     /// index, which indicates the ratchet position at which the message was
     /// encrypted.
+    ///
+    /// Messages which were bound to associated data need to be decrypted using
+    /// [`InboundGroupSession::decrypt_with_associated_data()`] instead.
     pub fn decrypt(
         &mut self,
         message: &MegolmMessage,
     ) -> Result<DecryptedMessage, DecryptionError> {
-        self.signing_key.verify(&message.to_signature_bytes(), &message.signature)?;
+        self.decrypt_helper(message, &[])
+    }
 
-        if let Some(ratchet) = self.find_ratchet(message.message_index) {
-            let cipher = Cipher::new_megolm(ratchet.as_bytes());
+    /// Decrypts the provided [`MegolmMessage`] using this
+    /// [`InboundGroupSession`], checking that the message was bound to the
+    /// given `associated_data`.
+    ///
+    /// The associated data needs to be the same as the one the sender passed
+    /// to [`GroupSession::encrypt_with_associated_data()`], if it differs,
+    /// e.g. because the message was replayed in another room, decryption fails.
+    ///
+    /// Only sessions using [`SessionConfig::version_3()`] can authenticate
+    /// associated data, an error is returned for other versions unless the
+    /// associated data is empty.
+    pub fn decrypt_with_associated_data(
+        &mut self,
+        message: &MegolmMessage,
+        associated_data: impl AsRef<[u8]>,
+    ) -> Result<DecryptedMessage, DecryptionError> {
+        let associated_data = associated_data.as_ref();
 
-            self.verify_mac(&cipher, message)?;
+        if associated_data.is_empty() || self.config.supports_associated_data() {
+            self.decrypt_helper(message, associated_data)
+        } else {
+            Err(DecryptionError::AssociatedDataUnsupported(self.config.version()))
+        }
+    }
 
-            let plaintext = cipher.decrypt(&message.ciphertext)?;
+    fn decrypt_helper(
+        &mut self,
+        message: &MegolmMessage,
+        associated_data: &[u8],
+    ) -> Result<DecryptedMessage, DecryptionError> {
+        self.signing_key.verify(&message.to_signature_bytes(), &message.signature)?;
+
+        let config = self.config;
 
-            Ok(DecryptedMessage { plaintext, message_index: message.message_index })
+        if let Some(ratchet) = self.find_ratchet(message.message_index) {
+            let plaintext = Self::decrypt_ciphertext(ratchet, &config, message, associated_data)?;
+
+            // The padded flag is covered by the MAC and the signature, so only
+            // the sender could have set it.
+            let plaintext = if message.padded {
+                unpad(plaintext).map_err(|_| DecryptionError::InvalidLengthHidingPadding)?
+            } else {
+                plaintext
+            };
+
+            Ok(DecryptedMessage {
+                plaintext,
+                message_index: message.message_index,
+                origin: self.origin,
+            })
         } else {
             Err(DecryptionError::UnknownMessageIndex(
                 self.initial_ratchet.index(),
@@ -414,7 +668,10 @@ impl InboundGroupSession {
         InboundGroupSessionPickle {
             initial_ratchet: self.initial_ratchet.clone(),
             signing_key: self.signing_key,
-            signing_key_verified: self.signing_key_verified,
+            signing_key_verified: self.origin.is_authenticated(),
+            origin: Some(self.origin),
+            forwarding_chain: self.forwarding_chain.clone(),
+            shareable: self.shareable,
             config: self.config,
         }
     }
@@ -449,7 +706,7 @@ mod libolm_compat {
     use matrix_pickle::Decode;
     use zeroize::{Zeroize, ZeroizeOnDrop};
 
-    use super::InboundGroupSession;
+    use super::{InboundGroupSession, SessionOrigin};
     use crate::{
         Ed25519PublicKey,
         megolm::{SessionConfig, libolm::LibolmRatchetPickle},
@@ -481,7 +738,9 @@ mod libolm_compat {
                 initial_ratchet,
                 latest_ratchet,
                 signing_key,
-                signing_key_verified,
+                origin: SessionOrigin::from_verified_flag(signing_key_verified),
+                forwarding_chain: Vec::new(),
+                shareable: false,
                 config: SessionConfig::version_1(),
             })
         }
@@ -495,18 +754,57 @@ mod libolm_compat {
 pub struct InboundGroupSessionPickle {
     initial_ratchet: Ratchet,
     signing_key: Ed25519PublicKey,
-    #[allow(dead_code)]
     signing_key_verified: bool,
+    #[serde(default)]
+    origin: Option<SessionOrigin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    forwarding_chain: Vec<Curve25519PublicKey>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    shareable: bool,
     #[serde(default = "default_config")]
     config: SessionConfig,
 }
 
 impl InboundGroupSessionPickle {
+    const PICKLE_TYPE: &'static str = "InboundGroupSession";
+
     /// Serialize and encrypt the pickle using the given key.
     ///
     /// This is the inverse of [`InboundGroupSessionPickle::from_encrypted`].
     pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
-        pickle(&self, pickle_key)
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`InboundGroupSessionPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, encoding it using
+    /// the given [`PickleEncoding`].
+    ///
+    /// This is the inverse of [`InboundGroupSessionPickle::from_encrypted`],
+    /// which detects the encoding automatically.
+    pub fn encrypt_with_encoding(self, pickle_key: &[u8; 32], encoding: PickleEncoding) -> String {
+        pickle_with_encoding(&self, pickle_key, Self::PICKLE_TYPE, encoding)
+    }
+
+    /// Serialize and encrypt the pickle using the given key and
+    /// [`PickleEncoding`], generating the salt of the encrypted pickle using
+    /// the given random number generator.
+    ///
+    /// See [`InboundGroupSessionPickle::encrypt_with_encoding`] for more
+    /// details.
+    pub fn encrypt_with_encoding_and_rng(
+        self,
+        pickle_key: &[u8; 32],
+        encoding: PickleEncoding,
+        rng: &mut impl CryptoRngCore,
+    ) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, encoding, rng)
     }
 
     /// Obtain a pickle from a ciphertext by decrypting and deserializing using
@@ -514,7 +812,7 @@ impl InboundGroupSessionPickle {
     ///
     /// This is the inverse of [`InboundGroupSessionPickle::encrypt`].
     pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
-        unpickle(ciphertext, pickle_key)
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
     }
 }
 
@@ -530,7 +828,11 @@ impl From<InboundGroupSessionPickle> for InboundGroupSession {
             initial_ratchet: pickle.initial_ratchet.clone(),
             latest_ratchet: pickle.initial_ratchet,
             signing_key: pickle.signing_key,
-            signing_key_verified: pickle.signing_key_verified,
+            origin: pickle
+                .origin
+                .unwrap_or(SessionOrigin::from_verified_flag(pickle.signing_key_verified)),
+            forwarding_chain: pickle.forwarding_chain,
+            shareable: pickle.shareable,
             config: pickle.config,
         }
     }
@@ -544,11 +846,12 @@ impl From<&GroupSession> for InboundGroupSession {
 
 #[cfg(test)]
 mod test {
+    use assert_matches2::assert_matches;
     use olm_rs::outbound_group_session::OlmOutboundGroupSession;
 
-    use super::InboundGroupSession;
+    use super::{DecryptionError, InboundGroupSession, SessionOrigin};
     use crate::{
-        cipher::Cipher,
+        PaddingPolicy,
         megolm::{GroupSession, SessionConfig, SessionKey, SessionOrdering},
     };
 
@@ -602,6 +905,30 @@ mod test {
         assert!(!different_config.connected(&mut session));
     }
 
+    #[test]
+    fn padding_is_ignored_when_comparing() {
+        let outbound =
+            GroupSession::new(SessionConfig::version_2().with_padding(PaddingPolicy::Padme));
+        let mut session = InboundGroupSession::from(&outbound);
+        let mut received =
+            InboundGroupSession::new(&outbound.session_key(), SessionConfig::version_2());
+
+        assert_eq!(session.compare(&mut received), SessionOrdering::Equal);
+        assert!(session.merge(&mut received).is_some());
+    }
+
+    #[test]
+    fn merging_keeps_the_shareable_flag() {
+        let outbound = GroupSession::new(Default::default());
+        let mut session = InboundGroupSession::from(&outbound);
+        let mut shareable =
+            InboundGroupSession::import(&session.export_at_first_known_index(), Default::default());
+        shareable.set_shareable(true);
+
+        assert!(!session.merge(&mut shareable).unwrap().is_shareable());
+        assert!(shareable.merge(&mut session).unwrap().is_shareable());
+    }
+
     #[test]
     fn comparison() {
         let outbound = GroupSession::new(Default::default());
@@ -637,41 +964,92 @@ mod test {
         // This one is less trusted because it's imported from an `ExportedSessionKey`.
         let mut second_session =
             InboundGroupSession::import(&first_session.export_at(10).unwrap(), Default::default());
-        assert!(!second_session.signing_key_verified);
+        assert_eq!(second_session.origin(), SessionOrigin::ExportedSessionKey);
 
         assert_eq!(first_session.compare(&mut second_session), SessionOrdering::Better);
 
         let mut merged = second_session.merge(&mut first_session).unwrap();
 
-        assert!(merged.signing_key_verified);
+        assert_eq!(merged.origin(), SessionOrigin::Merged { authenticated: true });
         assert_eq!(merged.compare(&mut second_session), SessionOrdering::Better);
         assert_eq!(merged.compare(&mut first_session), SessionOrdering::Equal);
     }
 
+    #[test]
+    fn upgrade_with_session_key() {
+        let mut outbound = GroupSession::new(Default::default());
+        let session_key = outbound.session_key();
+        let mut inbound = InboundGroupSession::new(&session_key, Default::default());
+
+        let mut imported =
+            InboundGroupSession::import(&inbound.export_at(0).unwrap(), Default::default());
+        let message = outbound.encrypt("Hello");
+
+        let decrypted = imported.decrypt(&message).unwrap();
+        assert_eq!(decrypted.origin, SessionOrigin::ExportedSessionKey);
+        assert!(!decrypted.origin.is_authenticated());
+
+        let mut other = InboundGroupSession::from(&GroupSession::new(Default::default()));
+        assert!(!imported.upgrade(&GroupSession::new(Default::default()).session_key()));
+        assert!(!other.upgrade(&session_key));
+        assert_eq!(imported.origin(), SessionOrigin::ExportedSessionKey);
+
+        // The session key is ratcheted further than the imported session, the
+        // lower first known index is kept.
+        assert!(imported.upgrade(&outbound.session_key()));
+        assert_eq!(imported.first_known_index(), 0);
+        assert_eq!(imported.origin(), SessionOrigin::Merged { authenticated: true });
+
+        let decrypted = imported.decrypt(&message).unwrap();
+        assert!(decrypted.origin.is_authenticated());
+        assert_eq!(inbound.decrypt(&message).unwrap().origin, SessionOrigin::SessionKey);
+    }
+
+    #[test]
+    fn origin_is_pickled() {
+        let session = GroupSession::new(Default::default());
+        let mut inbound = InboundGroupSession::new(&session.session_key(), Default::default());
+        let mut imported =
+            InboundGroupSession::import(&inbound.export_at_first_known_index(), Default::default());
+        let merged = imported.merge(&mut inbound).unwrap();
+
+        for session in [inbound, imported, merged] {
+            let unpickled = InboundGroupSession::from_pickle(session.pickle());
+            assert_eq!(unpickled.origin(), session.origin());
+
+            // Pickles created before the origin was recorded only know if the
+            // session is authenticated.
+            let mut pickle = serde_json::to_value(session.pickle()).unwrap();
+            pickle.as_object_mut().unwrap().remove("origin");
+            let unpickled: InboundGroupSession = serde_json::from_value(pickle).unwrap();
+
+            assert_eq!(unpickled.origin().is_authenticated(), session.origin().is_authenticated());
+        }
+    }
+
     #[test]
     fn verify_mac() {
         let olm_session = OlmOutboundGroupSession::new();
         let session_key = SessionKey::from_base64(&olm_session.session_key()).unwrap();
         let message = olm_session.encrypt("Hello").as_str().try_into().unwrap();
 
-        let mut session = InboundGroupSession::new(&session_key, SessionConfig::version_1());
+        let config = SessionConfig::version_1();
+        let mut session = InboundGroupSession::new(&session_key, config);
         let ratchet = session.find_ratchet(0).unwrap();
-        let cipher = Cipher::new_megolm(ratchet.as_bytes());
 
-        session
-            .verify_mac(&cipher, &message)
+        InboundGroupSession::decrypt_ciphertext(ratchet, &config, &message, &[])
             .expect("Should verify MAC from matching outbound session");
 
         let olm_session = OlmOutboundGroupSession::new();
         let session_key = SessionKey::from_base64(&olm_session.session_key()).unwrap();
 
-        let mut session = InboundGroupSession::new(&session_key, SessionConfig::version_1());
+        let mut session = InboundGroupSession::new(&session_key, config);
         let ratchet = session.find_ratchet(0).unwrap();
-        let cipher = Cipher::new_megolm(ratchet.as_bytes());
 
-        session
-            .verify_mac(&cipher, &message)
-            .expect_err("Should not verify MAC from different outbound session");
+        assert_matches!(
+            InboundGroupSession::decrypt_ciphertext(ratchet, &config, &message, &[]),
+            Err(DecryptionError::InvalidMAC(_))
+        );
     }
 
     /// Test that [`InboundGroupSession::get_cipher_at`] correctly handles the
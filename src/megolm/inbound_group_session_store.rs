@@ -0,0 +1,398 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, btree_map::Entry};
+
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    DecryptedMessage, DecryptionError, InboundGroupSession, InboundGroupSessionPickle,
+    MegolmMessage, SessionOrdering,
+};
+use crate::{
+    PickleEncoding, PickleError,
+    utilities::{pickle, pickle_with_rng, unpickle},
+};
+
+/// Error type for the failures of an [`InboundGroupSessionStore`].
+#[derive(Debug, Error)]
+pub enum InboundGroupSessionStoreError {
+    /// The store already contains a session with the same session ID, but the
+    /// two sessions aren't connected, i.e. they don't share the same ratchet.
+    #[error(
+        "The store already contains a session with the session ID {0}, \
+        which isn't connected to the inserted session"
+    )]
+    UnconnectedSession(String),
+    /// The store doesn't contain the session the message was encrypted with.
+    #[error("The store doesn't contain the session the message was encrypted with")]
+    UnknownSession,
+    /// The session the message belongs to failed to decrypt it.
+    #[error(transparent)]
+    Decryption(#[from] DecryptionError),
+}
+
+/// What happened to a session inserted into an [`InboundGroupSessionStore`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertionResult {
+    /// The store didn't contain a session with the same session ID yet, the
+    /// session was added.
+    Inserted,
+    /// The store already contained a session with the same session ID, the
+    /// two sessions were [merged], since the inserted one was better or more
+    /// trustworthy.
+    ///
+    /// [merged]: InboundGroupSession::merge
+    Merged,
+    /// The store already contained a session with the same session ID which
+    /// was at least as good as the inserted one, the store was left unchanged.
+    Unchanged,
+}
+
+/// A collection of [`InboundGroupSession`]s, indexed by their session ID.
+///
+/// The same Megolm session is often received multiple times, e.g. in a room
+/// key, as a forwarded key, from a key backup and from a key export. The store
+/// takes care of [comparing] and [merging] such duplicates, keeping the
+/// session with the lowest first known index and the most trustworthy
+/// [`SessionOrigin`](super::SessionOrigin).
+///
+/// [comparing]: InboundGroupSession::compare
+/// [merging]: InboundGroupSession::merge
+#[derive(Default)]
+pub struct InboundGroupSessionStore {
+    sessions: BTreeMap<String, InboundGroupSession>,
+}
+
+impl std::fmt::Debug for InboundGroupSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InboundGroupSessionStore")
+            .field("session_ids", &self.sessions.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl InboundGroupSessionStore {
+    /// Create a new, empty, [`InboundGroupSessionStore`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of sessions in the store.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Is the store empty.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// The IDs of all the sessions in the store.
+    pub fn session_ids(&self) -> impl Iterator<Item = &str> {
+        self.sessions.keys().map(String::as_str)
+    }
+
+    /// Get the session with the given session ID.
+    pub fn get(&self, session_id: &str) -> Option<&InboundGroupSession> {
+        self.sessions.get(session_id)
+    }
+
+    /// Get a mutable reference to the session with the given session ID.
+    pub fn get_mut(&mut self, session_id: &str) -> Option<&mut InboundGroupSession> {
+        self.sessions.get_mut(session_id)
+    }
+
+    /// Remove the session with the given session ID from the store.
+    pub fn remove(&mut self, session_id: &str) -> Option<InboundGroupSession> {
+        self.sessions.remove(session_id)
+    }
+
+    /// Insert a session into the store.
+    ///
+    /// If the store already contains a session with the same session ID, the
+    /// better of the two sessions is kept. If the inserted session has a lower
+    /// first known index, or was received in a signed room key while the
+    /// stored one wasn't, the two sessions are merged.
+    ///
+    /// Returns an [`InboundGroupSessionStoreError::UnconnectedSession`] error
+    /// if the stored session claims the same session ID but isn't connected to
+    /// the inserted session, the store is left unchanged in that case.
+    pub fn insert(
+        &mut self,
+        mut session: InboundGroupSession,
+    ) -> Result<InsertionResult, InboundGroupSessionStoreError> {
+        let session_id = session.session_id();
+
+        match self.sessions.entry(session_id.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(session);
+                Ok(InsertionResult::Inserted)
+            }
+            Entry::Occupied(mut entry) => {
+                let existing = entry.get_mut();
+
+                let is_better = match existing.compare(&mut session) {
+                    SessionOrdering::Unconnected => {
+                        return Err(InboundGroupSessionStoreError::UnconnectedSession(session_id));
+                    }
+                    SessionOrdering::Equal | SessionOrdering::Better => false,
+                    SessionOrdering::Worse => true,
+                };
+
+                let is_more_trusted =
+                    session.origin().is_authenticated() && !existing.origin().is_authenticated();
+
+                if is_better || is_more_trusted {
+                    let merged = existing
+                        .merge(&mut session)
+                        .ok_or(InboundGroupSessionStoreError::UnconnectedSession(session_id))?;
+                    entry.insert(merged);
+
+                    Ok(InsertionResult::Merged)
+                } else {
+                    Ok(InsertionResult::Unchanged)
+                }
+            }
+        }
+    }
+
+    /// Decrypt the given [`MegolmMessage`] using the session it was encrypted
+    /// with.
+    ///
+    /// Megolm messages don't contain the ID of their session, the session is
+    /// found by checking the signature of the message against the signing key
+    /// of every session in the store. Prefer
+    /// [`InboundGroupSessionStore::decrypt_with_session_id()`] if the session
+    /// ID is known, as it is for Matrix room events.
+    pub fn decrypt(
+        &mut self,
+        message: &MegolmMessage,
+    ) -> Result<DecryptedMessage, InboundGroupSessionStoreError> {
+        let session = self
+            .sessions
+            .values_mut()
+            .find(|session| message.verify_signature(session.signing_key()).is_ok())
+            .ok_or(InboundGroupSessionStoreError::UnknownSession)?;
+
+        Ok(session.decrypt(message)?)
+    }
+
+    /// Decrypt the given [`MegolmMessage`] using the session with the given
+    /// session ID.
+    pub fn decrypt_with_session_id(
+        &mut self,
+        session_id: &str,
+        message: &MegolmMessage,
+    ) -> Result<DecryptedMessage, InboundGroupSessionStoreError> {
+        let session = self
+            .sessions
+            .get_mut(session_id)
+            .ok_or(InboundGroupSessionStoreError::UnknownSession)?;
+
+        Ok(session.decrypt(message)?)
+    }
+
+    /// Convert the store into a struct which implements [`serde::Serialize`]
+    /// and [`serde::Deserialize`].
+    pub fn pickle(&self) -> InboundGroupSessionStorePickle {
+        InboundGroupSessionStorePickle {
+            sessions: self.sessions.values().map(InboundGroupSession::pickle).collect(),
+        }
+    }
+
+    /// Restore an [`InboundGroupSessionStore`] from a previously saved
+    /// [`InboundGroupSessionStorePickle`].
+    pub fn from_pickle(pickle: InboundGroupSessionStorePickle) -> Self {
+        pickle.into()
+    }
+}
+
+/// A format suitable for serialization which implements [`serde::Serialize`]
+/// and [`serde::Deserialize`]. Obtainable by calling
+/// [`InboundGroupSessionStore::pickle`].
+///
+/// The store is pickled as the list of the [`InboundGroupSessionPickle`]s of
+/// its sessions.
+#[derive(Serialize, Deserialize)]
+pub struct InboundGroupSessionStorePickle {
+    sessions: Vec<InboundGroupSessionPickle>,
+}
+
+impl InboundGroupSessionStorePickle {
+    const PICKLE_TYPE: &'static str = "InboundGroupSessionStore";
+
+    /// Serialize and encrypt the pickle using the given key.
+    ///
+    /// This is the inverse of
+    /// [`InboundGroupSessionStorePickle::from_encrypted`].
+    pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`InboundGroupSessionStorePickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Obtain a pickle from a ciphertext by decrypting and deserializing using
+    /// the given key.
+    ///
+    /// This is the inverse of [`InboundGroupSessionStorePickle::encrypt`].
+    pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
+    }
+}
+
+impl From<InboundGroupSessionStorePickle> for InboundGroupSessionStore {
+    fn from(pickle: InboundGroupSessionStorePickle) -> Self {
+        let sessions = pickle
+            .sessions
+            .into_iter()
+            .map(InboundGroupSession::from_pickle)
+            .map(|session| (session.session_id(), session))
+            .collect();
+
+        Self { sessions }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{
+        InboundGroupSessionStore, InboundGroupSessionStoreError, InboundGroupSessionStorePickle,
+        InsertionResult,
+    };
+    use crate::megolm::{GroupSession, InboundGroupSession, SessionConfig, SessionOrigin};
+
+    const PICKLE_KEY: [u8; 32] = [0u8; 32];
+
+    #[test]
+    fn insert_keeps_the_best_session() {
+        let mut outbound = GroupSession::new(SessionConfig::version_2());
+        let session_key = outbound.session_key();
+        let mut store = InboundGroupSessionStore::new();
+
+        let mut full = InboundGroupSession::new(&session_key, SessionConfig::version_2());
+        let later =
+            InboundGroupSession::import(&full.export_at(5).unwrap(), SessionConfig::version_2());
+        let earlier =
+            InboundGroupSession::import(&full.export_at(0).unwrap(), SessionConfig::version_2());
+
+        assert_eq!(store.insert(later).unwrap(), InsertionResult::Inserted);
+        assert_eq!(store.insert(earlier).unwrap(), InsertionResult::Merged);
+        assert_eq!(store.get(&outbound.session_id()).unwrap().first_known_index(), 0);
+
+        // The signed room key has the same index, but is more trustworthy.
+        assert_eq!(store.insert(full).unwrap(), InsertionResult::Merged);
+        let session = store.get(&outbound.session_id()).unwrap();
+        assert!(session.origin().is_authenticated());
+
+        // Now that the stored session is the best one, further duplicates are
+        // ignored.
+        let duplicate = InboundGroupSession::new(&session_key, SessionConfig::version_2());
+        assert_eq!(store.insert(duplicate).unwrap(), InsertionResult::Unchanged);
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store.get(&outbound.session_id()).unwrap().origin(),
+            SessionOrigin::Merged { authenticated: true }
+        );
+
+        let message = outbound.encrypt("Hello");
+        assert_eq!(store.decrypt(&message).unwrap().plaintext, b"Hello");
+    }
+
+    #[test]
+    fn unconnected_sessions_are_rejected() {
+        let outbound = GroupSession::new(SessionConfig::version_2());
+        let mut store = InboundGroupSessionStore::new();
+
+        let session = InboundGroupSession::new(&outbound.session_key(), SessionConfig::version_2());
+        store.insert(session).unwrap();
+
+        // The same signing key, but a different config makes the sessions
+        // unconnected.
+        let other = InboundGroupSession::new(&outbound.session_key(), SessionConfig::version_1());
+        assert_matches!(
+            store.insert(other),
+            Err(InboundGroupSessionStoreError::UnconnectedSession(session_id))
+        );
+        assert_eq!(session_id, outbound.session_id());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn decrypt_routes_by_session() {
+        let mut first = GroupSession::new(SessionConfig::version_2());
+        let mut second = GroupSession::new(SessionConfig::version_2());
+        let mut store = InboundGroupSessionStore::new();
+
+        store.insert(InboundGroupSession::from(&first)).unwrap();
+        store.insert(InboundGroupSession::from(&second)).unwrap();
+
+        let message = second.encrypt("Second");
+        assert_eq!(store.decrypt(&message).unwrap().plaintext, b"Second");
+        assert_eq!(
+            store.decrypt_with_session_id(&second.session_id(), &message).unwrap().plaintext,
+            b"Second"
+        );
+
+        let message = first.encrypt("First");
+        assert_eq!(store.decrypt(&message).unwrap().plaintext, b"First");
+        assert_matches!(
+            store.decrypt_with_session_id(&second.session_id(), &message),
+            Err(InboundGroupSessionStoreError::Decryption(_))
+        );
+
+        let unknown = GroupSession::new(SessionConfig::version_2()).encrypt("Unknown");
+        assert_matches!(
+            store.decrypt(&unknown),
+            Err(InboundGroupSessionStoreError::UnknownSession)
+        );
+        assert_matches!(
+            store.decrypt_with_session_id("unknown", &unknown),
+            Err(InboundGroupSessionStoreError::UnknownSession)
+        );
+    }
+
+    #[test]
+    fn pickle_roundtrip() {
+        let mut outbound = GroupSession::new(SessionConfig::version_2());
+        let mut store = InboundGroupSessionStore::new();
+
+        store.insert(InboundGroupSession::from(&outbound)).unwrap();
+        store
+            .insert(InboundGroupSession::from(&GroupSession::new(SessionConfig::version_1())))
+            .unwrap();
+
+        let pickle = store.pickle().encrypt(&PICKLE_KEY);
+        let pickle = InboundGroupSessionStorePickle::from_encrypted(&pickle, &PICKLE_KEY).unwrap();
+        let mut unpickled = InboundGroupSessionStore::from_pickle(pickle);
+
+        assert_eq!(
+            unpickled.session_ids().collect::<Vec<_>>(),
+            store.session_ids().collect::<Vec<_>>()
+        );
+
+        let message = outbound.encrypt("Hello");
+        assert_eq!(unpickled.decrypt(&message).unwrap().plaintext, b"Hello");
+    }
+}
@@ -18,17 +18,17 @@ use std::fmt::Debug;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 
+use super::{SessionConfig, session_config::Version};
 use crate::{
-    DecodeError,
-    cipher::{Cipher, Mac, MessageMac},
+    DecodeError, Ed25519PublicKey, SignatureError,
+    cipher::{Cipher, Mac, MessageMac, aead::AeadCipher},
     types::{Ed25519Keypair, Ed25519Signature},
     utilities::{VarInt, base64_decode, base64_encode, extract_mac},
 };
-#[cfg(feature = "low-level-api")]
-use crate::{Ed25519PublicKey, SignatureError};
 
 const MAC_TRUNCATED_VERSION: u8 = 3;
 const VERSION: u8 = 4;
+const AEAD_VERSION: u8 = 5;
 
 /// An encrypted Megolm message.
 ///
@@ -41,14 +41,31 @@ pub struct MegolmMessage {
     pub(super) version: u8,
     pub(super) ciphertext: Vec<u8>,
     pub(super) message_index: u32,
+    pub(super) padded: bool,
     pub(super) mac: MessageMac,
     pub(super) signature: Ed25519Signature,
 }
 
 const MESSAGE_TRUNCATED_SUFFIX_LENGTH: usize = Mac::TRUNCATED_LEN + Ed25519Signature::LENGTH;
 const MESSAGE_SUFFIX_LENGTH: usize = Mac::LENGTH + Ed25519Signature::LENGTH;
+const MESSAGE_AEAD_SUFFIX_LENGTH: usize = Ed25519Signature::LENGTH;
+
+/// Get the message version that sessions using the given [`SessionConfig`]
+/// version produce and accept.
+pub(super) const fn message_version(version: Version) -> u8 {
+    match version {
+        Version::V1 => MAC_TRUNCATED_VERSION,
+        Version::V2 => VERSION,
+        Version::V3 => AEAD_VERSION,
+    }
+}
 
 impl MegolmMessage {
+    /// The version of the Megolm message.
+    pub const fn version(&self) -> u8 {
+        self.version
+    }
+
     /// The actual ciphertext of the message.
     pub fn ciphertext(&self) -> &[u8] {
         &self.ciphertext
@@ -59,7 +76,19 @@ impl MegolmMessage {
         self.message_index
     }
 
+    /// Has the plaintext of this message been padded to hide its length.
+    ///
+    /// The padding is removed when the message gets decrypted, see
+    /// [`PaddingPolicy`](crate::PaddingPolicy).
+    pub const fn is_padded(&self) -> bool {
+        self.padded
+    }
+
     /// Get the megolm message's mac.
+    ///
+    /// Messages which were encrypted using an AEAD don't have a separate MAC,
+    /// the authentication tag is part of the ciphertext, so the MAC of those
+    /// is empty.
     pub fn mac(&self) -> &[u8] {
         self.mac.as_bytes()
     }
@@ -69,6 +98,18 @@ impl MegolmMessage {
         &self.signature
     }
 
+    /// Verify that the message was signed by the given signing key.
+    ///
+    /// The signing key of a Megolm session is part of its [`SessionKey`],
+    /// [`InboundGroupSession::decrypt()`] checks the signature on its own, this
+    /// is only useful to inspect messages which can't be decrypted.
+    ///
+    /// [`SessionKey`]: super::SessionKey
+    /// [`InboundGroupSession::decrypt()`]: super::InboundGroupSession::decrypt
+    pub fn verify_signature(&self, signing_key: &Ed25519PublicKey) -> Result<(), SignatureError> {
+        signing_key.verify(&self.to_signature_bytes(), &self.signature)
+    }
+
     /// Try to decode the given byte slice as a [`MegolmMessage`].
     ///
     /// The expected format of the byte array is described in the
@@ -97,6 +138,14 @@ impl MegolmMessage {
     /// :-----------:|:-----:|:------:|:---------------------------------------:
     /// Message-Index|  0x08 | Integer|The index of the ratchet, i
     /// Cipher-Text  |  0x12 | String |The cipher-text, Xi, of the message
+    /// Padded       |  0x18 | Integer|Set to 1 if the plaintext is padded
+    ///
+    /// The padded flag is only present if the plaintext is padded, receivers
+    /// which don't know about it ignore it like any other unknown key.
+    ///
+    /// Messages of version 5 are encrypted using ChaCha20-Poly1305, the
+    /// authentication tag is appended to the cipher-text and the MAC bytes are
+    /// left out.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut message = self.encode_message();
 
@@ -141,6 +190,7 @@ impl MegolmMessage {
         let message = ProtobufMegolmMessage {
             message_index: self.message_index,
             ciphertext: self.ciphertext.clone(),
+            padded: self.padded,
         };
 
         message.encode_manual(self.version)
@@ -150,6 +200,8 @@ impl MegolmMessage {
         match self.mac {
             MessageMac::Truncated(_) => self.mac = mac.truncate().into(),
             MessageMac::Full(_) => self.mac = mac.into(),
+            // The authentication tag is part of the ciphertext.
+            MessageMac::Aead => (),
         }
     }
 
@@ -161,51 +213,74 @@ impl MegolmMessage {
         signing_key: &Ed25519Keypair,
         plaintext: &[u8],
     ) -> Self {
-        MegolmMessage::encrypt_truncated_mac(message_index, cipher, signing_key, plaintext)
+        let ciphertext = cipher.encrypt(plaintext);
+        let message = Self::new_unsigned(MAC_TRUNCATED_VERSION, message_index, ciphertext);
+
+        Self::encrypt_helper(cipher, signing_key, message)
     }
 
     /// Implementation of [`MegolmMessage::encrypt`] that is used by rest of the
-    /// crate.
-    pub(super) fn encrypt_full_mac(
+    /// crate, the [`SessionConfig`] decides the encryption scheme and the
+    /// padding of the plaintext.
+    ///
+    /// The associated data is only authenticated by the AEAD used in Megolm
+    /// version 3, the caller needs to ensure that it's empty for other
+    /// versions.
+    pub(super) fn encrypt_with_config(
         message_index: u32,
-        cipher: &Cipher,
+        message_key: &[u8; 128],
         signing_key: &Ed25519Keypair,
         plaintext: &[u8],
+        associated_data: &[u8],
+        config: &SessionConfig,
     ) -> Self {
-        let ciphertext = cipher.encrypt(plaintext);
+        let padded = config.padding.pad(plaintext);
+        let plaintext = padded.as_deref().unwrap_or(plaintext);
+        let version = message_version(config.version);
 
-        let message = Self {
-            version: VERSION,
-            ciphertext,
-            message_index,
-            mac: Mac([0u8; Mac::LENGTH]).into(),
-            #[allow(clippy::expect_used)]
-            signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
-                .expect("We should be able to create a signature from an empty zero slice"),
-        };
+        match config.version {
+            Version::V1 | Version::V2 => {
+                let cipher = Cipher::new_megolm(message_key);
 
-        Self::encrypt_helper(cipher, signing_key, message)
+                let mut message =
+                    Self::new_unsigned(version, message_index, cipher.encrypt(plaintext));
+                message.padded = padded.is_some();
+
+                Self::encrypt_helper(&cipher, signing_key, message)
+            }
+            Version::V3 => {
+                let cipher = AeadCipher::new_megolm(message_key);
+
+                let mut message = Self::new_unsigned(version, message_index, Vec::new());
+                message.padded = padded.is_some();
+                message.ciphertext =
+                    cipher.encrypt(plaintext, &message.to_associated_data(associated_data));
+                message.signature = signing_key.sign(&message.to_signature_bytes());
+
+                message
+            }
+        }
     }
 
-    pub(super) fn encrypt_truncated_mac(
-        message_index: u32,
-        cipher: &Cipher,
-        signing_key: &Ed25519Keypair,
-        plaintext: &[u8],
-    ) -> Self {
-        let ciphertext = cipher.encrypt(plaintext);
+    /// Create a message with an all-zero MAC and signature, which will get
+    /// filled in by [`MegolmMessage::encrypt_helper`].
+    fn new_unsigned(version: u8, message_index: u32, ciphertext: Vec<u8>) -> Self {
+        let mac = match version {
+            MAC_TRUNCATED_VERSION => [0u8; Mac::TRUNCATED_LEN].into(),
+            AEAD_VERSION => MessageMac::Aead,
+            _ => Mac([0u8; Mac::LENGTH]).into(),
+        };
 
-        let message = Self {
-            version: MAC_TRUNCATED_VERSION,
+        Self {
+            version,
             ciphertext,
             message_index,
-            mac: [0u8; Mac::TRUNCATED_LEN].into(),
+            padded: false,
+            mac,
             #[allow(clippy::expect_used)]
             signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH])
                 .expect("We should be able to create a signature from an empty zero slice"),
-        };
-
-        Self::encrypt_helper(cipher, signing_key, message)
+        }
     }
 
     fn encrypt_helper(
@@ -226,6 +301,19 @@ impl MegolmMessage {
         self.encode_message()
     }
 
+    /// Get the associated data which is authenticated by the AEAD, binding the
+    /// unencrypted parts of the message to the caller supplied associated
+    /// data.
+    pub(super) fn to_associated_data(&self, associated_data: &[u8]) -> Vec<u8> {
+        [
+            [self.version].as_slice(),
+            &self.message_index.to_be_bytes(),
+            &[u8::from(self.padded)],
+            associated_data,
+        ]
+        .concat()
+    }
+
     pub(super) fn to_signature_bytes(&self) -> Vec<u8> {
         let mut message = self.encode_message();
         message.extend(self.mac.as_bytes());
@@ -278,6 +366,7 @@ impl TryFrom<&[u8]> for MegolmMessage {
         let suffix_length = match version {
             VERSION => MESSAGE_SUFFIX_LENGTH,
             MAC_TRUNCATED_VERSION => MESSAGE_TRUNCATED_SUFFIX_LENGTH,
+            AEAD_VERSION => MESSAGE_AEAD_SUFFIX_LENGTH,
             _ => return Err(DecodeError::InvalidVersion(VERSION, version)),
         };
 
@@ -295,12 +384,17 @@ impl TryFrom<&[u8]> for MegolmMessage {
             let signature = Ed25519Signature::from_slice(signature_slice)?;
 
             let mac_slice = &message[message.len() - suffix_length..];
-            let mac = extract_mac(mac_slice, version == MAC_TRUNCATED_VERSION);
+            let mac = if version == AEAD_VERSION {
+                MessageMac::Aead
+            } else {
+                extract_mac(mac_slice, version == MAC_TRUNCATED_VERSION)
+            };
 
             Ok(MegolmMessage {
                 version,
                 ciphertext: inner.ciphertext,
                 message_index: inner.message_index,
+                padded: inner.padded,
                 mac,
                 signature,
             })
@@ -310,11 +404,12 @@ impl TryFrom<&[u8]> for MegolmMessage {
 
 impl Debug for MegolmMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self { version, ciphertext: _, message_index, mac: _, signature: _ } = self;
+        let Self { version, ciphertext: _, message_index, padded, mac: _, signature: _ } = self;
 
         f.debug_struct("MegolmMessage")
             .field("version", version)
             .field("message_index", message_index)
+            .field("padded", padded)
             .finish_non_exhaustive()
     }
 }
@@ -325,25 +420,33 @@ struct ProtobufMegolmMessage {
     pub message_index: u32,
     #[prost(bytes, tag = "2")]
     pub ciphertext: Vec<u8>,
+    #[prost(bool, tag = "3")]
+    pub padded: bool,
 }
 
 impl ProtobufMegolmMessage {
     const INDEX_TAG: &'static [u8; 1] = b"\x08";
     const CIPHER_TAG: &'static [u8; 1] = b"\x12";
+    /// The padded tag followed by a value of 1.
+    const PADDED_FLAG: &'static [u8; 2] = b"\x18\x01";
 
     fn encode_manual(&self, version: u8) -> Vec<u8> {
         // Prost optimizes away the message index if it's 0, libolm can't decode
         // this, so encode our messages the pedestrian way instead.
         let index = self.message_index.to_var_int();
         let ciphertext_len = self.ciphertext.len().to_var_int();
+        // The padded flag is left out for unpadded messages, this keeps them
+        // byte for byte identical to messages from before padding existed.
+        let padded: &[u8] = if self.padded { Self::PADDED_FLAG } else { &[] };
 
         [
-            [version].as_ref(),
-            Self::INDEX_TAG.as_ref(),
+            [version].as_slice(),
+            Self::INDEX_TAG.as_slice(),
             &index,
-            Self::CIPHER_TAG.as_ref(),
+            Self::CIPHER_TAG.as_slice(),
             &ciphertext_len,
             &self.ciphertext,
+            padded,
         ]
         .concat()
     }
@@ -360,7 +463,7 @@ mod test {
         DecodeError, Ed25519Signature,
         cipher::Mac,
         megolm::{
-            MegolmMessage,
+            GroupSession, MegolmMessage, SessionConfig,
             message::{
                 MAC_TRUNCATED_VERSION, MESSAGE_SUFFIX_LENGTH, MESSAGE_TRUNCATED_SUFFIX_LENGTH,
                 VERSION,
@@ -381,7 +484,7 @@ mod test {
         let mut bytes = [1u8; 97];
         bytes[0] = VERSION;
         assert_matches!(
-            MegolmMessage::try_from(bytes.as_ref()),
+            MegolmMessage::try_from(bytes.as_slice()),
             Err(DecodeError::MessageTooShort(_))
         );
     }
@@ -391,7 +494,7 @@ mod test {
         let mut bytes = [1u8; 73];
         bytes[0] = MAC_TRUNCATED_VERSION;
         assert_matches!(
-            MegolmMessage::try_from(bytes.as_ref()),
+            MegolmMessage::try_from(bytes.as_slice()),
             Err(DecodeError::MessageTooShort(_))
         );
     }
@@ -401,7 +504,7 @@ mod test {
         let mut bytes = [1u8; 74];
         bytes[0] = MAC_TRUNCATED_VERSION;
         assert_matches!(
-            MegolmMessage::try_from(bytes.as_ref()),
+            MegolmMessage::try_from(bytes.as_slice()),
             Err(DecodeError::ProtoBufError(_))
         );
     }
@@ -413,6 +516,7 @@ mod test {
             version: VERSION,
             ciphertext: vec![],
             message_index: 0,
+            padded: false,
             mac: Mac([0u8; Mac::LENGTH]).into(),
             signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH]).unwrap(),
         };
@@ -433,6 +537,7 @@ mod test {
             version: VERSION,
             ciphertext: vec![],
             message_index: 0,
+            padded: false,
             mac: Mac([0u8; Mac::LENGTH]).into(),
             signature: Ed25519Signature::from_slice(&[0; Ed25519Signature::LENGTH]).unwrap(),
         };
@@ -445,4 +550,19 @@ mod test {
             .expect_err("Should not be able to add invalid signature");
         assert_ne!(message.signature, signature);
     }
+
+    #[test]
+    fn verify_signature() {
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        let other = GroupSession::new(SessionConfig::version_1());
+        let message = session.encrypt("Hello");
+
+        assert_eq!(message.version(), MAC_TRUNCATED_VERSION);
+        message
+            .verify_signature(&session.session_key().signing_key())
+            .expect("The message should be signed by the session's signing key");
+        message
+            .verify_signature(&other.session_key().signing_key())
+            .expect_err("The message shouldn't be signed by another session's signing key");
+    }
 }
@@ -14,19 +14,34 @@
 
 //! An implementation of the Megolm ratchet.
 
+mod envelope;
+mod forwarded_room_key;
 mod group_session;
 mod inbound_group_session;
+mod inbound_group_session_store;
 pub(crate) mod message;
 mod ratchet;
+mod room_key_bundle;
+mod rotation_policy;
 mod session_config;
 mod session_keys;
 
-pub use group_session::{GroupSession, GroupSessionPickle};
+pub use envelope::{DecryptedMegolmEnvelope, MegolmEnvelope, MegolmEnvelopeError};
+pub use forwarded_room_key::{ForwardedRoomKey, ForwardedRoomKeyError};
+pub use group_session::{EncryptionError, GroupSession, GroupSessionPickle};
 pub use inbound_group_session::{
     DecryptedMessage, DecryptionError, InboundGroupSession, InboundGroupSessionPickle,
-    SessionOrdering,
+    SessionOrdering, SessionOrigin,
+};
+pub use inbound_group_session_store::{
+    InboundGroupSessionStore, InboundGroupSessionStoreError, InboundGroupSessionStorePickle,
+    InsertionResult,
 };
 pub use message::MegolmMessage;
+pub use room_key_bundle::{
+    EncryptedRoomKeyBundle, RoomKeyBundle, RoomKeyBundleError, RoomKeyBundleKey,
+};
+pub use rotation_policy::RotationPolicy;
 pub use session_config::SessionConfig;
 pub use session_keys::{ExportedSessionKey, SessionKey, SessionKeyDecodeError};
 
@@ -58,13 +73,18 @@ mod libolm {
 #[cfg(test)]
 mod test {
     use anyhow::Result;
+    use assert_matches2::assert_matches;
     use olm_rs::{
         inbound_group_session::OlmInboundGroupSession,
         outbound_group_session::OlmOutboundGroupSession,
     };
+    use rand::{SeedableRng, rngs::StdRng};
 
-    use super::{ExportedSessionKey, GroupSession, InboundGroupSession, MegolmMessage};
+    use super::{
+        DecryptionError, ExportedSessionKey, GroupSession, InboundGroupSession, MegolmMessage,
+    };
     use crate::{
+        PaddingPolicy, PickleEncoding,
         megolm::{
             GroupSessionPickle, InboundGroupSessionPickle, SessionConfig, SessionKey,
             default_config,
@@ -80,6 +100,19 @@ mod test {
         assert_ne!(default_config(), SessionConfig::default());
     }
 
+    #[test]
+    fn deterministic_session_creation() {
+        let create_session = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut session = GroupSession::new_with_rng(SessionConfig::version_2(), &mut rng);
+
+            (session.session_key().to_base64(), session.encrypt("It's a secret").to_base64())
+        };
+
+        assert_eq!(create_session(0), create_session(0));
+        assert_ne!(create_session(0), create_session(1));
+    }
+
     #[test]
     fn encrypting() -> Result<()> {
         let mut session = GroupSession::new(SessionConfig::version_1());
@@ -120,6 +153,119 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn padded_encryption() -> Result<()> {
+        let config = SessionConfig::version_2().with_padding(PaddingPolicy::Block(64));
+        let mut session = GroupSession::new(config);
+        let mut inbound_session = InboundGroupSession::new(&session.session_key(), config);
+
+        let plaintext = "It's a secret to everybody";
+        let message = session.encrypt(plaintext);
+
+        assert!(message.is_padded());
+        // A 64 byte plaintext gets an extra block of PKCS#7 padding.
+        assert_eq!(message.ciphertext().len(), 80);
+
+        let message = MegolmMessage::from_base64(&message.to_base64())?;
+        assert!(message.is_padded());
+
+        let decrypted = inbound_session.decrypt(&message)?;
+        assert_eq!(decrypted.plaintext, plaintext.as_bytes());
+
+        // The receiving side removes the padding regardless of its own config.
+        let mut inbound_session =
+            InboundGroupSession::new(&session.session_key(), SessionConfig::version_2());
+        let message = session.encrypt(plaintext);
+
+        assert_eq!(inbound_session.decrypt(&message)?.plaintext, plaintext.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn associated_data() -> Result<()> {
+        let config = SessionConfig::version_3().with_padding(PaddingPolicy::Padme);
+        let mut session = GroupSession::new(config);
+        let mut inbound_session = InboundGroupSession::new(&session.session_key(), config);
+
+        let plaintext = "It's a secret to everybody";
+        let associated_data = "!room:server|@alice:server";
+        let message = session.encrypt_with_associated_data(plaintext, associated_data)?;
+
+        assert!(message.mac().is_empty());
+
+        let message = MegolmMessage::from_base64(&message.to_base64())?;
+        let decrypted = inbound_session.decrypt_with_associated_data(&message, associated_data)?;
+        assert_eq!(decrypted.plaintext, plaintext.as_bytes());
+
+        assert_matches!(
+            inbound_session.decrypt_with_associated_data(&message, "!other:server|@alice:server"),
+            Err(DecryptionError::Aead)
+        );
+        assert_matches!(inbound_session.decrypt(&message), Err(DecryptionError::Aead));
+
+        // Messages without associated data can be decrypted using the plain methods.
+        let message = session.encrypt(plaintext);
+        assert_eq!(inbound_session.decrypt(&message)?.plaintext, plaintext.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn aead_version_mismatch() -> Result<()> {
+        let mut session = GroupSession::new(SessionConfig::version_3());
+        let session_key = session.session_key();
+        let message = session.encrypt("It's a secret to everybody");
+
+        let mut inbound_session =
+            InboundGroupSession::new(&session_key, SessionConfig::version_2());
+        assert_matches!(
+            inbound_session.decrypt(&message),
+            Err(DecryptionError::InvalidMessageVersion(4, 5))
+        );
+        assert_matches!(
+            inbound_session.decrypt_with_associated_data(&message, "!room:server"),
+            Err(DecryptionError::AssociatedDataUnsupported(2))
+        );
+
+        let mut session = GroupSession::new(SessionConfig::version_1());
+        let session_key = session.session_key();
+        let message = session.encrypt("It's a secret to everybody");
+
+        let mut inbound_session =
+            InboundGroupSession::new(&session_key, SessionConfig::version_3());
+        assert_matches!(
+            inbound_session.decrypt(&message),
+            Err(DecryptionError::InvalidMessageVersion(5, 3))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn aead_pickle_roundtrip() -> Result<()> {
+        let session = GroupSession::new(SessionConfig::version_3());
+        let inbound_session = InboundGroupSession::from(&session);
+
+        let mut session = GroupSession::from_pickle(GroupSessionPickle::from_encrypted(
+            &session.pickle().encrypt(&PICKLE_KEY),
+            &PICKLE_KEY,
+        )?);
+        let mut inbound_session =
+            InboundGroupSession::from_pickle(InboundGroupSessionPickle::from_encrypted(
+                &inbound_session.pickle().encrypt(&PICKLE_KEY),
+                &PICKLE_KEY,
+            )?);
+
+        assert_eq!(session.session_config(), SessionConfig::version_3());
+
+        let message = session.encrypt_with_associated_data("secret", "!room:server")?;
+        let decrypted = inbound_session.decrypt_with_associated_data(&message, "!room:server")?;
+        assert_eq!(decrypted.plaintext, b"secret");
+
+        Ok(())
+    }
+
     #[test]
     fn decrypting() -> Result<()> {
         let olm_session = OlmOutboundGroupSession::new();
@@ -252,6 +398,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn binary_pickling_roundtrip() -> Result<()> {
+        let mut session = GroupSession::new(Default::default());
+        session.encrypt("It's a secret to everybody");
+        let inbound_session = InboundGroupSession::from(&session);
+
+        let json = session.pickle().encrypt(&PICKLE_KEY);
+        let binary = session.pickle().encrypt_with_encoding(&PICKLE_KEY, PickleEncoding::Binary);
+        assert!(binary.len() < json.len() / 2);
+
+        let unpickled = GroupSessionPickle::from_encrypted(&binary, &PICKLE_KEY)?;
+        assert_eq!(serde_json::to_value(unpickled)?, serde_json::to_value(session.pickle())?);
+
+        let binary =
+            inbound_session.pickle().encrypt_with_encoding(&PICKLE_KEY, PickleEncoding::Binary);
+        let unpickled = InboundGroupSessionPickle::from_encrypted(&binary, &PICKLE_KEY)?;
+        assert_eq!(
+            serde_json::to_value(unpickled)?,
+            serde_json::to_value(inbound_session.pickle())?
+        );
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn libolm_inbound_unpickling() -> Result<()> {
@@ -14,8 +14,9 @@
 // limitations under the License.
 
 use hmac::{Hmac, Mac as _};
-use rand::{RngCore, thread_rng};
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_bytes::{ByteBuf as SerdeByteBuf, Bytes as SerdeBytes};
 use sha2::{Sha256, digest::CtOutput};
 use subtle::{Choice, ConstantTimeEq};
 use thiserror::Error;
@@ -64,8 +65,9 @@ impl Serialize for RatchetBytes {
     where
         S: Serializer,
     {
-        let bytes = &self.0;
-        bytes.serialize(serializer)
+        // Serialized as bytes so binary formats can encode the ratchet
+        // compactly, JSON still encodes it as a list of numbers.
+        SerdeBytes::new(self.0.as_slice()).serialize(serializer)
     }
 }
 
@@ -74,7 +76,7 @@ impl<'d> Deserialize<'d> for RatchetBytes {
     where
         D: Deserializer<'d>,
     {
-        let mut bytes = <Vec<u8>>::deserialize(deserializer)?;
+        let mut bytes = <SerdeByteBuf>::deserialize(deserializer)?.into_vec();
         let ratchet = Self::from_bytes(bytes.as_ref()).map_err(serde::de::Error::custom)?;
 
         bytes.zeroize();
@@ -138,9 +140,7 @@ impl Ratchet {
     const RATCHET_PART_COUNT: usize = 4;
     const LAST_RATCHET_INDEX: usize = Self::RATCHET_PART_COUNT - 1;
 
-    pub fn new() -> Self {
-        let mut rng = thread_rng();
-
+    pub fn new(rng: &mut impl CryptoRngCore) -> Self {
         let mut ratchet =
             Self { inner: RatchetBytes(Box::new([0u8; Self::RATCHET_LENGTH])), counter: 0 };
 
@@ -262,25 +262,27 @@ enum RatchetBytesError {
 
 #[cfg(test)]
 mod tests {
+    use rand::thread_rng;
+
     use super::*;
 
     #[test]
     fn advancing_high_counter_ratchet_doesnt_panic() {
-        let mut ratchet = Ratchet::new();
+        let mut ratchet = Ratchet::new(&mut thread_rng());
         ratchet.counter = 0x00FFFFFF;
         ratchet.advance();
     }
 
     #[test]
     fn advance_to_with_high_counter_doesnt_panic() {
-        let mut ratchet = Ratchet::new();
+        let mut ratchet = Ratchet::new(&mut thread_rng());
         ratchet.counter = (1 << 24) - 1;
         ratchet.advance_to(1 << 24);
     }
 
     #[test]
     fn advance_forward_and_back() {
-        let mut ratchet = Ratchet::new();
+        let mut ratchet = Ratchet::new(&mut thread_rng());
         assert_eq!(ratchet.counter, 0);
         ratchet.advance();
         assert_eq!(ratchet.counter, 1);
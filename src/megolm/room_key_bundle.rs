@@ -0,0 +1,433 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bundles of historical room keys, shared with users invited to a room.
+//!
+//! A [`RoomKeyBundle`] collects the [`ExportedSessionKey`]s of past sessions of
+//! a room, along with the metadata of a [`ForwardedRoomKey`]. The bundle is
+//! encrypted using ChaCha20-Poly1305 under a fresh [`RoomKeyBundleKey`], bound
+//! to the Ed25519 key of the sharer, and the ciphertext is signed by the
+//! sharer's [`Account`].
+//!
+//! The encrypted bundle can be uploaded to untrusted storage, while the
+//! [`RoomKeyBundleKey`] needs to be sent to the invited user over an
+//! authenticated channel, e.g. an Olm [`Session`].
+//!
+//! [`ExportedSessionKey`]: super::ExportedSessionKey
+//! [`Session`]: crate::olm::Session
+
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::{
+    ForwardedRoomKey, ForwardedRoomKeyError, InboundGroupSession,
+    forwarded_room_key::ForwardedRoomKeyContent,
+};
+use crate::{
+    Curve25519PublicKey, Ed25519PublicKey, Ed25519Signature, KeyError, SignatureError,
+    cipher::aead::AeadCipher,
+    olm::Account,
+    utilities::{base64_decode, base64_encode},
+};
+
+/// Error type describing failures to decrypt an [`EncryptedRoomKeyBundle`].
+#[derive(Debug, Error)]
+pub enum RoomKeyBundleError {
+    /// The bundle wasn't signed by the expected sharer.
+    #[error("The signature of the room key bundle was invalid: {0}")]
+    Signature(#[from] SignatureError),
+    /// The bundle couldn't be decrypted using the given key.
+    #[error("The room key bundle couldn't be decrypted")]
+    Decryption,
+    /// The decrypted bundle wasn't valid JSON.
+    #[error("The room key bundle couldn't be deserialized: {0}")]
+    Json(#[from] serde_json::Error),
+    /// One of the room keys in the bundle was invalid.
+    #[error("The room key bundle contained an invalid room key: {0}")]
+    RoomKey(#[from] ForwardedRoomKeyError),
+}
+
+/// The symmetric key a [`RoomKeyBundle`] is encrypted with.
+///
+/// A new key is generated for every bundle.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct RoomKeyBundleKey(Box<[u8; 32]>);
+
+impl RoomKeyBundleKey {
+    /// Generate a new random [`RoomKeyBundleKey`].
+    pub fn new() -> Self {
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Generate a new [`RoomKeyBundleKey`] using the given random number
+    /// generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let mut key = Box::new([0u8; 32]);
+        rng.fill_bytes(key.as_mut_slice());
+
+        Self(key)
+    }
+
+    /// Create a [`RoomKeyBundleKey`] from the given bytes.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Self(Box::new(*bytes))
+    }
+
+    /// Get the raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Encode the key as a base64 string.
+    pub fn to_base64(&self) -> String {
+        base64_encode(self.0.as_slice())
+    }
+
+    /// Decode a key from a base64 string.
+    pub fn from_base64(input: &str) -> Result<Self, KeyError> {
+        let mut bytes = base64_decode(input)?;
+
+        let key = if let Ok(key) = <&[u8; 32]>::try_from(bytes.as_slice()) {
+            Ok(Self::from_bytes(key))
+        } else {
+            Err(KeyError::InvalidKeyLength {
+                key_type: "RoomKeyBundleKey",
+                expected_length: 32,
+                length: bytes.len(),
+            })
+        };
+
+        bytes.zeroize();
+
+        key
+    }
+}
+
+impl Default for RoomKeyBundleKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A collection of room keys to be shared with a user who was invited to a
+/// room.
+#[derive(Debug, Default)]
+pub struct RoomKeyBundle {
+    room_keys: Vec<ForwardedRoomKey>,
+}
+
+/// The plaintext JSON form of a [`RoomKeyBundle`].
+#[derive(Serialize, Deserialize)]
+struct RoomKeyBundleContent {
+    room_keys: Vec<ForwardedRoomKeyContent>,
+}
+
+impl RoomKeyBundle {
+    /// Create a new, empty [`RoomKeyBundle`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the given session to the bundle.
+    ///
+    /// The `sender_key` and `sender_claimed_ed25519_key` are the keys of the
+    /// device which created the session. The session is exported at its first
+    /// known index, and its forwarding chain and [`SessionConfig`] are
+    /// retained.
+    ///
+    /// [`SessionConfig`]: super::SessionConfig
+    ///
+    /// Only sessions which are [shareable] are added, returns `false` if the
+    /// session was skipped.
+    ///
+    /// [shareable]: InboundGroupSession::is_shareable
+    pub fn add_session(
+        &mut self,
+        room_id: &str,
+        sender_key: Curve25519PublicKey,
+        sender_claimed_ed25519_key: Ed25519PublicKey,
+        session: &InboundGroupSession,
+    ) -> bool {
+        if !session.is_shareable() {
+            return false;
+        }
+
+        let room_key = ForwardedRoomKey::new(
+            room_id.to_owned(),
+            sender_key,
+            sender_claimed_ed25519_key,
+            session.export_at_first_known_index(),
+            session.session_config(),
+        )
+        .with_forwarding_chain(session.forwarding_curve25519_key_chain().to_vec());
+
+        self.room_keys.push(room_key);
+
+        true
+    }
+
+    /// The room keys contained in the bundle.
+    pub fn room_keys(&self) -> &[ForwardedRoomKey] {
+        &self.room_keys
+    }
+
+    /// The number of room keys in the bundle.
+    pub fn len(&self) -> usize {
+        self.room_keys.len()
+    }
+
+    /// Whether the bundle contains no room keys.
+    pub fn is_empty(&self) -> bool {
+        self.room_keys.is_empty()
+    }
+
+    /// Create [`InboundGroupSession`]s for all the room keys in the bundle.
+    ///
+    /// Since the [`ExportedSessionKey`]s of the bundle aren't signed by the
+    /// creators of the sessions, the resulting sessions are not authenticated,
+    /// see [`InboundGroupSession::import()`]. Their forwarding chain includes
+    /// the sharer of the bundle, and they use the [`SessionConfig`] of the room
+    /// key.
+    ///
+    /// The sessions aren't [shareable], so they won't be passed on to the next
+    /// user who gets invited unless the caller explicitly marks them as such
+    /// using [`InboundGroupSession::set_shareable()`].
+    ///
+    /// [`ExportedSessionKey`]: super::ExportedSessionKey
+    /// [`SessionConfig`]: super::SessionConfig
+    /// [shareable]: InboundGroupSession::is_shareable
+    pub fn import(&self) -> impl Iterator<Item = (&ForwardedRoomKey, InboundGroupSession)> {
+        self.room_keys
+            .iter()
+            .map(|room_key| (room_key, InboundGroupSession::import_forwarded(room_key)))
+    }
+
+    /// Encrypt the bundle under a fresh [`RoomKeyBundleKey`] and sign it using
+    /// the given [`Account`].
+    ///
+    /// The Curve25519 key of the [`Account`] is appended to the forwarding
+    /// chain of every room key in the bundle.
+    pub fn encrypt(&self, account: &Account) -> (RoomKeyBundleKey, EncryptedRoomKeyBundle) {
+        self.encrypt_with_rng(account, &mut thread_rng())
+    }
+
+    /// Encrypt the bundle under a fresh [`RoomKeyBundleKey`], generated using
+    /// the given random number generator, and sign it using the given
+    /// [`Account`].
+    pub fn encrypt_with_rng(
+        &self,
+        account: &Account,
+        rng: &mut impl CryptoRngCore,
+    ) -> (RoomKeyBundleKey, EncryptedRoomKeyBundle) {
+        let our_identity_key = account.curve25519_key().to_base64();
+
+        let content = RoomKeyBundleContent {
+            room_keys: self
+                .room_keys
+                .iter()
+                .map(|room_key| {
+                    let mut room_key = ForwardedRoomKeyContent::from(room_key);
+                    room_key.forwarding_curve25519_key_chain.push(our_identity_key.clone());
+
+                    room_key
+                })
+                .collect(),
+        };
+
+        #[allow(clippy::expect_used)]
+        let mut plaintext = serde_json::to_vec(&content)
+            .expect("A room key bundle should always be serializable into JSON");
+
+        let key = RoomKeyBundleKey::new_with_rng(rng);
+        let cipher = AeadCipher::new_room_key_bundle(key.as_bytes());
+        let ciphertext = cipher.encrypt(&plaintext, account.ed25519_key().as_bytes());
+        let signature = account.sign(&ciphertext);
+
+        plaintext.zeroize();
+
+        (key, EncryptedRoomKeyBundle { ciphertext, signature })
+    }
+}
+
+/// A [`RoomKeyBundle`] which was encrypted and signed by the sharer.
+#[derive(Debug, Clone)]
+pub struct EncryptedRoomKeyBundle {
+    /// The ciphertext of the bundle, including the authentication tag.
+    pub ciphertext: Vec<u8>,
+    /// The signature of the sharer over the ciphertext.
+    pub signature: Ed25519Signature,
+}
+
+impl EncryptedRoomKeyBundle {
+    /// Verify the signature of the bundle and decrypt it.
+    ///
+    /// The `sharer_key` is the Ed25519 key of the device which shared the
+    /// bundle, it needs to be known from an authenticated source, e.g. the
+    /// Olm session the [`RoomKeyBundleKey`] was received over.
+    pub fn decrypt(
+        &self,
+        key: &RoomKeyBundleKey,
+        sharer_key: Ed25519PublicKey,
+    ) -> Result<RoomKeyBundle, RoomKeyBundleError> {
+        sharer_key.verify(&self.ciphertext, &self.signature)?;
+
+        let cipher = AeadCipher::new_room_key_bundle(key.as_bytes());
+        let mut plaintext = cipher
+            .decrypt(&self.ciphertext, sharer_key.as_bytes())
+            .map_err(|_| RoomKeyBundleError::Decryption)?;
+
+        let content = serde_json::from_slice::<RoomKeyBundleContent>(&plaintext);
+        plaintext.zeroize();
+
+        let room_keys = content?
+            .room_keys
+            .into_iter()
+            .map(ForwardedRoomKey::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(RoomKeyBundle { room_keys })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{RoomKeyBundle, RoomKeyBundleError, RoomKeyBundleKey};
+    use crate::{
+        Curve25519PublicKey, Ed25519Keypair,
+        megolm::{GroupSession, InboundGroupSession, SessionConfig},
+        olm::Account,
+    };
+
+    #[test]
+    fn bundle_roundtrip() {
+        let sharer = Account::new();
+        let creator = Curve25519PublicKey::from_bytes([1u8; 32]);
+        let creator_signing_key = Ed25519Keypair::new().public_key();
+
+        let mut group_session = GroupSession::new(SessionConfig::version_3());
+        let mut shareable = InboundGroupSession::from(&group_session);
+        shareable.set_shareable(true);
+        let private = InboundGroupSession::from(&GroupSession::new(SessionConfig::version_1()));
+        let mut other_version =
+            InboundGroupSession::from(&GroupSession::new(SessionConfig::version_1()));
+        other_version.set_shareable(true);
+
+        let mut bundle = RoomKeyBundle::new();
+        assert!(bundle.add_session("!room:example.org", creator, creator_signing_key, &shareable));
+        assert!(!bundle.add_session("!room:example.org", creator, creator_signing_key, &private));
+        assert!(bundle.add_session(
+            "!room:example.org",
+            creator,
+            creator_signing_key,
+            &other_version
+        ));
+        assert_eq!(bundle.len(), 2);
+
+        let (key, encrypted) = bundle.encrypt(&sharer);
+        let key = RoomKeyBundleKey::from_base64(&key.to_base64()).unwrap();
+        let decrypted = encrypted.decrypt(&key, sharer.ed25519_key()).unwrap();
+
+        let room_key = &decrypted.room_keys()[0];
+        assert_eq!(room_key.session_config(), SessionConfig::version_3());
+        assert_eq!(room_key.room_id(), "!room:example.org");
+        assert_eq!(room_key.sender_key(), creator);
+        assert_eq!(room_key.sender_claimed_ed25519_key(), creator_signing_key);
+        assert_eq!(room_key.forwarding_curve25519_key_chain(), [sharer.curve25519_key()]);
+
+        let message = group_session.encrypt("It's a secret to everybody");
+        let mut sessions = decrypted.import();
+        let (_, mut session) = sessions.next().unwrap();
+        let (_, other_session) = sessions.next().unwrap();
+
+        assert_eq!(session.session_id(), group_session.session_id());
+        assert_eq!(session.session_config(), SessionConfig::version_3());
+        assert_eq!(other_session.session_config(), SessionConfig::version_1());
+        assert!(!session.origin().is_authenticated());
+        assert!(!session.is_shareable());
+        assert_eq!(session.decrypt(&message).unwrap().plaintext, b"It's a secret to everybody");
+    }
+
+    #[test]
+    fn imported_sessions_are_not_reshared() {
+        let sharer = Account::new();
+        let mut session = InboundGroupSession::from(&GroupSession::new(SessionConfig::version_1()));
+        session.set_shareable(true);
+
+        let mut bundle = RoomKeyBundle::new();
+        bundle.add_session(
+            "!room:example.org",
+            sharer.curve25519_key(),
+            sharer.ed25519_key(),
+            &session,
+        );
+
+        let (key, encrypted) = bundle.encrypt(&sharer);
+        let decrypted = encrypted.decrypt(&key, sharer.ed25519_key()).unwrap();
+        let (room_key, mut imported) = decrypted.import().next().unwrap();
+
+        let mut next_bundle = RoomKeyBundle::new();
+        assert!(!next_bundle.add_session(
+            room_key.room_id(),
+            room_key.sender_key(),
+            room_key.sender_claimed_ed25519_key(),
+            &imported
+        ));
+        assert!(next_bundle.is_empty());
+
+        imported.set_shareable(true);
+        assert!(next_bundle.add_session(
+            room_key.room_id(),
+            room_key.sender_key(),
+            room_key.sender_claimed_ed25519_key(),
+            &imported
+        ));
+    }
+
+    #[test]
+    fn bundle_is_bound_to_the_sharer() {
+        let sharer = Account::new();
+        let mut session = InboundGroupSession::from(&GroupSession::new(SessionConfig::version_1()));
+        session.set_shareable(true);
+
+        let mut bundle = RoomKeyBundle::new();
+        bundle.add_session(
+            "!room:example.org",
+            sharer.curve25519_key(),
+            sharer.ed25519_key(),
+            &session,
+        );
+
+        let (key, mut encrypted) = bundle.encrypt(&sharer);
+
+        assert_matches!(
+            encrypted.decrypt(&key, Account::new().ed25519_key()),
+            Err(RoomKeyBundleError::Signature(_))
+        );
+        assert_matches!(
+            encrypted.decrypt(&RoomKeyBundleKey::new(), sharer.ed25519_key()),
+            Err(RoomKeyBundleError::Decryption)
+        );
+
+        encrypted.ciphertext[0] ^= 1;
+        assert_matches!(
+            encrypted.decrypt(&key, sharer.ed25519_key()),
+            Err(RoomKeyBundleError::Signature(_))
+        );
+    }
+}
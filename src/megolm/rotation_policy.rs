@@ -0,0 +1,101 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Policy describing when a [`GroupSession`] should be replaced by a new one.
+///
+/// Rotating group sessions limits the number of messages a leaked session
+/// exposes. The limits mirror the `rotation_period_ms` and
+/// `rotation_period_msgs` fields of the Matrix `m.room.encryption` state
+/// event, the default policy uses their default values of a week and 100
+/// messages.
+///
+/// [`GroupSession`]: super::GroupSession
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationPolicy {
+    max_age: Duration,
+    max_messages: u64,
+}
+
+impl RotationPolicy {
+    /// The maximal age of a session under the default policy, one week.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_millis(604_800_000);
+    /// The maximal number of messages a session encrypts under the default
+    /// policy.
+    pub const DEFAULT_MAX_MESSAGES: u64 = 100;
+
+    /// Create a new policy rotating sessions once they are older than
+    /// `max_age` or have encrypted `max_messages` messages.
+    pub const fn new(max_age: Duration, max_messages: u64) -> Self {
+        Self { max_age, max_messages }
+    }
+
+    /// Create a new policy from the `rotation_period_ms` and
+    /// `rotation_period_msgs` fields of a Matrix `m.room.encryption` event,
+    /// missing fields fall back to the default values.
+    pub const fn from_rotation_periods(
+        rotation_period_ms: Option<u64>,
+        rotation_period_msgs: Option<u64>,
+    ) -> Self {
+        let max_age = match rotation_period_ms {
+            Some(period) => Duration::from_millis(period),
+            None => Self::DEFAULT_MAX_AGE,
+        };
+        let max_messages = match rotation_period_msgs {
+            Some(period) => period,
+            None => Self::DEFAULT_MAX_MESSAGES,
+        };
+
+        Self::new(max_age, max_messages)
+    }
+
+    /// The age after which a session should be rotated.
+    pub const fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// The number of messages after which a session should be rotated.
+    pub const fn max_messages(&self) -> u64 {
+        self.max_messages
+    }
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_AGE, Self::DEFAULT_MAX_MESSAGES)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::RotationPolicy;
+
+    #[test]
+    fn from_rotation_periods() {
+        assert_eq!(RotationPolicy::from_rotation_periods(None, None), RotationPolicy::default());
+
+        let policy = RotationPolicy::from_rotation_periods(Some(3_600_000), None);
+        assert_eq!(policy.max_age(), Duration::from_secs(3600));
+        assert_eq!(policy.max_messages(), RotationPolicy::DEFAULT_MAX_MESSAGES);
+
+        let policy = RotationPolicy::from_rotation_periods(None, Some(10));
+        assert_eq!(policy.max_age(), RotationPolicy::DEFAULT_MAX_AGE);
+        assert_eq!(policy.max_messages(), 10);
+    }
+}
@@ -14,17 +14,23 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::PaddingPolicy;
+
 /// A struct to configure how Megolm sessions should work under the hood.
-/// Currently only the MAC truncation behaviour can be configured.
+/// The version decides how individual messages are encrypted and
+/// authenticated, additionally the padding of plaintexts can be configured.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SessionConfig {
     pub(super) version: Version,
+    #[serde(default, skip_serializing_if = "PaddingPolicy::is_none")]
+    pub(super) padding: PaddingPolicy,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub(super) enum Version {
     V1 = 1,
     V2 = 2,
+    V3 = 3,
 }
 
 impl SessionConfig {
@@ -37,14 +43,78 @@ impl SessionConfig {
     /// Megolm uses AES-256 and HMAC with a truncated MAC to encrypt individual
     /// messages. The MAC will be truncated to 8 bytes.
     pub const fn version_1() -> Self {
-        SessionConfig { version: Version::V1 }
+        SessionConfig { version: Version::V1, padding: PaddingPolicy::None }
     }
 
     /// Create a `SessionConfig` for the Megolm version 2. This version of
     /// Megolm uses AES-256 and HMAC to encrypt individual messages. The MAC
     /// won't be truncated.
     pub const fn version_2() -> Self {
-        SessionConfig { version: Version::V2 }
+        SessionConfig { version: Version::V2, padding: PaddingPolicy::None }
+    }
+
+    /// Create a `SessionConfig` for the Megolm version 3. This version of
+    /// Megolm uses ChaCha20-Poly1305, keyed from the ratchet, to encrypt and
+    /// authenticate individual messages.
+    ///
+    /// Unlike the previous versions, it allows the caller to bind messages to
+    /// a context, e.g. the room and the sender, using associated data. A
+    /// message can then only be decrypted if the same associated data is
+    /// supplied, see [`GroupSession::encrypt_with_associated_data()`].
+    ///
+    /// Messages of this version can't be decrypted by sessions using another
+    /// version, or by libolm.
+    ///
+    /// [`GroupSession::encrypt_with_associated_data()`]: super::GroupSession::encrypt_with_associated_data
+    pub const fn version_3() -> Self {
+        SessionConfig { version: Version::V3, padding: PaddingPolicy::None }
+    }
+
+    /// The Matrix algorithm identifier of room keys and encrypted events
+    /// belonging to sessions using this `SessionConfig`.
+    ///
+    /// Megolm version 3 isn't part of the Matrix specification, its identifier
+    /// is only understood by vodozemac.
+    pub(super) const fn algorithm(&self) -> &'static str {
+        match self.version {
+            Version::V1 => "m.megolm.v1.aes-sha2",
+            Version::V2 => "m.megolm.v2.aes-sha2",
+            Version::V3 => "org.matrix.vodozemac.megolm.v3.chacha20-poly1305",
+        }
+    }
+
+    /// Get the `SessionConfig` the given Matrix algorithm identifier belongs
+    /// to, the inverse of [`SessionConfig::algorithm()`].
+    ///
+    /// Returns `None` if the algorithm isn't a supported version of Megolm.
+    /// The padding isn't part of the algorithm, the returned `SessionConfig`
+    /// doesn't pad plaintexts.
+    pub(super) fn from_algorithm(algorithm: &str) -> Option<Self> {
+        [Self::version_1(), Self::version_2(), Self::version_3()]
+            .into_iter()
+            .find(|config| config.algorithm() == algorithm)
+    }
+
+    /// Does this `SessionConfig` support authenticating caller-supplied
+    /// associated data, i.e. is it using Megolm version 3.
+    pub const fn supports_associated_data(&self) -> bool {
+        matches!(self.version, Version::V3)
+    }
+
+    /// Get the [`PaddingPolicy`] plaintexts are padded with before they get
+    /// encrypted.
+    pub const fn padding(&self) -> PaddingPolicy {
+        self.padding
+    }
+
+    /// Pad plaintexts using the given [`PaddingPolicy`] before they get
+    /// encrypted, to hide their length.
+    ///
+    /// Padded messages are marked as such, the receiving side removes the
+    /// padding regardless of its own configuration.
+    pub const fn with_padding(mut self, padding: PaddingPolicy) -> Self {
+        self.padding = padding;
+        self
     }
 }
 
@@ -56,11 +126,48 @@ impl Default for SessionConfig {
 
 #[cfg(test)]
 mod test {
-    use crate::megolm::{SessionConfig, session_config::Version};
+    use crate::{
+        PaddingPolicy,
+        megolm::{SessionConfig, session_config::Version},
+    };
 
     #[test]
     fn version() {
         assert_eq!(SessionConfig::version_1().version(), Version::V1 as u8);
         assert_eq!(SessionConfig::version_2().version(), Version::V2 as u8);
+        assert_eq!(SessionConfig::version_3().version(), Version::V3 as u8);
+    }
+
+    #[test]
+    fn padding() {
+        let config = SessionConfig::version_2().with_padding(PaddingPolicy::Padme);
+
+        assert_eq!(SessionConfig::version_2().padding(), PaddingPolicy::None);
+        assert_eq!(config.padding(), PaddingPolicy::Padme);
+        assert_eq!(config.version(), Version::V2 as u8);
+    }
+
+    #[test]
+    fn algorithm() {
+        for config in
+            [SessionConfig::version_1(), SessionConfig::version_2(), SessionConfig::version_3()]
+        {
+            assert_eq!(SessionConfig::from_algorithm(config.algorithm()), Some(config));
+        }
+
+        assert_eq!(SessionConfig::version_1().algorithm(), "m.megolm.v1.aes-sha2");
+        assert_eq!(SessionConfig::from_algorithm("m.olm.v1.curve25519-aes-sha2"), None);
+    }
+
+    #[test]
+    fn serialization_without_padding() {
+        let config = SessionConfig::version_1();
+        let json = serde_json::to_string(&config).expect("The config should serialize");
+
+        assert_eq!(json, r#"{"version":"V1"}"#);
+        assert_eq!(
+            serde_json::from_str::<SessionConfig>(&json).expect("The config should deserialize"),
+            config
+        );
     }
 }
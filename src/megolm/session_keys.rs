@@ -68,10 +68,16 @@ impl ExportedSessionKey {
     fn to_bytes_with_version(&self, version: u8) -> Vec<u8> {
         let index = self.ratchet_index.to_be_bytes();
 
-        [[version].as_ref(), index.as_ref(), self.ratchet.as_ref(), self.signing_key.as_bytes()]
+        [[version].as_slice(), index.as_slice(), self.ratchet.as_ref(), self.signing_key.as_bytes()]
             .concat()
     }
 
+    /// The public part of the Ed25519 key the messages of the session are
+    /// signed with.
+    pub const fn signing_key(&self) -> Ed25519PublicKey {
+        self.signing_key
+    }
+
     /// Serialize the `ExportedSessionKey` to a byte vector.
     pub fn to_bytes(&self) -> Vec<u8> {
         self.to_bytes_with_version(Self::VERSION)
@@ -233,6 +239,12 @@ impl SessionKey {
         self.session_key.to_bytes_with_version(Self::VERSION)
     }
 
+    /// The public part of the Ed25519 key the messages of the session are
+    /// signed with.
+    pub const fn signing_key(&self) -> Ed25519PublicKey {
+        self.session_key.signing_key
+    }
+
     /// Serialize the `SessionKey` to a byte vector.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = self.to_signature_bytes();
@@ -0,0 +1,175 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+
+use super::PUBLIC_MAX_ONE_TIME_KEYS;
+use crate::types::{KeyId, MlKem768PublicKey, MlKem768SecretKey};
+
+/// The single-use ML-KEM-768 keys of an [`Account`], used to establish hybrid,
+/// Olm version 3, sessions.
+///
+/// [`Account`]: super::Account
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "KemKeysPickle")]
+#[serde(into = "KemKeysPickle")]
+pub(super) struct KemKeys {
+    pub next_key_id: u64,
+    pub unpublished_public_keys: BTreeMap<KeyId, MlKem768PublicKey>,
+    pub private_keys: BTreeMap<KeyId, MlKem768SecretKey>,
+    pub key_ids_by_hash: HashMap<[u8; 32], KeyId>,
+}
+
+impl KemKeys {
+    // ML-KEM-768 public keys are more than a kilobyte large, so we keep far fewer
+    // of them around than we do for Curve25519 one-time keys.
+    const MAX_KEM_KEYS: usize = 10 * PUBLIC_MAX_ONE_TIME_KEYS;
+
+    pub fn new() -> Self {
+        Self {
+            next_key_id: 0,
+            unpublished_public_keys: Default::default(),
+            private_keys: Default::default(),
+            key_ids_by_hash: Default::default(),
+        }
+    }
+
+    pub fn mark_as_published(&mut self) {
+        self.unpublished_public_keys.clear();
+    }
+
+    pub fn get_secret_key(&self, key_hash: &[u8; 32]) -> Option<&MlKem768SecretKey> {
+        self.key_ids_by_hash.get(key_hash).and_then(|key_id| self.private_keys.get(key_id))
+    }
+
+    pub fn remove_secret_key(&mut self, key_hash: &[u8; 32]) -> Option<MlKem768SecretKey> {
+        self.key_ids_by_hash.remove(key_hash).and_then(|key_id| {
+            self.unpublished_public_keys.remove(&key_id);
+            self.private_keys.remove(&key_id)
+        })
+    }
+
+    fn insert_secret_key(
+        &mut self,
+        key_id: KeyId,
+        key: MlKem768SecretKey,
+        public_key: MlKem768PublicKey,
+        published: bool,
+    ) {
+        // Like for one-time keys, the oldest key makes place for the new one once
+        // the store is full.
+        if self.private_keys.len() >= Self::MAX_KEM_KEYS {
+            if let Some((key_id, private_key)) = self.private_keys.pop_first() {
+                self.key_ids_by_hash.remove(&private_key.public_key().key_hash());
+                self.unpublished_public_keys.remove(&key_id);
+            }
+        }
+
+        self.private_keys.insert(key_id, key);
+        self.key_ids_by_hash.insert(public_key.key_hash(), key_id);
+
+        if !published {
+            self.unpublished_public_keys.insert(key_id, public_key);
+        }
+    }
+
+    pub fn generate(&mut self, count: usize, rng: &mut impl CryptoRngCore) {
+        for _ in 0..count {
+            let key = MlKem768SecretKey::new(rng);
+            let public_key = key.public_key();
+
+            self.insert_secret_key(KeyId(self.next_key_id), key, public_key, false);
+            self.next_key_id = self.next_key_id.wrapping_add(1);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(super) struct KemKeysPickle {
+    next_key_id: u64,
+    private_keys: BTreeMap<KeyId, MlKem768SecretKey>,
+    unpublished_key_ids: BTreeSet<KeyId>,
+}
+
+impl Default for KemKeysPickle {
+    fn default() -> Self {
+        KemKeys::new().into()
+    }
+}
+
+impl From<KemKeysPickle> for KemKeys {
+    fn from(pickle: KemKeysPickle) -> Self {
+        let mut keys = KemKeys { next_key_id: pickle.next_key_id, ..KemKeys::new() };
+
+        // Only the private keys are pickled, the public keys are derived from
+        // them again.
+        for (key_id, key) in pickle.private_keys {
+            let public_key = key.public_key();
+            let published = !pickle.unpublished_key_ids.contains(&key_id);
+
+            keys.insert_secret_key(key_id, key, public_key, published);
+        }
+
+        keys
+    }
+}
+
+impl From<KemKeys> for KemKeysPickle {
+    fn from(keys: KemKeys) -> Self {
+        KemKeysPickle {
+            next_key_id: keys.next_key_id,
+            unpublished_key_ids: keys.unpublished_public_keys.into_keys().collect(),
+            private_keys: keys.private_keys,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use super::{KemKeys, KemKeysPickle};
+
+    #[test]
+    fn store_limit() {
+        let mut store = KemKeys::new();
+
+        store.generate(KemKeys::MAX_KEM_KEYS, &mut thread_rng());
+        assert_eq!(store.private_keys.len(), KemKeys::MAX_KEM_KEYS);
+
+        let oldest_hash = store.unpublished_public_keys.values().next().unwrap().key_hash();
+        store.generate(1, &mut thread_rng());
+
+        assert_eq!(store.private_keys.len(), KemKeys::MAX_KEM_KEYS);
+        assert_eq!(store.key_ids_by_hash.len(), KemKeys::MAX_KEM_KEYS);
+        assert!(store.get_secret_key(&oldest_hash).is_none());
+    }
+
+    #[test]
+    fn pickle_roundtrip() {
+        let mut store = KemKeys::new();
+        store.generate(2, &mut thread_rng());
+        store.mark_as_published();
+        store.generate(1, &mut thread_rng());
+
+        let unpickled = KemKeys::from(KemKeysPickle::from(store.clone()));
+
+        assert_eq!(unpickled.next_key_id, 3);
+        assert_eq!(unpickled.unpublished_public_keys, store.unpublished_public_keys);
+        assert_eq!(unpickled.key_ids_by_hash, store.key_ids_by_hash);
+    }
+}
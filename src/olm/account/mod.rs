@@ -13,6 +13,7 @@
 // limitations under the License.
 
 mod fallback_keys;
+mod kem_keys;
 mod one_time_keys;
 
 use std::collections::HashMap;
@@ -22,6 +23,7 @@ use chacha20poly1305::{
     aead::{Aead, AeadCore, KeyInit},
 };
 use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use x25519_dalek::ReusableSecret;
@@ -30,22 +32,23 @@ use zeroize::Zeroize;
 pub use self::one_time_keys::OneTimeKeyGenerationResult;
 use self::{
     fallback_keys::FallbackKeys,
+    kem_keys::{KemKeys, KemKeysPickle},
     one_time_keys::{OneTimeKeys, OneTimeKeysPickle},
 };
 use super::{
     SessionConfig,
-    messages::PreKeyMessage,
+    messages::{KemEncapsulation, PreKeyMessage},
     session::{DecryptionError, Session},
     session_keys::SessionKeys,
     shared_secret::{RemoteShared3DHSecret, Shared3DHSecret},
 };
 use crate::{
-    Ed25519Signature, PickleError,
+    Ed25519Signature, PickleEncoding, PickleError,
     types::{
         Curve25519Keypair, Curve25519KeypairPickle, Curve25519PublicKey, Curve25519SecretKey,
-        Ed25519Keypair, Ed25519KeypairPickle, Ed25519PublicKey, KeyId,
+        Ed25519Keypair, Ed25519KeypairPickle, Ed25519PublicKey, KeyId, MlKem768PublicKey,
     },
-    utilities::{pickle, unpickle},
+    utilities::{pickle, pickle_with_encoding, pickle_with_rng, unpickle},
 };
 
 const PUBLIC_MAX_ONE_TIME_KEYS: usize = 50;
@@ -59,6 +62,11 @@ pub enum SessionCreationError {
     /// already been used up.
     #[error("The pre-key message contained an unknown one-time key: {0}")]
     MissingOneTimeKey(Curve25519PublicKey),
+    /// The pre-key message was encapsulated for an unknown ML-KEM-768 key.
+    /// This happens either because we never had such a key, or because it has
+    /// already been used up.
+    #[error("The pre-key message was encapsulated for an unknown ML-KEM-768 key")]
+    MissingKemKey,
     /// The pre-key message contains a Curve25519 identity key that doesn't
     /// match to the identity key that was given.
     #[error(
@@ -116,16 +124,26 @@ pub struct Account {
     /// the 3DH, in case we run out of those. We keep track of both the current
     /// and the previous fallback key in any given moment.
     fallback_keys: FallbackKeys,
+    /// The single-use ML-KEM-768 keys used, in addition to the 3DH, to
+    /// establish hybrid post-quantum sessions.
+    kem_keys: KemKeys,
 }
 
 impl Account {
     /// Create a new [`Account`] with new random identity keys.
     pub fn new() -> Self {
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Create a new [`Account`] with new random identity keys, generated using
+    /// the given random number generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
         Self {
-            signing_key: Ed25519Keypair::new(),
-            diffie_hellman_key: Curve25519Keypair::new(),
+            signing_key: Ed25519Keypair::new_with_rng(rng),
+            diffie_hellman_key: Curve25519Keypair::new(rng),
             one_time_keys: OneTimeKeys::new(),
             fallback_keys: FallbackKeys::new(),
+            kem_keys: KemKeys::new(),
         }
     }
 
@@ -169,31 +187,117 @@ impl Account {
     }
 
     /// Create a [`Session`] with the given identity key and one-time key.
+    ///
+    /// A [`SessionConfig::version_3()`] config falls back to version 2 since
+    /// no ML-KEM-768 key is given, use
+    /// [`Account::create_outbound_session_with_kem_key()`] to establish a
+    /// hybrid session.
     pub fn create_outbound_session(
         &self,
         session_config: SessionConfig,
         identity_key: Curve25519PublicKey,
         one_time_key: Curve25519PublicKey,
     ) -> Session {
-        let rng = thread_rng();
+        self.create_outbound_session_with_rng(
+            session_config,
+            identity_key,
+            one_time_key,
+            &mut thread_rng(),
+        )
+    }
 
-        let base_key = ReusableSecret::random_from_rng(rng);
+    /// Create a [`Session`] with the given identity key and one-time key, using
+    /// the given random number generator to create the base key and the first
+    /// ratchet key of the [`Session`].
+    pub fn create_outbound_session_with_rng(
+        &self,
+        session_config: SessionConfig,
+        identity_key: Curve25519PublicKey,
+        one_time_key: Curve25519PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Session {
+        self.create_outbound_session_with_kem_key_and_rng(
+            session_config,
+            identity_key,
+            one_time_key,
+            None,
+            rng,
+        )
+    }
+
+    /// Create a [`Session`] with the given identity key, one-time key and,
+    /// optionally, ML-KEM-768 key.
+    ///
+    /// If the `session_config` is [`SessionConfig::version_3()`] and a
+    /// `kem_key` is given, a shared secret is encapsulated for the `kem_key`
+    /// and mixed into the triple Diffie-Hellman, protecting the session
+    /// against attackers that record the traffic today and gain access to a
+    /// quantum computer in the future. The ciphertext of the encapsulation is
+    /// sent along in every pre-key message.
+    ///
+    /// If the other side didn't publish an ML-KEM-768 key, the session falls
+    /// back to version 2. The `kem_key` is ignored for other versions.
+    pub fn create_outbound_session_with_kem_key(
+        &self,
+        session_config: SessionConfig,
+        identity_key: Curve25519PublicKey,
+        one_time_key: Curve25519PublicKey,
+        kem_key: Option<&MlKem768PublicKey>,
+    ) -> Session {
+        self.create_outbound_session_with_kem_key_and_rng(
+            session_config,
+            identity_key,
+            one_time_key,
+            kem_key,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Create a [`Session`] with the given identity key, one-time key and,
+    /// optionally, ML-KEM-768 key, using the given random number generator.
+    ///
+    /// See [`Account::create_outbound_session_with_kem_key()`] for more
+    /// details.
+    pub fn create_outbound_session_with_kem_key_and_rng(
+        &self,
+        session_config: SessionConfig,
+        identity_key: Curve25519PublicKey,
+        one_time_key: Curve25519PublicKey,
+        kem_key: Option<&MlKem768PublicKey>,
+        rng: &mut impl CryptoRngCore,
+    ) -> Session {
+        let base_key = ReusableSecret::random_from_rng(&mut *rng);
         let public_base_key = Curve25519PublicKey::from(&base_key);
 
-        let shared_secret = Shared3DHSecret::new(
+        let mut shared_secret = Shared3DHSecret::new(
             self.diffie_hellman_key.secret_key(),
             &base_key,
             &identity_key,
             &one_time_key,
         );
 
+        let (session_config, kem) = match kem_key {
+            Some(kem_key) if session_config.uses_kem() => {
+                let (ciphertext, kem_secret) = kem_key.encapsulate(rng);
+                shared_secret = shared_secret.with_kem_secret(kem_secret, &ciphertext, kem_key);
+
+                let kem = KemEncapsulation { key_hash: kem_key.key_hash(), ciphertext };
+
+                (session_config, Some(kem))
+            }
+            None if session_config.uses_kem() => {
+                (SessionConfig::version_2().with_padding(session_config.padding()), None)
+            }
+            _ => (session_config, None),
+        };
+
         let session_keys = SessionKeys {
             identity_key: self.curve25519_key(),
             base_key: public_base_key,
             one_time_key,
         };
 
-        Session::new(session_config, shared_secret, session_keys)
+        Session::new(session_config, shared_secret, session_keys, kem, rng)
     }
 
     /// Try to find a [`Curve25519SecretKey`] that forms a pair with the given
@@ -232,6 +336,20 @@ impl Account {
         &mut self,
         their_identity_key: Curve25519PublicKey,
         pre_key_message: &PreKeyMessage,
+    ) -> Result<InboundCreationResult, SessionCreationError> {
+        self.create_inbound_session_with_rng(their_identity_key, pre_key_message, &mut thread_rng())
+    }
+
+    /// Create a [`Session`] from the given [`PreKeyMessage`] message and
+    /// identity key, using the given random number generator if decrypting the
+    /// message requires a new ratchet key.
+    ///
+    /// See [`Account::create_inbound_session()`] for more details.
+    pub fn create_inbound_session_with_rng(
+        &mut self,
+        their_identity_key: Curve25519PublicKey,
+        pre_key_message: &PreKeyMessage,
+        rng: &mut impl CryptoRngCore,
     ) -> Result<InboundCreationResult, SessionCreationError> {
         if their_identity_key != pre_key_message.identity_key() {
             Err(SessionCreationError::MismatchedIdentityKey(
@@ -247,13 +365,29 @@ impl Account {
                 .ok_or(SessionCreationError::MissingOneTimeKey(public_otk))?;
 
             // Construct a 3DH shared secret from the various curve25519 keys.
-            let shared_secret = RemoteShared3DHSecret::new(
+            let mut shared_secret = RemoteShared3DHSecret::new(
                 self.diffie_hellman_key.secret_key(),
                 private_otk,
                 &pre_key_message.identity_key(),
                 &pre_key_message.base_key(),
             );
 
+            // Hybrid sessions additionally mix in the secret that was
+            // encapsulated for one of our ML-KEM-768 keys.
+            if let Some(kem) = &pre_key_message.kem {
+                let kem_key = self
+                    .kem_keys
+                    .get_secret_key(&kem.key_hash)
+                    .ok_or(SessionCreationError::MissingKemKey)?;
+
+                let kem_secret = kem_key.decapsulate(&kem.ciphertext);
+                shared_secret = shared_secret.with_kem_secret(
+                    kem_secret,
+                    &kem.ciphertext,
+                    &kem_key.public_key(),
+                );
+            }
+
             // These will be used to uniquely identify the Session.
             let session_keys = SessionKeys {
                 identity_key: pre_key_message.identity_key(),
@@ -263,21 +397,21 @@ impl Account {
 
             let config = if pre_key_message.message.mac_truncated() {
                 SessionConfig::version_1()
+            } else if pre_key_message.message.is_header_encrypted() {
+                SessionConfig::version_4()
+            } else if pre_key_message.kem.is_some() {
+                SessionConfig::version_3()
             } else {
                 SessionConfig::version_2()
             };
 
             // Create a Session, AKA a double ratchet, this one will have an
             // inactive sending chain until we decide to encrypt a message.
-            let mut session = Session::new_remote(
-                config,
-                shared_secret,
-                pre_key_message.message.ratchet_key,
-                session_keys,
-            );
+            let mut session =
+                Session::new_remote(config, shared_secret, &pre_key_message.message, session_keys)?;
 
             // Decrypt the message to check if the Session is actually valid.
-            let plaintext = session.decrypt_decoded(&pre_key_message.message)?;
+            let plaintext = session.decrypt_decoded(&pre_key_message.message, rng)?;
 
             // We only drop the one-time key now, this is why we can't use a
             // one-time key type that takes `self`. If we didn't do this,
@@ -288,6 +422,11 @@ impl Account {
             // scenario.
             self.remove_one_time_key_helper(pre_key_message.one_time_key());
 
+            // The same goes for the ML-KEM-768 key.
+            if let Some(kem) = &pre_key_message.kem {
+                self.kem_keys.remove_secret_key(&kem.key_hash);
+            }
+
             Ok(InboundCreationResult { session, plaintext })
         }
     }
@@ -301,7 +440,19 @@ impl Account {
     /// is completely populated, the oldest one-time keys will get discarded
     /// to make place for new ones.
     pub fn generate_one_time_keys(&mut self, count: usize) -> OneTimeKeyGenerationResult {
-        self.one_time_keys.generate(count)
+        self.generate_one_time_keys_with_rng(count, &mut thread_rng())
+    }
+
+    /// Generates the supplied number of one time keys using the given random
+    /// number generator.
+    ///
+    /// See [`Account::generate_one_time_keys()`] for more details.
+    pub fn generate_one_time_keys_with_rng(
+        &mut self,
+        count: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> OneTimeKeyGenerationResult {
+        self.one_time_keys.generate(count, rng)
     }
 
     /// Get the number of one-time keys we have stored locally.
@@ -326,6 +477,42 @@ impl Account {
             .collect()
     }
 
+    /// Generates the supplied number of single-use ML-KEM-768 keys.
+    ///
+    /// The keys should be published alongside the one-time keys, others can
+    /// then use them to establish hybrid post-quantum sessions with
+    /// [`Account::create_outbound_session_with_kem_key()`]. Like for one-time
+    /// keys, the oldest keys are discarded once the store inside the
+    /// [`Account`] is full.
+    pub fn generate_kem_keys(&mut self, count: usize) {
+        self.generate_kem_keys_with_rng(count, &mut thread_rng())
+    }
+
+    /// Generates the supplied number of single-use ML-KEM-768 keys using the
+    /// given random number generator.
+    ///
+    /// See [`Account::generate_kem_keys()`] for more details.
+    pub fn generate_kem_keys_with_rng(&mut self, count: usize, rng: &mut impl CryptoRngCore) {
+        self.kem_keys.generate(count, rng)
+    }
+
+    /// Get the number of ML-KEM-768 keys we have stored locally.
+    ///
+    /// Each time a hybrid [`Session`] is created using the
+    /// [`Account::create_inbound_session()`] an ML-KEM-768 key will be used up
+    /// and removed.
+    pub fn stored_kem_key_count(&self) -> usize {
+        self.kem_keys.private_keys.len()
+    }
+
+    /// Get the currently unpublished ML-KEM-768 keys.
+    ///
+    /// The keys should be published to a server and marked as published using
+    /// the `mark_keys_as_published()` method.
+    pub fn kem_keys(&self) -> HashMap<KeyId, MlKem768PublicKey> {
+        self.kem_keys.unpublished_public_keys.clone().into_iter().collect()
+    }
+
     /// Generate a single new fallback key.
     ///
     /// The fallback key will be used by other users to establish a [`Session`]
@@ -335,7 +522,18 @@ impl Account {
     /// is, the one that will get removed from the [`Account`] when this method
     /// is called. This return value is mostly useful for logging purposes.
     pub fn generate_fallback_key(&mut self) -> Option<Curve25519PublicKey> {
-        self.fallback_keys.generate_fallback_key()
+        self.generate_fallback_key_with_rng(&mut thread_rng())
+    }
+
+    /// Generate a single new fallback key using the given random number
+    /// generator.
+    ///
+    /// See [`Account::generate_fallback_key()`] for more details.
+    pub fn generate_fallback_key_with_rng(
+        &mut self,
+        rng: &mut impl CryptoRngCore,
+    ) -> Option<Curve25519PublicKey> {
+        self.fallback_keys.generate_fallback_key(rng)
     }
 
     /// Get the currently unpublished fallback key.
@@ -359,9 +557,11 @@ impl Account {
         self.fallback_keys.forget_previous_fallback_key().is_some()
     }
 
-    /// Mark all currently unpublished one-time and fallback keys as published.
+    /// Mark all currently unpublished one-time, ML-KEM-768 and fallback keys as
+    /// published.
     pub fn mark_keys_as_published(&mut self) {
         self.one_time_keys.mark_as_published();
+        self.kem_keys.mark_as_published();
         self.fallback_keys.mark_as_published();
     }
 
@@ -373,6 +573,7 @@ impl Account {
             diffie_hellman_key: self.diffie_hellman_key.clone().into(),
             one_time_keys: self.one_time_keys.clone().into(),
             fallback_keys: self.fallback_keys.clone(),
+            kem_keys: self.kem_keys.clone().into(),
         }
     }
 
@@ -473,6 +674,18 @@ impl Account {
     pub fn to_dehydrated_device(
         &self,
         key: &[u8; 32],
+    ) -> Result<DehydratedDeviceResult, crate::DehydratedDeviceError> {
+        self.to_dehydrated_device_with_rng(key, &mut thread_rng())
+    }
+
+    /// Create a dehydrated device from the account, using the given random
+    /// number generator to create the nonce.
+    ///
+    /// See [`Account::to_dehydrated_device()`] for more details.
+    pub fn to_dehydrated_device_with_rng(
+        &self,
+        key: &[u8; 32],
+        rng: &mut impl CryptoRngCore,
     ) -> Result<DehydratedDeviceResult, crate::DehydratedDeviceError> {
         use matrix_pickle::Encode;
 
@@ -485,7 +698,6 @@ impl Account {
             .map_err(|e| DehydratedDeviceError::LibolmPickle(LibolmPickleError::Encode(e)))?;
 
         let cipher = ChaCha20Poly1305::new(key.into());
-        let rng = thread_rng();
         let nonce = ChaCha20Poly1305::generate_nonce(rng);
         let ciphertext = cipher.encrypt(&nonce, encoded.as_slice());
 
@@ -569,16 +781,51 @@ pub struct AccountPickle {
     diffie_hellman_key: Curve25519KeypairPickle,
     one_time_keys: OneTimeKeysPickle,
     fallback_keys: FallbackKeys,
+    #[serde(default)]
+    kem_keys: KemKeysPickle,
 }
 
 /// A format suitable for serialization which implements [`serde::Serialize`]
 /// and [`serde::Deserialize`]. Obtainable by calling [`Account::pickle`].
 impl AccountPickle {
+    const PICKLE_TYPE: &'static str = "Account";
+
     /// Serialize and encrypt the pickle using the given key.
     ///
     /// This is the inverse of [`AccountPickle::from_encrypted`].
     pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
-        pickle(&self, pickle_key)
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`AccountPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, encoding it using
+    /// the given [`PickleEncoding`].
+    ///
+    /// This is the inverse of [`AccountPickle::from_encrypted`], which detects
+    /// the encoding automatically.
+    pub fn encrypt_with_encoding(self, pickle_key: &[u8; 32], encoding: PickleEncoding) -> String {
+        pickle_with_encoding(&self, pickle_key, Self::PICKLE_TYPE, encoding)
+    }
+
+    /// Serialize and encrypt the pickle using the given key and
+    /// [`PickleEncoding`], generating the salt of the encrypted pickle using
+    /// the given random number generator.
+    ///
+    /// See [`AccountPickle::encrypt_with_encoding`] for more details.
+    pub fn encrypt_with_encoding_and_rng(
+        self,
+        pickle_key: &[u8; 32],
+        encoding: PickleEncoding,
+        rng: &mut impl CryptoRngCore,
+    ) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, encoding, rng)
     }
 
     /// Obtain a pickle from a ciphertext by decrypting and deserializing using
@@ -586,7 +833,7 @@ impl AccountPickle {
     ///
     /// This is the inverse of [`AccountPickle::encrypt`].
     pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
-        unpickle(ciphertext, pickle_key)
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
     }
 }
 
@@ -597,6 +844,7 @@ impl From<AccountPickle> for Account {
             diffie_hellman_key: pickle.diffie_hellman_key.into(),
             one_time_keys: pickle.one_time_keys.into(),
             fallback_keys: pickle.fallback_keys,
+            kem_keys: pickle.kem_keys.into(),
         }
     }
 }
@@ -609,6 +857,7 @@ mod libolm {
     use super::{
         Account,
         fallback_keys::{FallbackKey, FallbackKeys},
+        kem_keys::KemKeys,
         one_time_keys::OneTimeKeys,
     };
     use crate::{
@@ -791,6 +1040,7 @@ mod libolm {
                 ),
                 one_time_keys,
                 fallback_keys,
+                kem_keys: KemKeys::new(),
             })
         }
     }
@@ -803,6 +1053,7 @@ mod dehydrated_device {
     use super::{
         Account,
         fallback_keys::{FallbackKey, FallbackKeys},
+        kem_keys::KemKeys,
         one_time_keys::OneTimeKeys,
     };
     use crate::{
@@ -946,6 +1197,7 @@ mod dehydrated_device {
                 ),
                 one_time_keys,
                 fallback_keys,
+                kem_keys: KemKeys::new(),
             })
         }
     }
@@ -964,10 +1216,10 @@ mod test {
         Account, InboundCreationResult, SessionConfig, SessionCreationError, dehydrated_device,
     };
     use crate::{
-        Curve25519PublicKey as PublicKey,
+        Curve25519PublicKey as PublicKey, PaddingPolicy, PickleEncoding,
         cipher::Mac,
         olm::{
-            AccountPickle,
+            AccountPickle, Session,
             account::PUBLIC_MAX_ONE_TIME_KEYS,
             messages::{OlmMessage, PreKeyMessage},
         },
@@ -1146,6 +1398,126 @@ mod test {
         Ok(())
     }
 
+    fn hybrid_session_pair(alice: &Account, bob: &mut Account) -> Result<(Session, PreKeyMessage)> {
+        bob.generate_one_time_keys(1);
+        bob.generate_kem_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().context("Bob should have an OTK")?;
+        let kem_key = bob.kem_keys().into_values().next().context("Bob should have a KEM key")?;
+        bob.mark_keys_as_published();
+
+        let mut session = alice.create_outbound_session_with_kem_key(
+            SessionConfig::version_3(),
+            bob.curve25519_key(),
+            one_time_key,
+            Some(&kem_key),
+        );
+
+        assert_matches!(session.encrypt("It's a secret to everybody"), OlmMessage::PreKey(m));
+        assert_eq!(m.kem_key_hash(), Some(&kem_key.key_hash()));
+
+        Ok((session, m))
+    }
+
+    #[test]
+    fn hybrid_session_communication() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+
+        let (mut alice_session, message) = hybrid_session_pair(&alice, &mut bob)?;
+        assert_eq!(alice_session.session_config(), SessionConfig::version_3());
+
+        let message = PreKeyMessage::from_base64(&message.to_base64())?;
+        assert!(message.kem_ciphertext().is_some());
+
+        let InboundCreationResult { session: mut bob_session, plaintext } =
+            bob.create_inbound_session(alice.curve25519_key(), &message)?;
+
+        assert_eq!(plaintext, b"It's a secret to everybody");
+        assert_eq!(bob_session.session_config(), SessionConfig::version_3());
+        assert_eq!(bob.stored_kem_key_count(), 0);
+
+        let reply = bob_session.encrypt("Yes, take this, it's dangerous out there");
+        assert_matches!(&reply, OlmMessage::Normal(_));
+        assert_eq!(alice_session.decrypt(&reply)?, b"Yes, take this, it's dangerous out there");
+
+        let last = alice_session.encrypt("Nope, I'll have the last word");
+        assert_eq!(bob_session.decrypt(&last)?, b"Nope, I'll have the last word");
+
+        Ok(())
+    }
+
+    #[test]
+    fn hybrid_session_falls_back_to_version_2() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().context("Bob should have an OTK")?;
+
+        let session = alice.create_outbound_session(
+            SessionConfig::version_3(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+        assert_eq!(session.session_config(), SessionConfig::version_2());
+
+        let mut session = alice.create_outbound_session_with_kem_key(
+            SessionConfig::version_3().with_padding(PaddingPolicy::Padme),
+            bob.curve25519_key(),
+            one_time_key,
+            None,
+        );
+        assert_eq!(session.session_config().version(), 2);
+        assert_eq!(session.session_config().padding(), PaddingPolicy::Padme);
+
+        assert_matches!(session.encrypt("Hello"), OlmMessage::PreKey(m));
+        assert!(m.kem_key_hash().is_none());
+
+        let InboundCreationResult { session, .. } =
+            bob.create_inbound_session(alice.curve25519_key(), &m)?;
+        assert_eq!(session.session_config(), SessionConfig::version_2());
+
+        Ok(())
+    }
+
+    #[test]
+    fn hybrid_session_requires_the_kem_key() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+
+        let (_, message) = hybrid_session_pair(&alice, &mut bob)?;
+
+        // Stripping the encapsulation results in a different shared secret.
+        let mut stripped = message.clone();
+        stripped.kem = None;
+
+        assert_matches!(
+            bob.create_inbound_session(alice.curve25519_key(), &stripped),
+            Err(SessionCreationError::Decryption(_))
+        );
+        assert_eq!(bob.stored_one_time_key_count(), 1);
+        assert_eq!(bob.stored_kem_key_count(), 1);
+
+        let mut malory = Account::new();
+        malory.generate_one_time_keys(1);
+        malory.mark_keys_as_published();
+
+        let (_, mut unknown) = hybrid_session_pair(&alice, &mut malory)?;
+        unknown.session_keys = message.session_keys;
+
+        assert_matches!(
+            bob.create_inbound_session(alice.curve25519_key(), &unknown),
+            Err(SessionCreationError::MissingKemKey)
+        );
+
+        bob.create_inbound_session(alice.curve25519_key(), &message)?;
+
+        Ok(())
+    }
+
     #[test]
     fn inbound_session_creation() -> Result<()> {
         let alice = OlmAccount::new();
@@ -1222,6 +1594,9 @@ mod test {
         let mut account = Account::new();
 
         account.generate_one_time_keys(50);
+        account.generate_kem_keys(2);
+        account.mark_keys_as_published();
+        account.generate_kem_keys(1);
 
         // Generate two fallback keys so the previous fallback key field gets populated.
         account.generate_fallback_key();
@@ -1234,6 +1609,8 @@ mod test {
         let repickle = unpickled_account.pickle();
 
         assert_eq!(account.identity_keys(), unpickled_account.identity_keys());
+        assert_eq!(account.kem_keys(), unpickled_account.kem_keys());
+        assert_eq!(unpickled_account.stored_kem_key_count(), 3);
 
         let decrypted_pickle = AccountPickle::from_encrypted(&pickle, &PICKLE_KEY)?;
         let pickle = serde_json::to_value(decrypted_pickle)?;
@@ -1244,6 +1621,26 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn binary_pickling_roundtrip() -> Result<()> {
+        let mut account = Account::new();
+
+        account.generate_one_time_keys(50);
+        account.generate_fallback_key();
+
+        let pickle = account.pickle().encrypt_with_encoding(&PICKLE_KEY, PickleEncoding::Binary);
+        let unpickled_account =
+            Account::from_pickle(AccountPickle::from_encrypted(&pickle, &PICKLE_KEY)?);
+
+        assert_eq!(account.identity_keys(), unpickled_account.identity_keys());
+        assert_eq!(
+            serde_json::to_value(account.pickle())?,
+            serde_json::to_value(unpickled_account.pickle())?
+        );
+
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "libolm-compat")]
     fn libolm_unpickling() -> Result<()> {
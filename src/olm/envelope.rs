@@ -0,0 +1,439 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The Matrix `m.olm.v1.curve25519-aes-sha2` layer of Olm encrypted to-device
+//! events.
+//!
+//! An [`OlmMessage`] only carries the ciphertext, Matrix wraps it in a
+//! `ciphertext` map keyed by the Curve25519 key of the recipient. The
+//! plaintext is a JSON object containing the event type and content, along
+//! with the user IDs and the Ed25519 keys of the sender and the recipient.
+//!
+//! The Olm session only authenticates the Curve25519 key of the sender, the
+//! remaining identity claims need to be checked by the recipient, otherwise a
+//! device can impersonate other users and devices. [`OlmEnvelope::decrypt()`]
+//! checks all claims it can check on its own, the claimed Ed25519 key of the
+//! sender needs to be compared to the device keys of the sender using
+//! [`OlmEnvelope::check_sender_keys()`].
+//!
+//! [`OlmMessage`]: super::OlmMessage
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::{Account, IdentityKeys, OlmMessage, Session, SessionSet, SessionSetError};
+use crate::{Curve25519PublicKey, Ed25519PublicKey, KeyError};
+
+/// The algorithm identifier of Olm encrypted events in Matrix.
+const OLM_ALGORITHM: &str = "m.olm.v1.curve25519-aes-sha2";
+
+/// Error type describing failures to decrypt or validate an Olm encrypted
+/// to-device event.
+#[derive(Debug, Error)]
+pub enum EnvelopeError {
+    /// The event, or its decrypted plaintext, wasn't valid JSON or was missing
+    /// fields.
+    #[error("The Olm encrypted event couldn't be deserialized: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The event is encrypted using an algorithm other than Olm.
+    #[error("The event uses an unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    /// One of the keys of the event was invalid.
+    #[error("The Olm encrypted event contained an invalid key: {0}")]
+    InvalidKey(#[from] KeyError),
+    /// The event doesn't contain a ciphertext for our Curve25519 key.
+    #[error("The Olm encrypted event doesn't contain a ciphertext for our device")]
+    MissingCiphertext,
+    /// The ciphertext couldn't be decrypted.
+    #[error(transparent)]
+    Decryption(#[from] SessionSetError),
+    /// The sender of the event doesn't match the sender claimed in the
+    /// plaintext.
+    #[error("The event was sent by {expected}, but the plaintext claims {claimed}")]
+    MismatchedSender {
+        /// The user ID of the sender of the event.
+        expected: String,
+        /// The user ID the plaintext claims the event was sent by.
+        claimed: String,
+    },
+    /// The plaintext claims the event was meant for another user.
+    #[error("The event was meant for {claimed}, but we are {expected}")]
+    MismatchedRecipient {
+        /// Our own user ID.
+        expected: String,
+        /// The user ID the plaintext claims the event was meant for.
+        claimed: String,
+    },
+    /// The plaintext claims the event was meant for another device.
+    #[error("The event was meant for a device with a different Ed25519 key")]
+    MismatchedRecipientKey,
+    /// The identity keys of the sender don't match the keys of their device.
+    #[error("The identity keys of the sender don't match the keys of their device")]
+    MismatchedSenderKeys,
+}
+
+/// The `ed25519` key of the sender or the recipient in the plaintext.
+#[derive(Serialize, Deserialize)]
+struct KeysContent {
+    ed25519: String,
+}
+
+/// The plaintext of an Olm encrypted event.
+#[derive(Serialize, Deserialize)]
+struct PlaintextContent {
+    #[serde(rename = "type")]
+    event_type: String,
+    content: Map<String, Value>,
+    sender: String,
+    recipient: String,
+    recipient_keys: KeysContent,
+    keys: KeysContent,
+}
+
+/// The content of an `m.room.encrypted` to-device event using Olm.
+#[derive(Serialize, Deserialize)]
+struct EncryptedContent {
+    algorithm: String,
+    sender_key: String,
+    ciphertext: BTreeMap<String, Value>,
+}
+
+/// The plaintext of an Olm encrypted to-device event, along with the identity
+/// claims of the sender and the recipient.
+#[derive(Debug, Clone)]
+pub struct OlmEnvelope {
+    event_type: String,
+    content: Map<String, Value>,
+    sender: String,
+    sender_keys: IdentityKeys,
+    recipient: String,
+    recipient_keys: IdentityKeys,
+}
+
+impl OlmEnvelope {
+    /// Create a new envelope for an event we send to the given device.
+    ///
+    /// The `sender` is our own user ID, the `recipient` and its identity keys
+    /// describe the device the event is meant for. The `content` needs to be a
+    /// JSON object.
+    pub fn new(
+        account: &Account,
+        sender: &str,
+        recipient: &str,
+        recipient_keys: IdentityKeys,
+        event_type: &str,
+        content: &str,
+    ) -> Result<Self, EnvelopeError> {
+        Ok(Self {
+            event_type: event_type.to_owned(),
+            content: serde_json::from_str(content)?,
+            sender: sender.to_owned(),
+            sender_keys: account.identity_keys(),
+            recipient: recipient.to_owned(),
+            recipient_keys,
+        })
+    }
+
+    /// The type of the wrapped event.
+    pub fn event_type(&self) -> &str {
+        &self.event_type
+    }
+
+    /// The content of the wrapped event, serialized as JSON.
+    pub fn content(&self) -> String {
+        #[allow(clippy::expect_used)]
+        serde_json::to_string(&self.content)
+            .expect("A JSON object should always be serializable into JSON")
+    }
+
+    /// The user ID of the sender.
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// The identity keys of the sender.
+    ///
+    /// For received events, only the Curve25519 key is authenticated by the
+    /// Olm session, the Ed25519 key is a claim of the sender, see
+    /// [`OlmEnvelope::check_sender_keys()`].
+    pub const fn sender_keys(&self) -> IdentityKeys {
+        self.sender_keys
+    }
+
+    /// The user ID of the recipient.
+    pub fn recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    /// The identity keys of the recipient.
+    pub const fn recipient_keys(&self) -> IdentityKeys {
+        self.recipient_keys
+    }
+
+    /// Encrypt the envelope using the given [`Session`], which needs to be a
+    /// session with the recipient.
+    ///
+    /// Returns the JSON content of the `m.room.encrypted` to-device event.
+    pub fn encrypt(&self, session: &mut Session) -> String {
+        let plaintext = PlaintextContent {
+            event_type: self.event_type.clone(),
+            content: self.content.clone(),
+            sender: self.sender.clone(),
+            recipient: self.recipient.clone(),
+            recipient_keys: KeysContent { ed25519: self.recipient_keys.ed25519.to_base64() },
+            keys: KeysContent { ed25519: self.sender_keys.ed25519.to_base64() },
+        };
+
+        #[allow(clippy::expect_used)]
+        let plaintext = serde_json::to_vec(&plaintext)
+            .expect("The plaintext of an Olm envelope should always be serializable into JSON");
+        let message = session.encrypt(plaintext);
+
+        #[allow(clippy::expect_used)]
+        let message = serde_json::to_value(message)
+            .expect("An Olm message should always be serializable into JSON");
+
+        let content = EncryptedContent {
+            algorithm: OLM_ALGORITHM.to_owned(),
+            sender_key: self.sender_keys.curve25519.to_base64(),
+            ciphertext: BTreeMap::from([(self.recipient_keys.curve25519.to_base64(), message)]),
+        };
+
+        #[allow(clippy::expect_used)]
+        serde_json::to_string(&content)
+            .expect("The content of an Olm encrypted event should always be serializable into JSON")
+    }
+
+    /// Decrypt the content of an `m.room.encrypted` to-device event, which was
+    /// sent to us by the given `sender`, and validate the envelope.
+    ///
+    /// The ciphertext is decrypted using the [`SessionSet`] of the device the
+    /// event was sent by, creating a new session from pre-key messages if
+    /// needed. Afterwards the sender, the recipient and the Ed25519 key of
+    /// the recipient claimed in the plaintext are checked against the
+    /// `sender` of the event, our own user ID and our own identity keys.
+    ///
+    /// **Warning**: The claimed Ed25519 key of the sender can't be checked
+    /// here, it needs to be checked using [`OlmEnvelope::check_sender_keys()`]
+    /// before the sender is trusted.
+    pub fn decrypt(
+        account: &mut Account,
+        sessions: &mut SessionSet,
+        our_user_id: &str,
+        sender: &str,
+        encrypted_content: &str,
+    ) -> Result<Self, EnvelopeError> {
+        let encrypted: EncryptedContent = serde_json::from_str(encrypted_content)?;
+
+        if encrypted.algorithm != OLM_ALGORITHM {
+            return Err(EnvelopeError::UnsupportedAlgorithm(encrypted.algorithm));
+        }
+
+        let sender_key = Curve25519PublicKey::from_base64(&encrypted.sender_key)?;
+        let our_keys = account.identity_keys();
+
+        let message = encrypted
+            .ciphertext
+            .get(&our_keys.curve25519.to_base64())
+            .ok_or(EnvelopeError::MissingCiphertext)?;
+        let message = OlmMessage::deserialize(message)?;
+
+        let plaintext = sessions.decrypt(account, sender_key, &message)?;
+        let plaintext: PlaintextContent = serde_json::from_slice(&plaintext)?;
+
+        if plaintext.sender != sender {
+            return Err(EnvelopeError::MismatchedSender {
+                expected: sender.to_owned(),
+                claimed: plaintext.sender,
+            });
+        }
+
+        if plaintext.recipient != our_user_id {
+            return Err(EnvelopeError::MismatchedRecipient {
+                expected: our_user_id.to_owned(),
+                claimed: plaintext.recipient,
+            });
+        }
+
+        if Ed25519PublicKey::from_base64(&plaintext.recipient_keys.ed25519)? != our_keys.ed25519 {
+            return Err(EnvelopeError::MismatchedRecipientKey);
+        }
+
+        Ok(Self {
+            event_type: plaintext.event_type,
+            content: plaintext.content,
+            sender: plaintext.sender,
+            sender_keys: IdentityKeys {
+                ed25519: Ed25519PublicKey::from_base64(&plaintext.keys.ed25519)?,
+                curve25519: sender_key,
+            },
+            recipient: plaintext.recipient,
+            recipient_keys: our_keys,
+        })
+    }
+
+    /// Check that the identity keys of the sender match the given keys of the
+    /// sender's device, e.g. from the device keys the sender has uploaded.
+    pub fn check_sender_keys(&self, device_keys: IdentityKeys) -> Result<(), EnvelopeError> {
+        if self.sender_keys == device_keys {
+            Ok(())
+        } else {
+            Err(EnvelopeError::MismatchedSenderKeys)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{EnvelopeError, OlmEnvelope};
+    use crate::olm::{Account, Session, SessionConfig, SessionSet};
+
+    fn session_pair() -> (Account, Account, Session) {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let one_time_key = *bob.one_time_keys().values().next().unwrap();
+        let session = alice.create_outbound_session(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        (alice, bob, session)
+    }
+
+    #[test]
+    fn envelope_roundtrip() {
+        let (alice, mut bob, mut session) = session_pair();
+        let mut sessions = SessionSet::new();
+
+        let envelope = OlmEnvelope::new(
+            &alice,
+            "@alice:example.org",
+            "@bob:example.org",
+            bob.identity_keys(),
+            "m.room_key",
+            r#"{"room_id": "!room:example.org"}"#,
+        )
+        .unwrap();
+        let encrypted = envelope.encrypt(&mut session);
+
+        let decrypted = OlmEnvelope::decrypt(
+            &mut bob,
+            &mut sessions,
+            "@bob:example.org",
+            "@alice:example.org",
+            &encrypted,
+        )
+        .unwrap();
+
+        assert_eq!(decrypted.event_type(), "m.room_key");
+        assert_eq!(decrypted.content(), r#"{"room_id":"!room:example.org"}"#);
+        assert_eq!(decrypted.sender(), "@alice:example.org");
+        assert_eq!(decrypted.recipient(), "@bob:example.org");
+        assert_eq!(decrypted.recipient_keys(), bob.identity_keys());
+        decrypted.check_sender_keys(alice.identity_keys()).unwrap();
+        assert_matches!(
+            decrypted.check_sender_keys(Account::new().identity_keys()),
+            Err(EnvelopeError::MismatchedSenderKeys)
+        );
+    }
+
+    #[test]
+    fn envelope_identity_claims_are_checked() {
+        let (alice, mut bob, mut session) = session_pair();
+        let mut sessions = SessionSet::new();
+
+        let envelope = |recipient, recipient_keys| {
+            OlmEnvelope::new(
+                &alice,
+                "@alice:example.org",
+                recipient,
+                recipient_keys,
+                "m.dummy",
+                "{}",
+            )
+            .unwrap()
+        };
+
+        let encrypted = envelope("@bob:example.org", bob.identity_keys()).encrypt(&mut session);
+        assert_matches!(
+            OlmEnvelope::decrypt(
+                &mut bob,
+                &mut sessions,
+                "@bob:example.org",
+                "@mallory:example.org",
+                &encrypted
+            ),
+            Err(EnvelopeError::MismatchedSender { .. })
+        );
+
+        let encrypted = envelope("@carol:example.org", bob.identity_keys()).encrypt(&mut session);
+        assert_matches!(
+            OlmEnvelope::decrypt(
+                &mut bob,
+                &mut sessions,
+                "@bob:example.org",
+                "@alice:example.org",
+                &encrypted
+            ),
+            Err(EnvelopeError::MismatchedRecipient { .. })
+        );
+
+        let mut other_keys = Account::new().identity_keys();
+        other_keys.curve25519 = bob.curve25519_key();
+        let encrypted = envelope("@bob:example.org", other_keys).encrypt(&mut session);
+        assert_matches!(
+            OlmEnvelope::decrypt(
+                &mut bob,
+                &mut sessions,
+                "@bob:example.org",
+                "@alice:example.org",
+                &encrypted
+            ),
+            Err(EnvelopeError::MismatchedRecipientKey)
+        );
+
+        let encrypted =
+            envelope("@bob:example.org", Account::new().identity_keys()).encrypt(&mut session);
+        assert_matches!(
+            OlmEnvelope::decrypt(
+                &mut bob,
+                &mut sessions,
+                "@bob:example.org",
+                "@alice:example.org",
+                &encrypted
+            ),
+            Err(EnvelopeError::MissingCiphertext)
+        );
+
+        let encrypted = encrypted.replace("m.olm.v1.curve25519-aes-sha2", "m.megolm.v1.aes-sha2");
+        assert_matches!(
+            OlmEnvelope::decrypt(
+                &mut bob,
+                &mut sessions,
+                "@bob:example.org",
+                "@alice:example.org",
+                &encrypted
+            ),
+            Err(EnvelopeError::UnsupportedAlgorithm(_))
+        );
+    }
+}
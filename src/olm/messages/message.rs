@@ -25,6 +25,11 @@ use crate::{
 
 const MAC_TRUNCATED_VERSION: u8 = 3;
 const VERSION: u8 = 4;
+const HEADER_ENCRYPTED_VERSION: u8 = 5;
+
+/// The length of an encrypted header: a 16 byte salt, the 40 byte header and a
+/// 16 byte authentication tag.
+pub(crate) const ENCRYPTED_HEADER_LENGTH: usize = 16 + 40 + 16;
 
 /// An encrypted Olm message.
 ///
@@ -38,19 +43,37 @@ pub struct Message {
     pub(crate) ratchet_key: Curve25519PublicKey,
     pub(crate) chain_index: u64,
     pub(crate) ciphertext: Vec<u8>,
+    pub(crate) padded: bool,
+    pub(crate) encrypted_header: Option<Vec<u8>>,
     pub(crate) mac: MessageMac,
 }
 
 impl Message {
     /// The public part of the ratchet key, that was used when the message was
     /// encrypted.
-    pub const fn ratchet_key(&self) -> Curve25519PublicKey {
-        self.ratchet_key
+    ///
+    /// The ratchet key of a header-encrypted message is only known to the
+    /// [`Session`], `None` is returned for those, see
+    /// [`Message::is_header_encrypted()`].
+    ///
+    /// [`Session`]: crate::olm::Session
+    pub const fn ratchet_key(&self) -> Option<Curve25519PublicKey> {
+        if self.is_header_encrypted() { None } else { Some(self.ratchet_key) }
     }
 
     /// The index of the chain that was used when the message was encrypted.
-    pub const fn chain_index(&self) -> u64 {
-        self.chain_index
+    ///
+    /// Like the ratchet key, the chain index of a header-encrypted message is
+    /// hidden, `None` is returned for those.
+    pub const fn chain_index(&self) -> Option<u64> {
+        if self.is_header_encrypted() { None } else { Some(self.chain_index) }
+    }
+
+    /// Have the ratchet key and chain index of this Olm message been
+    /// encrypted, as it's done by sessions using
+    /// [`SessionConfig::version_4()`](crate::olm::SessionConfig::version_4).
+    pub const fn is_header_encrypted(&self) -> bool {
+        self.encrypted_header.is_some()
     }
 
     /// The actual ciphertext of the message.
@@ -58,11 +81,25 @@ impl Message {
         &self.ciphertext
     }
 
+    /// The message authentication code of the message, it's truncated to 8
+    /// bytes if [`Message::mac_truncated()`] is set.
+    pub fn mac(&self) -> &[u8] {
+        self.mac.as_bytes()
+    }
+
     /// The version of the Olm message.
     pub const fn version(&self) -> u8 {
         self.version
     }
 
+    /// Has the plaintext of this Olm message been padded to hide its length.
+    ///
+    /// The padding is removed when the message gets decrypted, see
+    /// [`PaddingPolicy`](crate::PaddingPolicy).
+    pub const fn is_padded(&self) -> bool {
+        self.padded
+    }
+
     /// Has the MAC been truncated in this Olm message.
     pub const fn mac_truncated(&self) -> bool {
         self.version == MAC_TRUNCATED_VERSION
@@ -95,6 +132,14 @@ impl Message {
     /// Ratchet-Key|  0x0A | String |The public part of the ratchet key
     /// Chain-Index|  0x10 | Integer|The chain index, of the message
     /// Cipher-Text|  0x22 | String |The cipher-text of the message
+    /// Padded     |  0x28 | Integer|Set to 1 if the plaintext is padded
+    /// Header     |  0x32 | String |The encrypted ratchet key and chain index
+    ///
+    /// The padded flag is only present if the plaintext is padded, receivers
+    /// which don't know about it ignore it like any other unknown key.
+    ///
+    /// Header-encrypted messages use the version 5 and replace the ratchet key
+    /// and the chain index with the encrypted header.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut message = self.encode();
         message.extend(self.mac.as_bytes());
@@ -128,10 +173,21 @@ impl Message {
             ratchet_key,
             chain_index,
             ciphertext,
+            padded: false,
+            encrypted_header: None,
             mac: Mac([0u8; Mac::LENGTH]).into(),
         }
     }
 
+    /// Turn the message into a header-encrypted one, replacing the ratchet key
+    /// and chain index with the given encrypted header.
+    pub(crate) fn encrypt_header(&mut self, encrypted_header: Vec<u8>) {
+        self.version = HEADER_ENCRYPTED_VERSION;
+        self.ratchet_key = Curve25519PublicKey::from_bytes([0u8; 32]);
+        self.chain_index = 0;
+        self.encrypted_header = Some(encrypted_header);
+    }
+
     pub(crate) fn new_truncated_mac(
         ratchet_key: Curve25519PublicKey,
         chain_index: u64,
@@ -142,6 +198,8 @@ impl Message {
             ratchet_key,
             chain_index,
             ciphertext,
+            padded: false,
+            encrypted_header: None,
             mac: [0u8; Mac::TRUNCATED_LEN].into(),
         }
     }
@@ -151,6 +209,8 @@ impl Message {
             ratchet_key: self.ratchet_key.to_bytes().to_vec(),
             chain_index: self.chain_index,
             ciphertext: self.ciphertext.clone(),
+            padded: self.padded,
+            encrypted_header: self.encrypted_header.clone(),
         }
         .encode_manual(self.version)
     }
@@ -163,6 +223,8 @@ impl Message {
         match self.mac {
             MessageMac::Truncated(_) => self.mac = mac.truncate().into(),
             MessageMac::Full(_) => self.mac = mac.into(),
+            // Olm messages are never encrypted using an AEAD.
+            MessageMac::Aead => (),
         }
     }
 }
@@ -209,7 +271,7 @@ impl TryFrom<&[u8]> for Message {
         let version = *value.first().ok_or(DecodeError::MissingVersion)?;
 
         let mac_length = match version {
-            VERSION => Mac::LENGTH,
+            VERSION | HEADER_ENCRYPTED_VERSION => Mac::LENGTH,
             MAC_TRUNCATED_VERSION => Mac::TRUNCATED_LEN,
             _ => return Err(DecodeError::InvalidVersion(VERSION, version)),
         };
@@ -232,9 +294,28 @@ impl TryFrom<&[u8]> for Message {
 
                 let chain_index = inner.chain_index;
                 let ciphertext = inner.ciphertext;
-                let ratchet_key = Curve25519PublicKey::from_slice(&inner.ratchet_key)?;
-
-                let message = Message { version, ratchet_key, chain_index, ciphertext, mac };
+                let padded = inner.padded;
+
+                let (ratchet_key, encrypted_header) = if version == HEADER_ENCRYPTED_VERSION {
+                    let encrypted_header = inner
+                        .encrypted_header
+                        .filter(|header| header.len() == ENCRYPTED_HEADER_LENGTH)
+                        .ok_or(DecodeError::InvalidEncryptedHeader)?;
+
+                    (Curve25519PublicKey::from_bytes([0u8; 32]), Some(encrypted_header))
+                } else {
+                    (Curve25519PublicKey::from_slice(&inner.ratchet_key)?, None)
+                };
+
+                let message = Message {
+                    version,
+                    ratchet_key,
+                    chain_index,
+                    ciphertext,
+                    padded,
+                    encrypted_header,
+                    mac,
+                };
 
                 Ok(message)
             }
@@ -244,12 +325,22 @@ impl TryFrom<&[u8]> for Message {
 
 impl Debug for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self { version, ratchet_key, chain_index, ciphertext: _, mac: _ } = self;
+        let Self {
+            version,
+            ratchet_key,
+            chain_index,
+            ciphertext: _,
+            padded,
+            encrypted_header,
+            mac: _,
+        } = self;
 
         f.debug_struct("Message")
             .field("version", version)
             .field("ratchet_key", ratchet_key)
             .field("chain_index", chain_index)
+            .field("padded", padded)
+            .field("header_encrypted", &encrypted_header.is_some())
             .finish_non_exhaustive()
     }
 }
@@ -262,28 +353,58 @@ struct ProtoBufMessage {
     chain_index: u64,
     #[prost(bytes, tag = "4")]
     ciphertext: Vec<u8>,
+    #[prost(bool, tag = "5")]
+    padded: bool,
+    #[prost(bytes, optional, tag = "6")]
+    encrypted_header: Option<Vec<u8>>,
 }
 
 impl ProtoBufMessage {
     const RATCHET_TAG: &'static [u8; 1] = b"\x0A";
     const INDEX_TAG: &'static [u8; 1] = b"\x10";
     const CIPHER_TAG: &'static [u8; 1] = b"\x22";
+    /// The padded tag followed by a value of 1.
+    const PADDED_FLAG: &'static [u8; 2] = b"\x28\x01";
+    const HEADER_TAG: &'static [u8; 1] = b"\x32";
 
     fn encode_manual(&self, version: u8) -> Vec<u8> {
+        // The padded flag is left out for unpadded messages, this keeps them
+        // byte for byte identical to messages from before padding existed.
+        let padded: &[u8] = if self.padded { Self::PADDED_FLAG } else { &[] };
+        let ciphertext_len = self.ciphertext.len().to_var_int();
+
+        // Header-encrypted messages carry neither the ratchet key nor the chain
+        // index in the clear.
+        if let Some(encrypted_header) = &self.encrypted_header {
+            let header_len = encrypted_header.len().to_var_int();
+
+            return [
+                [version].as_slice(),
+                Self::HEADER_TAG.as_slice(),
+                &header_len,
+                encrypted_header,
+                Self::CIPHER_TAG.as_slice(),
+                &ciphertext_len,
+                &self.ciphertext,
+                padded,
+            ]
+            .concat();
+        }
+
         let index = self.chain_index.to_var_int();
         let ratchet_len = self.ratchet_key.len().to_var_int();
-        let ciphertext_len = self.ciphertext.len().to_var_int();
 
         [
-            [version].as_ref(),
-            Self::RATCHET_TAG.as_ref(),
+            [version].as_slice(),
+            Self::RATCHET_TAG.as_slice(),
             &ratchet_len,
             &self.ratchet_key,
-            Self::INDEX_TAG.as_ref(),
+            Self::INDEX_TAG.as_slice(),
             &index,
-            Self::CIPHER_TAG.as_ref(),
+            Self::CIPHER_TAG.as_slice(),
             &ciphertext_len,
             &self.ciphertext,
+            padded,
         ]
         .concat()
     }
@@ -296,7 +417,10 @@ mod test {
     use super::Message;
     use crate::{
         Curve25519PublicKey, DecodeError,
-        olm::messages::message::{MAC_TRUNCATED_VERSION, VERSION},
+        cipher::Mac,
+        olm::messages::message::{
+            ENCRYPTED_HEADER_LENGTH, HEADER_ENCRYPTED_VERSION, MAC_TRUNCATED_VERSION, VERSION,
+        },
     };
 
     #[test]
@@ -312,10 +436,10 @@ mod test {
         let mut encoded = Message::new_truncated_mac(ratchet_key, chain_index, ciphertext.to_vec());
         encoded.mac = (*b"MACHEREE").into();
 
-        assert_eq!(encoded.to_mac_bytes(), message.as_ref());
-        assert_eq!(encoded.to_bytes(), message_mac.as_ref());
+        assert_eq!(encoded.to_mac_bytes(), message.as_slice());
+        assert_eq!(encoded.to_bytes(), message_mac.as_slice());
         assert_eq!(encoded.ciphertext(), ciphertext.to_vec());
-        assert_eq!(encoded.chain_index(), chain_index);
+        assert_eq!(encoded.chain_index(), Some(chain_index));
         assert_eq!(encoded.version(), MAC_TRUNCATED_VERSION);
     }
 
@@ -325,15 +449,75 @@ mod test {
         let result = Message::try_from(bytes.as_slice());
         assert_let!(Ok(message) = result);
 
-        let Message { version, ratchet_key, chain_index, ciphertext, mac } = message;
+        let Message {
+            version,
+            ratchet_key,
+            chain_index,
+            ciphertext,
+            padded,
+            encrypted_header,
+            mac,
+        } = message;
 
         assert_eq!(version, VERSION);
         assert_eq!(ratchet_key.as_bytes(), b"ratchetkeyhereprettyplease123456");
         assert_eq!(chain_index, 2);
         assert_eq!(ciphertext, b"ciphertext");
+        assert!(!padded);
+        assert!(encrypted_header.is_none());
         assert_eq!(mac.as_bytes(), b"MAC_01234567890_01234567890_HERE");
     }
 
+    #[test]
+    fn padded_flag() {
+        let message = b"\x03\n\x20ratchetkeyhereprettyplease123456\x10\x02\"\nciphertext\x28\x01";
+        let message_mac =
+            b"\x03\n\x20ratchetkeyhereprettyplease123456\x10\x02\"\nciphertext\x28\x01MACHEREE";
+
+        let ratchet_key = Curve25519PublicKey::from(*b"ratchetkeyhereprettyplease123456");
+
+        let mut encoded = Message::new_truncated_mac(ratchet_key, 2, b"ciphertext".to_vec());
+        encoded.padded = true;
+        encoded.mac = (*b"MACHEREE").into();
+
+        assert_eq!(encoded.to_mac_bytes(), message.as_slice());
+        assert_eq!(encoded.to_bytes(), message_mac.as_slice());
+
+        let decoded =
+            Message::from_bytes(message_mac).expect("We should be able to decode the message");
+        assert!(decoded.is_padded());
+        assert_eq!(decoded, encoded);
+    }
+
+    #[test]
+    fn header_encrypted_encoding() {
+        let ratchet_key = Curve25519PublicKey::from(*b"ratchetkeyhereprettyplease123456");
+        let header = vec![7u8; ENCRYPTED_HEADER_LENGTH];
+
+        let mut encoded = Message::new(ratchet_key, 2, b"ciphertext".to_vec());
+        encoded.encrypt_header(header.clone());
+        encoded.mac = Mac([1u8; Mac::LENGTH]).into();
+
+        let bytes = encoded.to_bytes();
+        assert_eq!(bytes[0], HEADER_ENCRYPTED_VERSION);
+        assert!(!bytes.windows(32).any(|w| w == ratchet_key.as_bytes()));
+
+        let decoded = Message::from_bytes(&bytes).expect("We should be able to decode the message");
+        assert!(decoded.is_header_encrypted());
+        assert_eq!(decoded.ratchet_key(), None);
+        assert_eq!(decoded.chain_index(), None);
+        assert_eq!(decoded.encrypted_header, Some(header));
+        assert_eq!(decoded, encoded);
+
+        // A header-encrypted message without a header is invalid.
+        let mut unencrypted = Message::new(ratchet_key, 2, b"ciphertext".to_vec()).to_bytes();
+        unencrypted[0] = HEADER_ENCRYPTED_VERSION;
+        assert_matches!(
+            Message::from_bytes(&unencrypted),
+            Err(DecodeError::InvalidEncryptedHeader)
+        );
+    }
+
     #[test]
     fn from_bytes_too_short() {
         let bytes = vec![MAC_TRUNCATED_VERSION, 0, 0, 0, 0, 0, 0, 0, 0];
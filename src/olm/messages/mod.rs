@@ -15,7 +15,9 @@
 mod message;
 mod pre_key;
 
+pub(crate) use message::ENCRYPTED_HEADER_LENGTH;
 pub use message::Message;
+pub(crate) use pre_key::KemEncapsulation;
 pub use pre_key::PreKeyMessage;
 use serde::{Deserialize, Serialize};
 
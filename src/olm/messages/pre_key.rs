@@ -21,9 +21,29 @@ use super::Message;
 use crate::{
     Curve25519PublicKey, DecodeError,
     olm::SessionKeys,
+    types::MlKem768Ciphertext,
     utilities::{base64_decode, base64_encode},
 };
 
+/// The ML-KEM-768 encapsulation that a hybrid, Olm version 3, pre-key message
+/// carries.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct KemEncapsulation {
+    /// The SHA3-256 hash of the ML-KEM-768 public key of the receiver, used to
+    /// find the matching private key.
+    pub key_hash: [u8; 32],
+    /// The ciphertext that encapsulates the shared secret.
+    pub ciphertext: MlKem768Ciphertext,
+}
+
+impl Debug for KemEncapsulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KemEncapsulation")
+            .field("key_hash", &base64_encode(self.key_hash))
+            .finish_non_exhaustive()
+    }
+}
+
 /// An encrypted Olm pre-key message.
 ///
 /// Contains metadata that is required to establish a [`Session`] and a normal
@@ -33,6 +53,7 @@ use crate::{
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PreKeyMessage {
     pub(crate) session_keys: SessionKeys,
+    pub(crate) kem: Option<KemEncapsulation>,
     pub(crate) message: Message,
 }
 
@@ -81,6 +102,22 @@ impl PreKeyMessage {
         self.session_keys.session_id()
     }
 
+    /// The SHA3-256 hash of the ML-KEM-768 key of the receiver, if this
+    /// pre-key message establishes a hybrid, post-quantum, [`Session`].
+    ///
+    /// [`Session`]: crate::olm::Session
+    pub fn kem_key_hash(&self) -> Option<&[u8; 32]> {
+        self.kem.as_ref().map(|kem| &kem.key_hash)
+    }
+
+    /// The ML-KEM-768 ciphertext, if this pre-key message establishes a
+    /// hybrid, post-quantum, [`Session`].
+    ///
+    /// [`Session`]: crate::olm::Session
+    pub fn kem_ciphertext(&self) -> Option<&[u8]> {
+        self.kem.as_ref().map(|kem| kem.ciphertext.as_bytes().as_slice())
+    }
+
     /// The actual message that contains the ciphertext.
     pub const fn message(&self) -> &Message {
         &self.message
@@ -114,15 +151,21 @@ impl PreKeyMessage {
     ///   Base-Key  | 0x12  | String |The public part of Alice's single-use key
     /// Identity-Key| 0x1A  | String |The public part of Alice's identity key
     ///   Message   | 0x22  | String |An embedded Olm message
+    /// KEM-Key-Hash| 0x2A  | String |The hash of Bob's ML-KEM-768 key
+    ///KEM-Ciphertext|0x32  | String |The ML-KEM-768 encapsulated secret
+    ///
+    /// The last two key/value pairs are only present in pre-key messages that
+    /// establish a hybrid, Olm version 3, [`Session`].
     ///
-    /// The last key/value pair in a [`PreKeyMessage`] is a normal Olm
-    /// [`Message`].
+    /// [`Session`]: crate::olm::Session
     pub fn to_bytes(&self) -> Vec<u8> {
         let message = ProtoBufPreKeyMessage {
             one_time_key: self.session_keys.one_time_key.as_bytes().to_vec(),
             base_key: self.session_keys.base_key.as_bytes().to_vec(),
             identity_key: self.session_keys.identity_key.as_bytes().to_vec(),
             message: self.message.to_bytes(),
+            kem_key_hash: self.kem.as_ref().map(|kem| kem.key_hash.to_vec()),
+            kem_ciphertext: self.kem.as_ref().map(|kem| kem.ciphertext.as_bytes().to_vec()),
         };
 
         let mut output: Vec<u8> = vec![0u8; message.encoded_len() + 1];
@@ -155,11 +198,15 @@ impl PreKeyMessage {
     /// Create a new pre-key message from the session keys and standard message.
     #[cfg(feature = "low-level-api")]
     pub const fn wrap(session_keys: SessionKeys, message: Message) -> Self {
-        PreKeyMessage::new(session_keys, message)
+        PreKeyMessage::new(session_keys, None, message)
     }
 
-    pub(crate) const fn new(session_keys: SessionKeys, message: Message) -> Self {
-        Self { session_keys, message }
+    pub(crate) const fn new(
+        session_keys: SessionKeys,
+        kem: Option<KemEncapsulation>,
+        message: Message,
+    ) -> Self {
+        Self { session_keys, kem, message }
     }
 }
 
@@ -214,9 +261,22 @@ impl TryFrom<&[u8]> for PreKeyMessage {
 
             let message = decoded.message.try_into()?;
 
+            let kem = match (decoded.kem_key_hash, decoded.kem_ciphertext) {
+                (None, None) => None,
+                (Some(key_hash), Some(ciphertext)) => Some(KemEncapsulation {
+                    key_hash: key_hash
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| DecodeError::InvalidKemEncapsulation)?,
+                    ciphertext: MlKem768Ciphertext::from_slice(&ciphertext)
+                        .ok_or(DecodeError::InvalidKemEncapsulation)?,
+                }),
+                _ => return Err(DecodeError::InvalidKemEncapsulation),
+            };
+
             let session_keys = SessionKeys { one_time_key, identity_key, base_key };
 
-            Ok(Self { session_keys, message })
+            Ok(Self { session_keys, kem, message })
         }
     }
 }
@@ -231,4 +291,8 @@ struct ProtoBufPreKeyMessage {
     identity_key: Vec<u8>,
     #[prost(bytes, tag = "4")]
     message: Vec<u8>,
+    #[prost(bytes, optional, tag = "5")]
+    kem_key_hash: Option<Vec<u8>>,
+    #[prost(bytes, optional, tag = "6")]
+    kem_ciphertext: Option<Vec<u8>>,
 }
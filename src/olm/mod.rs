@@ -98,17 +98,21 @@
 //! other side.
 
 mod account;
+mod envelope;
 mod messages;
 pub(crate) mod session;
 mod session_config;
 mod session_keys;
+mod session_set;
 mod shared_secret;
 
 pub use account::{
     Account, AccountPickle, IdentityKeys, InboundCreationResult, OneTimeKeyGenerationResult,
     SessionCreationError,
 };
+pub use envelope::{EnvelopeError, OlmEnvelope};
 pub use messages::{Message, MessageType, OlmMessage, PreKeyMessage};
 pub use session::{DecryptionError, Session, SessionPickle, ratchet::RatchetPublicKey};
 pub use session_config::SessionConfig;
 pub use session_keys::SessionKeys;
+pub use session_set::{SessionSet, SessionSetError, SessionSetPickle, SessionSetPolicy};
@@ -113,6 +113,11 @@ impl ChainKey {
         self.index
     }
 
+    #[cfg(feature = "test-vectors")]
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
     pub fn create_message_key(&mut self, ratchet_key: RatchetPublicKey) -> MessageKey {
         let key = expand_chain_key(&self.key);
         let message_key = MessageKey::new(key, ratchet_key, self.index);
@@ -14,16 +14,18 @@
 
 use std::fmt::{Debug, Formatter};
 
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 
 use super::{
     chain_key::ChainKey,
+    header_key::HeaderKey,
     message_key::MessageKey,
     ratchet::{Ratchet, RatchetPublicKey, RemoteRatchetKey},
     receiver_chain::ReceiverChain,
     root_key::{RemoteRootKey, RootKey},
 };
-use crate::olm::{messages::Message, shared_secret::Shared3DHSecret};
+use crate::olm::{SessionConfig, messages::Message, shared_secret::Shared3DHSecret};
 
 /// The sender side of a double-ratchet implementation.
 ///
@@ -40,6 +42,11 @@ use crate::olm::{messages::Message, shared_secret::Shared3DHSecret};
 /// specifically, the public part of the other side's ratchet key
 /// `T`<sub>`i`</sub> which was sent to us in the message, and the remote root
 /// key `R`<sub>`i`</sub>.
+///
+/// Sessions which encrypt message headers additionally keep track of the
+/// header keys: the one of our own active chain, the one of the next chain of
+/// the other side while we're active, and the one of our own next chain while
+/// we're inactive.
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(transparent)]
 pub(super) struct DoubleRatchet {
@@ -47,10 +54,10 @@ pub(super) struct DoubleRatchet {
 }
 
 impl DoubleRatchet {
-    pub fn next_message_key(&mut self) -> MessageKey {
+    pub fn next_message_key(&mut self, rng: &mut impl CryptoRngCore) -> MessageKey {
         match &mut self.inner {
             DoubleRatchetState::Inactive(ratchet) => {
-                let mut ratchet = ratchet.activate();
+                let mut ratchet = ratchet.activate(rng);
 
                 let message_key = ratchet.next_message_key();
                 self.inner = DoubleRatchetState::Active(ratchet);
@@ -61,27 +68,100 @@ impl DoubleRatchet {
         }
     }
 
-    pub fn encrypt(&mut self, plaintext: &[u8]) -> Message {
-        self.next_message_key().encrypt(plaintext)
+    /// Get the current root key, and the current chain key if we are in the
+    /// active state.
+    #[cfg(feature = "test-vectors")]
+    pub fn keys(&self) -> (&[u8; 32], Option<&[u8; 32]>) {
+        match &self.inner {
+            DoubleRatchetState::Inactive(r) => (&r.root_key.key, None),
+            DoubleRatchetState::Active(r) => {
+                (&r.active_ratchet.root_key().key, Some(r.symmetric_key_ratchet.key()))
+            }
+        }
+    }
+
+    pub fn encrypt(
+        &mut self,
+        plaintext: &[u8],
+        config: &SessionConfig,
+        rng: &mut impl CryptoRngCore,
+    ) -> Message {
+        let message_key = self.next_message_key(rng);
+
+        match &self.inner {
+            DoubleRatchetState::Active(ActiveDoubleRatchet {
+                header_key: Some(header_key),
+                ..
+            }) => message_key.encrypt_with_header_key(plaintext, config, header_key, rng),
+            _ => message_key.encrypt_with_config(plaintext, config),
+        }
     }
 
-    pub fn encrypt_truncated_mac(&mut self, plaintext: &[u8]) -> Message {
-        self.next_message_key().encrypt_truncated_mac(plaintext)
+    /// The number of random bytes [`DoubleRatchet::encrypt()`] consumes for
+    /// the next message.
+    ///
+    /// An inactive ratchet creates a new ratchet key, and header-encrypted
+    /// messages need a random salt.
+    #[cfg(feature = "libolm-compat")]
+    pub fn encrypt_random_length(&self) -> usize {
+        let (ratchet_key_length, encrypts_headers) = match &self.inner {
+            DoubleRatchetState::Inactive(r) => (32, r.next_header_key.is_some()),
+            DoubleRatchetState::Active(r) => (0, r.header_key.is_some()),
+        };
+
+        ratchet_key_length + if encrypts_headers { super::header_key::SALT_LENGTH } else { 0 }
+    }
+
+    /// Create a [`Message`] with the same length as the message
+    /// [`DoubleRatchet::encrypt()`] would produce for a plaintext of the given
+    /// length, without advancing the ratchet.
+    #[cfg(feature = "libolm-compat")]
+    pub fn message_template(&self, plaintext_length: usize, config: &SessionConfig) -> Message {
+        let (index, encrypts_headers) = match &self.inner {
+            // Activating the ratchet starts a new chain.
+            DoubleRatchetState::Inactive(r) => (0, r.next_header_key.is_some()),
+            DoubleRatchetState::Active(r) => {
+                (r.symmetric_key_ratchet.index(), r.header_key.is_some())
+            }
+        };
+
+        super::message_key::message_template(plaintext_length, config, index, encrypts_headers)
+    }
+
+    /// The header key of the next chain of the other side, messages whose
+    /// header decrypts with it start a new receiver chain.
+    pub fn next_remote_header_key(&self) -> Option<&HeaderKey> {
+        match &self.inner {
+            DoubleRatchetState::Active(r) => r.next_header_key.as_ref(),
+            DoubleRatchetState::Inactive(_) => None,
+        }
     }
 
     /// Create a new `DoubleRatchet` instance, based on a newly-calculated
-    /// shared secret.
-    pub fn active(shared_secret: Shared3DHSecret) -> Self {
-        let (root_key, chain_key) = shared_secret.expand();
+    /// shared secret, deriving the header keys of the first chains if
+    /// `encrypt_headers` is set.
+    pub fn active(
+        shared_secret: Shared3DHSecret,
+        encrypt_headers: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let (root_key, chain_key, [header_key, next_header_key]) = shared_secret.expand();
 
         let root_key = RootKey::new(root_key);
         let chain_key = ChainKey::new(chain_key);
+        let (header_key, next_header_key) = if encrypt_headers {
+            (Some(HeaderKey::new(header_key)), Some(HeaderKey::new(next_header_key)))
+        } else {
+            (None, None)
+        };
 
         let ratchet = ActiveDoubleRatchet {
             parent_ratchet_key: None, // First chain in a session lacks parent ratchet key
             ratchet_count: RatchetCount::new(),
-            active_ratchet: Ratchet::new(root_key),
+            active_ratchet: Ratchet::new(root_key, rng),
             symmetric_key_ratchet: chain_key,
+            header_key,
+            next_header_key,
         };
 
         Self { inner: ratchet.into() }
@@ -95,6 +175,8 @@ impl DoubleRatchet {
                 ratchet_count: RatchetCount::unknown(), // nor the ratchet count
                 active_ratchet: ratchet,
                 symmetric_key_ratchet: chain_key,
+                header_key: None,
+                next_header_key: None,
             }
             .into(),
         }
@@ -103,9 +185,11 @@ impl DoubleRatchet {
     pub fn inactive_from_prekey_data(
         root_key: RemoteRootKey,
         ratchet_key: RemoteRatchetKey,
+        next_header_key: Option<HeaderKey>,
     ) -> Self {
         let ratchet_count = RatchetCount::new();
-        let ratchet = InactiveDoubleRatchet { root_key, ratchet_key, ratchet_count };
+        let ratchet =
+            InactiveDoubleRatchet { root_key, ratchet_key, ratchet_count, next_header_key };
 
         Self { inner: ratchet.into() }
     }
@@ -116,16 +200,21 @@ impl DoubleRatchet {
         ratchet_key: RemoteRatchetKey,
     ) -> Self {
         let ratchet_count = RatchetCount::unknown();
-        let ratchet = InactiveDoubleRatchet { root_key, ratchet_key, ratchet_count };
+        let ratchet =
+            InactiveDoubleRatchet { root_key, ratchet_key, ratchet_count, next_header_key: None };
 
         Self { inner: ratchet.into() }
     }
 
-    pub fn advance(&mut self, ratchet_key: RemoteRatchetKey) -> (DoubleRatchet, ReceiverChain) {
+    pub fn advance(
+        &mut self,
+        ratchet_key: RemoteRatchetKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> (DoubleRatchet, ReceiverChain) {
         let (ratchet, receiver_chain) = match &self.inner {
             DoubleRatchetState::Active(r) => r.advance(ratchet_key),
             DoubleRatchetState::Inactive(r) => {
-                let ratchet = r.activate();
+                let ratchet = r.activate(rng);
                 // Advancing an inactive ratchet shouldn't be possible since the
                 // other side did not yet receive our new ratchet key.
                 //
@@ -192,11 +281,16 @@ struct InactiveDoubleRatchet {
     /// for diagnostic output.
     #[serde(default = "RatchetCount::unknown")]
     ratchet_count: RatchetCount,
+
+    /// The header key of our next chain, if the session encrypts headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_header_key: Option<HeaderKey>,
 }
 
 impl InactiveDoubleRatchet {
-    fn activate(&self) -> ActiveDoubleRatchet {
-        let (root_key, chain_key, ratchet_key) = self.root_key.advance(&self.ratchet_key);
+    fn activate(&self, rng: &mut impl CryptoRngCore) -> ActiveDoubleRatchet {
+        let (root_key, chain_key, ratchet_key, next_header_key) =
+            self.root_key.advance(&self.ratchet_key, rng);
         let active_ratchet = Ratchet::new_with_ratchet_key(root_key, ratchet_key);
 
         ActiveDoubleRatchet {
@@ -204,6 +298,8 @@ impl InactiveDoubleRatchet {
             ratchet_count: self.ratchet_count.advance(),
             active_ratchet,
             symmetric_key_ratchet: chain_key,
+            header_key: self.next_header_key.clone(),
+            next_header_key: self.next_header_key.as_ref().map(|_| next_header_key),
         }
     }
 }
@@ -248,19 +344,34 @@ struct ActiveDoubleRatchet {
 
     active_ratchet: Ratchet,
     symmetric_key_ratchet: ChainKey,
+
+    /// The header key of our active chain, if the session encrypts headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header_key: Option<HeaderKey>,
+
+    /// The header key of the next chain of the other side, if the session
+    /// encrypts headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_header_key: Option<HeaderKey>,
 }
 
 impl ActiveDoubleRatchet {
     fn advance(&self, ratchet_key: RemoteRatchetKey) -> (InactiveDoubleRatchet, ReceiverChain) {
-        let (root_key, remote_chain) = self.active_ratchet.advance(ratchet_key);
+        let (root_key, remote_chain, next_header_key) = self.active_ratchet.advance(ratchet_key);
 
         let new_ratchet_count = self.ratchet_count.advance();
         let ratchet = InactiveDoubleRatchet {
             root_key,
             ratchet_key,
             ratchet_count: new_ratchet_count.clone(),
+            next_header_key: self.next_header_key.as_ref().map(|_| next_header_key),
         };
-        let receiver_chain = ReceiverChain::new(ratchet_key, remote_chain, new_ratchet_count);
+        let receiver_chain = ReceiverChain::new(
+            ratchet_key,
+            remote_chain,
+            new_ratchet_count,
+            self.next_header_key.clone(),
+        );
 
         (ratchet, receiver_chain)
     }
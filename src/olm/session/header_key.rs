@@ -0,0 +1,119 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::ratchet::RemoteRatchetKey;
+use crate::{
+    Curve25519PublicKey, cipher::aead::AeadCipher, olm::messages::ENCRYPTED_HEADER_LENGTH,
+};
+
+/// The length of the random salt that precedes every encrypted header.
+pub(super) const SALT_LENGTH: usize = 16;
+/// The length of a header, the ratchet key followed by the chain index.
+const HEADER_LENGTH: usize = 32 + 8;
+
+/// A key to encrypt the headers of the messages in a single chain of a
+/// header-encrypted, Olm version 4, session.
+///
+/// The header key of a chain is derived together with the root key *before*
+/// the chain itself, the receiver therefore already knows it when the first
+/// message of a new chain, containing the new ratchet key, arrives.
+///
+/// The header, the ratchet key `T`<sub>`i`</sub> and the chain index `j`, is
+/// encrypted using ChaCha20-Poly1305, the key and nonce are derived from the
+/// header key and a random salt which is sent along:
+///
+/// ```text
+/// +-----------+------------------------------------------+----------+
+/// | Salt (16) | ChaCha20(T_i || big endian j) (32 + 8)   | Tag (16) |
+/// +-----------+------------------------------------------+----------+
+/// ```
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+#[serde(transparent)]
+pub(super) struct HeaderKey(Box<[u8; 32]>);
+
+impl HeaderKey {
+    pub const fn new(bytes: Box<[u8; 32]>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn encrypt(
+        &self,
+        ratchet_key: &Curve25519PublicKey,
+        chain_index: u64,
+        rng: &mut impl CryptoRngCore,
+    ) -> Vec<u8> {
+        let mut salt = [0u8; SALT_LENGTH];
+        rng.fill_bytes(&mut salt);
+
+        let mut header = [0u8; HEADER_LENGTH];
+        header[..32].copy_from_slice(ratchet_key.as_bytes());
+        header[32..].copy_from_slice(&chain_index.to_be_bytes());
+
+        let ciphertext = AeadCipher::new_olm_header(&self.0, &salt).encrypt(&header, &[]);
+        header.zeroize();
+
+        [salt.as_slice(), &ciphertext].concat()
+    }
+
+    /// Try to decrypt the given encrypted header, returns `None` if the header
+    /// wasn't encrypted using this key.
+    pub fn decrypt(&self, encrypted_header: &[u8]) -> Option<(RemoteRatchetKey, u64)> {
+        if encrypted_header.len() != ENCRYPTED_HEADER_LENGTH {
+            return None;
+        }
+
+        let (salt, ciphertext) = encrypted_header.split_at(SALT_LENGTH);
+        let mut header = AeadCipher::new_olm_header(&self.0, salt).decrypt(ciphertext, &[]).ok()?;
+
+        let ratchet_key: [u8; 32] = header.get(..32)?.try_into().ok()?;
+        let chain_index = u64::from_be_bytes(header.get(32..)?.try_into().ok()?);
+        header.zeroize();
+
+        Some((RemoteRatchetKey::from(ratchet_key), chain_index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use super::{HeaderKey, SALT_LENGTH};
+    use crate::{Curve25519PublicKey, olm::messages::ENCRYPTED_HEADER_LENGTH};
+
+    #[test]
+    fn roundtrip() {
+        let key = HeaderKey::new(Box::new([1u8; 32]));
+        let ratchet_key = Curve25519PublicKey::from_bytes([2u8; 32]);
+
+        let encrypted = key.encrypt(&ratchet_key, 7, &mut thread_rng());
+        assert_eq!(encrypted.len(), ENCRYPTED_HEADER_LENGTH);
+
+        let (decrypted_key, chain_index) =
+            key.decrypt(&encrypted).expect("The header should decrypt with the same key");
+        assert_eq!(decrypted_key, ratchet_key.into());
+        assert_eq!(chain_index, 7);
+
+        // The salt makes sure that the same header never encrypts to the same
+        // ciphertext.
+        let again = key.encrypt(&ratchet_key, 7, &mut thread_rng());
+        assert_ne!(encrypted[SALT_LENGTH..], again[SALT_LENGTH..]);
+
+        assert!(HeaderKey::new(Box::new([3u8; 32])).decrypt(&encrypted).is_none());
+        assert!(key.decrypt(&encrypted[1..]).is_none());
+    }
+}
@@ -14,13 +14,17 @@
 
 use std::fmt::Debug;
 
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use zeroize::Zeroize;
 
-use super::{DecryptionError, ratchet::RatchetPublicKey};
+use super::{DecryptionError, header_key::HeaderKey, ratchet::RatchetPublicKey};
+#[cfg(feature = "libolm-compat")]
+use crate::olm::messages::ENCRYPTED_HEADER_LENGTH;
 use crate::{
+    Curve25519PublicKey,
     cipher::{Cipher, Mac},
-    olm::messages::Message,
+    olm::{SessionConfig, messages::Message, session_config::Version},
 };
 
 /// A single-use encryption key for per-message encryption.
@@ -74,17 +78,9 @@ impl MessageKey {
     /// authentication code (MAC). If you need the message authentication code
     /// to be truncated, please take a look at the
     /// [`MessageKey::encrypt_truncated_mac()`] method instead.
+    #[cfg(feature = "low-level-api")]
     pub fn encrypt(self, plaintext: &[u8]) -> Message {
-        let cipher = Cipher::new(&self.key);
-
-        let ciphertext = cipher.encrypt(plaintext);
-
-        let mut message = Message::new(*self.ratchet_key.as_ref(), self.index, ciphertext);
-
-        let mac = cipher.mac(&message.to_mac_bytes());
-        message.set_mac(mac);
-
-        message
+        self.encrypt_with_config(plaintext, &SessionConfig::version_2())
     }
 
     /// Encrypts the provided plaintext using this [`MessageKey`].
@@ -92,13 +88,51 @@ impl MessageKey {
     /// This method authenticates the ciphertext with an 8-byte message
     /// authentication code (MAC). If you require the full, non-truncated
     /// MAC, refer to the [`MessageKey::encrypt()`] method.
+    #[cfg(feature = "low-level-api")]
     pub fn encrypt_truncated_mac(self, plaintext: &[u8]) -> Message {
+        self.encrypt_with_config(plaintext, &SessionConfig::version_1())
+    }
+
+    /// Encrypt the given plaintext using this [`MessageKey`], padding the
+    /// plaintext and picking the MAC length as the [`SessionConfig`] requires.
+    pub(super) fn encrypt_with_config(self, plaintext: &[u8], config: &SessionConfig) -> Message {
+        self.encrypt_helper(plaintext, config, None)
+    }
+
+    /// Encrypt the given plaintext using this [`MessageKey`], like
+    /// [`MessageKey::encrypt_with_config()`] does, but additionally encrypt
+    /// the ratchet key and index of the message using the given
+    /// [`HeaderKey`].
+    pub(super) fn encrypt_with_header_key(
+        self,
+        plaintext: &[u8],
+        config: &SessionConfig,
+        header_key: &HeaderKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Message {
+        let encrypted_header = header_key.encrypt(self.ratchet_key.as_ref(), self.index, rng);
+        self.encrypt_helper(plaintext, config, Some(encrypted_header))
+    }
+
+    fn encrypt_helper(
+        self,
+        plaintext: &[u8],
+        config: &SessionConfig,
+        encrypted_header: Option<Vec<u8>>,
+    ) -> Message {
         let cipher = Cipher::new(&self.key);
 
-        let ciphertext = cipher.encrypt(plaintext);
+        let padded = config.padding.pad(plaintext);
+        let ciphertext = cipher.encrypt(padded.as_deref().unwrap_or(plaintext));
 
-        let mut message =
-            Message::new_truncated_mac(*self.ratchet_key.as_ref(), self.index, ciphertext);
+        let mut message = new_message(
+            config,
+            *self.ratchet_key.as_ref(),
+            self.index,
+            ciphertext,
+            padded.is_some(),
+            encrypted_header,
+        );
 
         let mac = cipher.mac(&message.to_mac_bytes());
         message.set_mac(mac);
@@ -125,6 +159,55 @@ impl MessageKey {
     }
 }
 
+/// Create a [`Message`] in the format the [`SessionConfig`] requires, without
+/// a MAC.
+fn new_message(
+    config: &SessionConfig,
+    ratchet_key: Curve25519PublicKey,
+    index: u64,
+    ciphertext: Vec<u8>,
+    padded: bool,
+    encrypted_header: Option<Vec<u8>>,
+) -> Message {
+    let mut message = match config.version {
+        Version::V1 => Message::new_truncated_mac(ratchet_key, index, ciphertext),
+        Version::V2 | Version::V3 | Version::V4 => Message::new(ratchet_key, index, ciphertext),
+    };
+    message.padded = padded;
+
+    if let Some(encrypted_header) = encrypted_header {
+        message.encrypt_header(encrypted_header);
+    }
+
+    message
+}
+
+/// Create a [`Message`] which has the same length as the message a
+/// [`MessageKey`] with the given index produces for a plaintext of the given
+/// length, without encrypting anything.
+#[cfg(feature = "libolm-compat")]
+pub(super) fn message_template(
+    plaintext_length: usize,
+    config: &SessionConfig,
+    index: u64,
+    encrypts_headers: bool,
+) -> Message {
+    // AES-256-CBC with PKCS#7 padding always adds between 1 and 16 bytes.
+    let padded_length = config.padding.padded_length(plaintext_length);
+    let ciphertext = vec![0u8; (padded_length / 16 + 1) * 16];
+
+    // The ratchet key is encoded with a fixed length, its value doesn't
+    // matter.
+    new_message(
+        config,
+        Curve25519PublicKey::from_bytes([0u8; 32]),
+        index,
+        ciphertext,
+        !config.padding.is_none(),
+        encrypts_headers.then(|| vec![0u8; ENCRYPTED_HEADER_LENGTH]),
+    )
+}
+
 impl RemoteMessageKey {
     pub const fn new(key: Box<[u8; 32]>, index: u64) -> Self {
         Self { key, index }
@@ -15,6 +15,7 @@
 
 mod chain_key;
 mod double_ratchet;
+mod header_key;
 pub mod message_key;
 pub mod ratchet;
 mod receiver_chain;
@@ -26,7 +27,10 @@ use aes::cipher::block_padding::UnpadError;
 use arrayvec::ArrayVec;
 use chain_key::RemoteChainKey;
 use double_ratchet::DoubleRatchet;
+use header_key::HeaderKey;
 use hmac::digest::MacError;
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use ratchet::RemoteRatchetKey;
 use receiver_chain::ReceiverChain;
 use root_key::RemoteRootKey;
@@ -35,19 +39,19 @@ use thiserror::Error;
 
 use super::{
     SessionConfig,
-    session_config::Version,
     session_keys::SessionKeys,
     shared_secret::{RemoteShared3DHSecret, Shared3DHSecret},
 };
 #[cfg(feature = "low-level-api")]
 use crate::hazmat::olm::MessageKey;
 use crate::{
-    Curve25519PublicKey, PickleError,
+    PaddingPolicy, PickleEncoding, PickleError,
     olm::{
-        messages::{Message, OlmMessage, PreKeyMessage},
+        messages::{KemEncapsulation, Message, OlmMessage, PreKeyMessage},
         session::double_ratchet::RatchetCount,
     },
-    utilities::{pickle, unpickle},
+    padding::unpad,
+    utilities::{pickle, pickle_with_encoding, pickle_with_rng, unpickle},
 };
 
 const MAX_RECEIVING_CHAINS: usize = 5;
@@ -73,6 +77,14 @@ pub enum DecryptionError {
     /// Too many messages have been skipped to attempt decrypting this message.
     #[error("The message gap was too big, got {0}, max allowed {1}")]
     TooBigMessageGap(u64, u64),
+    /// The message was marked as padded, but the plaintext didn't contain
+    /// valid length-hiding padding.
+    #[error("Failed decrypting Olm message, invalid length-hiding padding")]
+    InvalidLengthHidingPadding,
+    /// The header of a message couldn't be decrypted, or the message didn't
+    /// encrypt its header even though the session requires it.
+    #[error("Failed decrypting Olm message, the header couldn't be decrypted")]
+    InvalidHeader,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -97,7 +109,6 @@ impl ChainStore {
         self.inner.is_empty()
     }
 
-    #[cfg(test)]
     pub const fn len(&self) -> usize {
         self.inner.len()
     }
@@ -110,6 +121,15 @@ impl ChainStore {
     fn find_ratchet(&mut self, ratchet_key: &RemoteRatchetKey) -> Option<&mut ReceiverChain> {
         self.inner.iter_mut().find(|r| r.belongs_to(ratchet_key))
     }
+
+    /// Try to decrypt an encrypted header with the header keys of all the
+    /// chains, returning the ratchet key of the matching chain and the chain
+    /// index of the message.
+    fn decrypt_header(&self, encrypted_header: &[u8]) -> Option<(RemoteRatchetKey, u64)> {
+        self.inner.iter().find_map(|r| {
+            r.decrypt_header(encrypted_header).map(|chain_index| (r.ratchet_key(), chain_index))
+        })
+    }
 }
 
 impl Default for ChainStore {
@@ -147,6 +167,9 @@ impl Default for ChainStore {
 /// [`Account::create_inbound_session`]: crate::olm::Account::create_inbound_session
 pub struct Session {
     session_keys: SessionKeys,
+    /// The ML-KEM-768 encapsulation of a hybrid outbound session, sent along
+    /// in every pre-key message until the session is established.
+    kem: Option<KemEncapsulation>,
     sending_ratchet: DoubleRatchet,
     receiving_chains: ChainStore,
     config: SessionConfig,
@@ -154,7 +177,7 @@ pub struct Session {
 
 impl Debug for Session {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self { session_keys: _, sending_ratchet, receiving_chains, config } = self;
+        let Self { session_keys: _, kem: _, sending_ratchet, receiving_chains, config } = self;
 
         f.debug_struct("Session")
             .field("session_id", &self.session_id())
@@ -170,11 +193,14 @@ impl Session {
         config: SessionConfig,
         shared_secret: Shared3DHSecret,
         session_keys: SessionKeys,
+        kem: Option<KemEncapsulation>,
+        rng: &mut impl CryptoRngCore,
     ) -> Self {
-        let local_ratchet = DoubleRatchet::active(shared_secret);
+        let local_ratchet = DoubleRatchet::active(shared_secret, config.encrypts_headers(), rng);
 
         Self {
             session_keys,
+            kem,
             sending_ratchet: local_ratchet,
             receiving_chains: Default::default(),
             config,
@@ -184,28 +210,48 @@ impl Session {
     pub(super) fn new_remote(
         config: SessionConfig,
         shared_secret: RemoteShared3DHSecret,
-        remote_ratchet_key: Curve25519PublicKey,
+        message: &Message,
         session_keys: SessionKeys,
-    ) -> Self {
-        let (root_key, remote_chain_key) = shared_secret.expand();
+    ) -> Result<Self, DecryptionError> {
+        let (root_key, remote_chain_key, [header_key, next_header_key]) = shared_secret.expand();
 
-        let remote_ratchet_key = RemoteRatchetKey::from(remote_ratchet_key);
         let root_key = RemoteRootKey::new(root_key);
         let remote_chain_key = RemoteChainKey::new(remote_chain_key);
 
-        let local_ratchet = DoubleRatchet::inactive_from_prekey_data(root_key, remote_ratchet_key);
-        let remote_ratchet =
-            ReceiverChain::new(remote_ratchet_key, remote_chain_key, RatchetCount::new());
+        // The ratchet key of a header-encrypted message is only known once
+        // the header is decrypted using the header key of the first chain.
+        let (remote_ratchet_key, header_key, next_header_key) = if config.encrypts_headers() {
+            let header_key = HeaderKey::new(header_key);
+            let (ratchet_key, _) = message
+                .encrypted_header
+                .as_deref()
+                .and_then(|h| header_key.decrypt(h))
+                .ok_or(DecryptionError::InvalidHeader)?;
+
+            (ratchet_key, Some(header_key), Some(HeaderKey::new(next_header_key)))
+        } else {
+            (RemoteRatchetKey::from(message.ratchet_key), None, None)
+        };
+
+        let local_ratchet =
+            DoubleRatchet::inactive_from_prekey_data(root_key, remote_ratchet_key, next_header_key);
+        let remote_ratchet = ReceiverChain::new(
+            remote_ratchet_key,
+            remote_chain_key,
+            RatchetCount::new(),
+            header_key,
+        );
 
         let mut ratchet_store = ChainStore::new();
         ratchet_store.push(remote_ratchet);
 
-        Self {
+        Ok(Self {
             session_keys,
+            kem: None,
             sending_ratchet: local_ratchet,
             receiving_chains: ratchet_store,
             config,
-        }
+        })
     }
 
     /// Returns the globally unique session ID, in base64-encoded form.
@@ -223,6 +269,14 @@ impl Session {
         !self.receiving_chains.is_empty()
     }
 
+    /// The number of receiving chains the session keeps around.
+    ///
+    /// A new receiving chain is created every time the other side advances the
+    /// Diffie-Hellman ratchet, only the most recent ones are kept.
+    pub const fn receiving_chain_count(&self) -> usize {
+        self.receiving_chains.len()
+    }
+
     /// Encrypt the `plaintext` and construct an [`OlmMessage`].
     ///
     /// The message will either be a pre-key message or a normal message,
@@ -230,20 +284,56 @@ impl Session {
     /// fully established once you receive (and decrypt) at least one
     /// message from the other side.
     pub fn encrypt(&mut self, plaintext: impl AsRef<[u8]>) -> OlmMessage {
-        let message = match self.config.version {
-            Version::V1 => self.sending_ratchet.encrypt_truncated_mac(plaintext.as_ref()),
-            Version::V2 => self.sending_ratchet.encrypt(plaintext.as_ref()),
-        };
+        self.encrypt_with_rng(plaintext, &mut thread_rng())
+    }
+
+    /// Encrypt the `plaintext` and construct an [`OlmMessage`], using the given
+    /// random number generator to create a new ratchet key if the
+    /// conversation changed direction.
+    ///
+    /// See [`Session::encrypt()`] for more details.
+    pub fn encrypt_with_rng(
+        &mut self,
+        plaintext: impl AsRef<[u8]>,
+        rng: &mut impl CryptoRngCore,
+    ) -> OlmMessage {
+        let message = self.sending_ratchet.encrypt(plaintext.as_ref(), &self.config, rng);
 
+        self.wrap_message(message)
+    }
+
+    /// Wrap the message in a pre-key message, unless the session is fully
+    /// established.
+    fn wrap_message(&self, message: Message) -> OlmMessage {
         if self.has_received_message() {
             OlmMessage::Normal(message)
         } else {
-            let message = PreKeyMessage::new(self.session_keys, message);
+            let message = PreKeyMessage::new(self.session_keys, self.kem.clone(), message);
 
             OlmMessage::PreKey(message)
         }
     }
 
+    /// The number of random bytes the next call to
+    /// [`Session::encrypt_with_rng()`] consumes.
+    #[cfg(feature = "libolm-compat")]
+    pub fn encrypt_random_length(&self) -> usize {
+        self.sending_ratchet.encrypt_random_length()
+    }
+
+    /// The length, in bytes, of the message [`Session::encrypt()`] produces
+    /// for a plaintext of the given length, i.e. the length of the bytes
+    /// [`OlmMessage::to_parts()`] returns.
+    ///
+    /// Nothing is encrypted and the session isn't modified, this is useful to
+    /// allocate a buffer for the message up front.
+    #[cfg(feature = "libolm-compat")]
+    pub fn encrypted_message_length(&self, plaintext_length: usize) -> usize {
+        let message = self.sending_ratchet.message_template(plaintext_length, &self.config);
+
+        self.wrap_message(message).to_parts().1.len()
+    }
+
     /// Get the keys associated with this session.
     pub const fn session_keys(&self) -> SessionKeys {
         self.session_keys
@@ -254,6 +344,15 @@ impl Session {
         self.config
     }
 
+    /// Change the [`PaddingPolicy`] used to pad plaintexts of messages this
+    /// [`Session`] encrypts from now on.
+    ///
+    /// This is mostly useful for inbound sessions, which always start out with
+    /// [`PaddingPolicy::None`] since the padding isn't negotiated.
+    pub const fn set_padding_policy(&mut self, padding: PaddingPolicy) {
+        self.config = self.config.with_padding(padding);
+    }
+
     /// Get the [`MessageKey`] to encrypt the next message.
     ///
     /// **Note**: Each key obtained in this way should be used to encrypt
@@ -265,15 +364,44 @@ impl Session {
     /// undecryptable messages.
     #[cfg(feature = "low-level-api")]
     pub fn next_message_key(&mut self) -> MessageKey {
-        self.sending_ratchet.next_message_key()
+        self.next_message_key_with_rng(&mut thread_rng())
+    }
+
+    /// Get the [`MessageKey`] to encrypt the next message, using the given
+    /// random number generator to create a new ratchet key if the
+    /// conversation changed direction.
+    ///
+    /// See [`Session::next_message_key()`] for more details.
+    #[cfg(feature = "low-level-api")]
+    pub fn next_message_key_with_rng(&mut self, rng: &mut impl CryptoRngCore) -> MessageKey {
+        self.sending_ratchet.next_message_key(rng)
+    }
+
+    /// Get the root key and, if we're currently sending, the chain key of our
+    /// sending ratchet.
+    #[cfg(feature = "test-vectors")]
+    pub(crate) fn sending_ratchet_keys(&self) -> (&[u8; 32], Option<&[u8; 32]>) {
+        self.sending_ratchet.keys()
     }
 
     /// Try to decrypt an Olm message, which will either return the plaintext or
     /// result in a [`DecryptionError`].
     pub fn decrypt(&mut self, message: &OlmMessage) -> Result<Vec<u8>, DecryptionError> {
+        self.decrypt_with_rng(message, &mut thread_rng())
+    }
+
+    /// Try to decrypt an Olm message, using the given random number generator
+    /// to create a new ratchet key if one is needed.
+    ///
+    /// See [`Session::decrypt()`] for more details.
+    pub fn decrypt_with_rng(
+        &mut self,
+        message: &OlmMessage,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Vec<u8>, DecryptionError> {
         let decrypted = match message {
-            OlmMessage::Normal(m) => self.decrypt_decoded(m)?,
-            OlmMessage::PreKey(m) => self.decrypt_decoded(&m.message)?,
+            OlmMessage::Normal(m) => self.decrypt_decoded(m, rng)?,
+            OlmMessage::PreKey(m) => self.decrypt_decoded(&m.message, rng)?,
         };
 
         Ok(decrypted)
@@ -282,28 +410,64 @@ impl Session {
     pub(super) fn decrypt_decoded(
         &mut self,
         message: &Message,
+        rng: &mut impl CryptoRngCore,
     ) -> Result<Vec<u8>, DecryptionError> {
-        let ratchet_key = RemoteRatchetKey::from(message.ratchet_key);
+        let (ratchet_key, chain_index) = self.decrypt_header(message)?;
 
-        if let Some(ratchet) = self.receiving_chains.find_ratchet(&ratchet_key) {
-            ratchet.decrypt(message, &self.config)
+        let plaintext = if let Some(ratchet) = self.receiving_chains.find_ratchet(&ratchet_key) {
+            ratchet.decrypt(message, chain_index, &self.config)?
         } else {
-            let (sending_ratchet, mut remote_ratchet) = self.sending_ratchet.advance(ratchet_key);
+            let (sending_ratchet, mut remote_ratchet) =
+                self.sending_ratchet.advance(ratchet_key, rng);
 
-            let plaintext = remote_ratchet.decrypt(message, &self.config)?;
+            let plaintext = remote_ratchet.decrypt(message, chain_index, &self.config)?;
 
             self.sending_ratchet = sending_ratchet;
             self.receiving_chains.push(remote_ratchet);
 
+            plaintext
+        };
+
+        // The padded flag is covered by the MAC, so only the sender could have
+        // set it.
+        if message.padded {
+            unpad(plaintext).map_err(|_| DecryptionError::InvalidLengthHidingPadding)
+        } else {
             Ok(plaintext)
         }
     }
 
+    /// Get the ratchet key and chain index of the given message, decrypting
+    /// its header first if the session encrypts headers.
+    fn decrypt_header(
+        &self,
+        message: &Message,
+    ) -> Result<(RemoteRatchetKey, u64), DecryptionError> {
+        match (&message.encrypted_header, self.config.encrypts_headers()) {
+            (None, false) => Ok((RemoteRatchetKey::from(message.ratchet_key), message.chain_index)),
+            (Some(encrypted_header), true) => self
+                .receiving_chains
+                .decrypt_header(encrypted_header)
+                .or_else(|| {
+                    // The header might belong to the next chain of the other
+                    // side, in which case we'll need to advance our ratchet.
+                    self.sending_ratchet
+                        .next_remote_header_key()
+                        .and_then(|header_key| header_key.decrypt(encrypted_header))
+                })
+                .ok_or(DecryptionError::InvalidHeader),
+            // Falling back to plaintext headers would defeat the purpose of
+            // header encryption.
+            _ => Err(DecryptionError::InvalidHeader),
+        }
+    }
+
     /// Convert the session into a struct which implements [`serde::Serialize`]
     /// and [`serde::Deserialize`].
     pub fn pickle(&self) -> SessionPickle {
         SessionPickle {
             session_keys: self.session_keys,
+            kem: self.kem.clone(),
             sending_ratchet: self.sending_ratchet.clone(),
             receiving_chains: self.receiving_chains.clone(),
             config: self.config,
@@ -377,7 +541,7 @@ mod libolm_compat {
                 chain.chain_key_index,
             );
 
-            ReceiverChain::new(ratchet_key, chain_key, RatchetCount::unknown())
+            ReceiverChain::new(ratchet_key, chain_key, RatchetCount::unknown(), None)
         }
     }
 
@@ -452,6 +616,7 @@ mod libolm_compat {
 
                 Ok(Self {
                     session_keys: pickle.session_keys,
+                    kem: None,
                     sending_ratchet,
                     receiving_chains,
                     config: SessionConfig::version_1(),
@@ -464,6 +629,7 @@ mod libolm_compat {
 
                 Ok(Self {
                     session_keys: pickle.session_keys,
+                    kem: None,
                     sending_ratchet,
                     receiving_chains,
                     config: SessionConfig::version_1(),
@@ -480,6 +646,8 @@ mod libolm_compat {
 #[derive(Deserialize, Serialize)]
 pub struct SessionPickle {
     session_keys: SessionKeys,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kem: Option<KemEncapsulation>,
     sending_ratchet: DoubleRatchet,
     receiving_chains: ChainStore,
     #[serde(default = "default_config")]
@@ -491,11 +659,44 @@ const fn default_config() -> SessionConfig {
 }
 
 impl SessionPickle {
+    const PICKLE_TYPE: &'static str = "Session";
+
     /// Serialize and encrypt the pickle using the given key.
     ///
     /// This is the inverse of [`SessionPickle::from_encrypted`].
     pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
-        pickle(&self, pickle_key)
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`SessionPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, encoding it using
+    /// the given [`PickleEncoding`].
+    ///
+    /// This is the inverse of [`SessionPickle::from_encrypted`], which detects
+    /// the encoding automatically.
+    pub fn encrypt_with_encoding(self, pickle_key: &[u8; 32], encoding: PickleEncoding) -> String {
+        pickle_with_encoding(&self, pickle_key, Self::PICKLE_TYPE, encoding)
+    }
+
+    /// Serialize and encrypt the pickle using the given key and
+    /// [`PickleEncoding`], generating the salt of the encrypted pickle using
+    /// the given random number generator.
+    ///
+    /// See [`SessionPickle::encrypt_with_encoding`] for more details.
+    pub fn encrypt_with_encoding_and_rng(
+        self,
+        pickle_key: &[u8; 32],
+        encoding: PickleEncoding,
+        rng: &mut impl CryptoRngCore,
+    ) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, encoding, rng)
     }
 
     /// Obtain a pickle from a ciphertext by decrypting and deserializing using
@@ -503,7 +704,7 @@ impl SessionPickle {
     ///
     /// This is the inverse of [`SessionPickle::encrypt`].
     pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
-        unpickle(ciphertext, pickle_key)
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
     }
 }
 
@@ -511,6 +712,7 @@ impl From<SessionPickle> for Session {
     fn from(pickle: SessionPickle) -> Self {
         Self {
             session_keys: pickle.session_keys,
+            kem: pickle.kem,
             sending_ratchet: pickle.sending_ratchet,
             receiving_chains: pickle.receiving_chains,
             config: pickle.config,
@@ -526,10 +728,11 @@ mod test {
         account::OlmAccount,
         session::{OlmMessage, OlmSession},
     };
+    use rand::{SeedableRng, rngs::StdRng};
 
     use super::{DecryptionError, Session};
     use crate::{
-        Curve25519PublicKey,
+        Curve25519PublicKey, PaddingPolicy, PickleEncoding,
         olm::{
             Account, SessionConfig, SessionPickle, messages,
             session::receiver_chain::{MAX_MESSAGE_GAP, MAX_MESSAGE_KEYS},
@@ -595,6 +798,152 @@ mod test {
         assert!(!bob_session.has_received_message());
     }
 
+    #[test]
+    fn padded_session() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let config = SessionConfig::version_2().with_padding(PaddingPolicy::PowerOfTwo);
+        let mut alice_session =
+            alice.create_outbound_session(config, bob.curve25519_key(), one_time_key);
+
+        let plaintext = "It's a secret to everybody";
+        let message = alice_session.encrypt(plaintext);
+
+        let messages::OlmMessage::PreKey(message) = message else {
+            bail!("The first message should be a pre-key message");
+        };
+
+        assert!(message.message().is_padded());
+        // 32 bytes of padded plaintext, and another block of PKCS#7 padding.
+        assert_eq!(message.message().ciphertext().len(), 48);
+
+        let result = bob.create_inbound_session(alice.curve25519_key(), &message)?;
+        let mut bob_session = result.session;
+
+        assert_eq!(result.plaintext, plaintext.as_bytes());
+        assert_eq!(bob_session.session_config().padding(), PaddingPolicy::None);
+
+        let reply = bob_session.encrypt("Reply");
+        let messages::OlmMessage::Normal(reply) = reply else {
+            bail!("The reply should be a normal message");
+        };
+        assert!(!reply.is_padded());
+
+        assert_eq!(alice_session.decrypt(&reply.into())?, b"Reply");
+
+        bob_session.set_padding_policy(PaddingPolicy::Padme);
+        let reply = bob_session.encrypt("Padded reply");
+        assert_eq!(alice_session.decrypt(&reply)?, b"Padded reply");
+
+        let message = alice_session.encrypt(plaintext);
+        assert_eq!(bob_session.decrypt(&message)?, plaintext.as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_encrypted_session() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let mut alice_session = alice.create_outbound_session(
+            SessionConfig::version_4(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        let plaintext = "It's a secret to everybody";
+        let messages::OlmMessage::PreKey(message) = alice_session.encrypt(plaintext) else {
+            bail!("The first message should be a pre-key message");
+        };
+
+        assert!(message.message().is_header_encrypted());
+        assert_eq!(message.message().chain_index(), None);
+
+        let result = bob.create_inbound_session(alice.curve25519_key(), &message)?;
+        let mut bob_session = result.session;
+
+        assert_eq!(result.plaintext, plaintext.as_bytes());
+        assert_eq!(bob_session.session_config(), SessionConfig::version_4());
+
+        // Go through a couple of ratchet turns, with some of the messages
+        // arriving out of order.
+        for turn in 0..3 {
+            let first = bob_session.encrypt(format!("Bob {turn} first"));
+            let second = bob_session.encrypt(format!("Bob {turn} second"));
+
+            assert_eq!(alice_session.decrypt(&second)?, format!("Bob {turn} second").as_bytes());
+
+            let reply = alice_session.encrypt(format!("Alice {turn}"));
+            assert_eq!(alice_session.decrypt(&first)?, format!("Bob {turn} first").as_bytes());
+
+            let messages::OlmMessage::Normal(reply) = reply else {
+                bail!("The reply should be a normal message");
+            };
+            assert!(reply.is_header_encrypted());
+            assert_eq!(reply.ratchet_key(), None);
+
+            assert_eq!(bob_session.decrypt(&reply.into())?, format!("Alice {turn}").as_bytes());
+        }
+
+        assert_eq!(bob_session.receiving_chain_count(), 4);
+
+        // The header keys survive a pickle roundtrip.
+        let mut bob_session = Session::from_pickle(bob_session.pickle());
+        let message = alice_session.encrypt("After the pickle");
+        assert_eq!(bob_session.decrypt(&message)?, b"After the pickle");
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_encrypted_session_rejects_plain_headers() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let mut alice_session = alice.create_outbound_session(
+            SessionConfig::version_4(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        let messages::OlmMessage::PreKey(message) = alice_session.encrypt("Hello") else {
+            bail!("The first message should be a pre-key message");
+        };
+        let mut bob_session = bob.create_inbound_session(alice.curve25519_key(), &message)?.session;
+
+        let messages::OlmMessage::PreKey(message) = alice_session.encrypt("Hello again") else {
+            bail!("The second message should still be a pre-key message");
+        };
+
+        let mut stripped = message.message().clone();
+        stripped.encrypted_header = None;
+        assert_matches!(bob_session.decrypt(&stripped.into()), Err(DecryptionError::InvalidHeader));
+
+        let mut tampered = message.message().clone();
+        if let Some(header) = &mut tampered.encrypted_header {
+            header[20] ^= 1;
+        }
+        assert_matches!(bob_session.decrypt(&tampered.into()), Err(DecryptionError::InvalidHeader));
+
+        assert_eq!(bob_session.decrypt(&message.into())?, b"Hello again");
+
+        Ok(())
+    }
+
     #[test]
     fn out_of_order_decryption() {
         let (_, _, mut alice_session, bob_session) = session_and_libolm_pair().unwrap();
@@ -796,4 +1145,175 @@ mod test {
 
         assert_eq!(pickle, repickle);
     }
+
+    #[test]
+    fn binary_pickling_roundtrip() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        bob.generate_one_time_keys(1);
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+
+        let mut alice_session = alice.create_outbound_session(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        );
+
+        let messages::OlmMessage::PreKey(message) = alice_session.encrypt("Hello") else {
+            bail!("The first message should be a pre-key message");
+        };
+        let session = bob.create_inbound_session(alice.curve25519_key(), &message)?.session;
+
+        let json = session.pickle().encrypt(&PICKLE_KEY);
+        let binary = session.pickle().encrypt_with_encoding(&PICKLE_KEY, PickleEncoding::Binary);
+        assert!(binary.len() < json.len());
+
+        let unpickled = SessionPickle::from_encrypted(&binary, &PICKLE_KEY)?;
+        assert_eq!(serde_json::to_value(&unpickled)?, serde_json::to_value(session.pickle())?);
+
+        let mut unpickled = Session::from_pickle(unpickled);
+        assert_eq!(unpickled.session_id(), session.session_id());
+
+        let message = alice_session.encrypt("It's a secret to everybody");
+        assert_eq!(unpickled.decrypt(&message)?, b"It's a secret to everybody");
+
+        Ok(())
+    }
+
+    /// Run a full conversation between two accounts, with all the randomness
+    /// coming from a random number generator seeded with the given seed.
+    fn seeded_transcript(seed: u64) -> Result<Vec<(usize, Vec<u8>)>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let alice = Account::new_with_rng(&mut rng);
+        let mut bob = Account::new_with_rng(&mut rng);
+
+        bob.generate_one_time_keys_with_rng(1, &mut rng);
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+        bob.mark_keys_as_published();
+
+        let mut alice_session = alice.create_outbound_session_with_rng(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+            &mut rng,
+        );
+
+        let mut transcript = Vec::new();
+        let message = alice_session.encrypt_with_rng("It's a secret to everybody", &mut rng);
+        transcript.push(message.to_parts());
+
+        let messages::OlmMessage::PreKey(pre_key_message) = message else {
+            bail!("The first message should be a pre-key message");
+        };
+        let mut bob_session = bob
+            .create_inbound_session_with_rng(alice.curve25519_key(), &pre_key_message, &mut rng)?
+            .session;
+
+        for plaintext in ["Take this", "It's dangerous to go alone"] {
+            let message = bob_session.encrypt_with_rng(plaintext, &mut rng);
+            alice_session.decrypt_with_rng(&message, &mut rng)?;
+            transcript.push(message.to_parts());
+
+            let message = alice_session.encrypt_with_rng(plaintext, &mut rng);
+            bob_session.decrypt_with_rng(&message, &mut rng)?;
+            transcript.push(message.to_parts());
+        }
+
+        Ok(transcript)
+    }
+
+    /// A random number generator which counts the random bytes it generates.
+    #[cfg(feature = "libolm-compat")]
+    #[derive(Default)]
+    struct CountingRng(usize);
+
+    #[cfg(feature = "libolm-compat")]
+    impl rand::RngCore for CountingRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), dest);
+            self.0 += dest.len();
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "libolm-compat")]
+    impl rand::CryptoRng for CountingRng {}
+
+    /// Check that the lengths the session predicts match the message it then
+    /// actually encrypts.
+    #[cfg(feature = "libolm-compat")]
+    fn assert_predicted_lengths(session: &mut Session) {
+        for plaintext_length in [0, 1, 15, 16, 17, 100] {
+            let random_length = session.encrypt_random_length();
+            let message_length = session.encrypted_message_length(plaintext_length);
+
+            let mut rng = CountingRng::default();
+            let message = session.encrypt_with_rng(vec![b'a'; plaintext_length], &mut rng);
+
+            assert_eq!(rng.0, random_length);
+            assert_eq!(message.to_parts().1.len(), message_length);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "libolm-compat")]
+    fn predicted_lengths() -> Result<()> {
+        for config in [
+            SessionConfig::version_1(),
+            SessionConfig::version_2().with_padding(PaddingPolicy::Padme),
+            SessionConfig::version_4(),
+            SessionConfig::version_4().with_padding(PaddingPolicy::Block(64)),
+        ] {
+            let alice = Account::new();
+            let mut bob = Account::new();
+
+            bob.generate_one_time_keys(1);
+            let one_time_key =
+                *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+
+            let mut alice_session =
+                alice.create_outbound_session(config, bob.curve25519_key(), one_time_key);
+
+            // Pre-key messages.
+            assert_predicted_lengths(&mut alice_session);
+
+            let messages::OlmMessage::PreKey(message) = alice_session.encrypt("Hello") else {
+                bail!("The first message should be a pre-key message");
+            };
+            let mut bob_session =
+                bob.create_inbound_session(alice.curve25519_key(), &message)?.session;
+
+            // Normal messages, starting with an inactive ratchet.
+            assert_predicted_lengths(&mut bob_session);
+
+            // A reply makes Alice's ratchet inactive again.
+            alice_session.decrypt(&bob_session.encrypt("Hi"))?;
+            assert_predicted_lengths(&mut alice_session);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_transcript() -> Result<()> {
+        assert_eq!(seeded_transcript(0)?, seeded_transcript(0)?);
+        assert_ne!(seeded_transcript(0)?, seeded_transcript(1)?);
+
+        Ok(())
+    }
 }
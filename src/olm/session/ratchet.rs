@@ -15,11 +15,13 @@
 use std::fmt::Debug;
 
 use matrix_pickle::Decode;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use x25519_dalek::SharedSecret;
 
 use super::{
     chain_key::RemoteChainKey,
+    header_key::HeaderKey,
     root_key::{RemoteRootKey, RootKey},
 };
 use crate::{Curve25519PublicKey, types::Curve25519SecretKey};
@@ -63,6 +65,10 @@ impl RatchetKey {
         Self(Curve25519SecretKey::new())
     }
 
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        Self(Curve25519SecretKey::new_with_rng(rng))
+    }
+
     pub fn diffie_hellman(&self, other: &RemoteRatchetKey) -> SharedSecret {
         self.0.diffie_hellman(&other.0)
     }
@@ -123,8 +129,8 @@ pub(super) struct Ratchet {
 }
 
 impl Ratchet {
-    pub fn new(root_key: RootKey) -> Self {
-        let ratchet_key = RatchetKey::new();
+    pub fn new(root_key: RootKey, rng: &mut impl CryptoRngCore) -> Self {
+        let ratchet_key = RatchetKey::new_with_rng(rng);
 
         Self { root_key, ratchet_key }
     }
@@ -133,11 +139,16 @@ impl Ratchet {
         Self { root_key, ratchet_key }
     }
 
-    pub fn advance(&self, remote_key: RemoteRatchetKey) -> (RemoteRootKey, RemoteChainKey) {
-        let (remote_root_key, remote_chain_key) =
-            self.root_key.advance(&self.ratchet_key, &remote_key);
+    pub fn advance(
+        &self,
+        remote_key: RemoteRatchetKey,
+    ) -> (RemoteRootKey, RemoteChainKey, HeaderKey) {
+        self.root_key.advance(&self.ratchet_key, &remote_key)
+    }
 
-        (remote_root_key, remote_chain_key)
+    #[cfg(feature = "test-vectors")]
+    pub const fn root_key(&self) -> &RootKey {
+        &self.root_key
     }
 
     pub const fn ratchet_key(&self) -> &RatchetKey {
@@ -161,6 +172,6 @@ mod test {
     fn ratchet_public_key_from_bytes() {
         let bytes = b"aaaaaaaaaaaaaaawaaaaaaaaaaaaaaaa";
         let key = RatchetPublicKey::from(*bytes);
-        assert_eq!(key.0.to_bytes().as_ref(), bytes);
+        assert_eq!(key.0.to_bytes().as_slice(), bytes);
     }
 }
@@ -18,8 +18,8 @@ use arrayvec::ArrayVec;
 use serde::{Deserialize, Serialize};
 
 use super::{
-    DecryptionError, chain_key::RemoteChainKey, message_key::RemoteMessageKey,
-    ratchet::RemoteRatchetKey,
+    DecryptionError, chain_key::RemoteChainKey, header_key::HeaderKey,
+    message_key::RemoteMessageKey, ratchet::RemoteRatchetKey,
 };
 use crate::olm::{
     SessionConfig, messages::Message, session::double_ratchet::RatchetCount,
@@ -86,7 +86,7 @@ impl FoundMessageKey<'_> {
 
         match config.version {
             Version::V1 => message_key.decrypt_truncated_mac(message),
-            Version::V2 => message_key.decrypt(message),
+            Version::V2 | Version::V3 | Version::V4 => message_key.decrypt(message),
         }
     }
 }
@@ -133,11 +133,16 @@ pub(super) struct ReceiverChain {
     /// for diagnostic output.
     #[serde(default = "RatchetCount::unknown")]
     ratchet_count: RatchetCount,
+
+    /// The header key of this chain, if the session encrypts headers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header_key: Option<HeaderKey>,
 }
 
 impl Debug for ReceiverChain {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Self { ratchet_count, ratchet_key, hkdf_ratchet, skipped_message_keys } = self;
+        let Self { ratchet_count, ratchet_key, hkdf_ratchet, skipped_message_keys, header_key: _ } =
+            self;
 
         f.debug_struct("ReceiverChain")
             .field("ratchet_count", &ratchet_count)
@@ -153,15 +158,28 @@ impl ReceiverChain {
         ratchet_key: RemoteRatchetKey,
         chain_key: RemoteChainKey,
         ratchet_count: RatchetCount,
+        header_key: Option<HeaderKey>,
     ) -> Self {
         ReceiverChain {
             ratchet_key,
             hkdf_ratchet: chain_key,
             skipped_message_keys: Default::default(),
             ratchet_count,
+            header_key,
         }
     }
 
+    /// Try to decrypt the encrypted header of a message using the header key
+    /// of this chain.
+    ///
+    /// Returns the chain index of the message if the header was encrypted for
+    /// this chain.
+    pub fn decrypt_header(&self, encrypted_header: &[u8]) -> Option<u64> {
+        let (ratchet_key, chain_index) = self.header_key.as_ref()?.decrypt(encrypted_header)?;
+
+        self.belongs_to(&ratchet_key).then_some(chain_index)
+    }
+
     fn find_message_key(&self, chain_index: u64) -> Result<FoundMessageKey<'_>, DecryptionError> {
         let message_gap = chain_index.saturating_sub(self.hkdf_ratchet.chain_index());
 
@@ -196,9 +214,9 @@ impl ReceiverChain {
     pub fn decrypt(
         &mut self,
         message: &Message,
+        chain_index: u64,
         config: &SessionConfig,
     ) -> Result<Vec<u8>, DecryptionError> {
-        let chain_index = message.chain_index;
         let message_key = self.find_message_key(chain_index)?;
 
         let plaintext = message_key.decrypt(message, config)?;
@@ -219,7 +237,6 @@ impl ReceiverChain {
         Ok(plaintext)
     }
 
-    #[cfg(feature = "libolm-compat")]
     pub const fn ratchet_key(&self) -> RemoteRatchetKey {
         self.ratchet_key
     }
@@ -293,7 +310,8 @@ mod test {
         let ratchet_key = RemoteRatchetKey::from([0u8; 32]);
         let chain_key = RemoteChainKey::new([0u8; 32].into());
 
-        let receiver_chain = ReceiverChain::new(ratchet_key, chain_key, RatchetCount::Known(0));
+        let receiver_chain =
+            ReceiverChain::new(ratchet_key, chain_key, RatchetCount::Known(0), None);
 
         assert_matches!(
             receiver_chain.find_message_key(MAX_MESSAGE_GAP + 1),
@@ -13,12 +13,14 @@
 // limitations under the License.
 
 use hkdf::Hkdf;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use super::{
     chain_key::{ChainKey, RemoteChainKey},
+    header_key::HeaderKey,
     ratchet::{RatchetKey, RemoteRatchetKey},
 };
 
@@ -32,6 +34,11 @@ const ADVANCEMENT_SEED: &[u8; 11] = b"OLM_RATCHET";
 /// `T`<sub>`i`</sub>. It is used only to calculate the *next* root key
 /// `R`<sub>`i+1`</sub> and [chain key](ChainKey) `C`<sub>`i+1`</sub>.
 ///
+/// Sessions which encrypt message headers additionally use the root key to
+/// derive the [header key](HeaderKey) `H`<sub>`i+2`</sub>, the header key of
+/// the chain *after* the new one. This way the receiver of a message on a new
+/// chain already has the key to decrypt its header.
+///
 /// This struct holds the root key corresponding to chains where we are the
 /// sender. See also [`RemoteRootKey`].
 #[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
@@ -53,10 +60,12 @@ fn kdf(
     root_key: &[u8; 32],
     ratchet_key: &RatchetKey,
     remote_ratchet_key: &RemoteRatchetKey,
-) -> Box<[u8; 64]> {
+) -> Box<[u8; 96]> {
     let shared_secret = ratchet_key.diffie_hellman(remote_ratchet_key);
     let hkdf: Hkdf<Sha256> = Hkdf::new(Some(root_key.as_ref()), shared_secret.as_bytes());
-    let mut output = Box::new([0u8; 64]);
+    // HKDF output is prefix-consistent, the header key which follows the root
+    // and chain key doesn't change them.
+    let mut output = Box::new([0u8; 96]);
 
     #[allow(clippy::expect_used)]
     hkdf.expand(ADVANCEMENT_SEED, output.as_mut_slice())
@@ -73,20 +82,24 @@ impl RemoteRootKey {
     pub(super) fn advance(
         &self,
         remote_ratchet_key: &RemoteRatchetKey,
-    ) -> (RootKey, ChainKey, RatchetKey) {
-        let ratchet_key = RatchetKey::new();
+        rng: &mut impl CryptoRngCore,
+    ) -> (RootKey, ChainKey, RatchetKey, HeaderKey) {
+        let ratchet_key = RatchetKey::new_with_rng(rng);
         let output = kdf(&self.key, &ratchet_key, remote_ratchet_key);
 
         let mut chain_key = Box::new([0u8; 32]);
         let mut root_key = Box::new([0u8; 32]);
+        let mut header_key = Box::new([0u8; 32]);
 
-        chain_key.copy_from_slice(&output[32..]);
+        chain_key.copy_from_slice(&output[32..64]);
         root_key.copy_from_slice(&output[..32]);
+        header_key.copy_from_slice(&output[64..]);
 
         let chain_key = ChainKey::new(chain_key);
         let root_key = RootKey::new(root_key);
+        let header_key = HeaderKey::new(header_key);
 
-        (root_key, chain_key, ratchet_key)
+        (root_key, chain_key, ratchet_key, header_key)
     }
 }
 
@@ -99,18 +112,21 @@ impl RootKey {
         &self,
         old_ratchet_key: &RatchetKey,
         remote_ratchet_key: &RemoteRatchetKey,
-    ) -> (RemoteRootKey, RemoteChainKey) {
+    ) -> (RemoteRootKey, RemoteChainKey, HeaderKey) {
         let output = kdf(&self.key, old_ratchet_key, remote_ratchet_key);
 
         let mut chain_key = Box::new([0u8; 32]);
         let mut root_key = Box::new([0u8; 32]);
+        let mut header_key = Box::new([0u8; 32]);
 
         root_key.copy_from_slice(&output[..32]);
-        chain_key.copy_from_slice(&output[32..]);
+        chain_key.copy_from_slice(&output[32..64]);
+        header_key.copy_from_slice(&output[64..]);
 
         let root_key = RemoteRootKey::new(root_key);
         let chain_key = RemoteChainKey::new(chain_key);
+        let header_key = HeaderKey::new(header_key);
 
-        (root_key, chain_key)
+        (root_key, chain_key, header_key)
     }
 }
@@ -14,17 +14,23 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::PaddingPolicy;
+
 /// A struct to configure how Olm sessions should work under the hood.
 /// Currently only the MAC truncation behaviour can be configured.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SessionConfig {
     pub(super) version: Version,
+    #[serde(default, skip_serializing_if = "PaddingPolicy::is_none")]
+    pub(super) padding: PaddingPolicy,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub(super) enum Version {
     V1 = 1,
     V2 = 2,
+    V3 = 3,
+    V4 = 4,
 }
 
 impl SessionConfig {
@@ -37,14 +43,65 @@ impl SessionConfig {
     /// use AES-256 and HMAC with a truncated MAC to encrypt individual
     /// messages. The MAC will be truncated to 8 bytes.
     pub const fn version_1() -> Self {
-        SessionConfig { version: Version::V1 }
+        SessionConfig { version: Version::V1, padding: PaddingPolicy::None }
     }
 
     /// Create a `SessionConfig` for the Olm version 2. This version of Olm will
     /// use AES-256 and HMAC to encrypt individual messages. The MAC won't be
     /// truncated.
     pub const fn version_2() -> Self {
-        SessionConfig { version: Version::V2 }
+        SessionConfig { version: Version::V2, padding: PaddingPolicy::None }
+    }
+
+    /// Create a `SessionConfig` for the Olm version 3. Messages are encrypted
+    /// like in version 2, but the session is established using a hybrid
+    /// post-quantum key agreement: a shared secret encapsulated for the
+    /// ML-KEM-768 key of the recipient is mixed into the triple
+    /// Diffie-Hellman.
+    ///
+    /// Outbound sessions fall back to version 2 if the recipient didn't
+    /// publish an ML-KEM-768 key, see
+    /// [`Account::create_outbound_session_with_kem_key()`].
+    ///
+    /// [`Account::create_outbound_session_with_kem_key()`]: crate::olm::Account::create_outbound_session_with_kem_key
+    pub const fn version_3() -> Self {
+        SessionConfig { version: Version::V3, padding: PaddingPolicy::None }
+    }
+
+    /// Create a `SessionConfig` for the Olm version 4. Messages are encrypted
+    /// like in version 2, but the ratchet key and chain index of every message
+    /// are encrypted as well, following the double ratchet with header
+    /// encryption construction. This hides from the server which messages
+    /// belong to the same chain and when the conversation changes direction.
+    pub const fn version_4() -> Self {
+        SessionConfig { version: Version::V4, padding: PaddingPolicy::None }
+    }
+
+    /// Does this `SessionConfig` establish sessions using ML-KEM-768 in
+    /// addition to the triple Diffie-Hellman.
+    pub(super) const fn uses_kem(&self) -> bool {
+        matches!(self.version, Version::V3)
+    }
+
+    /// Does this `SessionConfig` encrypt the headers of messages.
+    pub(super) const fn encrypts_headers(&self) -> bool {
+        matches!(self.version, Version::V4)
+    }
+
+    /// Get the [`PaddingPolicy`] plaintexts are padded with before they get
+    /// encrypted.
+    pub const fn padding(&self) -> PaddingPolicy {
+        self.padding
+    }
+
+    /// Pad plaintexts using the given [`PaddingPolicy`] before they get
+    /// encrypted, to hide their length.
+    ///
+    /// Padded messages are marked as such, the receiving side removes the
+    /// padding regardless of its own configuration.
+    pub const fn with_padding(mut self, padding: PaddingPolicy) -> Self {
+        self.padding = padding;
+        self
     }
 }
 
@@ -57,11 +114,34 @@ impl Default for SessionConfig {
 #[cfg(test)]
 mod test {
     use super::SessionConfig;
-    use crate::olm::session_config::Version;
+    use crate::{PaddingPolicy, olm::session_config::Version};
 
     #[test]
     fn version() {
         assert_eq!(SessionConfig::version_1().version(), Version::V1 as u8);
         assert_eq!(SessionConfig::version_2().version(), Version::V2 as u8);
+        assert_eq!(SessionConfig::version_3().version(), Version::V3 as u8);
+        assert_eq!(SessionConfig::version_4().version(), Version::V4 as u8);
+    }
+
+    #[test]
+    fn padding() {
+        let config = SessionConfig::version_2().with_padding(PaddingPolicy::Padme);
+
+        assert_eq!(SessionConfig::version_2().padding(), PaddingPolicy::None);
+        assert_eq!(config.padding(), PaddingPolicy::Padme);
+        assert_eq!(config.version(), Version::V2 as u8);
+    }
+
+    #[test]
+    fn serialization_without_padding() {
+        let config = SessionConfig::version_1();
+        let json = serde_json::to_string(&config).expect("The config should serialize");
+
+        assert_eq!(json, r#"{"version":"V1"}"#);
+        assert_eq!(
+            serde_json::from_str::<SessionConfig>(&json).expect("The config should deserialize"),
+            config
+        );
     }
 }
@@ -0,0 +1,586 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{Account, DecryptionError, OlmMessage, Session, SessionCreationError, SessionPickle};
+use crate::{
+    Curve25519PublicKey, PickleEncoding, PickleError,
+    utilities::{pickle, pickle_with_rng, unpickle},
+};
+
+/// Error type describing failures of the [`SessionSet`] to decrypt a message.
+#[derive(Debug, Error)]
+pub enum SessionSetError {
+    /// None of the sessions we share with the peer could decrypt the normal
+    /// message.
+    #[error("None of the Olm sessions with {0} could decrypt the message")]
+    NoMatchingSession(Curve25519PublicKey),
+    /// The pre-key message belongs to a session we already have, but the
+    /// session failed to decrypt it.
+    #[error(transparent)]
+    Decryption(#[from] DecryptionError),
+    /// The pre-key message didn't belong to any of our sessions and a new
+    /// inbound session couldn't be created from it.
+    #[error(transparent)]
+    SessionCreation(#[from] SessionCreationError),
+}
+
+/// Policy deciding which sessions a [`SessionSet`] evicts.
+///
+/// Once a peer has more than the allowed number of sessions, the least
+/// recently used ones are dropped.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SessionSetPolicy {
+    max_sessions_per_peer: usize,
+}
+
+impl SessionSetPolicy {
+    /// The number of sessions per peer the default policy keeps.
+    pub const DEFAULT_MAX_SESSIONS_PER_PEER: usize = 10;
+
+    /// Create a policy which keeps at most the given number of sessions per
+    /// peer. A maximum of zero is treated as a maximum of one.
+    pub const fn new(max_sessions_per_peer: usize) -> Self {
+        let max_sessions_per_peer =
+            if max_sessions_per_peer == 0 { 1 } else { max_sessions_per_peer };
+
+        Self { max_sessions_per_peer }
+    }
+
+    /// The maximal number of sessions kept per peer.
+    pub const fn max_sessions_per_peer(&self) -> usize {
+        self.max_sessions_per_peer
+    }
+}
+
+impl Default for SessionSetPolicy {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_SESSIONS_PER_PEER)
+    }
+}
+
+struct Entry {
+    session: Session,
+    last_used: u64,
+}
+
+/// A collection of the Olm [`Session`]s shared with a number of peers, keyed
+/// by the Curve25519 identity key of the peer.
+///
+/// Peers can end up sharing multiple sessions, for example if both sides
+/// created an outbound session at the same time, the [`SessionSet`] takes care
+/// of the bookkeeping this requires:
+///
+/// - Normal messages are decrypted by trying all the sessions of the peer, most
+///   recently used first.
+/// - Pre-key messages are routed to the session they belong to, a new inbound
+///   session is created using the [`Account`] if there's no such session.
+/// - Messages are encrypted using the most recently used session of the peer.
+/// - Stale sessions are evicted as the [`SessionSetPolicy`] requires.
+#[derive(Default)]
+pub struct SessionSet {
+    policy: SessionSetPolicy,
+    sessions: HashMap<Curve25519PublicKey, Vec<Entry>>,
+    /// A counter which is incremented every time a session is used, to keep
+    /// track of the order in which sessions were used.
+    use_counter: u64,
+}
+
+impl std::fmt::Debug for SessionSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { policy, sessions, use_counter: _ } = self;
+
+        f.debug_struct("SessionSet")
+            .field("policy", policy)
+            .field("peer_count", &sessions.len())
+            .field("session_count", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionSet {
+    /// Create a new, empty, [`SessionSet`] using the default
+    /// [`SessionSetPolicy`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new, empty, [`SessionSet`] using the given
+    /// [`SessionSetPolicy`].
+    pub fn with_policy(policy: SessionSetPolicy) -> Self {
+        Self { policy, ..Default::default() }
+    }
+
+    /// Get the [`SessionSetPolicy`] of this [`SessionSet`].
+    pub const fn policy(&self) -> SessionSetPolicy {
+        self.policy
+    }
+
+    /// Change the [`SessionSetPolicy`], evicting any sessions the new policy
+    /// doesn't allow to keep.
+    pub fn set_policy(&mut self, policy: SessionSetPolicy) {
+        self.policy = policy;
+
+        let peers: Vec<_> = self.sessions.keys().copied().collect();
+
+        for peer in peers {
+            self.evict(&peer);
+        }
+    }
+
+    /// The total number of sessions in the set.
+    pub fn len(&self) -> usize {
+        self.sessions.values().map(Vec::len).sum()
+    }
+
+    /// Is the set empty.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// The identity keys of all the peers we have sessions with.
+    pub fn peers(&self) -> impl Iterator<Item = &Curve25519PublicKey> {
+        self.sessions.keys()
+    }
+
+    /// The sessions shared with the given peer, most recently used first.
+    pub fn sessions(&self, peer: &Curve25519PublicKey) -> impl Iterator<Item = &Session> {
+        self.sessions.get(peer).into_iter().flatten().map(|entry| &entry.session)
+    }
+
+    /// Get the session with the given session ID shared with the given peer.
+    pub fn get(&self, peer: &Curve25519PublicKey, session_id: &str) -> Option<&Session> {
+        self.sessions(peer).find(|session| session.session_id() == session_id)
+    }
+
+    /// Get a mutable reference to the session with the given session ID shared
+    /// with the given peer.
+    ///
+    /// Using the session this way doesn't change which session is preferred
+    /// for encryption.
+    pub fn get_mut(
+        &mut self,
+        peer: &Curve25519PublicKey,
+        session_id: &str,
+    ) -> Option<&mut Session> {
+        self.sessions
+            .get_mut(peer)?
+            .iter_mut()
+            .map(|entry| &mut entry.session)
+            .find(|session| session.session_id() == session_id)
+    }
+
+    /// Add a session shared with the given peer to the set, the session
+    /// becomes the preferred one for encryption.
+    ///
+    /// If the set already contained a session with the same session ID, the
+    /// session gets replaced and the old session is returned.
+    pub fn insert(&mut self, peer: Curve25519PublicKey, session: Session) -> Option<Session> {
+        let removed = self.remove(&peer, &session.session_id());
+        let last_used = self.next_use();
+
+        self.sessions.entry(peer).or_default().insert(0, Entry { session, last_used });
+        self.evict(&peer);
+
+        removed
+    }
+
+    /// Remove the session with the given session ID shared with the given
+    /// peer from the set.
+    pub fn remove(&mut self, peer: &Curve25519PublicKey, session_id: &str) -> Option<Session> {
+        let entries = self.sessions.get_mut(peer)?;
+        let position = entries.iter().position(|e| e.session.session_id() == session_id)?;
+        let entry = entries.remove(position);
+
+        if entries.is_empty() {
+            self.sessions.remove(peer);
+        }
+
+        Some(entry.session)
+    }
+
+    /// The session which should be used to encrypt messages for the given
+    /// peer, the most recently used one.
+    pub fn preferred_session(&self, peer: &Curve25519PublicKey) -> Option<&Session> {
+        self.sessions(peer).next()
+    }
+
+    /// Encrypt the `plaintext` for the given peer using the preferred session,
+    /// returns `None` if we don't share a session with the peer.
+    pub fn encrypt(
+        &mut self,
+        peer: &Curve25519PublicKey,
+        plaintext: impl AsRef<[u8]>,
+    ) -> Option<OlmMessage> {
+        let message = self.sessions.get_mut(peer)?.first_mut()?.session.encrypt(plaintext);
+        self.mark_as_used(peer, 0);
+
+        Some(message)
+    }
+
+    /// Decrypt a message the given peer sent us.
+    ///
+    /// Normal messages are tried against the sessions of the peer, most
+    /// recently used first. Pre-key messages are decrypted by the session they
+    /// belong to, if we don't have such a session yet a new inbound session is
+    /// created using the given [`Account`] and added to the set.
+    pub fn decrypt(
+        &mut self,
+        account: &mut Account,
+        peer: Curve25519PublicKey,
+        message: &OlmMessage,
+    ) -> Result<Vec<u8>, SessionSetError> {
+        match message {
+            OlmMessage::Normal(_) => {
+                let entries =
+                    self.sessions.get_mut(&peer).ok_or(SessionSetError::NoMatchingSession(peer))?;
+
+                // Sessions only change their state if they successfully decrypt
+                // a message, so trying the wrong ones is harmless.
+                let (position, plaintext) = entries
+                    .iter_mut()
+                    .enumerate()
+                    .find_map(|(i, e)| e.session.decrypt(message).ok().map(|p| (i, p)))
+                    .ok_or(SessionSetError::NoMatchingSession(peer))?;
+
+                self.mark_as_used(&peer, position);
+
+                Ok(plaintext)
+            }
+            OlmMessage::PreKey(pre_key_message) => {
+                let session_id = pre_key_message.session_id();
+                let position = self.sessions.get(&peer).and_then(|entries| {
+                    entries.iter().position(|e| e.session.session_id() == session_id)
+                });
+
+                if let Some(position) = position {
+                    let plaintext = self
+                        .sessions
+                        .get_mut(&peer)
+                        .and_then(|entries| entries.get_mut(position))
+                        .ok_or(SessionSetError::NoMatchingSession(peer))?
+                        .session
+                        .decrypt(message)?;
+
+                    self.mark_as_used(&peer, position);
+
+                    Ok(plaintext)
+                } else {
+                    let result = account.create_inbound_session(peer, pre_key_message)?;
+                    self.insert(peer, result.session);
+
+                    Ok(result.plaintext)
+                }
+            }
+        }
+    }
+
+    /// Convert the set into a struct which implements [`serde::Serialize`]
+    /// and [`serde::Deserialize`].
+    pub fn pickle(&self) -> SessionSetPickle {
+        let mut peers: Vec<_> = self
+            .sessions
+            .iter()
+            .map(|(peer, entries)| PeerSessionsPickle {
+                peer: *peer,
+                sessions: entries
+                    .iter()
+                    .map(|e| EntryPickle { session: e.session.pickle(), last_used: e.last_used })
+                    .collect(),
+            })
+            .collect();
+
+        // Sort the peers so pickling the same set always produces the same
+        // pickle.
+        peers.sort_by_key(|p| *p.peer.as_bytes());
+
+        SessionSetPickle { policy: self.policy, use_counter: self.use_counter, peers }
+    }
+
+    /// Restore a [`SessionSet`] from a previously saved [`SessionSetPickle`].
+    pub fn from_pickle(pickle: SessionSetPickle) -> Self {
+        pickle.into()
+    }
+
+    const fn next_use(&mut self) -> u64 {
+        self.use_counter = self.use_counter.wrapping_add(1);
+        self.use_counter
+    }
+
+    /// Mark the session at the given position as used, moving it to the front.
+    fn mark_as_used(&mut self, peer: &Curve25519PublicKey, position: usize) {
+        let last_used = self.next_use();
+
+        if let Some(entries) = self.sessions.get_mut(peer) {
+            if let Some(entry) = entries.get_mut(position) {
+                entry.last_used = last_used;
+                entries[..=position].rotate_right(1);
+            }
+        }
+    }
+
+    /// Drop the least recently used sessions of the peer which exceed the
+    /// limit of the policy.
+    fn evict(&mut self, peer: &Curve25519PublicKey) {
+        if let Some(entries) = self.sessions.get_mut(peer) {
+            entries.truncate(self.policy.max_sessions_per_peer());
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntryPickle {
+    session: SessionPickle,
+    last_used: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeerSessionsPickle {
+    peer: Curve25519PublicKey,
+    sessions: Vec<EntryPickle>,
+}
+
+/// A format suitable for serialization which implements [`serde::Serialize`]
+/// and [`serde::Deserialize`]. Obtainable by calling [`SessionSet::pickle`].
+#[derive(Serialize, Deserialize)]
+pub struct SessionSetPickle {
+    policy: SessionSetPolicy,
+    use_counter: u64,
+    peers: Vec<PeerSessionsPickle>,
+}
+
+impl SessionSetPickle {
+    const PICKLE_TYPE: &'static str = "SessionSet";
+
+    /// Serialize and encrypt the pickle using the given key.
+    ///
+    /// This is the inverse of [`SessionSetPickle::from_encrypted`].
+    pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`SessionSetPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Obtain a pickle from a ciphertext by decrypting and deserializing using
+    /// the given key.
+    ///
+    /// This is the inverse of [`SessionSetPickle::encrypt`].
+    pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
+    }
+}
+
+impl From<SessionSetPickle> for SessionSet {
+    fn from(pickle: SessionSetPickle) -> Self {
+        let sessions = pickle
+            .peers
+            .into_iter()
+            .map(|peer| {
+                let mut entries: Vec<_> = peer
+                    .sessions
+                    .into_iter()
+                    .map(|e| Entry {
+                        session: Session::from_pickle(e.session),
+                        last_used: e.last_used,
+                    })
+                    .collect();
+                entries.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+
+                (peer.peer, entries)
+            })
+            .collect();
+
+        let mut set = Self { policy: pickle.policy, sessions, use_counter: pickle.use_counter };
+        set.set_policy(pickle.policy);
+
+        set
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+    use assert_matches2::assert_matches;
+
+    use super::{SessionSet, SessionSetError, SessionSetPickle, SessionSetPolicy};
+    use crate::olm::{Account, OlmMessage, Session, SessionConfig};
+
+    const PICKLE_KEY: [u8; 32] = [0u8; 32];
+
+    fn outbound_session(alice: &Account, bob: &mut Account) -> Session {
+        bob.generate_one_time_keys(1);
+        let one_time_key =
+            *bob.one_time_keys().values().next().expect("Bob should have a one-time key");
+        bob.mark_keys_as_published();
+
+        alice.create_outbound_session(
+            SessionConfig::version_2(),
+            bob.curve25519_key(),
+            one_time_key,
+        )
+    }
+
+    #[test]
+    fn pre_key_messages_create_and_reuse_sessions() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        let mut alice_sessions = SessionSet::new();
+        let mut bob_sessions = SessionSet::new();
+
+        alice_sessions.insert(bob.curve25519_key(), outbound_session(&alice, &mut bob));
+
+        let message = alice_sessions.encrypt(&bob.curve25519_key(), "Hello").unwrap();
+        let plaintext = bob_sessions.decrypt(&mut bob, alice.curve25519_key(), &message)?;
+        assert_eq!(plaintext, b"Hello");
+        assert_eq!(bob_sessions.len(), 1);
+
+        // A second pre-key message of the same session is routed to the
+        // existing session, even though the one-time key is gone by now.
+        let message = alice_sessions.encrypt(&bob.curve25519_key(), "Hello again").unwrap();
+        assert_matches!(message, OlmMessage::PreKey(_));
+        let plaintext = bob_sessions.decrypt(&mut bob, alice.curve25519_key(), &message)?;
+        assert_eq!(plaintext, b"Hello again");
+        assert_eq!(bob_sessions.len(), 1);
+
+        let reply = bob_sessions.encrypt(&alice.curve25519_key(), "Reply").unwrap();
+        assert_matches!(reply, OlmMessage::Normal(_));
+        let plaintext =
+            alice_sessions.decrypt(&mut Account::new(), bob.curve25519_key(), &reply)?;
+        assert_eq!(plaintext, b"Reply");
+
+        Ok(())
+    }
+
+    #[test]
+    fn normal_messages_try_all_sessions() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        let mut alice_sessions = SessionSet::new();
+        let mut bob_sessions = SessionSet::new();
+
+        // Create two sessions, the second one becomes the preferred one.
+        let session_ids: Vec<_> = ["First", "Second"]
+            .into_iter()
+            .map(|text| {
+                let mut session = outbound_session(&alice, &mut bob);
+                let session_id = session.session_id();
+                let message = session.encrypt(text);
+
+                alice_sessions.insert(bob.curve25519_key(), session);
+                bob_sessions.decrypt(&mut bob, alice.curve25519_key(), &message).map(|_| session_id)
+            })
+            .collect::<Result<_, _>>()?;
+
+        assert_eq!(bob_sessions.len(), 2);
+        assert_eq!(
+            alice_sessions.preferred_session(&bob.curve25519_key()).unwrap().session_id(),
+            session_ids[1]
+        );
+
+        // Bob replies using the older session, Alice has to try both of hers.
+        let reply = bob_sessions
+            .get_mut(&alice.curve25519_key(), &session_ids[0])
+            .unwrap()
+            .encrypt("From the first session");
+        assert_matches!(reply, OlmMessage::Normal(_));
+
+        let plaintext =
+            alice_sessions.decrypt(&mut Account::new(), bob.curve25519_key(), &reply)?;
+        assert_eq!(plaintext, b"From the first session");
+        assert_eq!(
+            alice_sessions.preferred_session(&bob.curve25519_key()).unwrap().session_id(),
+            session_ids[0]
+        );
+
+        let stranger = Account::new();
+        assert_matches!(
+            alice_sessions.decrypt(&mut Account::new(), stranger.curve25519_key(), &reply),
+            Err(SessionSetError::NoMatchingSession(_))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn policy_evicts_least_recently_used() {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        let mut sessions = SessionSet::with_policy(SessionSetPolicy::new(2));
+
+        let session_ids: Vec<_> = (0..3)
+            .map(|_| {
+                let session = outbound_session(&alice, &mut bob);
+                let session_id = session.session_id();
+                sessions.insert(bob.curve25519_key(), session);
+                session_id
+            })
+            .collect();
+
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.get(&bob.curve25519_key(), &session_ids[0]).is_none());
+        assert!(sessions.get(&bob.curve25519_key(), &session_ids[1]).is_some());
+        assert_eq!(
+            sessions.preferred_session(&bob.curve25519_key()).unwrap().session_id(),
+            session_ids[2]
+        );
+
+        sessions.set_policy(SessionSetPolicy::new(0));
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.get(&bob.curve25519_key(), &session_ids[2]).is_some());
+    }
+
+    #[test]
+    fn pickle_roundtrip() -> Result<()> {
+        let alice = Account::new();
+        let mut bob = Account::new();
+        let mut sessions = SessionSet::new();
+
+        for _ in 0..3 {
+            sessions.insert(bob.curve25519_key(), outbound_session(&alice, &mut bob));
+        }
+        let carol = Account::new();
+        let mut dave = Account::new();
+        sessions.insert(dave.curve25519_key(), outbound_session(&carol, &mut dave));
+
+        let pickle = sessions.pickle().encrypt(&PICKLE_KEY);
+        let unpickled =
+            SessionSet::from_pickle(SessionSetPickle::from_encrypted(&pickle, &PICKLE_KEY)?);
+
+        assert_eq!(unpickled.len(), 4);
+        assert_eq!(unpickled.policy(), sessions.policy());
+
+        let session_ids = |set: &SessionSet| -> Vec<String> {
+            set.sessions(&bob.curve25519_key()).map(Session::session_id).collect()
+        };
+        assert_eq!(session_ids(&unpickled), session_ids(&sessions));
+
+        assert_eq!(
+            serde_json::to_value(unpickled.pickle())?,
+            serde_json::to_value(sessions.pickle())?
+        );
+
+        Ok(())
+    }
+}
@@ -29,6 +29,28 @@
 //!     R0, C0,0 = HKDF(0, S, "OLM_ROOT", 64)
 //! ```
 //!
+//! Sessions using the hybrid post-quantum key agreement of Olm version 3
+//! additionally mix the shared secret K, encapsulated for the ML-KEM-768 key of
+//! the recipient, into the key derivation and use "OLM_PQ_ROOT" as the info.
+//! The KEM ciphertext CT and the ML-KEM-768 public key PK are mixed in as well,
+//! binding the root key to the exact encapsulation.
+//!
+//! ```text
+//!     R0, C0,0 = HKDF(0, S || K || CT || PK, "OLM_PQ_ROOT", 64)
+//! ```
+//!
+//! Sessions which encrypt message headers, Olm version 4, expand the shared
+//! secret further, into the header keys of the first chain of each side, H0 and
+//! H1.
+//!
+//! ```text
+//!     R0, C0,0, H0, H1 = HKDF(0, S, "OLM_ROOT", 128)
+//! ```
+//!
+//! The header keys are always derived, sessions which don't encrypt headers
+//! simply ignore them. Since HKDF produces its output block by block, R0 and
+//! C0,0 are unaffected by the longer output.
+//!
 //! [spec]: https://gitlab.matrix.org/matrix-org/olm/-/blob/master/docs/olm.md#initial-setup
 
 use hkdf::Hkdf;
@@ -36,31 +58,82 @@ use sha2::Sha256;
 use x25519_dalek::{ReusableSecret, SharedSecret};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::{Curve25519PublicKey as PublicKey, types::Curve25519SecretKey as StaticSecret};
+use crate::{
+    Curve25519PublicKey as PublicKey,
+    types::{Curve25519SecretKey as StaticSecret, MlKem768Ciphertext, MlKem768PublicKey},
+};
+
+/// The shared secret that was encapsulated using ML-KEM-768, along with the
+/// ciphertext and the public key it was encapsulated with.
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct KemSecret {
+    secret: Box<[u8; 32]>,
+    ciphertext: Box<[u8; MlKem768Ciphertext::LENGTH]>,
+    public_key: Box<[u8; MlKem768PublicKey::LENGTH]>,
+}
+
+impl KemSecret {
+    fn new(
+        secret: Box<[u8; 32]>,
+        ciphertext: &MlKem768Ciphertext,
+        public_key: &MlKem768PublicKey,
+    ) -> Self {
+        Self {
+            secret,
+            ciphertext: Box::new(*ciphertext.as_bytes()),
+            public_key: Box::new(*public_key.as_bytes()),
+        }
+    }
+}
 
 #[derive(Zeroize, ZeroizeOnDrop)]
-pub struct Shared3DHSecret(Box<[u8; 96]>);
+pub struct Shared3DHSecret(Box<[u8; 96]>, Option<KemSecret>);
 
 #[derive(Zeroize, ZeroizeOnDrop)]
-pub struct RemoteShared3DHSecret(Box<[u8; 96]>);
+pub struct RemoteShared3DHSecret(Box<[u8; 96]>, Option<KemSecret>);
 
-fn expand(shared_secret: &[u8; 96]) -> (Box<[u8; 32]>, Box<[u8; 32]>) {
-    let hkdf: Hkdf<Sha256> = Hkdf::new(Some(&[0]), shared_secret);
+/// The root key, the first chain key and the header keys of the first chain of
+/// each side, expanded from a shared secret.
+type ExpandedKeys = (Box<[u8; 32]>, Box<[u8; 32]>, [Box<[u8; 32]>; 2]);
+
+fn expand(shared_secret: &[u8; 96], kem_secret: Option<&KemSecret>) -> ExpandedKeys {
     let mut root_key = Box::new([0u8; 32]);
     let mut chain_key = Box::new([0u8; 32]);
+    let mut header_keys = [Box::new([0u8; 32]), Box::new([0u8; 32])];
+
+    // HKDF output is prefix-consistent, expanding the additional header keys
+    // doesn't change the root and chain key.
+    let mut expanded_keys = [0u8; 128];
 
-    let mut expanded_keys = [0u8; 64];
+    let result = if let Some(kem_secret) = kem_secret {
+        let mut input = [
+            shared_secret.as_slice(),
+            kem_secret.secret.as_slice(),
+            kem_secret.ciphertext.as_slice(),
+            kem_secret.public_key.as_slice(),
+        ]
+        .concat();
+
+        let hkdf: Hkdf<Sha256> = Hkdf::new(Some(&[0]), &input);
+        input.zeroize();
+
+        hkdf.expand(b"OLM_PQ_ROOT", &mut expanded_keys)
+    } else {
+        let hkdf: Hkdf<Sha256> = Hkdf::new(Some(&[0]), shared_secret);
+        hkdf.expand(b"OLM_ROOT", &mut expanded_keys)
+    };
 
     #[allow(clippy::expect_used)]
-    hkdf.expand(b"OLM_ROOT", &mut expanded_keys)
-        .expect("We should be able to expand the shared 3DH secret into the Olm root");
+    result.expect("We should be able to expand the shared 3DH secret into the Olm root");
 
     root_key.copy_from_slice(&expanded_keys[0..32]);
     chain_key.copy_from_slice(&expanded_keys[32..64]);
+    header_keys[0].copy_from_slice(&expanded_keys[64..96]);
+    header_keys[1].copy_from_slice(&expanded_keys[96..128]);
 
     expanded_keys.zeroize();
 
-    (root_key, chain_key)
+    (root_key, chain_key, header_keys)
 }
 
 fn merge_secrets(
@@ -88,11 +161,23 @@ impl RemoteShared3DHSecret {
         let second_secret = identity_key.diffie_hellman(remote_one_time_key);
         let third_secret = one_time_key.diffie_hellman(remote_one_time_key);
 
-        Self(merge_secrets(first_secret, second_secret, third_secret))
+        Self(merge_secrets(first_secret, second_secret, third_secret), None)
+    }
+
+    /// Mix the shared secret that was decapsulated from the `ciphertext`
+    /// using our ML-KEM-768 key into the shared 3DH secret.
+    pub(crate) fn with_kem_secret(
+        mut self,
+        kem_secret: Box<[u8; 32]>,
+        ciphertext: &MlKem768Ciphertext,
+        public_key: &MlKem768PublicKey,
+    ) -> Self {
+        self.1 = Some(KemSecret::new(kem_secret, ciphertext, public_key));
+        self
     }
 
-    pub fn expand(self) -> (Box<[u8; 32]>, Box<[u8; 32]>) {
-        expand(&self.0)
+    pub fn expand(self) -> ExpandedKeys {
+        expand(&self.0, self.1.as_ref())
     }
 }
 
@@ -107,11 +192,23 @@ impl Shared3DHSecret {
         let second_secret = one_time_key.diffie_hellman(&remote_identity_key.inner);
         let third_secret = one_time_key.diffie_hellman(&remote_one_time_key.inner);
 
-        Self(merge_secrets(first_secret, second_secret, third_secret))
+        Self(merge_secrets(first_secret, second_secret, third_secret), None)
     }
 
-    pub fn expand(self) -> (Box<[u8; 32]>, Box<[u8; 32]>) {
-        expand(&self.0)
+    /// Mix the shared secret that was encapsulated in the `ciphertext` for the
+    /// ML-KEM-768 key of the other side into the shared 3DH secret.
+    pub(crate) fn with_kem_secret(
+        mut self,
+        kem_secret: Box<[u8; 32]>,
+        ciphertext: &MlKem768Ciphertext,
+        public_key: &MlKem768PublicKey,
+    ) -> Self {
+        self.1 = Some(KemSecret::new(kem_secret, ciphertext, public_key));
+        self
+    }
+
+    pub fn expand(self) -> ExpandedKeys {
+        expand(&self.0, self.1.as_ref())
     }
 }
 
@@ -121,7 +218,10 @@ mod test {
     use x25519_dalek::ReusableSecret;
 
     use super::{RemoteShared3DHSecret, Shared3DHSecret};
-    use crate::{Curve25519PublicKey as PublicKey, types::Curve25519SecretKey as StaticSecret};
+    use crate::{
+        Curve25519PublicKey as PublicKey,
+        types::{Curve25519SecretKey as StaticSecret, MlKem768SecretKey},
+    };
 
     #[test]
     fn triple_diffie_hellman() {
@@ -154,4 +254,86 @@ mod test {
 
         assert_eq!(alice_result, bob_result);
     }
+
+    #[test]
+    fn header_keys() {
+        let rng = thread_rng();
+
+        let alice_identity = StaticSecret::new();
+        let alice_one_time = ReusableSecret::random_from_rng(rng);
+
+        let bob_identity = StaticSecret::new();
+        let bob_one_time = StaticSecret::new();
+
+        let secret = || {
+            Shared3DHSecret::new(
+                &alice_identity,
+                &alice_one_time,
+                &PublicKey::from(&bob_identity),
+                &PublicKey::from(&bob_one_time),
+            )
+        };
+
+        let (root_key, chain_key, [first, second]) = secret().expand();
+
+        assert_ne!(first, second);
+        assert_ne!(first, root_key);
+        assert_ne!(second, chain_key);
+    }
+
+    #[test]
+    fn hybrid_key_agreement() {
+        let rng = thread_rng();
+
+        let alice_identity = StaticSecret::new();
+        let alice_one_time = ReusableSecret::random_from_rng(rng);
+
+        let bob_identity = StaticSecret::new();
+        let bob_one_time = StaticSecret::new();
+
+        let secret = || {
+            (
+                Shared3DHSecret::new(
+                    &alice_identity,
+                    &alice_one_time,
+                    &PublicKey::from(&bob_identity),
+                    &PublicKey::from(&bob_one_time),
+                ),
+                RemoteShared3DHSecret::new(
+                    &bob_identity,
+                    &bob_one_time,
+                    &PublicKey::from(&alice_identity),
+                    &PublicKey::from(&alice_one_time),
+                ),
+            )
+        };
+
+        let kem_key = MlKem768SecretKey::new(&mut thread_rng());
+        let kem_public_key = kem_key.public_key();
+        let (ciphertext, kem_secret) = kem_public_key.encapsulate(&mut thread_rng());
+
+        let (alice_secret, bob_secret) = secret();
+        let alice_result =
+            alice_secret.with_kem_secret(kem_secret, &ciphertext, &kem_public_key).expand();
+        let bob_result = bob_secret
+            .with_kem_secret(kem_key.decapsulate(&ciphertext), &ciphertext, &kem_public_key)
+            .expand();
+        assert_eq!(alice_result, bob_result);
+
+        let (classic_secret, _) = secret();
+        assert_ne!(alice_result, classic_secret.expand());
+
+        let (_, bob_secret) = secret();
+        let bob_result =
+            bob_secret.with_kem_secret(Box::new([2u8; 32]), &ciphertext, &kem_public_key).expand();
+        assert_ne!(alice_result, bob_result);
+
+        // The same KEM secret bound to another public key yields another root.
+        let other_public_key = MlKem768SecretKey::new(&mut thread_rng()).public_key();
+        let (_, bob_secret) = secret();
+        let bob_result = bob_secret
+            .with_kem_secret(kem_key.decapsulate(&ciphertext), &ciphertext, &other_public_key)
+            .expand();
+        assert_ne!(alice_result, bob_result);
+    }
 }
@@ -0,0 +1,190 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The byte which separates the plaintext from the padding.
+const PADDING_MARKER: u8 = b'\n';
+/// The byte the rest of the padding consists of.
+const PADDING_BYTE: u8 = b' ';
+
+/// Policy describing how plaintexts are padded before they get encrypted, to
+/// hide their exact length.
+///
+/// The padding consists of a newline followed by a number of spaces. Matrix
+/// event payloads are JSON, for which such trailing whitespace is
+/// insignificant, so receivers which don't know about padding can still parse
+/// the plaintext. Receivers which do know about padding remove it
+/// transparently, messages signal that they are padded in their
+/// authenticated, unencrypted part.
+///
+/// ⚠️ ***Security Warning***: Padding only hides the length of a plaintext
+/// within the bucket it falls into, it can't hide which bucket that is.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// Don't pad the plaintext, this is the default.
+    #[default]
+    None,
+    /// Pad the plaintext to the next power of two. Leaks at most
+    /// `O(log log n)` bits of the length, at the cost of up to doubling the
+    /// size of the message.
+    PowerOfTwo,
+    /// Pad the plaintext using the [PADMÉ] scheme. Leaks `O(log log n)` bits of
+    /// the length, like [`PaddingPolicy::PowerOfTwo`], but with an overhead of
+    /// at most 12%.
+    ///
+    /// [PADMÉ]: https://lbarman.ch/blog/padme/
+    Padme,
+    /// Pad the plaintext to the next multiple of the given block size. A block
+    /// size of zero is treated as a block size of one.
+    Block(usize),
+}
+
+/// Error type describing a padded plaintext that doesn't contain valid
+/// padding.
+#[derive(Debug, Error)]
+#[error("The plaintext was marked as padded but doesn't contain valid padding")]
+pub(crate) struct PaddingError;
+
+impl PaddingPolicy {
+    /// Is this policy going to pad plaintexts.
+    pub const fn is_none(&self) -> bool {
+        matches!(self, PaddingPolicy::None)
+    }
+
+    /// Calculate the length a plaintext of the given length will have after it
+    /// has been padded.
+    pub fn padded_length(&self, length: usize) -> usize {
+        // Padding always needs room for the marker byte.
+        let minimum = length.saturating_add(1);
+
+        match self {
+            PaddingPolicy::None => length,
+            PaddingPolicy::PowerOfTwo => minimum.checked_next_power_of_two().unwrap_or(minimum),
+            PaddingPolicy::Padme => padme(minimum),
+            PaddingPolicy::Block(size) => {
+                let size = (*size).max(1);
+                minimum.div_ceil(size).checked_mul(size).unwrap_or(minimum)
+            }
+        }
+    }
+
+    /// Pad the given plaintext according to this policy.
+    ///
+    /// Returns `None` if the policy doesn't pad plaintexts.
+    pub(crate) fn pad(&self, plaintext: &[u8]) -> Option<Vec<u8>> {
+        if self.is_none() {
+            None
+        } else {
+            let padded_length = self.padded_length(plaintext.len());
+
+            let mut padded = Vec::with_capacity(padded_length);
+            padded.extend_from_slice(plaintext);
+            padded.push(PADDING_MARKER);
+            padded.resize(padded_length, PADDING_BYTE);
+
+            Some(padded)
+        }
+    }
+}
+
+/// Remove the padding, which was added by [`PaddingPolicy::pad()`], from the
+/// given plaintext.
+pub(crate) fn unpad(mut plaintext: Vec<u8>) -> Result<Vec<u8>, PaddingError> {
+    let padding_start = plaintext.iter().rposition(|b| *b != PADDING_BYTE).ok_or(PaddingError)?;
+
+    if plaintext[padding_start] == PADDING_MARKER {
+        plaintext.truncate(padding_start);
+        Ok(plaintext)
+    } else {
+        Err(PaddingError)
+    }
+}
+
+/// Calculate the padded length using the PADMÉ scheme from the paper
+/// "Reducing Metadata Leakage from Encrypted Files and Communication with
+/// PURBs".
+fn padme(length: usize) -> usize {
+    if length < 2 {
+        length
+    } else {
+        let exponent = length.ilog2();
+        let exponent_bits = exponent.ilog2() + 1;
+        let last_bits = exponent - exponent_bits;
+        let mask = (1usize << last_bits) - 1;
+
+        length.checked_add(mask).map_or(length, |l| l & !mask)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{PaddingPolicy, padme, unpad};
+
+    #[test]
+    fn padded_lengths() {
+        assert_eq!(PaddingPolicy::None.padded_length(10), 10);
+        assert_eq!(PaddingPolicy::PowerOfTwo.padded_length(10), 16);
+        assert_eq!(PaddingPolicy::PowerOfTwo.padded_length(15), 16);
+        assert_eq!(PaddingPolicy::PowerOfTwo.padded_length(16), 32);
+        assert_eq!(PaddingPolicy::Block(32).padded_length(0), 32);
+        assert_eq!(PaddingPolicy::Block(32).padded_length(31), 32);
+        assert_eq!(PaddingPolicy::Block(32).padded_length(32), 64);
+        assert_eq!(PaddingPolicy::Block(0).padded_length(5), 6);
+    }
+
+    #[test]
+    fn padme_lengths() {
+        // Values from the reference implementation.
+        assert_eq!(padme(9), 10);
+        assert_eq!(padme(100), 104);
+        assert_eq!(padme(1000), 1024);
+        assert_eq!(padme(1025), 1088);
+        assert_eq!(padme(10_000), 10_240);
+    }
+
+    #[test]
+    fn invalid_padding() {
+        unpad(b"no padding".to_vec()).expect_err("Missing padding should be detected");
+        unpad(b"    ".to_vec()).expect_err("A plaintext without a marker should be rejected");
+        unpad(Vec::new()).expect_err("An empty plaintext should be rejected");
+    }
+
+    #[test]
+    fn padding_is_json_whitespace() {
+        let plaintext = br#"{"type":"m.room.message"}"#;
+        let padded =
+            PaddingPolicy::PowerOfTwo.pad(plaintext).expect("The plaintext should be padded");
+
+        assert_eq!(padded.len(), 32);
+        serde_json::from_slice::<serde_json::Value>(&padded)
+            .expect("A padded JSON plaintext should still be valid JSON");
+    }
+
+    proptest! {
+        #[test]
+        fn padding_roundtrip(plaintext in prop::collection::vec(any::<u8>(), 0..2048), block in 0usize..512) {
+            for policy in [PaddingPolicy::PowerOfTwo, PaddingPolicy::Padme, PaddingPolicy::Block(block)] {
+                let padded = policy.pad(&plaintext).expect("The plaintext should be padded");
+
+                prop_assert_eq!(padded.len(), policy.padded_length(plaintext.len()));
+                prop_assert!(padded.len() > plaintext.len());
+                prop_assert_eq!(unpad(padded).expect("The padding should be removable"), plaintext.clone());
+            }
+        }
+    }
+}
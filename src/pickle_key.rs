@@ -0,0 +1,457 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Deref;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{
+    Base64DecodeError,
+    utilities::{base64_decode, base64_encode},
+};
+
+/// The version of the encoded [`PickleKeyHeader`].
+const HEADER_VERSION: u8 = 1;
+/// The length of the salt a new [`PickleKeyHeader`] gets.
+const SALT_LENGTH: usize = 16;
+/// The minimal salt length Argon2 accepts.
+const MIN_SALT_LENGTH: usize = 8;
+/// The length of the encoded header, without the salt.
+const HEADER_PREFIX_LENGTH: usize = 1 + 3 * 4 + 1;
+/// The maximal Argon2 memory cost we accept, 1 GiB.
+const MAX_MEMORY_COST: u32 = 1024 * 1024;
+/// The maximal number of Argon2 iterations we accept.
+const MAX_TIME_COST: u32 = 64;
+/// The maximal degree of Argon2 parallelism we accept.
+const MAX_PARALLELISM: u32 = 64;
+
+/// Error type describing the ways a [`PickleKey`] can fail to be derived, or a
+/// [`PickleKeyHeader`] can fail to be decoded.
+#[derive(Debug, Error)]
+pub enum PickleKeyError {
+    /// The Argon2 parameters were rejected.
+    #[error("The Argon2 parameters are invalid")]
+    InvalidParams,
+    /// The pickle key couldn't be derived, e.g. because the salt was too
+    /// short.
+    #[error("The pickle key couldn't be derived")]
+    KeyDerivation,
+    /// The header wasn't valid base64.
+    #[error("The pickle key header wasn't valid base64: {0}")]
+    Base64(#[from] Base64DecodeError),
+    /// The header uses an unsupported version.
+    #[error("The pickle key header uses an unsupported version, expected {0}, got {1}")]
+    Version(u8, u8),
+    /// The header was too short, or its salt didn't have the expected length.
+    #[error("The pickle key header has an invalid length: {0}")]
+    InvalidLength(usize),
+    /// The Argon2 parameters exceed the limits we accept, deriving the key
+    /// would take too much memory or time.
+    #[error("The Argon2 parameters exceed the accepted limits: {0:?}")]
+    ExcessiveParams(Argon2Params),
+}
+
+/// The cost parameters of the Argon2id key derivation function.
+///
+/// The defaults follow the [OWASP recommendations] and need about 19 MiB of
+/// memory to derive a key.
+///
+/// [OWASP recommendations]: https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#argon2id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    /// The memory size in KiB.
+    pub memory_cost: u32,
+    /// The number of iterations.
+    pub time_cost: u32,
+    /// The degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Argon2Params {
+    /// Check that the parameters don't exceed the limits we accept.
+    ///
+    /// The parameters of a [`PickleKeyHeader`] are stored alongside the
+    /// pickles, a tampered header shouldn't be able to make the key derivation
+    /// allocate gigabytes of memory or run for hours.
+    fn check_limits(&self) -> Result<(), PickleKeyError> {
+        if self.memory_cost > MAX_MEMORY_COST
+            || self.time_cost > MAX_TIME_COST
+            || self.parallelism > MAX_PARALLELISM
+        {
+            Err(PickleKeyError::ExcessiveParams(*self))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// The parameters and the salt a [`PickleKey`] was derived with.
+///
+/// The header isn't secret, it needs to be stored alongside the pickles, so
+/// the same key can be derived from the passphrase again.
+///
+/// The header is encoded as a one byte version, followed by the Argon2 memory
+/// cost, time cost and parallelism as 32-bit big-endian integers, a one byte
+/// salt length and the salt. It serializes as the base64 encoded form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PickleKeyHeader {
+    params: Argon2Params,
+    salt: Vec<u8>,
+}
+
+impl PickleKeyHeader {
+    /// Create a new header with the given parameters and a random salt.
+    pub fn new(params: Argon2Params) -> Self {
+        Self::new_with_rng(params, &mut thread_rng())
+    }
+
+    /// Create a new header with the given parameters and a salt generated by
+    /// the given random number generator.
+    pub fn new_with_rng(params: Argon2Params, rng: &mut impl CryptoRngCore) -> Self {
+        let mut salt = vec![0u8; SALT_LENGTH];
+        rng.fill_bytes(&mut salt);
+
+        Self { params, salt }
+    }
+
+    /// Get the Argon2 parameters of this header.
+    pub const fn params(&self) -> Argon2Params {
+        self.params
+    }
+
+    /// Get the salt of this header.
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Derive the [`PickleKey`] from the given passphrase using the parameters
+    /// and the salt of this header.
+    pub fn derive_key(&self, passphrase: impl AsRef<[u8]>) -> Result<PickleKey, PickleKeyError> {
+        PickleKey::from_passphrase(passphrase, &self.salt, &self.params)
+    }
+
+    /// Encode the header as an array of bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let Argon2Params { memory_cost, time_cost, parallelism } = self.params;
+        // The salt is at most 255 bytes long, see `PickleKeyHeader::from_bytes()`,
+        // and new headers always get a 16 byte salt.
+        let salt_length = self.salt.len() as u8;
+
+        [
+            [HEADER_VERSION].as_slice(),
+            &memory_cost.to_be_bytes(),
+            &time_cost.to_be_bytes(),
+            &parallelism.to_be_bytes(),
+            &[salt_length],
+            &self.salt,
+        ]
+        .concat()
+    }
+
+    /// Try to decode a header from the given byte slice.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PickleKeyError> {
+        let (&version, rest) =
+            bytes.split_first().ok_or(PickleKeyError::InvalidLength(bytes.len()))?;
+
+        if version != HEADER_VERSION {
+            Err(PickleKeyError::Version(HEADER_VERSION, version))
+        } else if bytes.len() < HEADER_PREFIX_LENGTH {
+            Err(PickleKeyError::InvalidLength(bytes.len()))
+        } else {
+            let read_u32 = |offset: usize| {
+                let mut integer = [0u8; 4];
+                integer.copy_from_slice(&rest[offset..offset + 4]);
+                u32::from_be_bytes(integer)
+            };
+
+            let params = Argon2Params {
+                memory_cost: read_u32(0),
+                time_cost: read_u32(4),
+                parallelism: read_u32(8),
+            };
+
+            params.check_limits()?;
+
+            let salt_length = usize::from(rest[12]);
+            let salt = &rest[13..];
+
+            if salt.len() != salt_length || salt_length < MIN_SALT_LENGTH {
+                Err(PickleKeyError::InvalidLength(bytes.len()))
+            } else {
+                Ok(Self { params, salt: salt.to_vec() })
+            }
+        }
+    }
+
+    /// Encode the header as a base64 string.
+    pub fn to_base64(&self) -> String {
+        base64_encode(self.to_bytes())
+    }
+
+    /// Try to decode a header from the given base64 string.
+    pub fn from_base64(header: &str) -> Result<Self, PickleKeyError> {
+        Self::from_bytes(&base64_decode(header)?)
+    }
+}
+
+impl Serialize for PickleKeyHeader {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for PickleKeyHeader {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let header = String::deserialize(d)?;
+        Self::from_base64(&header).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A 32-byte key which is used to encrypt pickles.
+///
+/// The key can be derived from a passphrase using Argon2id, or be created from
+/// raw bytes, e.g. a secret stored in the keychain of the operating system.
+///
+/// The key dereferences to a `[u8; 32]`, so it can be passed to all methods
+/// which encrypt or decrypt pickles, e.g. [`AccountPickle::encrypt()`]. It's
+/// compared in constant time and zeroized when it gets dropped.
+///
+/// # Examples
+///
+/// ```
+/// use vodozemac::{Argon2Params, PickleKeyHeader, olm::{Account, AccountPickle}};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let header = PickleKeyHeader::new(Argon2Params::default());
+/// let pickle_key = header.derive_key("It's a secret to everybody")?;
+///
+/// let pickle = Account::new().pickle().encrypt(&pickle_key);
+///
+/// // Store the header next to the pickle and derive the same key from it later on.
+/// let header = PickleKeyHeader::from_base64(&header.to_base64())?;
+/// let pickle_key = header.derive_key("It's a secret to everybody")?;
+///
+/// let account = AccountPickle::from_encrypted(&pickle, &pickle_key)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`AccountPickle::encrypt()`]: crate::olm::AccountPickle::encrypt
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct PickleKey(Box<[u8; 32]>);
+
+impl PickleKey {
+    /// Create a [`PickleKey`] from raw bytes.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Self(Box::new(*bytes))
+    }
+
+    /// Derive a [`PickleKey`] from the given passphrase using Argon2id.
+    ///
+    /// The salt needs to be at least 8 bytes long, a [`PickleKeyHeader`] can
+    /// be used to generate and persist the salt and the parameters. The
+    /// parameters are limited to 1 GiB of memory, 64 iterations and a
+    /// parallelism of 64.
+    pub fn from_passphrase(
+        passphrase: impl AsRef<[u8]>,
+        salt: &[u8],
+        params: &Argon2Params,
+    ) -> Result<Self, PickleKeyError> {
+        params.check_limits()?;
+
+        let params =
+            Params::new(params.memory_cost, params.time_cost, params.parallelism, Some(32))
+                .map_err(|_| PickleKeyError::InvalidParams)?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = Self(Box::new([0u8; 32]));
+        argon2
+            .hash_password_into(passphrase.as_ref(), salt, key.0.as_mut_slice())
+            .map_err(|_| PickleKeyError::KeyDerivation)?;
+
+        Ok(key)
+    }
+
+    /// Get the raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Deref for PickleKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_bytes()
+    }
+}
+
+impl ConstantTimeEq for PickleKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(other.0.as_ref())
+    }
+}
+
+impl PartialEq for PickleKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for PickleKey {}
+
+impl std::fmt::Debug for PickleKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PickleKey").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{Argon2Params, PickleKey, PickleKeyError, PickleKeyHeader};
+    use crate::olm::{Account, AccountPickle};
+
+    /// Cheap parameters, so the tests don't take ages.
+    const PARAMS: Argon2Params = Argon2Params { memory_cost: 64, time_cost: 1, parallelism: 1 };
+    const PASSPHRASE: &str = "It's a secret to everybody";
+
+    #[test]
+    fn derivation_is_deterministic() -> anyhow::Result<()> {
+        let header = PickleKeyHeader::new(PARAMS);
+
+        assert_eq!(header.derive_key(PASSPHRASE)?, header.derive_key(PASSPHRASE)?);
+        assert_ne!(header.derive_key(PASSPHRASE)?, header.derive_key("Another secret")?);
+        assert_ne!(
+            header.derive_key(PASSPHRASE)?,
+            PickleKeyHeader::new(PARAMS).derive_key(PASSPHRASE)?
+        );
+        assert_ne!(
+            header.derive_key(PASSPHRASE)?,
+            PickleKey::from_passphrase(
+                PASSPHRASE,
+                header.salt(),
+                &Argon2Params { time_cost: 2, ..PARAMS }
+            )?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_roundtrip() -> anyhow::Result<()> {
+        let header = PickleKeyHeader::new(Argon2Params::default());
+
+        assert_eq!(header.to_bytes().len(), 14 + 16);
+        assert_eq!(PickleKeyHeader::from_base64(&header.to_base64())?, header);
+
+        let json = serde_json::to_string(&header)?;
+        assert_eq!(json, format!("\"{}\"", header.to_base64()));
+        assert_eq!(serde_json::from_str::<PickleKeyHeader>(&json)?, header);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut bytes = PickleKeyHeader::new(PARAMS).to_bytes();
+
+        assert_matches!(
+            PickleKeyHeader::from_bytes(&bytes[..20]),
+            Err(PickleKeyError::InvalidLength(20))
+        );
+        assert_matches!(PickleKeyHeader::from_bytes(&[]), Err(PickleKeyError::InvalidLength(0)));
+
+        bytes[0] = 2;
+        assert_matches!(PickleKeyHeader::from_bytes(&bytes), Err(PickleKeyError::Version(1, 2)));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert_matches!(
+            PickleKey::from_passphrase(PASSPHRASE, b"short", &PARAMS),
+            Err(PickleKeyError::KeyDerivation)
+        );
+        assert_matches!(
+            PickleKey::from_passphrase(
+                PASSPHRASE,
+                b"long enough salt",
+                &Argon2Params { parallelism: 0, ..PARAMS }
+            ),
+            Err(PickleKeyError::InvalidParams)
+        );
+    }
+
+    #[test]
+    fn excessive_parameters() -> anyhow::Result<()> {
+        for params in [
+            Argon2Params { memory_cost: u32::MAX, ..PARAMS },
+            Argon2Params { time_cost: u32::MAX, ..PARAMS },
+            Argon2Params { parallelism: u32::MAX, ..PARAMS },
+        ] {
+            let header = PickleKeyHeader::new(params);
+
+            assert_matches!(
+                PickleKeyHeader::from_bytes(&header.to_bytes()),
+                Err(PickleKeyError::ExcessiveParams(_))
+            );
+            assert!(
+                serde_json::from_str::<PickleKeyHeader>(&serde_json::to_string(&header)?).is_err()
+            );
+            assert_matches!(header.derive_key(PASSPHRASE), Err(PickleKeyError::ExcessiveParams(_)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn pickling_with_derived_key() -> anyhow::Result<()> {
+        let header = PickleKeyHeader::new(PARAMS);
+        let pickle_key = header.derive_key(PASSPHRASE)?;
+
+        let account = Account::new();
+        let pickle = account.pickle().encrypt(&pickle_key);
+
+        let pickle_key = header.derive_key(PASSPHRASE)?;
+        let unpickled = Account::from_pickle(AccountPickle::from_encrypted(&pickle, &pickle_key)?);
+        assert_eq!(account.identity_keys(), unpickled.identity_keys());
+
+        let wrong_key = header.derive_key("Another secret")?;
+        AccountPickle::from_encrypted(&pickle, &wrong_key)
+            .err()
+            .expect("A key derived from another passphrase shouldn't decrypt the pickle");
+
+        Ok(())
+    }
+}
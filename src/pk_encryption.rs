@@ -52,6 +52,8 @@ use aes::cipher::{
 };
 use hmac::{Mac as _, digest::MacError};
 use matrix_pickle::{Decode, Encode};
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use thiserror::Error;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
@@ -136,7 +138,15 @@ impl PkDecryption {
     /// long-term key to derive individual message keys and effectively serves
     /// as the decryption secret.
     pub fn new() -> Self {
-        let secret_key = Curve25519SecretKey::new();
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Create a new random [`PkDecryption`] object using the given random
+    /// number generator.
+    ///
+    /// See [`PkDecryption::new()`] for more details.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let secret_key = Curve25519SecretKey::new_with_rng(rng);
         let public_key = Curve25519PublicKey::from(&secret_key);
 
         Self { secret_key, public_key }
@@ -295,7 +305,13 @@ impl PkEncryption {
 
     /// Encrypt a message using this [`PkEncryption`] object.
     pub fn encrypt(&self, message: &[u8]) -> Message {
-        let ephemeral_key = Curve25519SecretKey::new();
+        self.encrypt_with_rng(message, &mut thread_rng())
+    }
+
+    /// Encrypt a message using this [`PkEncryption`] object, the ephemeral key
+    /// is generated using the given random number generator.
+    pub fn encrypt_with_rng(&self, message: &[u8], rng: &mut impl CryptoRngCore) -> Message {
+        let ephemeral_key = Curve25519SecretKey::new_with_rng(rng);
         let shared_secret = ephemeral_key.diffie_hellman(&self.public_key);
 
         let expanded_keys = ExpandedKeys::new_helper(shared_secret.as_bytes(), b"");
@@ -0,0 +1,549 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key verification by scanning QR codes.
+//!
+//! This module implements the binary payload of the QR codes used by the
+//! `m.qr_code.show.v1`, `m.qr_code.scan.v1` and `m.reciprocate.v1`
+//! verification methods, as defined in the [spec].
+//!
+//! One side displays a QR code containing the keys it believes to be correct,
+//! together with a randomly generated shared secret. The other side scans the
+//! QR code, checks the keys against its own view of the world, and sends the
+//! shared secret back in an `m.key.verification.start` event. Receiving the
+//! correct secret proves to the displaying side that the QR code was scanned,
+//! and thus that both sides agree on the keys.
+//!
+//! All the keys contained in the QR code are Ed25519 keys, either
+//! cross-signing master keys or device keys.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use vodozemac::{
+//!     Ed25519Keypair,
+//!     qr_verification::{QrVerificationData, QrVerificationMode},
+//! };
+//! # use anyhow::Result;
+//! # fn main() -> Result<()> {
+//! let alice_master_key = Ed25519Keypair::new().public_key();
+//! let bob_master_key = Ed25519Keypair::new().public_key();
+//!
+//! // Alice displays a QR code.
+//! let alice = QrVerificationData::new(
+//!     "$some_event_id",
+//!     QrVerificationMode::Verification {
+//!         own_master_key: alice_master_key,
+//!         other_master_key: bob_master_key,
+//!     },
+//! )?;
+//! let qr_code = alice.to_bytes();
+//!
+//! // Bob scans it, checks the keys and sends the secret back.
+//! let scanned = QrVerificationData::from_bytes(&qr_code)?;
+//! assert_eq!(scanned.mode(), alice.mode());
+//! let secret = scanned.secret().to_base64();
+//!
+//! // Alice checks that Bob scanned the right QR code.
+//! alice.verify_reciprocate_base64(&secret)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [spec]: https://spec.matrix.org/unstable/client-server-api/#qr-codes
+
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
+use subtle::ConstantTimeEq as _;
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{Ed25519PublicKey, KeyError, base64_decode, base64_encode};
+
+/// The ASCII prefix every QR code payload starts with.
+const PREFIX: &[u8] = b"MATRIX";
+/// The version of the QR code payload format we support.
+const VERSION: u8 = 0x02;
+/// The number of random bytes we generate for the shared secret.
+const SECRET_LENGTH: usize = 16;
+/// The minimal length of the shared secret the spec allows.
+const MIN_SECRET_LENGTH: usize = 8;
+/// The maximal length of the flow ID, its length is encoded as two bytes.
+const MAX_FLOW_ID_LENGTH: usize = u16::MAX as usize;
+
+/// Error type describing failures that can happen when creating QR code data.
+#[derive(Debug, Error)]
+pub enum QrEncodeError {
+    /// The flow ID is too long to be encoded into the QR code payload.
+    #[error("The flow ID is too long, expected at most {0} bytes, got {1}")]
+    FlowIdTooLong(usize, usize),
+}
+
+/// Error type describing failures that can happen when decoding a QR code
+/// payload.
+#[derive(Debug, Error)]
+pub enum QrDecodeError {
+    /// The payload didn't start with the `MATRIX` prefix.
+    #[error("The QR code payload doesn't start with the MATRIX prefix")]
+    InvalidPrefix,
+    /// The payload uses a version of the format we don't support.
+    #[error("The QR code payload has an unsupported version, expected {0}, got {1}")]
+    UnsupportedVersion(u8, u8),
+    /// The payload contains an unknown verification mode.
+    #[error("The QR code payload has an unknown verification mode {0}")]
+    UnknownMode(u8),
+    /// The payload was too short to contain all the required fields.
+    #[error("The QR code payload is too short")]
+    MissingData,
+    /// The flow ID wasn't valid UTF-8.
+    #[error("The flow ID of the QR code isn't valid UTF-8: {0}")]
+    FlowId(#[from] std::string::FromUtf8Error),
+    /// One of the keys in the payload wasn't a valid Ed25519 key.
+    #[error("The QR code contains an invalid Ed25519 key: {0}")]
+    Key(#[from] KeyError),
+    /// The shared secret was shorter than the spec allows.
+    #[error("The shared secret of the QR code is too short, expected at least {0} bytes, got {1}")]
+    SecretTooShort(usize, usize),
+}
+
+/// Error type describing failures that can happen when verifying the secret
+/// the other side sent back after scanning our QR code.
+#[derive(Debug, Error)]
+pub enum QrVerificationError {
+    /// The secret couldn't be decoded from Base64.
+    #[error("The reciprocated secret isn't valid Base64: {0}")]
+    Base64(#[from] crate::Base64DecodeError),
+    /// The secret doesn't match the one in our QR code.
+    #[error("The reciprocated secret doesn't match the shared secret of the QR code")]
+    Mismatch,
+}
+
+/// The verification mode of a QR code, together with the keys the mode
+/// requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrVerificationMode {
+    /// Verifying another user, mode `0x00`.
+    Verification {
+        /// The master cross-signing key of the user displaying the QR code.
+        own_master_key: Ed25519PublicKey,
+        /// The master cross-signing key the displaying side believes the other
+        /// user has.
+        other_master_key: Ed25519PublicKey,
+    },
+    /// Self-verification where the device displaying the QR code trusts the
+    /// master cross-signing key, mode `0x01`.
+    SelfVerification {
+        /// The master cross-signing key of the user.
+        master_key: Ed25519PublicKey,
+        /// The Ed25519 key the displaying side believes the other device has.
+        other_device_key: Ed25519PublicKey,
+    },
+    /// Self-verification where the device displaying the QR code doesn't yet
+    /// trust the master cross-signing key, mode `0x02`.
+    SelfVerificationNoMasterKey {
+        /// The Ed25519 key of the device displaying the QR code.
+        device_key: Ed25519PublicKey,
+        /// The master cross-signing key the displaying side believes the user
+        /// has.
+        master_key: Ed25519PublicKey,
+    },
+}
+
+impl QrVerificationMode {
+    const fn to_byte(self) -> u8 {
+        match self {
+            QrVerificationMode::Verification { .. } => 0x00,
+            QrVerificationMode::SelfVerification { .. } => 0x01,
+            QrVerificationMode::SelfVerificationNoMasterKey { .. } => 0x02,
+        }
+    }
+
+    const fn keys(&self) -> (&Ed25519PublicKey, &Ed25519PublicKey) {
+        match self {
+            QrVerificationMode::Verification { own_master_key, other_master_key } => {
+                (own_master_key, other_master_key)
+            }
+            QrVerificationMode::SelfVerification { master_key, other_device_key } => {
+                (master_key, other_device_key)
+            }
+            QrVerificationMode::SelfVerificationNoMasterKey { device_key, master_key } => {
+                (device_key, master_key)
+            }
+        }
+    }
+
+    fn from_parts(
+        mode: u8,
+        first_key: Ed25519PublicKey,
+        second_key: Ed25519PublicKey,
+    ) -> Result<Self, QrDecodeError> {
+        Ok(match mode {
+            0x00 => QrVerificationMode::Verification {
+                own_master_key: first_key,
+                other_master_key: second_key,
+            },
+            0x01 => QrVerificationMode::SelfVerification {
+                master_key: first_key,
+                other_device_key: second_key,
+            },
+            0x02 => QrVerificationMode::SelfVerificationNoMasterKey {
+                device_key: first_key,
+                master_key: second_key,
+            },
+            _ => return Err(QrDecodeError::UnknownMode(mode)),
+        })
+    }
+}
+
+/// The shared secret contained in a QR code.
+///
+/// The side scanning the QR code sends this secret back to prove that it
+/// scanned the QR code.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct QrSecret(Vec<u8>);
+
+impl QrSecret {
+    /// Generate a new random shared secret.
+    pub fn new() -> Self {
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Generate a new random shared secret using the given random number
+    /// generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        rng.fill_bytes(&mut secret);
+
+        Self(secret)
+    }
+
+    /// Create a shared secret from the given bytes.
+    ///
+    /// The secret needs to be at least 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, QrDecodeError> {
+        if bytes.len() < MIN_SECRET_LENGTH {
+            Err(QrDecodeError::SecretTooShort(MIN_SECRET_LENGTH, bytes.len()))
+        } else {
+            Ok(Self(bytes.to_vec()))
+        }
+    }
+
+    /// Get the raw bytes of the shared secret.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Convert the shared secret to a Base64 encoded string, as it should be
+    /// sent in the `secret` field of the `m.key.verification.start` event.
+    pub fn to_base64(&self) -> String {
+        base64_encode(&self.0)
+    }
+}
+
+impl Default for QrSecret {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for QrSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("QrSecret").finish_non_exhaustive()
+    }
+}
+
+/// The data contained in a verification QR code.
+#[derive(Debug, Clone)]
+pub struct QrVerificationData {
+    flow_id: String,
+    mode: QrVerificationMode,
+    secret: QrSecret,
+}
+
+impl QrVerificationData {
+    /// Create new QR code data for the given verification flow with a freshly
+    /// generated shared secret.
+    ///
+    /// The flow ID is the transaction ID of a to-device verification, or the
+    /// event ID of the `m.key.verification.request` event of an in-room
+    /// verification. Returns an error if the flow ID is longer than 65535
+    /// bytes.
+    pub fn new(flow_id: &str, mode: QrVerificationMode) -> Result<Self, QrEncodeError> {
+        Self::with_secret(flow_id, mode, QrSecret::new())
+    }
+
+    /// Create new QR code data for the given verification flow using the given
+    /// shared secret.
+    pub fn with_secret(
+        flow_id: &str,
+        mode: QrVerificationMode,
+        secret: QrSecret,
+    ) -> Result<Self, QrEncodeError> {
+        if flow_id.len() > MAX_FLOW_ID_LENGTH {
+            Err(QrEncodeError::FlowIdTooLong(MAX_FLOW_ID_LENGTH, flow_id.len()))
+        } else {
+            Ok(Self { flow_id: flow_id.to_owned(), mode, secret })
+        }
+    }
+
+    /// The ID of the verification flow this QR code belongs to.
+    pub fn flow_id(&self) -> &str {
+        &self.flow_id
+    }
+
+    /// The verification mode of the QR code, containing the keys the
+    /// displaying side believes to be correct.
+    pub const fn mode(&self) -> QrVerificationMode {
+        self.mode
+    }
+
+    /// The shared secret of the QR code.
+    pub const fn secret(&self) -> &QrSecret {
+        &self.secret
+    }
+
+    /// Encode the QR code data into the binary payload that should be
+    /// rendered as a QR code.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flow_id = self.flow_id.as_bytes();
+        let (first_key, second_key) = self.mode.keys();
+
+        // The length of the flow ID is checked when the data is created, so it
+        // always fits into the two bytes the format reserves.
+        let flow_id_length = (flow_id.len() as u16).to_be_bytes();
+
+        [
+            PREFIX,
+            &[VERSION, self.mode.to_byte()],
+            &flow_id_length,
+            flow_id,
+            first_key.as_bytes(),
+            second_key.as_bytes(),
+            self.secret.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Decode QR code data from the binary payload of a scanned QR code.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, QrDecodeError> {
+        let mut reader = Reader(bytes);
+
+        if reader.take(PREFIX.len())? != PREFIX {
+            return Err(QrDecodeError::InvalidPrefix);
+        }
+
+        let version = reader.take_byte()?;
+
+        if version != VERSION {
+            return Err(QrDecodeError::UnsupportedVersion(VERSION, version));
+        }
+
+        let mode = reader.take_byte()?;
+        let flow_id_length = u16::from_be_bytes([reader.take_byte()?, reader.take_byte()?]);
+        let flow_id = String::from_utf8(reader.take(flow_id_length.into())?.to_vec())?;
+
+        let first_key = reader.take_key()?;
+        let second_key = reader.take_key()?;
+
+        let mode = QrVerificationMode::from_parts(mode, first_key, second_key)?;
+        let secret = QrSecret::from_bytes(reader.0)?;
+
+        Ok(Self { flow_id, mode, secret })
+    }
+
+    /// Check that the secret the other side sent back after scanning our QR
+    /// code matches our shared secret.
+    ///
+    /// The comparison is done in constant time.
+    pub fn verify_reciprocate(&self, secret: &[u8]) -> Result<(), QrVerificationError> {
+        if bool::from(self.secret.as_bytes().ct_eq(secret)) {
+            Ok(())
+        } else {
+            Err(QrVerificationError::Mismatch)
+        }
+    }
+
+    /// Check that the Base64 encoded secret from the `m.key.verification.start`
+    /// event matches our shared secret.
+    ///
+    /// The comparison is done in constant time.
+    pub fn verify_reciprocate_base64(&self, secret: &str) -> Result<(), QrVerificationError> {
+        let mut secret = base64_decode(secret)?;
+        let result = self.verify_reciprocate(&secret);
+        secret.zeroize();
+
+        result
+    }
+}
+
+/// A small helper to read the fields of a QR code payload one by one.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], QrDecodeError> {
+        if self.0.len() < count {
+            Err(QrDecodeError::MissingData)
+        } else {
+            let (taken, rest) = self.0.split_at(count);
+            self.0 = rest;
+
+            Ok(taken)
+        }
+    }
+
+    fn take_byte(&mut self) -> Result<u8, QrDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take_key(&mut self) -> Result<Ed25519PublicKey, QrDecodeError> {
+        let mut key = [0u8; Ed25519PublicKey::LENGTH];
+        key.copy_from_slice(self.take(Ed25519PublicKey::LENGTH)?);
+
+        Ok(Ed25519PublicKey::from_slice(&key)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{
+        MAX_FLOW_ID_LENGTH, QrDecodeError, QrEncodeError, QrSecret, QrVerificationData,
+        QrVerificationError, QrVerificationMode,
+    };
+    use crate::Ed25519Keypair;
+
+    fn modes() -> [QrVerificationMode; 3] {
+        let first_key = Ed25519Keypair::new().public_key();
+        let second_key = Ed25519Keypair::new().public_key();
+
+        [
+            QrVerificationMode::Verification {
+                own_master_key: first_key,
+                other_master_key: second_key,
+            },
+            QrVerificationMode::SelfVerification {
+                master_key: first_key,
+                other_device_key: second_key,
+            },
+            QrVerificationMode::SelfVerificationNoMasterKey {
+                device_key: first_key,
+                master_key: second_key,
+            },
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        for mode in modes() {
+            let data = QrVerificationData::new("$some_event_id", mode).unwrap();
+            let decoded = QrVerificationData::from_bytes(&data.to_bytes())
+                .expect("We should be able to decode our own QR code");
+
+            assert_eq!(decoded.flow_id(), "$some_event_id");
+            assert_eq!(decoded.mode(), mode);
+            assert_eq!(decoded.secret().as_bytes(), data.secret().as_bytes());
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let [mode, ..] = modes();
+        let (first_key, second_key) = mode.keys();
+        let secret = QrSecret::from_bytes(b"12345678").expect("The secret should be long enough");
+
+        let bytes = QrVerificationData::with_secret("ab", mode, secret).unwrap().to_bytes();
+
+        assert_eq!(&bytes[..6], b"MATRIX");
+        assert_eq!(bytes[6], 0x02);
+        assert_eq!(bytes[7], 0x00);
+        assert_eq!(&bytes[8..12], &[0x00, 0x02, b'a', b'b']);
+        assert_eq!(&bytes[12..44], first_key.as_bytes());
+        assert_eq!(&bytes[44..76], second_key.as_bytes());
+        assert_eq!(&bytes[76..], b"12345678");
+    }
+
+    #[test]
+    fn flow_id_length() {
+        let [mode, ..] = modes();
+
+        let flow_id = "a".repeat(MAX_FLOW_ID_LENGTH);
+        let data = QrVerificationData::new(&flow_id, mode).unwrap();
+        let decoded = QrVerificationData::from_bytes(&data.to_bytes()).unwrap();
+        assert_eq!(decoded.flow_id(), flow_id);
+
+        let flow_id = "a".repeat(MAX_FLOW_ID_LENGTH + 1);
+        assert_matches!(
+            QrVerificationData::new(&flow_id, mode),
+            Err(QrEncodeError::FlowIdTooLong(65535, 65536))
+        );
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        let [mode, ..] = modes();
+        let bytes = QrVerificationData::new("flow", mode).unwrap().to_bytes();
+
+        let mut invalid = bytes.clone();
+        invalid[0] = b'X';
+        assert_matches!(
+            QrVerificationData::from_bytes(&invalid),
+            Err(QrDecodeError::InvalidPrefix)
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[6] = 0x01;
+        assert_matches!(
+            QrVerificationData::from_bytes(&invalid),
+            Err(QrDecodeError::UnsupportedVersion(0x02, 0x01))
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[7] = 0x03;
+        assert_matches!(
+            QrVerificationData::from_bytes(&invalid),
+            Err(QrDecodeError::UnknownMode(3))
+        );
+
+        assert_matches!(
+            QrVerificationData::from_bytes(&bytes[..50]),
+            Err(QrDecodeError::MissingData)
+        );
+        assert_matches!(
+            QrVerificationData::from_bytes(&bytes[..bytes.len() - 10]),
+            Err(QrDecodeError::SecretTooShort(8, 6))
+        );
+    }
+
+    #[test]
+    fn reciprocate() {
+        let [mode, ..] = modes();
+        let data = QrVerificationData::new("flow", mode).unwrap();
+
+        data.verify_reciprocate(data.secret().as_bytes())
+            .expect("The secret of the QR code should verify");
+        data.verify_reciprocate_base64(&data.secret().to_base64())
+            .expect("The Base64 encoded secret of the QR code should verify");
+
+        assert_matches!(
+            data.verify_reciprocate(QrSecret::new().as_bytes()),
+            Err(QrVerificationError::Mismatch)
+        );
+        assert_matches!(data.verify_reciprocate(&[]), Err(QrVerificationError::Mismatch));
+        assert_matches!(
+            data.verify_reciprocate_base64("not base64!"),
+            Err(QrVerificationError::Base64(_))
+        );
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The emoji table used for the emoji based short authentication string
+//! method, as defined in the [spec].
+//!
+//! [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+
+/// An emoji from the table defined in the Matrix [spec], together with its
+/// English description.
+///
+/// [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Emoji {
+    index: u8,
+    /// The emoji itself, as a Unicode string.
+    pub symbol: &'static str,
+    /// The English description of the emoji, as it should be shown to users.
+    pub description: &'static str,
+}
+
+impl Emoji {
+    /// Look up the emoji with the given index in the emoji table.
+    ///
+    /// Returns `None` if the index is out of range, the table contains 64
+    /// emojis.
+    pub fn from_index(index: u8) -> Option<Self> {
+        EMOJI_TABLE.get(index as usize).copied()
+    }
+
+    /// Get the index of this emoji in the emoji table.
+    pub const fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Get the description of this emoji translated into the given locale.
+    ///
+    /// Only the German (`de`), Spanish (`es`), French (`fr`) and Dutch (`nl`)
+    /// translations from the [spec] are bundled. The locale is matched first
+    /// exactly and then by the language part alone, so `de-AT` falls back to
+    /// `de`. Returns `None` if no translation is bundled for the locale.
+    ///
+    /// [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+    #[cfg(feature = "sas-emoji-translations")]
+    pub fn translated_description(&self, locale: &str) -> Option<&'static str> {
+        translations::lookup(self.index, locale)
+    }
+}
+
+/// The 64 emojis which are used for the emoji based short authentication
+/// string method, ordered by their index.
+pub(super) static EMOJI_TABLE: [Emoji; 64] = [
+    Emoji { index: 0, symbol: "🐶", description: "Dog" },
+    Emoji { index: 1, symbol: "🐱", description: "Cat" },
+    Emoji { index: 2, symbol: "🦁", description: "Lion" },
+    Emoji { index: 3, symbol: "🐎", description: "Horse" },
+    Emoji { index: 4, symbol: "🦄", description: "Unicorn" },
+    Emoji { index: 5, symbol: "🐷", description: "Pig" },
+    Emoji { index: 6, symbol: "🐘", description: "Elephant" },
+    Emoji { index: 7, symbol: "🐰", description: "Rabbit" },
+    Emoji { index: 8, symbol: "🐼", description: "Panda" },
+    Emoji { index: 9, symbol: "🐓", description: "Rooster" },
+    Emoji { index: 10, symbol: "🐧", description: "Penguin" },
+    Emoji { index: 11, symbol: "🐢", description: "Turtle" },
+    Emoji { index: 12, symbol: "🐟", description: "Fish" },
+    Emoji { index: 13, symbol: "🐙", description: "Octopus" },
+    Emoji { index: 14, symbol: "🦋", description: "Butterfly" },
+    Emoji { index: 15, symbol: "🌷", description: "Flower" },
+    Emoji { index: 16, symbol: "🌳", description: "Tree" },
+    Emoji { index: 17, symbol: "🌵", description: "Cactus" },
+    Emoji { index: 18, symbol: "🍄", description: "Mushroom" },
+    Emoji { index: 19, symbol: "🌏", description: "Globe" },
+    Emoji { index: 20, symbol: "🌙", description: "Moon" },
+    Emoji { index: 21, symbol: "☁️", description: "Cloud" },
+    Emoji { index: 22, symbol: "🔥", description: "Fire" },
+    Emoji { index: 23, symbol: "🍌", description: "Banana" },
+    Emoji { index: 24, symbol: "🍎", description: "Apple" },
+    Emoji { index: 25, symbol: "🍓", description: "Strawberry" },
+    Emoji { index: 26, symbol: "🌽", description: "Corn" },
+    Emoji { index: 27, symbol: "🍕", description: "Pizza" },
+    Emoji { index: 28, symbol: "🎂", description: "Cake" },
+    Emoji { index: 29, symbol: "❤️", description: "Heart" },
+    Emoji { index: 30, symbol: "😀", description: "Smiley" },
+    Emoji { index: 31, symbol: "🤖", description: "Robot" },
+    Emoji { index: 32, symbol: "🎩", description: "Hat" },
+    Emoji { index: 33, symbol: "👓", description: "Glasses" },
+    Emoji { index: 34, symbol: "🔧", description: "Spanner" },
+    Emoji { index: 35, symbol: "🎅", description: "Santa" },
+    Emoji { index: 36, symbol: "👍", description: "Thumbs Up" },
+    Emoji { index: 37, symbol: "☂️", description: "Umbrella" },
+    Emoji { index: 38, symbol: "⌛", description: "Hourglass" },
+    Emoji { index: 39, symbol: "⏰", description: "Clock" },
+    Emoji { index: 40, symbol: "🎁", description: "Gift" },
+    Emoji { index: 41, symbol: "💡", description: "Light Bulb" },
+    Emoji { index: 42, symbol: "📕", description: "Book" },
+    Emoji { index: 43, symbol: "✏️", description: "Pencil" },
+    Emoji { index: 44, symbol: "📎", description: "Paperclip" },
+    Emoji { index: 45, symbol: "✂️", description: "Scissors" },
+    Emoji { index: 46, symbol: "🔒", description: "Lock" },
+    Emoji { index: 47, symbol: "🔑", description: "Key" },
+    Emoji { index: 48, symbol: "🔨", description: "Hammer" },
+    Emoji { index: 49, symbol: "☎️", description: "Telephone" },
+    Emoji { index: 50, symbol: "🏁", description: "Flag" },
+    Emoji { index: 51, symbol: "🚂", description: "Train" },
+    Emoji { index: 52, symbol: "🚲", description: "Bicycle" },
+    Emoji { index: 53, symbol: "✈️", description: "Aeroplane" },
+    Emoji { index: 54, symbol: "🚀", description: "Rocket" },
+    Emoji { index: 55, symbol: "🏆", description: "Trophy" },
+    Emoji { index: 56, symbol: "⚽", description: "Ball" },
+    Emoji { index: 57, symbol: "🎸", description: "Guitar" },
+    Emoji { index: 58, symbol: "🎺", description: "Trumpet" },
+    Emoji { index: 59, symbol: "🔔", description: "Bell" },
+    Emoji { index: 60, symbol: "⚓", description: "Anchor" },
+    Emoji { index: 61, symbol: "🎧", description: "Headphones" },
+    Emoji { index: 62, symbol: "📁", description: "Folder" },
+    Emoji { index: 63, symbol: "📌", description: "Pin" },
+];
+
+#[cfg(feature = "sas-emoji-translations")]
+mod translations {
+    use std::{collections::HashMap, sync::OnceLock};
+
+    use serde::Deserialize;
+
+    /// The locales which have bundled translations.
+    #[cfg(test)]
+    pub(super) const LOCALES: [&str; 4] = ["de", "es", "fr", "nl"];
+
+    /// The translations of the emoji descriptions, in the format of the
+    /// `sas-emoji.json` file from the Matrix spec.
+    ///
+    /// This is a subset of the spec's file, it only contains the translations
+    /// for the [`LOCALES`].
+    pub(super) const SAS_EMOJI_JSON: &str = include_str!("sas-emoji-subset.json");
+
+    #[derive(Deserialize)]
+    struct EmojiEntry {
+        #[serde(default)]
+        translated_descriptions: HashMap<String, Option<String>>,
+    }
+
+    fn entries() -> &'static [EmojiEntry] {
+        static ENTRIES: OnceLock<Vec<EmojiEntry>> = OnceLock::new();
+
+        ENTRIES.get_or_init(|| {
+            #[allow(clippy::expect_used)]
+            serde_json::from_str(SAS_EMOJI_JSON)
+                .expect("The bundled SAS emoji translations should be valid JSON")
+        })
+    }
+
+    pub(super) fn lookup(index: u8, locale: &str) -> Option<&'static str> {
+        let translations = &entries().get(index as usize)?.translated_descriptions;
+        let find = |locale: &str| translations.get(locale).and_then(|d| d.as_deref());
+
+        // The spec uses underscores to separate the language from the region,
+        // e.g. `pt_BR`, but BCP 47 tags use dashes.
+        let locale = locale.replace('-', "_");
+
+        find(&locale).or_else(|| {
+            let (language, _) = locale.split_once('_')?;
+            find(language)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EMOJI_TABLE, Emoji};
+
+    #[test]
+    fn table_is_ordered() {
+        for (index, emoji) in EMOJI_TABLE.iter().enumerate() {
+            assert_eq!(emoji.index() as usize, index);
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let emoji = Emoji::from_index(0).expect("The first emoji should exist");
+        assert_eq!(emoji.symbol, "🐶");
+        assert_eq!(emoji.description, "Dog");
+
+        let emoji = Emoji::from_index(63).expect("The last emoji should exist");
+        assert_eq!(emoji.symbol, "📌");
+        assert_eq!(emoji.description, "Pin");
+
+        assert_eq!(Emoji::from_index(64), None);
+    }
+
+    #[test]
+    #[cfg(feature = "sas-emoji-translations")]
+    fn bundled_translations_match_table() {
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(super::translations::SAS_EMOJI_JSON)
+                .expect("The bundled SAS emoji translations should be valid JSON");
+        assert_eq!(entries.len(), EMOJI_TABLE.len());
+
+        for (entry, emoji) in entries.iter().zip(EMOJI_TABLE.iter()) {
+            assert_eq!(entry["number"], emoji.index());
+            assert_eq!(entry["emoji"], emoji.symbol);
+            assert_eq!(entry["description"], emoji.description);
+
+            let mut locales: Vec<&str> = entry["translated_descriptions"]
+                .as_object()
+                .expect("Every emoji should have translations")
+                .keys()
+                .map(String::as_str)
+                .collect();
+            locales.sort_unstable();
+            assert_eq!(locales, super::translations::LOCALES);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sas-emoji-translations")]
+    fn translated_description() {
+        let emoji = Emoji::from_index(0).expect("The first emoji should exist");
+
+        assert_eq!(emoji.translated_description("de"), Some("Hund"));
+        assert_eq!(emoji.translated_description("de-AT"), Some("Hund"));
+        assert_eq!(emoji.translated_description("fr_CA"), Some("Chien"));
+        assert_eq!(emoji.translated_description("xx"), None);
+    }
+}
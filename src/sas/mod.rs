@@ -40,24 +40,48 @@
 //! let alice_bytes = alice.bytes("AGREED_INFO");
 //! let bob_bytes = bob.bytes("AGREED_INFO");
 //!
-//! let alice_emojis = alice_bytes.emoji_indices();
-//! let bob_emojis = bob_bytes.emoji_indices();
+//! let alice_emojis = alice_bytes.emojis();
+//! let bob_emojis = bob_bytes.emojis();
 //!
 //! assert_eq!(alice_emojis, bob_emojis);
+//!
+//! for emoji in alice_emojis {
+//!     println!("{} {}", emoji.symbol, emoji.description);
+//! }
 //! # Ok(())
 //! # }
 //! ```
 //!
+//! Translations of the emoji descriptions into German, Spanish, French and
+//! Dutch, a subset of the locales from the [spec], are available with the
+//! `sas-emoji-translations` feature, see [`Emoji::translated_description()`].
+//!
+//! A [`Sas`] object can't be persisted, if the verification needs to survive
+//! an application restart, take a look at the [`PersistentSas`].
+//!
 //! [`Account`]: crate::olm::Account
 //! [ZRTP]: https://tools.ietf.org/html/rfc6189#section-4.4.1
+//! [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+
+mod emoji;
+mod persistent;
 
 use hkdf::Hkdf;
 use hmac::{Hmac, Mac as _, digest::MacError};
 use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use sha2::Sha256;
 use thiserror::Error;
 use x25519_dalek::{EphemeralSecret, SharedSecret};
 
+use self::emoji::EMOJI_TABLE;
+pub use self::{
+    emoji::Emoji,
+    persistent::{
+        PersistentEstablishedSas, PersistentEstablishedSasPickle, PersistentSas,
+        PersistentSasPickle,
+    },
+};
 use crate::{
     Curve25519PublicKey, KeyError,
     utilities::{base64_decode, base64_encode},
@@ -152,6 +176,19 @@ impl SasBytes {
         Self::bytes_to_emoji_index(&self.bytes)
     }
 
+    /// Get the 7 emojis that can be presented to users to perform the key
+    /// verification, together with their descriptions.
+    ///
+    /// This uses the emoji table from the [spec] to look up the indices
+    /// returned by [`SasBytes::emoji_indices()`].
+    ///
+    /// [spec]: https://spec.matrix.org/unstable/client-server-api/#sas-method-emoji
+    pub fn emojis(&self) -> [Emoji; 7] {
+        // The indices are 6 bits wide, so they always fit into the 64 entry
+        // table.
+        self.emoji_indices().map(|index| EMOJI_TABLE[index as usize])
+    }
+
     /// Get the three decimal numbers that can be presented to users to perform
     /// the key verification, as described in the [spec]
     ///
@@ -221,8 +258,12 @@ impl Sas {
     /// This creates an ephemeral curve25519 keypair that can be used to
     /// establish a shared secret.
     pub fn new() -> Self {
-        let rng = thread_rng();
+        Self::new_with_rng(&mut thread_rng())
+    }
 
+    /// Create a new random verification object using the given random number
+    /// generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
         let secret_key = EphemeralSecret::random_from_rng(rng);
         let public_key = Curve25519PublicKey::from(&secret_key);
 
@@ -643,6 +684,21 @@ mod test {
         assert_eq!(SasBytes { bytes }.emoji_indices(), index.as_ref());
     }
 
+    #[test]
+    fn emoji_lookup() {
+        let bytes: [u8; 6] = [0, 0, 0, 0, 0, 0];
+        let emojis = SasBytes { bytes }.emojis();
+        assert!(emojis.iter().all(|e| e.symbol == "🐶" && e.description == "Dog"));
+
+        let bytes: [u8; 6] = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let emojis = SasBytes { bytes }.emojis();
+        assert!(emojis.iter().all(|e| e.symbol == "📌" && e.description == "Pin"));
+
+        let sas_bytes = SasBytes { bytes: [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC] };
+        let indices: Vec<u8> = sas_bytes.emojis().iter().map(|e| e.index()).collect();
+        assert_eq!(indices, sas_bytes.emoji_indices());
+    }
+
     #[test]
     fn decimal_generation() {
         let bytes: [u8; 6] = [0, 0, 0, 0, 0, 0];
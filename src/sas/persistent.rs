@@ -0,0 +1,341 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+
+use super::EstablishedSas;
+use crate::{
+    Curve25519PublicKey, Curve25519SecretKey, KeyError, PickleEncoding, PickleError,
+    utilities::{pickle, pickle_with_rng, unpickle},
+};
+
+/// A short auth string verification object which, unlike [`Sas`], can be
+/// persisted.
+///
+/// ⚠️ ***Security Warning***: [`Sas`] uses an ephemeral key which can never
+/// leave memory, this object instead uses a [`Curve25519SecretKey`] which can
+/// be pickled. Only use this if the verification needs to survive the
+/// application being restarted, and make sure that the pickle is stored
+/// securely and deleted as soon as the verification is done, or cancelled.
+///
+/// The secret key is zeroized when the object is dropped.
+///
+/// [`Sas`]: super::Sas
+pub struct PersistentSas {
+    secret_key: Curve25519SecretKey,
+    public_key: Curve25519PublicKey,
+}
+
+impl PersistentSas {
+    /// Create a new random persistent verification object.
+    pub fn new() -> Self {
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Create a new random persistent verification object using the given
+    /// random number generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let secret_key = Curve25519SecretKey::new_with_rng(rng);
+        let public_key = Curve25519PublicKey::from(&secret_key);
+
+        Self { secret_key, public_key }
+    }
+
+    /// Get the public key that can be used to establish a shared secret.
+    pub const fn public_key(&self) -> Curve25519PublicKey {
+        self.public_key
+    }
+
+    /// Establishes a SAS secret by performing a DH handshake with another
+    /// public key.
+    ///
+    /// Returns a [`PersistentEstablishedSas`] object which can be used to
+    /// generate [`SasBytes`] if the given public key was valid.
+    ///
+    /// [`SasBytes`]: super::SasBytes
+    pub fn diffie_hellman(
+        self,
+        their_public_key: Curve25519PublicKey,
+    ) -> Result<PersistentEstablishedSas, KeyError> {
+        let established = establish(&self.secret_key, self.public_key, their_public_key)?;
+
+        Ok(PersistentEstablishedSas { secret_key: self.secret_key, established })
+    }
+
+    /// Establishes a SAS secret by performing a DH handshake with another
+    /// public key in "raw", base64-encoded form.
+    pub fn diffie_hellman_with_raw(
+        self,
+        other_public_key: &str,
+    ) -> Result<PersistentEstablishedSas, KeyError> {
+        let other_public_key = Curve25519PublicKey::from_base64(other_public_key)?;
+        self.diffie_hellman(other_public_key)
+    }
+
+    /// Convert the verification object into a struct which implements
+    /// [`serde::Serialize`] and [`serde::Deserialize`].
+    pub fn pickle(&self) -> PersistentSasPickle {
+        PersistentSasPickle { secret_key: self.secret_key.clone() }
+    }
+
+    /// Restore a [`PersistentSas`] from a previously saved
+    /// [`PersistentSasPickle`].
+    pub fn from_pickle(pickle: PersistentSasPickle) -> Self {
+        pickle.into()
+    }
+}
+
+impl Default for PersistentSas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A persistent short auth string verification object where the shared secret
+/// has been established.
+///
+/// This is the persistable counterpart of [`EstablishedSas`], the same
+/// ⚠️ ***Security Warning*** as for the [`PersistentSas`] applies.
+pub struct PersistentEstablishedSas {
+    secret_key: Curve25519SecretKey,
+    established: EstablishedSas,
+}
+
+impl PersistentEstablishedSas {
+    /// Get the [`EstablishedSas`] which can be used to generate the short auth
+    /// string and to calculate and verify MACs.
+    pub const fn established(&self) -> &EstablishedSas {
+        &self.established
+    }
+
+    /// Convert the verification object into a struct which implements
+    /// [`serde::Serialize`] and [`serde::Deserialize`].
+    pub fn pickle(&self) -> PersistentEstablishedSasPickle {
+        PersistentEstablishedSasPickle {
+            secret_key: self.secret_key.clone(),
+            their_public_key: self.established.their_public_key,
+        }
+    }
+
+    /// Restore a [`PersistentEstablishedSas`] from a previously saved
+    /// [`PersistentEstablishedSasPickle`].
+    ///
+    /// Like [`PersistentSas::diffie_hellman()`], this fails if the public key
+    /// of the other side doesn't contribute to the shared secret.
+    pub fn from_pickle(pickle: PersistentEstablishedSasPickle) -> Result<Self, KeyError> {
+        pickle.try_into()
+    }
+}
+
+impl std::fmt::Debug for PersistentEstablishedSas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PersistentEstablishedSas")
+            .field("established", &self.established)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A format suitable for serialization which implements [`serde::Serialize`]
+/// and [`serde::Deserialize`]. Obtainable by calling [`PersistentSas::pickle`].
+#[derive(Serialize, Deserialize)]
+pub struct PersistentSasPickle {
+    secret_key: Curve25519SecretKey,
+}
+
+impl PersistentSasPickle {
+    const PICKLE_TYPE: &'static str = "PersistentSas";
+
+    /// Serialize and encrypt the pickle using the given key.
+    ///
+    /// This is the inverse of [`PersistentSasPickle::from_encrypted`].
+    pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`PersistentSasPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Obtain a pickle from a ciphertext by decrypting and deserializing using
+    /// the given key.
+    ///
+    /// This is the inverse of [`PersistentSasPickle::encrypt`].
+    pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
+    }
+}
+
+impl From<PersistentSasPickle> for PersistentSas {
+    fn from(pickle: PersistentSasPickle) -> Self {
+        let public_key = Curve25519PublicKey::from(&pickle.secret_key);
+
+        Self { secret_key: pickle.secret_key, public_key }
+    }
+}
+
+/// A format suitable for serialization which implements [`serde::Serialize`]
+/// and [`serde::Deserialize`]. Obtainable by calling
+/// [`PersistentEstablishedSas::pickle`].
+#[derive(Serialize, Deserialize)]
+pub struct PersistentEstablishedSasPickle {
+    secret_key: Curve25519SecretKey,
+    their_public_key: Curve25519PublicKey,
+}
+
+impl PersistentEstablishedSasPickle {
+    const PICKLE_TYPE: &'static str = "PersistentEstablishedSas";
+
+    /// Serialize and encrypt the pickle using the given key.
+    ///
+    /// This is the inverse of
+    /// [`PersistentEstablishedSasPickle::from_encrypted`].
+    pub fn encrypt(self, pickle_key: &[u8; 32]) -> String {
+        pickle(&self, pickle_key, Self::PICKLE_TYPE)
+    }
+
+    /// Serialize and encrypt the pickle using the given key, generating the
+    /// salt of the encrypted pickle using the given random number generator.
+    ///
+    /// See [`PersistentEstablishedSasPickle::encrypt`] for more details.
+    pub fn encrypt_with_rng(self, pickle_key: &[u8; 32], rng: &mut impl CryptoRngCore) -> String {
+        pickle_with_rng(&self, pickle_key, Self::PICKLE_TYPE, PickleEncoding::Json, rng)
+    }
+
+    /// Obtain a pickle from a ciphertext by decrypting and deserializing using
+    /// the given key.
+    ///
+    /// This is the inverse of [`PersistentEstablishedSasPickle::encrypt`].
+    pub fn from_encrypted(ciphertext: &str, pickle_key: &[u8; 32]) -> Result<Self, PickleError> {
+        unpickle(ciphertext, pickle_key, Self::PICKLE_TYPE)
+    }
+}
+
+impl TryFrom<PersistentEstablishedSasPickle> for PersistentEstablishedSas {
+    type Error = KeyError;
+
+    fn try_from(pickle: PersistentEstablishedSasPickle) -> Result<Self, Self::Error> {
+        let our_public_key = Curve25519PublicKey::from(&pickle.secret_key);
+        let established = establish(&pickle.secret_key, our_public_key, pickle.their_public_key)?;
+
+        Ok(Self { secret_key: pickle.secret_key, established })
+    }
+}
+
+/// Perform the DH handshake, rejecting public keys which don't contribute to
+/// the shared secret.
+fn establish(
+    secret_key: &Curve25519SecretKey,
+    our_public_key: Curve25519PublicKey,
+    their_public_key: Curve25519PublicKey,
+) -> Result<EstablishedSas, KeyError> {
+    let shared_secret = secret_key.diffie_hellman(&their_public_key);
+
+    if shared_secret.was_contributory() {
+        Ok(EstablishedSas { shared_secret, our_public_key, their_public_key })
+    } else {
+        Err(KeyError::NonContributoryKey)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{
+        PersistentEstablishedSas, PersistentEstablishedSasPickle, PersistentSas,
+        PersistentSasPickle,
+    };
+    use crate::{Curve25519PublicKey, Curve25519SecretKey, KeyError, sas::Sas};
+
+    const PICKLE_KEY: [u8; 32] = [0u8; 32];
+
+    #[test]
+    fn pickle_before_diffie_hellman() -> anyhow::Result<()> {
+        let alice = PersistentSas::new();
+        let bob = Sas::new();
+
+        let pickle = alice.pickle().encrypt(&PICKLE_KEY);
+        let unpickled =
+            PersistentSas::from_pickle(PersistentSasPickle::from_encrypted(&pickle, &PICKLE_KEY)?);
+
+        assert_eq!(alice.public_key(), unpickled.public_key());
+
+        let bob = bob.diffie_hellman(unpickled.public_key())?;
+        let alice = unpickled.diffie_hellman(bob.our_public_key())?;
+
+        assert_eq!(alice.established().bytes("INFO"), bob.bytes("INFO"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pickle_after_diffie_hellman() -> anyhow::Result<()> {
+        let alice = PersistentSas::new();
+        let bob = Sas::new();
+
+        let bob_public_key = bob.public_key();
+        let bob = bob.diffie_hellman(alice.public_key())?;
+        let alice = alice.diffie_hellman(bob_public_key)?;
+
+        let pickle = alice.pickle().encrypt(&PICKLE_KEY);
+        let unpickled = PersistentEstablishedSas::from_pickle(
+            PersistentEstablishedSasPickle::from_encrypted(&pickle, &PICKLE_KEY)?,
+        )?;
+
+        let unpickled = unpickled.established();
+
+        assert_eq!(unpickled.our_public_key(), alice.established().our_public_key());
+        assert_eq!(unpickled.their_public_key(), bob_public_key);
+        assert_eq!(unpickled.bytes("INFO"), bob.bytes("INFO"));
+
+        let mac = bob.calculate_mac("Hello world", "MAC_INFO");
+        unpickled.verify_mac("Hello world", "MAC_INFO", &mac)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn unpickling_checks_contributory_key() {
+        let pickle = PersistentEstablishedSasPickle {
+            secret_key: Curve25519SecretKey::new(),
+            their_public_key: Curve25519PublicKey::from_bytes([0u8; 32]),
+        };
+
+        let pickle = PersistentEstablishedSasPickle::from_encrypted(
+            &pickle.encrypt(&PICKLE_KEY),
+            &PICKLE_KEY,
+        )
+        .unwrap();
+
+        assert_matches!(
+            PersistentEstablishedSas::from_pickle(pickle),
+            Err(KeyError::NonContributoryKey)
+        );
+    }
+
+    #[test]
+    fn pickle_with_wrong_key() {
+        let pickle = PersistentSas::new().pickle().encrypt(&PICKLE_KEY);
+
+        PersistentSasPickle::from_encrypted(&pickle, &[1u8; 32])
+            .err()
+            .expect("Unpickling with the wrong key should fail");
+    }
+}
@@ -0,0 +1,827 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic test vectors for cross-implementation testing.
+//!
+//! This module runs a fixed set of scripted scenarios, an Olm conversation, a
+//! Megolm session, a SAS verification and an ECIES channel, and records every
+//! intermediate value in a [`TestVectors`] struct which serializes to JSON.
+//!
+//! All the randomness the scenarios consume is recorded as well, each step
+//! lists the random bytes it consumed in the order they were requested. Other
+//! implementations can use those bytes as their fixed keys, and vodozemac can
+//! replay vectors, feeding the recorded random bytes back into the scenarios
+//! and checking that every value matches, using [`TestVectors::replay()`].
+//!
+//! ```
+//! use vodozemac::test_vectors::TestVectors;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let vectors = TestVectors::generate(0)?;
+//! let json = vectors.to_json()?;
+//!
+//! TestVectors::from_json(&json)?.replay()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ⚠️ ***Security Warning***: The random number generator used to replay
+//! vectors hands out the recorded bytes. This module is only meant to be used
+//! for testing.
+
+use std::collections::VecDeque;
+
+use rand_chacha::ChaCha20Rng;
+use rand_core::{CryptoRng, RngCore, SeedableRng, impls};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    KeyError, base64_decode, base64_encode,
+    ecies::{self, Ecies},
+    megolm::{self, GroupSession, InboundGroupSession},
+    olm::{
+        self, Account, IdentityKeys, OlmMessage, PreKeyMessage, Session, SessionConfig,
+        SessionCreationError,
+    },
+    sas::{Sas, SasError},
+};
+
+const OLM_SCRIPT: [(Party, &str); 6] = [
+    (Party::Alice, "It's a secret to everybody"),
+    (Party::Bob, "It's dangerous to go alone"),
+    (Party::Bob, "Take this"),
+    (Party::Alice, "Thank you"),
+    (Party::Alice, "Where is the princess?"),
+    (Party::Bob, "She's in another castle"),
+];
+const MEGOLM_PLAINTEXTS: [&str; 3] = ["First message", "Second message", "Third message"];
+const MEGOLM_RATCHET_INDICES: [u32; 8] = [0, 1, 2, 255, 256, 65_535, 65_536, 16_777_216];
+const SAS_INFO: &str = "MATRIX_KEY_VERIFICATION_SAS";
+const SAS_MAC_INFO: &str = "MATRIX_KEY_VERIFICATION_MAC";
+const SAS_MAC_INPUT: &str = "ed25519:DEVICEID";
+const ECIES_SCRIPT: [(Party, &str); 3] = [
+    (Party::Alice, "Initial message"),
+    (Party::Bob, "Reply from the other side"),
+    (Party::Alice, "And another one"),
+];
+
+/// Error type describing the ways test vectors can fail to be generated or
+/// replayed.
+#[derive(Debug, Error)]
+pub enum TestVectorError {
+    /// A value of the replayed scenario didn't match the recorded one.
+    #[error("The test vectors don't match at {path}, expected {expected}, got {got}")]
+    Mismatch {
+        /// The path of the mismatched value, e.g.
+        /// `olm.messages[0].message_key`.
+        path: String,
+        /// The recorded value.
+        expected: String,
+        /// The value the scenario produced.
+        got: String,
+    },
+    /// The scenarios needed more random bytes than the vectors contain.
+    #[error("The test vectors don't contain enough random bytes")]
+    RandomnessExhausted,
+    /// The recorded random bytes weren't valid base64.
+    #[error("The recorded random bytes weren't valid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    /// The vectors couldn't be serialized or deserialized.
+    #[error("The test vectors couldn't be (de)serialized: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The Olm session couldn't be created.
+    #[error(transparent)]
+    OlmSessionCreation(#[from] SessionCreationError),
+    /// An Olm message couldn't be decrypted.
+    #[error(transparent)]
+    OlmDecryption(#[from] olm::DecryptionError),
+    /// A Megolm message couldn't be decrypted.
+    #[error(transparent)]
+    MegolmDecryption(#[from] megolm::DecryptionError),
+    /// The Megolm session couldn't be exported at the given index.
+    #[error("The Megolm session couldn't be exported at index {0}")]
+    MegolmExport(u32),
+    /// The SAS couldn't be established.
+    #[error(transparent)]
+    SasKey(#[from] KeyError),
+    /// A SAS MAC couldn't be verified.
+    #[error(transparent)]
+    SasMac(#[from] SasError),
+    /// The ECIES channel couldn't be established, or a message couldn't be
+    /// decrypted.
+    #[error(transparent)]
+    Ecies(#[from] ecies::Error),
+}
+
+/// The two sides of the scripted conversations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Party {
+    /// The side which starts the conversation.
+    Alice,
+    /// The side which responds.
+    Bob,
+}
+
+/// The test vectors of all the scenarios.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// The vectors of the Olm scenario.
+    pub olm: OlmVectors,
+    /// The vectors of the Megolm scenario.
+    pub megolm: MegolmVectors,
+    /// The vectors of the SAS scenario.
+    pub sas: SasVectors,
+    /// The vectors of the ECIES scenario.
+    pub ecies: EciesVectors,
+}
+
+/// The test vectors of an Olm conversation between two accounts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OlmVectors {
+    /// The account which creates the outbound session.
+    pub alice: OlmAccountVector,
+    /// The account which publishes a one-time key and creates the inbound
+    /// session.
+    pub bob: OlmAccountVector,
+    /// The outbound session Alice creates.
+    pub session: OlmSessionVector,
+    /// The messages of the conversation, in the order they were sent.
+    pub messages: Vec<OlmMessageVector>,
+}
+
+/// The keys of an Olm account.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OlmAccountVector {
+    /// The random bytes consumed to create the account and its one-time keys.
+    pub random: Vec<String>,
+    /// The public Curve25519 identity key.
+    pub curve25519_key: String,
+    /// The public Ed25519 identity key.
+    pub ed25519_key: String,
+    /// The public one-time keys.
+    pub one_time_keys: Vec<String>,
+}
+
+/// The initial state of an outbound Olm session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OlmSessionVector {
+    /// The random bytes consumed to create the session.
+    pub random: Vec<String>,
+    /// The public part of the base key.
+    pub base_key: String,
+    /// The session ID.
+    pub session_id: String,
+    /// The root key of the sending ratchet.
+    pub root_key: String,
+    /// The chain key of the sending ratchet.
+    pub chain_key: Option<String>,
+}
+
+/// A single Olm message, with the state of the sender's ratchet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OlmMessageVector {
+    /// The side which sent the message.
+    pub sender: Party,
+    /// The random bytes consumed to encrypt and decrypt the message.
+    pub random: Vec<String>,
+    /// The plaintext of the message.
+    pub plaintext: String,
+    /// The public ratchet key the message key belongs to.
+    pub ratchet_key: String,
+    /// The index of the message key in its chain.
+    pub chain_index: u64,
+    /// The message key.
+    pub message_key: String,
+    /// The sender's root key, after the message key was created.
+    pub root_key: String,
+    /// The sender's chain key, after the message key was created.
+    pub chain_key: Option<String>,
+    /// The type of the message, 0 for pre-key messages and 1 for normal ones.
+    pub message_type: usize,
+    /// The encoded message.
+    pub message: String,
+}
+
+/// The test vectors of a Megolm session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MegolmVectors {
+    /// The random bytes consumed to create the session.
+    pub random: Vec<String>,
+    /// The session ID.
+    pub session_id: String,
+    /// The session key which is shared with the recipients.
+    pub session_key: String,
+    /// The messages the session encrypted.
+    pub messages: Vec<MegolmMessageVector>,
+    /// The state of the ratchet at various indices.
+    pub ratchets: Vec<MegolmRatchetVector>,
+}
+
+/// A single Megolm message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MegolmMessageVector {
+    /// The index of the message.
+    pub message_index: u32,
+    /// The plaintext of the message.
+    pub plaintext: String,
+    /// The encoded message.
+    pub message: String,
+}
+
+/// The state of a Megolm ratchet at a given index.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MegolmRatchetVector {
+    /// The index of the ratchet.
+    pub index: u32,
+    /// The 128 bytes of the ratchet.
+    pub ratchet: String,
+    /// The session key exported at this index.
+    pub exported_session_key: String,
+}
+
+/// The test vectors of a SAS verification.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SasVectors {
+    /// The random bytes consumed to create both sides of the verification.
+    pub random: Vec<String>,
+    /// Alice's ephemeral public key.
+    pub alice_public_key: String,
+    /// Bob's ephemeral public key.
+    pub bob_public_key: String,
+    /// The info which was used to create the short authentication string.
+    pub info: String,
+    /// The six bytes of the short authentication string.
+    pub sas_bytes: String,
+    /// The indices of the emojis.
+    pub emoji_indices: Vec<u8>,
+    /// The decimal representation of the short authentication string.
+    pub decimals: [u16; 3],
+    /// The input of the MAC.
+    pub mac_input: String,
+    /// The info which was used to calculate the MAC.
+    pub mac_info: String,
+    /// The MAC Alice calculated.
+    pub mac: String,
+}
+
+/// The test vectors of an ECIES channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EciesVectors {
+    /// The random bytes consumed to create both sides of the channel.
+    pub random: Vec<String>,
+    /// Alice's ephemeral public key.
+    pub alice_public_key: String,
+    /// Bob's ephemeral public key.
+    pub bob_public_key: String,
+    /// The bytes of the check code.
+    pub check_code: String,
+    /// The check code as a two-digit number.
+    pub check_code_digit: u8,
+    /// The messages of the channel, the first one being the initial message.
+    pub messages: Vec<EciesMessageVector>,
+}
+
+/// A single ECIES message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EciesMessageVector {
+    /// The side which sent the message.
+    pub sender: Party,
+    /// The plaintext of the message.
+    pub plaintext: String,
+    /// The encoded message.
+    pub message: String,
+}
+
+impl TestVectors {
+    /// Run the scenarios using a random number generator seeded with the
+    /// given seed.
+    pub fn generate(seed: u64) -> Result<Self, TestVectorError> {
+        let mut rng = TapeRng::seeded(seed);
+        run_scenarios(&mut rng)
+    }
+
+    /// Run the scenarios again using the recorded random bytes and check that
+    /// every value matches the recorded one.
+    pub fn replay(&self) -> Result<(), TestVectorError> {
+        let mut rng = TapeRng::replaying(&self.random_bytes()?);
+        let replayed = run_scenarios(&mut rng)?;
+
+        if rng.exhausted {
+            Err(TestVectorError::RandomnessExhausted)
+        } else {
+            let expected = serde_json::to_value(self)?;
+            let got = serde_json::to_value(&replayed)?;
+
+            match first_difference(String::new(), &expected, &got) {
+                Some(error) => Err(error),
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Serialize the vectors as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, TestVectorError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Deserialize vectors from JSON.
+    pub fn from_json(json: &str) -> Result<Self, TestVectorError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Collect the recorded random bytes, in the order the scenarios consume
+    /// them.
+    fn random_bytes(&self) -> Result<Vec<u8>, TestVectorError> {
+        let olm = &self.olm;
+
+        [&olm.alice.random, &olm.bob.random, &olm.session.random]
+            .into_iter()
+            .chain(olm.messages.iter().map(|m| &m.random))
+            .chain([&self.megolm.random, &self.sas.random, &self.ecies.random])
+            .flatten()
+            .try_fold(Vec::new(), |mut bytes, draw| {
+                bytes.extend(base64_decode(draw)?);
+                Ok(bytes)
+            })
+    }
+}
+
+/// A random number generator which records all the bytes it hands out.
+///
+/// The bytes either come from a seeded ChaCha20 generator or from a tape of
+/// previously recorded bytes.
+struct TapeRng {
+    source: TapeSource,
+    draws: Vec<Vec<u8>>,
+    exhausted: bool,
+}
+
+enum TapeSource {
+    Seeded(Box<ChaCha20Rng>),
+    Tape(VecDeque<u8>),
+}
+
+impl TapeRng {
+    fn seeded(seed: u64) -> Self {
+        Self {
+            source: TapeSource::Seeded(Box::new(ChaCha20Rng::seed_from_u64(seed))),
+            draws: Vec::new(),
+            exhausted: false,
+        }
+    }
+
+    fn replaying(bytes: &[u8]) -> Self {
+        Self {
+            source: TapeSource::Tape(bytes.iter().copied().collect()),
+            draws: Vec::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Take the random bytes which were handed out since the last call.
+    fn take_draws(&mut self) -> Vec<String> {
+        self.draws.drain(..).map(base64_encode).collect()
+    }
+}
+
+impl RngCore for TapeRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match &mut self.source {
+            TapeSource::Seeded(rng) => rng.fill_bytes(dest),
+            TapeSource::Tape(tape) => {
+                let available = tape.len().min(dest.len());
+                let (filled, missing) = dest.split_at_mut(available);
+
+                for (byte, recorded) in filled.iter_mut().zip(tape.drain(..available)) {
+                    *byte = recorded;
+                }
+
+                if !missing.is_empty() {
+                    missing.fill(0);
+                    self.exhausted = true;
+                }
+            }
+        }
+
+        self.draws.push(dest.to_vec());
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// The tape hands out recorded bytes when replaying, which is fine since the
+// vectors are only used for testing.
+impl CryptoRng for TapeRng {}
+
+fn run_scenarios(rng: &mut TapeRng) -> Result<TestVectors, TestVectorError> {
+    Ok(TestVectors {
+        olm: run_olm(rng)?,
+        megolm: run_megolm(rng)?,
+        sas: run_sas(rng)?,
+        ecies: run_ecies(rng)?,
+    })
+}
+
+fn account_vector(
+    account: &Account,
+    one_time_keys: Vec<String>,
+    rng: &mut TapeRng,
+) -> OlmAccountVector {
+    let IdentityKeys { curve25519, ed25519 } = account.identity_keys();
+
+    OlmAccountVector {
+        random: rng.take_draws(),
+        curve25519_key: curve25519.to_base64(),
+        ed25519_key: ed25519.to_base64(),
+        one_time_keys,
+    }
+}
+
+fn encrypt_olm(
+    session: &mut Session,
+    sender: Party,
+    plaintext: &str,
+    rng: &mut TapeRng,
+) -> (OlmMessage, OlmMessageVector) {
+    let message_key = session.next_message_key_with_rng(rng);
+
+    let ratchet_key = message_key.ratchet_key().as_ref().to_base64();
+    let chain_index = message_key.index();
+    let key = base64_encode(message_key.key());
+    let (root_key, chain_key) = session.sending_ratchet_keys();
+    let (root_key, chain_key) = (base64_encode(root_key), chain_key.map(base64_encode));
+
+    let message = message_key.encrypt(plaintext.as_bytes());
+    let message = if session.has_received_message() {
+        OlmMessage::Normal(message)
+    } else {
+        OlmMessage::PreKey(PreKeyMessage::wrap(session.session_keys(), message))
+    };
+    let (message_type, encoded) = message.to_parts();
+
+    let vector = OlmMessageVector {
+        sender,
+        random: Vec::new(),
+        plaintext: plaintext.to_owned(),
+        ratchet_key,
+        chain_index,
+        message_key: key,
+        root_key,
+        chain_key,
+        message_type,
+        message: base64_encode(encoded),
+    };
+
+    (message, vector)
+}
+
+fn check_plaintext(path: String, expected: &str, got: &[u8]) -> Result<(), TestVectorError> {
+    if expected.as_bytes() == got {
+        Ok(())
+    } else {
+        Err(TestVectorError::Mismatch {
+            path,
+            expected: expected.to_owned(),
+            got: String::from_utf8_lossy(got).into_owned(),
+        })
+    }
+}
+
+fn run_olm(rng: &mut TapeRng) -> Result<OlmVectors, TestVectorError> {
+    let alice = Account::new_with_rng(rng);
+    let alice_vector = account_vector(&alice, Vec::new(), rng);
+
+    let mut bob = Account::new_with_rng(rng);
+    let one_time_key = bob.generate_one_time_keys_with_rng(1, rng).created;
+    bob.mark_keys_as_published();
+    let bob_vector =
+        account_vector(&bob, one_time_key.iter().map(|k| k.to_base64()).collect(), rng);
+
+    #[allow(clippy::expect_used)]
+    let one_time_key = *one_time_key.first().expect("We should be able to generate a one-time key");
+
+    let mut alice_session = alice.create_outbound_session_with_rng(
+        SessionConfig::version_2(),
+        bob.curve25519_key(),
+        one_time_key,
+        rng,
+    );
+
+    let (root_key, chain_key) = alice_session.sending_ratchet_keys();
+    let session_vector = OlmSessionVector {
+        random: rng.take_draws(),
+        base_key: alice_session.session_keys().base_key.to_base64(),
+        session_id: alice_session.session_id(),
+        root_key: base64_encode(root_key),
+        chain_key: chain_key.map(base64_encode),
+    };
+
+    let mut bob_session = None;
+    let mut messages = Vec::new();
+
+    for (i, (sender, plaintext)) in OLM_SCRIPT.into_iter().enumerate() {
+        let path = format!(".olm.messages[{i}].plaintext");
+
+        let (message, mut vector) = match (sender, &mut bob_session) {
+            (Party::Alice, _) => encrypt_olm(&mut alice_session, sender, plaintext, rng),
+            (Party::Bob, Some(session)) => encrypt_olm(session, sender, plaintext, rng),
+            // Bob can't send anything before he received a pre-key message.
+            (Party::Bob, None) => continue,
+        };
+
+        let decrypted = match (sender, bob_session.as_mut(), &message) {
+            (Party::Alice, Some(session), _) => session.decrypt_with_rng(&message, rng)?,
+            (Party::Alice, None, OlmMessage::PreKey(m)) => {
+                let result = bob.create_inbound_session(alice.curve25519_key(), m)?;
+                bob_session = Some(result.session);
+                result.plaintext
+            }
+            // Alice's messages are pre-key messages until she receives a reply.
+            (Party::Alice, None, OlmMessage::Normal(_)) => Vec::new(),
+            (Party::Bob, _, _) => alice_session.decrypt_with_rng(&message, rng)?,
+        };
+
+        check_plaintext(path, plaintext, &decrypted)?;
+
+        vector.random = rng.take_draws();
+        messages.push(vector);
+    }
+
+    Ok(OlmVectors { alice: alice_vector, bob: bob_vector, session: session_vector, messages })
+}
+
+fn run_megolm(rng: &mut TapeRng) -> Result<MegolmVectors, TestVectorError> {
+    let mut session = GroupSession::new_with_rng(megolm::SessionConfig::version_2(), rng);
+    let random = rng.take_draws();
+
+    let session_key = session.session_key();
+    let mut inbound = InboundGroupSession::new(&session_key, megolm::SessionConfig::version_2());
+
+    let mut messages = Vec::new();
+
+    for (i, plaintext) in MEGOLM_PLAINTEXTS.into_iter().enumerate() {
+        let message = session.encrypt(plaintext);
+        let decrypted = inbound.decrypt(&message)?;
+
+        check_plaintext(
+            format!(".megolm.messages[{i}].plaintext"),
+            plaintext,
+            &decrypted.plaintext,
+        )?;
+
+        messages.push(MegolmMessageVector {
+            message_index: message.message_index(),
+            plaintext: plaintext.to_owned(),
+            message: message.to_base64(),
+        });
+    }
+
+    let ratchets = MEGOLM_RATCHET_INDICES
+        .into_iter()
+        .map(|index| {
+            let exported = inbound.export_at(index).ok_or(TestVectorError::MegolmExport(index))?;
+
+            Ok(MegolmRatchetVector {
+                index,
+                ratchet: base64_encode(exported.ratchet.as_slice()),
+                exported_session_key: exported.to_base64(),
+            })
+        })
+        .collect::<Result<_, TestVectorError>>()?;
+
+    Ok(MegolmVectors {
+        random,
+        session_id: session.session_id(),
+        session_key: session_key.to_base64(),
+        messages,
+        ratchets,
+    })
+}
+
+fn run_sas(rng: &mut TapeRng) -> Result<SasVectors, TestVectorError> {
+    let alice = Sas::new_with_rng(rng);
+    let bob = Sas::new_with_rng(rng);
+    let random = rng.take_draws();
+
+    let alice_public_key = alice.public_key();
+    let bob_public_key = bob.public_key();
+
+    let alice = alice.diffie_hellman(bob_public_key)?;
+    let bob = bob.diffie_hellman(alice_public_key)?;
+
+    let sas_bytes = alice.bytes(SAS_INFO);
+    let their_bytes = bob.bytes(SAS_INFO);
+
+    if sas_bytes.as_bytes() != their_bytes.as_bytes() {
+        return Err(TestVectorError::Mismatch {
+            path: ".sas.sas_bytes".to_owned(),
+            expected: base64_encode(sas_bytes.as_bytes()),
+            got: base64_encode(their_bytes.as_bytes()),
+        });
+    }
+
+    let mac = alice.calculate_mac(SAS_MAC_INPUT, SAS_MAC_INFO);
+    bob.verify_mac(SAS_MAC_INPUT, SAS_MAC_INFO, &mac)?;
+
+    let (first, second, third) = sas_bytes.decimals();
+
+    Ok(SasVectors {
+        random,
+        alice_public_key: alice_public_key.to_base64(),
+        bob_public_key: bob_public_key.to_base64(),
+        info: SAS_INFO.to_owned(),
+        sas_bytes: base64_encode(sas_bytes.as_bytes()),
+        emoji_indices: sas_bytes.emoji_indices().to_vec(),
+        decimals: [first, second, third],
+        mac_input: SAS_MAC_INPUT.to_owned(),
+        mac_info: SAS_MAC_INFO.to_owned(),
+        mac: mac.to_base64(),
+    })
+}
+
+fn run_ecies(rng: &mut TapeRng) -> Result<EciesVectors, TestVectorError> {
+    let alice = Ecies::new_with_rng(rng);
+    let bob = Ecies::new_with_rng(rng);
+    let random = rng.take_draws();
+
+    let alice_public_key = alice.public_key();
+    let bob_public_key = bob.public_key();
+
+    let mut messages = Vec::new();
+    let [(sender, initial_plaintext), rest @ ..] = ECIES_SCRIPT;
+
+    let outbound =
+        alice.establish_outbound_channel(bob_public_key, initial_plaintext.as_bytes())?;
+    let inbound = bob.establish_inbound_channel(&outbound.message)?;
+
+    check_plaintext(
+        ".ecies.messages[0].plaintext".to_owned(),
+        initial_plaintext,
+        &inbound.message,
+    )?;
+
+    let check_code = outbound.ecies.check_code();
+
+    if check_code != inbound.ecies.check_code() {
+        return Err(TestVectorError::Mismatch {
+            path: ".ecies.check_code".to_owned(),
+            expected: base64_encode(check_code.as_bytes()),
+            got: base64_encode(inbound.ecies.check_code().as_bytes()),
+        });
+    }
+
+    let (check_code, check_code_digit) =
+        (base64_encode(check_code.as_bytes()), check_code.to_digit());
+
+    messages.push(EciesMessageVector {
+        sender,
+        plaintext: initial_plaintext.to_owned(),
+        message: outbound.message.encode(),
+    });
+
+    let (mut alice, mut bob) = (outbound.ecies, inbound.ecies);
+
+    for (i, (sender, plaintext)) in rest.into_iter().enumerate() {
+        let (from, to) = match sender {
+            Party::Alice => (&mut alice, &mut bob),
+            Party::Bob => (&mut bob, &mut alice),
+        };
+
+        let message = from.encrypt(plaintext.as_bytes());
+        let decrypted = to.decrypt(&message)?;
+
+        check_plaintext(format!(".ecies.messages[{}].plaintext", i + 1), plaintext, &decrypted)?;
+
+        messages.push(EciesMessageVector {
+            sender,
+            plaintext: plaintext.to_owned(),
+            message: message.encode(),
+        });
+    }
+
+    Ok(EciesVectors {
+        random,
+        alice_public_key: alice_public_key.to_base64(),
+        bob_public_key: bob_public_key.to_base64(),
+        check_code,
+        check_code_digit,
+        messages,
+    })
+}
+
+/// Find the first value which differs between the two JSON values.
+fn first_difference(path: String, expected: &Value, got: &Value) -> Option<TestVectorError> {
+    let mismatch = |path: String| TestVectorError::Mismatch {
+        path,
+        expected: expected.to_string(),
+        got: got.to_string(),
+    };
+
+    match (expected, got) {
+        (Value::Object(expected), Value::Object(got)) => {
+            let missing = || {
+                got.iter().find(|(key, _)| !expected.contains_key(*key)).map(|(key, value)| {
+                    TestVectorError::Mismatch {
+                        path: format!("{path}.{key}"),
+                        expected: Value::Null.to_string(),
+                        got: value.to_string(),
+                    }
+                })
+            };
+
+            expected
+                .iter()
+                .find_map(|(key, value)| {
+                    first_difference(
+                        format!("{path}.{key}"),
+                        value,
+                        got.get(key).unwrap_or(&Value::Null),
+                    )
+                })
+                .or_else(missing)
+        }
+        (Value::Array(e), Value::Array(g)) if e.len() == g.len() => e
+            .iter()
+            .zip(g)
+            .enumerate()
+            .find_map(|(i, (e, g))| first_difference(format!("{path}[{i}]"), e, g)),
+        _ if expected == got => None,
+        _ => Some(mismatch(path)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+
+    use super::{TestVectorError, TestVectors};
+
+    const VECTORS: &str = include_str!("vectors.json");
+
+    #[test]
+    fn generation_is_deterministic() -> anyhow::Result<()> {
+        assert_eq!(TestVectors::generate(0)?, TestVectors::generate(0)?);
+        assert_ne!(TestVectors::generate(0)?, TestVectors::generate(1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay() -> anyhow::Result<()> {
+        let vectors = TestVectors::generate(1)?;
+        TestVectors::from_json(&vectors.to_json()?)?.replay()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn recorded_vectors() -> anyhow::Result<()> {
+        let vectors = TestVectors::from_json(VECTORS)?;
+
+        vectors.replay()?;
+        assert_eq!(vectors, TestVectors::generate(0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_vectors() -> anyhow::Result<()> {
+        let mut vectors = TestVectors::generate(0)?;
+        vectors.olm.messages[1].message_key = vectors.olm.messages[0].message_key.clone();
+
+        assert_matches!(vectors.replay(), Err(TestVectorError::Mismatch { path, .. }));
+        assert_eq!(path, ".olm.messages[1].message_key");
+
+        let mut vectors = TestVectors::generate(0)?;
+        vectors.sas.random.pop();
+
+        assert_matches!(vectors.replay(), Err(TestVectorError::RandomnessExhausted));
+
+        Ok(())
+    }
+}
@@ -17,6 +17,7 @@ use std::fmt::Display;
 use base64::decoded_len_estimate;
 use matrix_pickle::{Decode, DecodeError};
 use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 use x25519_dalek::{EphemeralSecret, PublicKey, ReusableSecret, SharedSecret, StaticSecret};
 use zeroize::Zeroize;
@@ -32,8 +33,12 @@ pub struct Curve25519SecretKey(Box<StaticSecret>);
 impl Curve25519SecretKey {
     /// Generate a new, random, Curve25519SecretKey.
     pub fn new() -> Self {
-        let rng = thread_rng();
+        Self::new_with_rng(&mut thread_rng())
+    }
 
+    /// Generate a new, random, Curve25519SecretKey using the given random
+    /// number generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
         Self(Box::new(StaticSecret::random_from_rng(rng)))
     }
 
@@ -80,8 +85,8 @@ pub(crate) struct Curve25519Keypair {
 }
 
 impl Curve25519Keypair {
-    pub fn new() -> Self {
-        let secret_key = Curve25519SecretKey::new();
+    pub fn new(rng: &mut impl CryptoRngCore) -> Self {
+        let secret_key = Curve25519SecretKey::new_with_rng(rng);
         let public_key = Curve25519PublicKey::from(&secret_key);
 
         Self { secret_key, public_key }
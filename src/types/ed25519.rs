@@ -23,6 +23,7 @@ use ed25519_dalek::{
     PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH, Signature, Signer, SigningKey, VerifyingKey,
 };
 use rand::thread_rng;
+use rand_core::CryptoRngCore;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_bytes::{ByteBuf as SerdeByteBuf, Bytes as SerdeBytes};
 use sha2::Sha512;
@@ -129,8 +130,13 @@ impl<'d> Deserialize<'d> for ExpandedSecretKey {
 impl Ed25519Keypair {
     /// Create a new, random, `Ed25519Keypair`.
     pub fn new() -> Self {
-        let mut rng = thread_rng();
-        let signing_key = SigningKey::generate(&mut rng);
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Create a new, random, `Ed25519Keypair` using the given random number
+    /// generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let signing_key = SigningKey::generate(rng);
 
         Self {
             public_key: Ed25519PublicKey(signing_key.verifying_key()),
@@ -208,8 +214,13 @@ impl Ed25519SecretKey {
 
     /// Create a new random `Ed25519SecretKey`.
     pub fn new() -> Self {
-        let mut rng = thread_rng();
-        let signing_key = SigningKey::generate(&mut rng);
+        Self::new_with_rng(&mut thread_rng())
+    }
+
+    /// Create a new random `Ed25519SecretKey` using the given random number
+    /// generator.
+    pub fn new_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let signing_key = SigningKey::generate(rng);
         let key = Box::new(signing_key);
 
         Self(key)
@@ -0,0 +1,329 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The ML-KEM-768 key encapsulation mechanism as specified in [FIPS 203].
+//!
+//! The mechanism itself is provided by the [`ml_kem`] crate, this module wraps
+//! its types into the key types Olm uses. Secret keys are stored as the 64
+//! byte seed `d || z` and expanded when needed.
+//!
+//! [FIPS 203]: https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.203.pdf
+
+use std::fmt::Debug;
+
+use ml_kem::{
+    B32, Ciphertext, EncodedSizeUser, KemCore, MlKem768,
+    array::Array,
+    kem::{Decapsulate, Encapsulate},
+};
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::KeyError;
+use crate::utilities::{base64_decode, base64_encode};
+
+type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+
+/// The secret part of an ML-KEM-768 key pair, stored as the seeds `d` and `z`
+/// it was generated from.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub(crate) struct MlKem768SecretKey {
+    d: Box<[u8; 32]>,
+    z: Box<[u8; 32]>,
+}
+
+impl MlKem768SecretKey {
+    pub fn new(rng: &mut impl CryptoRngCore) -> Self {
+        let mut d = Box::new([0u8; 32]);
+        let mut z = Box::new([0u8; 32]);
+
+        rng.fill_bytes(d.as_mut_slice());
+        rng.fill_bytes(z.as_mut_slice());
+
+        Self { d, z }
+    }
+
+    #[cfg(test)]
+    fn from_seed(d: [u8; 32], z: [u8; 32]) -> Self {
+        Self { d: Box::new(d), z: Box::new(z) }
+    }
+
+    /// Expand the seeds into the decapsulation key, `ML-KEM.KeyGen_internal`.
+    fn expand(&self) -> (DecapsulationKey, EncapsulationKey) {
+        let mut d = B32::from(*self.d);
+        let mut z = B32::from(*self.z);
+
+        let keys = MlKem768::generate_deterministic(&d, &z);
+
+        d.as_mut_slice().zeroize();
+        z.as_mut_slice().zeroize();
+
+        keys
+    }
+
+    /// Get the public key, the encapsulation key in the terms of the standard.
+    pub fn public_key(&self) -> MlKem768PublicKey {
+        let (_, public_key) = self.expand();
+        MlKem768PublicKey::from_encapsulation_key(&public_key)
+    }
+
+    /// Decapsulate the shared secret from the ciphertext.
+    ///
+    /// Decapsulation never fails, invalid ciphertexts produce a pseudorandom
+    /// shared secret which won't match the one of the sender.
+    pub fn decapsulate(&self, ciphertext: &MlKem768Ciphertext) -> Box<[u8; 32]> {
+        let (secret_key, _) = self.expand();
+        let ciphertext = Ciphertext::<MlKem768>::from(*ciphertext.as_bytes());
+
+        #[allow(clippy::expect_used)]
+        let mut shared_secret = secret_key
+            .decapsulate(&ciphertext)
+            .expect("ML-KEM-768 decapsulation should never fail");
+
+        let result = Box::new(shared_secret.into());
+        shared_secret.as_mut_slice().zeroize();
+
+        result
+    }
+}
+
+/// The public part of an ML-KEM-768 key pair, used to encapsulate a shared
+/// secret for the owner of the key pair.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct MlKem768PublicKey(Box<[u8; Self::LENGTH]>);
+
+impl MlKem768PublicKey {
+    /// The number of bytes an ML-KEM-768 public key has.
+    pub const LENGTH: usize = 1184;
+
+    fn from_encapsulation_key(key: &EncapsulationKey) -> Self {
+        Self(Box::new(key.as_bytes().into()))
+    }
+
+    fn encapsulation_key(&self) -> EncapsulationKey {
+        EncapsulationKey::from_bytes(&Array::from(*self.0))
+    }
+
+    /// Convert the public key to a vector of bytes.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    /// View the public key as a slice of bytes.
+    pub fn as_bytes(&self) -> &[u8; Self::LENGTH] {
+        &self.0
+    }
+
+    /// Try to create a `MlKem768PublicKey` from a slice of bytes.
+    ///
+    /// Besides the length, the modulus check from section 7.2 of FIPS 203 is
+    /// performed, keys with coefficients that aren't reduced are rejected.
+    pub fn from_slice(slice: &[u8]) -> Result<Self, KeyError> {
+        let bytes: &[u8; Self::LENGTH] =
+            slice.try_into().map_err(|_| KeyError::InvalidKeyLength {
+                key_type: "ML-KEM-768",
+                expected_length: Self::LENGTH,
+                length: slice.len(),
+            })?;
+
+        let key = Self(Box::new(*bytes));
+
+        // Decoding reduces the coefficients modulo q, the encoding of a key
+        // only survives the round trip if it was reduced to begin with.
+        if key.encapsulation_key().as_bytes().as_slice() == bytes.as_slice() {
+            Ok(key)
+        } else {
+            Err(KeyError::MalformedMlKemKey)
+        }
+    }
+
+    /// Instantiate a `MlKem768PublicKey` from an unpadded base64
+    /// representation.
+    pub fn from_base64(input: &str) -> Result<Self, KeyError> {
+        Self::from_slice(&base64_decode(input)?)
+    }
+
+    /// Serialize a `MlKem768PublicKey` to an unpadded base64 representation.
+    pub fn to_base64(&self) -> String {
+        base64_encode(self.0.as_slice())
+    }
+
+    /// The SHA3-256 hash of the public key, used to refer to the key without
+    /// having to include it.
+    pub fn key_hash(&self) -> [u8; 32] {
+        Sha3_256::digest(self.0.as_slice()).into()
+    }
+
+    /// Encapsulate a fresh shared secret for the owner of this public key.
+    pub(crate) fn encapsulate(
+        &self,
+        rng: &mut impl CryptoRngCore,
+    ) -> (MlKem768Ciphertext, Box<[u8; 32]>) {
+        #[allow(clippy::expect_used)]
+        let (ciphertext, mut shared_secret) = self
+            .encapsulation_key()
+            .encapsulate(rng)
+            .expect("ML-KEM-768 encapsulation should never fail");
+
+        let result =
+            (MlKem768Ciphertext(Box::new(ciphertext.into())), Box::new(shared_secret.into()));
+        shared_secret.as_mut_slice().zeroize();
+
+        result
+    }
+}
+
+impl Debug for MlKem768PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MlKem768PublicKey({})", base64_encode(self.key_hash()))
+    }
+}
+
+/// An ML-KEM-768 ciphertext, the encapsulated shared secret.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct MlKem768Ciphertext(Box<[u8; Self::LENGTH]>);
+
+impl MlKem768Ciphertext {
+    pub const LENGTH: usize = 1088;
+
+    pub fn as_bytes(&self) -> &[u8; Self::LENGTH] {
+        &self.0
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        let bytes: &[u8; Self::LENGTH] = slice.try_into().ok()?;
+
+        Some(Self(Box::new(*bytes)))
+    }
+}
+
+impl Serialize for MlKem768Ciphertext {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_bytes::serialize(self.0.as_slice(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MlKem768Ciphertext {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: serde_bytes::ByteBuf = serde_bytes::deserialize(deserializer)?;
+
+        Self::from_slice(&bytes).ok_or_else(|| {
+            serde::de::Error::invalid_length(bytes.len(), &"an ML-KEM-768 ciphertext")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::thread_rng;
+
+    use super::*;
+
+    /// The key pair OpenSSL 3.5 generates for the seed `d = 0x00..`,
+    /// `z = 0x11..`, identified by the SHA3-256 hash of the public key.
+    const OPENSSL_KEY_HASH: &str = "B/gaiw4maj7pLTpjza5c/5IZBVRMndeXqEnh0FQYDso";
+
+    /// A ciphertext OpenSSL encapsulated for the above key pair and the
+    /// resulting shared secret.
+    const OPENSSL_CIPHERTEXT: &str = concat!(
+        "5ARUlHycG8CmefH114DVoy5DIozDeBlomNL9Px9k3DI4Ppz3wluRxfGjlXkJqWP1qpQZewCZuPHZjjgW",
+        "QAEgFn+Mdwl2AoGr922szQjG4HQmDJ1IrwtGDZ9lwJvKrjX6hyWwE4NbTsvj8g/0+BScoqIJh6PAal2y",
+        "a52XoMrgqIkRylHI8tOP7hV0XRNdbdaA3qiz6Os3/8SMuM12pKS69Db9zCCSju9t9b0ueRkfs577+9vM",
+        "bbZwEqeBk/61+nwrn07orcxE4+BaCw2GtvZDLr8hR69qbocgAws9dRJQRa0a11aV0IM1O1BLuZaUboDA",
+        "C5ZI7tpas5+dlVlxOiu0T1FKzxZikhdtInXa4lg8TFQQCLcSJb0/cTK6F3khiB4t+teArCPrJQSCHJUv",
+        "d8nKYip+fdFyDSnnCcKIxIu+jxpUqNArdg/oCpWyTQ11977+v77qED8C1rL5FtXpOTPB0N3AecZzMF8Z",
+        "jgq16WFcVInVlWzy7/W2Xlw11Mhc8ZmUuSeZsTJXlSYfysuMajEH1/4Tr6434zJWyJB8NsKY47kyeRiV",
+        "xfQLcQhHckmyKoyw25VWH80IZiOkRgvcOibHlZxdp9t9xShP8R9/Ot8p0BAukppeH0yo894Cp9Ap4zzw",
+        "TJJmk77N1ghsKN64dj4SnfWkSipF7WaZ5zzJFU9LJyGCNt97vsvX2O6dxgjHe6bAZV6SzrqU+9Afi9SG",
+        "Uhbf/CAZsP1bHUq0X3z/JgvT0R6kE30472xdnwp5AngXZ0lDFpzTpgUy6KDR+F0JvRFG4MTfBo/p3Xav",
+        "nBe9KYGQp2E6JQA6gVRrMeRs+i7qna3feo5LHUBZcC/me47VbDhqV7OSy2DTP7ONf59XIZLbgIn1P7Ao",
+        "thjT5xYqRSaQY/3MoZ1nXQ77WjMqYAvsONO7nkeX8D1ffD9ciPf6ViWe8+xw+0PrEj9eQN1lLtqIEF2y",
+        "EfbjzVxj3M0mc0CFZwYLNh5wKIqs3kVOgcm6YMEtV6gajtJNyVYqXImwJwUp07eZR0zXbYySfYhuMfyh",
+        "BMWxlmd7uMGMmHFSWMjZo2y8bQvN7AYYuz6MCeh5w8QvJTQ9lsOecWsPIKVNq9QXYe5sThAD+/IyyZv0",
+        "jz8lnd8Ei/ijdtUMYJhoEC2I2UOFSO7n5ZIm2mh0pUNAGCEEp2241gH05CN+Pcj4vGESCa8G+tv6nz5x",
+        "IVS0j75vMFCTIs6/cfEVsRqGpW2oLWfx3Fz7o1hbLARlI0DNqQt+GM4P36b7wlmB1RiBXG3ju35/ElDw",
+        "wNdM9uAsKOPJxOUJ/dEinTSOYDh6Rba2x1aZj6MNamvpQ50M7yUy40WLsoZpytZeCjH/aGEL5Ah53paG",
+        "dbmxBAsvOg8xAIPBoeul9CNY2kKYYJ2B4dpQx8HPAfFodRFbVot515gx0NlmScX+Iiw7uD/G3SVQ6ree",
+        "bLu0J1HAbnk",
+    );
+    const OPENSSL_SHARED_SECRET: &str = "gR4L4UZKz9Te2q1dxP2zKqAd/MUuPNYmni7MjPyNJo4";
+
+    #[test]
+    fn key_generation_matches_openssl() {
+        let secret_key = MlKem768SecretKey::from_seed([0u8; 32], [0x11; 32]);
+
+        assert_eq!(base64_encode(secret_key.public_key().key_hash()), OPENSSL_KEY_HASH);
+    }
+
+    #[test]
+    fn decapsulation_matches_openssl() {
+        let secret_key = MlKem768SecretKey::from_seed([0u8; 32], [0x11; 32]);
+        let ciphertext = MlKem768Ciphertext::from_slice(
+            &base64_decode(OPENSSL_CIPHERTEXT).expect("The ciphertext should be valid base64"),
+        )
+        .expect("The ciphertext should have the correct length");
+
+        assert_eq!(base64_encode(*secret_key.decapsulate(&ciphertext)), OPENSSL_SHARED_SECRET);
+    }
+
+    #[test]
+    fn encapsulation_roundtrip() {
+        let secret_key = MlKem768SecretKey::new(&mut thread_rng());
+        let public_key = secret_key.public_key();
+
+        let (ciphertext, shared_secret) = public_key.encapsulate(&mut thread_rng());
+        assert_eq!(secret_key.decapsulate(&ciphertext), shared_secret);
+
+        let other = MlKem768SecretKey::new(&mut thread_rng());
+        assert_ne!(other.decapsulate(&ciphertext), shared_secret);
+    }
+
+    #[test]
+    fn implicit_rejection() {
+        let secret_key = MlKem768SecretKey::new(&mut thread_rng());
+        let (mut ciphertext, shared_secret) =
+            secret_key.public_key().encapsulate(&mut thread_rng());
+
+        ciphertext.0[0] ^= 1;
+        let rejected = secret_key.decapsulate(&ciphertext);
+
+        assert_ne!(rejected, shared_secret);
+        // The rejection secret only depends on the key and the ciphertext.
+        assert_eq!(secret_key.decapsulate(&ciphertext), rejected);
+    }
+
+    #[test]
+    fn public_key_decoding() {
+        let public_key = MlKem768SecretKey::new(&mut thread_rng()).public_key();
+
+        let decoded = MlKem768PublicKey::from_base64(&public_key.to_base64())
+            .expect("A generated public key should be valid");
+        assert_eq!(decoded, public_key);
+
+        assert!(matches!(
+            MlKem768PublicKey::from_slice(&[0u8; 32]),
+            Err(KeyError::InvalidKeyLength { length: 32, .. })
+        ));
+
+        // A coefficient of 0xfff isn't reduced modulo q.
+        let mut bytes = public_key.to_vec();
+        bytes[0] = 0xff;
+        bytes[1] |= 0x0f;
+
+        assert!(matches!(MlKem768PublicKey::from_slice(&bytes), Err(KeyError::MalformedMlKemKey)));
+    }
+}
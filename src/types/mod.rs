@@ -14,6 +14,7 @@
 
 mod curve25519;
 mod ed25519;
+mod ml_kem;
 
 pub(crate) use curve25519::{Curve25519Keypair, Curve25519KeypairPickle};
 pub use curve25519::{Curve25519PublicKey, Curve25519SecretKey};
@@ -21,6 +22,8 @@ pub use ed25519::{
     Ed25519Keypair, Ed25519KeypairPickle, Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature,
     SignatureError,
 };
+pub use ml_kem::MlKem768PublicKey;
+pub(crate) use ml_kem::{MlKem768Ciphertext, MlKem768SecretKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 pub use x25519_dalek::SharedSecret;
@@ -89,6 +92,11 @@ pub enum KeyError {
          resulting in an insecure shared secret"
     )]
     NonContributoryKey,
+
+    /// The ML-KEM-768 public key contained coefficients which weren't reduced
+    /// modulo `q`, the modulus check of FIPS 203 failed.
+    #[error("The ML-KEM-768 public key isn't encoded correctly")]
+    MalformedMlKemKey,
 }
 
 #[cfg(test)]
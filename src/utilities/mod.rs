@@ -14,6 +14,7 @@
 // limitations under the License.
 
 mod libolm_compat;
+mod pickle;
 
 pub use base64::DecodeError;
 use base64::{
@@ -23,6 +24,11 @@ use base64::{
 pub(crate) use libolm_compat::get_version as get_pickle_version;
 #[cfg(feature = "libolm-compat")]
 pub(crate) use libolm_compat::{LibolmEd25519Keypair, pickle_libolm, unpickle_libolm};
+pub use pickle::{
+    BinaryPickleError, PickleEncoding, decrypt_pickle, encrypt_pickle, rekey_pickle,
+    rekey_pickle_with_rng, rekey_pickles,
+};
+pub(crate) use pickle::{pickle, pickle_with_encoding, pickle_with_rng, unpickle};
 
 const STANDARD_NO_PAD: GeneralPurpose = GeneralPurpose::new(
     &alphabet::STANDARD,
@@ -40,38 +46,6 @@ pub fn base64_encode(input: impl AsRef<[u8]>) -> String {
     STANDARD_NO_PAD.encode(input)
 }
 
-pub(crate) fn unpickle<T: for<'b> serde::Deserialize<'b>>(
-    ciphertext: &str,
-    pickle_key: &[u8; 32],
-) -> Result<T, crate::PickleError> {
-    use zeroize::Zeroize;
-
-    let cipher = crate::cipher::Cipher::new_pickle(pickle_key);
-    let decoded = base64_decode(ciphertext)?;
-    let mut plaintext = cipher.decrypt_pickle(&decoded)?;
-
-    let pickle = serde_json::from_slice(&plaintext)?;
-
-    plaintext.zeroize();
-
-    Ok(pickle)
-}
-
-pub(crate) fn pickle<T: serde::Serialize>(thing: &T, pickle_key: &[u8; 32]) -> String {
-    use zeroize::Zeroize;
-
-    #[allow(clippy::expect_used)]
-    let mut json = serde_json::to_vec(&thing)
-        .expect("A pickled object should always be serializable into JSON");
-    let cipher = crate::cipher::Cipher::new_pickle(pickle_key);
-
-    let ciphertext = cipher.encrypt_pickle(json.as_slice());
-
-    json.zeroize();
-
-    base64_encode(ciphertext)
-}
-
 pub(crate) fn extract_mac(slice: &[u8], truncated: bool) -> crate::cipher::MessageMac {
     use crate::cipher::Mac;
 
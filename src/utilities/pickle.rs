@@ -0,0 +1,507 @@
+// Copyright 2025 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The encrypted envelope modern pickles are stored in.
+//!
+//! Pickles are encrypted using ChaCha20-Poly1305 and the envelope has the
+//! following layout:
+//!
+//! ```text
+//! +-------+---+---+-------------+------------+-------------------------+
+//! | Magic | V | L | Pickle type | Salt       | Ciphertext and tag      |
+//! +-------+---+---+-------------+------------+-------------------------+
+//! 0       3   4   5            5+L         37+L                        N   bytes
+//! ```
+//!
+//! The key and nonce are derived from the pickle key and the random salt. The
+//! header, i.e. the magic bytes, the version and the length prefixed pickle
+//! type, is authenticated as the associated data. This binds the pickle to the
+//! type of object it contains, a pickle of one type can't be restored as
+//! another type.
+//!
+//! Pickles which were created before the envelope existed are encrypted using
+//! AES-256-CBC and a truncated HMAC-SHA-256, they are still accepted when
+//! unpickling.
+//!
+//! The plaintext is either a JSON object, or, if it starts with a zero byte,
+//! the [MessagePack] encoding of the object. See [`PickleEncoding`].
+//!
+//! [MessagePack]: https://msgpack.org/
+
+use rand::thread_rng;
+use rand_core::CryptoRngCore;
+use serde::{Serialize, de::DeserializeOwned};
+use zeroize::Zeroize;
+
+use super::{base64_decode, base64_encode};
+use crate::{
+    PickleError,
+    cipher::{Cipher, DecryptionError, aead::AeadCipher},
+};
+
+const PICKLE_MAGIC: &[u8; 3] = b"VZP";
+const PICKLE_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 32;
+/// The tag that precedes binary encoded plaintexts. JSON plaintexts always
+/// start with an opening brace, so they can't be confused with binary ones.
+const BINARY_ENCODING_TAG: u8 = 0x00;
+
+/// Error type describing why a binary encoded pickle couldn't be deserialized.
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct BinaryPickleError(rmp_serde::decode::Error);
+
+/// The encoding of the plaintext of an encrypted pickle.
+///
+/// The encoding is tagged, the `from_encrypted()` methods detect it
+/// automatically, no matter which one was used to create the pickle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PickleEncoding {
+    /// Encode the pickle as JSON. This is the default, and the only encoding
+    /// understood by older versions of vodozemac.
+    #[default]
+    Json,
+    /// Encode the pickle using the compact, binary, [MessagePack] format.
+    ///
+    /// JSON encodes byte arrays as lists of numbers, a binary pickle is
+    /// usually less than half the size of the same pickle encoded as JSON.
+    ///
+    /// [MessagePack]: https://msgpack.org/
+    Binary,
+}
+
+/// A parsed, but not yet decrypted, pickle envelope.
+struct Envelope<'a> {
+    header: &'a [u8],
+    salt: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// Try to parse the given bytes as an envelope.
+    ///
+    /// Returns `None` if the bytes don't start with the header of a supported
+    /// envelope version, in which case they should be treated as a legacy
+    /// pickle.
+    fn parse(envelope: &'a [u8]) -> Option<Self> {
+        let rest = envelope.strip_prefix(PICKLE_MAGIC)?;
+        let (&version, rest) = rest.split_first()?;
+
+        if version != PICKLE_VERSION {
+            return None;
+        }
+
+        let (&type_length, rest) = rest.split_first()?;
+        let (pickle_type, rest) = rest.split_at_checked(type_length.into())?;
+        let (salt, ciphertext) = rest.split_at_checked(SALT_LENGTH)?;
+
+        let header_length = PICKLE_MAGIC.len() + 2 + pickle_type.len();
+        let header = &envelope[..header_length];
+
+        Some(Self { header, salt, ciphertext })
+    }
+}
+
+/// Build the header of the envelope for the given pickle type, the header
+/// doubles as the associated data of the AEAD.
+fn envelope_header(pickle_type: &str) -> Vec<u8> {
+    // Pickle types are short, constant names, truncating them is never going
+    // to happen in practice.
+    let pickle_type = &pickle_type.as_bytes()[..pickle_type.len().min(u8::MAX.into())];
+    let type_length = pickle_type.len() as u8;
+
+    [PICKLE_MAGIC.as_slice(), &[PICKLE_VERSION, type_length], pickle_type].concat()
+}
+
+fn encrypt_envelope(
+    plaintext: &[u8],
+    pickle_key: &[u8; 32],
+    header: &[u8],
+    rng: &mut impl CryptoRngCore,
+) -> Vec<u8> {
+    let mut salt = [0u8; SALT_LENGTH];
+    rng.fill_bytes(&mut salt);
+
+    let cipher = AeadCipher::new_pickle(pickle_key, &salt);
+    let ciphertext = cipher.encrypt(plaintext, header);
+
+    [header, &salt, &ciphertext].concat()
+}
+
+/// Decrypt the given envelope, or legacy pickle.
+///
+/// If an expected header is given, it's used as the associated data, otherwise
+/// the header stored in the envelope is used. Returns the header the envelope
+/// was decrypted with, or `None` if it was a legacy pickle, and the plaintext.
+fn decrypt_envelope<'a>(
+    envelope: &'a [u8],
+    pickle_key: &[u8; 32],
+    expected_header: Option<&'a [u8]>,
+) -> Result<(Option<&'a [u8]>, Vec<u8>), DecryptionError> {
+    let decrypt_legacy = || Ok((None, Cipher::new_pickle(pickle_key).decrypt_pickle(envelope)?));
+
+    if let Some(parsed) = Envelope::parse(envelope) {
+        let cipher = AeadCipher::new_pickle(pickle_key, parsed.salt);
+        let header = expected_header.unwrap_or(parsed.header);
+
+        match cipher.decrypt(parsed.ciphertext, header) {
+            Ok(plaintext) => Ok((Some(header), plaintext)),
+            // The ciphertext of a legacy pickle might, very rarely, start with
+            // the magic bytes. Report the AEAD error if it isn't one either.
+            Err(_) => decrypt_legacy().map_err(|_| DecryptionError::Aead),
+        }
+    } else {
+        decrypt_legacy()
+    }
+}
+
+/// Serialize the given object to JSON and encrypt it using the given pickle
+/// key, binding the pickle to the given type name.
+pub(crate) fn pickle<T: Serialize>(thing: &T, pickle_key: &[u8; 32], pickle_type: &str) -> String {
+    pickle_with_encoding(thing, pickle_key, pickle_type, PickleEncoding::Json)
+}
+
+/// Serialize the given object using the given encoding and encrypt it using
+/// the given pickle key, binding the pickle to the given type name.
+pub(crate) fn pickle_with_encoding<T: Serialize>(
+    thing: &T,
+    pickle_key: &[u8; 32],
+    pickle_type: &str,
+    encoding: PickleEncoding,
+) -> String {
+    pickle_with_rng(thing, pickle_key, pickle_type, encoding, &mut thread_rng())
+}
+
+/// Serialize the given object using the given encoding and encrypt it using
+/// the given pickle key, generating the salt of the envelope using the given
+/// random number generator.
+pub(crate) fn pickle_with_rng<T: Serialize>(
+    thing: &T,
+    pickle_key: &[u8; 32],
+    pickle_type: &str,
+    encoding: PickleEncoding,
+    rng: &mut impl CryptoRngCore,
+) -> String {
+    #[allow(clippy::expect_used)]
+    let mut plaintext = match encoding {
+        PickleEncoding::Json => serde_json::to_vec(&thing)
+            .expect("A pickled object should always be serializable into JSON"),
+        PickleEncoding::Binary => {
+            let mut plaintext = vec![BINARY_ENCODING_TAG];
+            rmp_serde::encode::write_named(&mut plaintext, &thing)
+                .expect("A pickled object should always be serializable into MessagePack");
+
+            plaintext
+        }
+    };
+
+    let envelope = encrypt_envelope(&plaintext, pickle_key, &envelope_header(pickle_type), rng);
+
+    plaintext.zeroize();
+
+    base64_encode(envelope)
+}
+
+/// Decrypt and deserialize a pickle which was created by [`pickle()`], or by a
+/// version of vodozemac before the envelope existed.
+pub(crate) fn unpickle<T: DeserializeOwned>(
+    ciphertext: &str,
+    pickle_key: &[u8; 32],
+    pickle_type: &str,
+) -> Result<T, PickleError> {
+    let decoded = base64_decode(ciphertext)?;
+    let header = envelope_header(pickle_type);
+    let (_, mut plaintext) = decrypt_envelope(&decoded, pickle_key, Some(&header))?;
+
+    let pickle = match plaintext.split_first() {
+        Some((&BINARY_ENCODING_TAG, encoded)) => {
+            rmp_serde::from_slice(encoded).map_err(|e| BinaryPickleError(e).into())
+        }
+        _ => serde_json::from_slice(&plaintext).map_err(Into::into),
+    };
+
+    plaintext.zeroize();
+
+    pickle
+}
+
+/// Serialize an object of your own type to JSON and encrypt it using the given
+/// pickle key, using the same envelope the pickles of vodozemac's types use.
+///
+/// This is useful to store state which accompanies a vodozemac object, e.g.
+/// a struct containing a [`SessionPickle`] and some metadata, as a single
+/// pickle. The `pickle_type` is authenticated, the pickle can only be restored
+/// by [`decrypt_pickle()`] using the same type. It shouldn't be the name of one
+/// of vodozemac's own types, e.g. `Account`.
+///
+/// [`SessionPickle`]: crate::olm::SessionPickle
+pub fn encrypt_pickle<T: Serialize>(thing: &T, pickle_key: &[u8; 32], pickle_type: &str) -> String {
+    pickle(thing, pickle_key, pickle_type)
+}
+
+/// Decrypt and deserialize a pickle which was created by
+/// [`encrypt_pickle()`] using the same pickle type.
+pub fn decrypt_pickle<T: DeserializeOwned>(
+    ciphertext: &str,
+    pickle_key: &[u8; 32],
+    pickle_type: &str,
+) -> Result<T, PickleError> {
+    unpickle(ciphertext, pickle_key, pickle_type)
+}
+
+/// Re-encrypt an encrypted pickle using a new pickle key, without
+/// deserializing it.
+///
+/// This is useful if the key protecting a store of pickles changes, the
+/// decrypted pickle never leaves the crate and is zeroized right after it has
+/// been encrypted using the new key. Any pickle created by one of the
+/// `encrypt()` methods, e.g. [`AccountPickle::encrypt()`], is accepted, the
+/// result can be restored using the matching `from_encrypted()` method and the
+/// new key.
+///
+/// Pickles created by older versions of vodozemac don't record the type of
+/// the object they contain, they are re-encrypted in the same, legacy, format.
+/// Restore and pickle them again using the typed API to upgrade them.
+///
+/// [`AccountPickle::encrypt()`]: crate::olm::AccountPickle::encrypt
+pub fn rekey_pickle(
+    ciphertext: &str,
+    old_pickle_key: &[u8; 32],
+    new_pickle_key: &[u8; 32],
+) -> Result<String, PickleError> {
+    rekey_pickle_with_rng(ciphertext, old_pickle_key, new_pickle_key, &mut thread_rng())
+}
+
+/// Re-encrypt an encrypted pickle using a new pickle key, generating the salt
+/// of the new envelope using the given random number generator.
+///
+/// See [`rekey_pickle()`] for more details.
+pub fn rekey_pickle_with_rng(
+    ciphertext: &str,
+    old_pickle_key: &[u8; 32],
+    new_pickle_key: &[u8; 32],
+    rng: &mut impl CryptoRngCore,
+) -> Result<String, PickleError> {
+    let decoded = base64_decode(ciphertext)?;
+    let (header, mut plaintext) = decrypt_envelope(&decoded, old_pickle_key, None)?;
+
+    let envelope = match header {
+        Some(header) => encrypt_envelope(&plaintext, new_pickle_key, header, rng),
+        None => Cipher::new_pickle(new_pickle_key).encrypt_pickle(&plaintext),
+    };
+
+    plaintext.zeroize();
+
+    Ok(base64_encode(envelope))
+}
+
+/// Re-encrypt many encrypted pickles using a new pickle key.
+///
+/// This is the bulk version of [`rekey_pickle()`]. The pickles are re-encrypted
+/// lazily, one at a time, while the returned iterator is consumed, so the
+/// pickles can be streamed out of and back into a store.
+///
+/// # Examples
+///
+/// ```
+/// use vodozemac::{megolm::GroupSession, rekey_pickles};
+///
+/// const OLD_KEY: [u8; 32] = [0u8; 32];
+/// const NEW_KEY: [u8; 32] = [1u8; 32];
+///
+/// let pickles: Vec<String> = (0..3)
+///     .map(|_| GroupSession::new(Default::default()).pickle().encrypt(&OLD_KEY))
+///     .collect();
+///
+/// let rekeyed: Result<Vec<String>, _> = rekey_pickles(&pickles, &OLD_KEY, &NEW_KEY).collect();
+///
+/// assert_eq!(rekeyed.unwrap().len(), 3);
+/// ```
+pub fn rekey_pickles<'a, I>(
+    pickles: I,
+    old_pickle_key: &'a [u8; 32],
+    new_pickle_key: &'a [u8; 32],
+) -> impl Iterator<Item = Result<String, PickleError>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    pickles
+        .into_iter()
+        .map(move |pickle| rekey_pickle(pickle.as_ref(), old_pickle_key, new_pickle_key))
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches2::assert_matches;
+    use rand::{SeedableRng, rngs::StdRng};
+    use serde_json::{Value, json};
+
+    use super::{
+        PICKLE_MAGIC, PICKLE_VERSION, PickleEncoding, pickle, pickle_with_encoding,
+        pickle_with_rng, rekey_pickle, rekey_pickle_with_rng, rekey_pickles, unpickle,
+    };
+    use crate::{
+        PickleError,
+        cipher::{Cipher, DecryptionError},
+        utilities::{base64_decode, base64_encode},
+    };
+
+    const PICKLE_KEY: [u8; 32] = [0u8; 32];
+
+    fn rng(seed: u64) -> StdRng {
+        StdRng::seed_from_u64(seed)
+    }
+
+    #[test]
+    fn roundtrip() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody" });
+        let pickled = pickle(&value, &PICKLE_KEY, "Account");
+
+        let decoded = base64_decode(&pickled)?;
+        assert!(decoded.starts_with(PICKLE_MAGIC));
+        assert_eq!(decoded[3], PICKLE_VERSION);
+        assert_eq!(&decoded[4..12], b"\x07Account");
+
+        assert_eq!(unpickle::<Value>(&pickled, &PICKLE_KEY, "Account")?, value);
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_envelope() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody" });
+        let plaintext = serde_json::to_vec(&value)?;
+        let legacy = base64_encode(Cipher::new_pickle(&PICKLE_KEY).encrypt_pickle(&plaintext));
+
+        assert_eq!(unpickle::<Value>(&legacy, &PICKLE_KEY, "Account")?, value);
+
+        Ok(())
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let pickled = pickle(&json!({}), &PICKLE_KEY, "Session");
+
+        assert_matches!(
+            unpickle::<Value>(&pickled, &PICKLE_KEY, "Account"),
+            Err(PickleError::Decryption(DecryptionError::Aead))
+        );
+    }
+
+    #[test]
+    fn wrong_key() {
+        let pickled = pickle(&json!({}), &PICKLE_KEY, "Account");
+
+        assert_matches!(
+            unpickle::<Value>(&pickled, &[1u8; 32], "Account"),
+            Err(PickleError::Decryption(DecryptionError::Aead))
+        );
+    }
+
+    #[test]
+    fn tampered_header() -> anyhow::Result<()> {
+        let mut decoded = base64_decode(pickle(&json!({}), &PICKLE_KEY, "Account"))?;
+        decoded[5] = b'a';
+
+        assert_matches!(
+            unpickle::<Value>(&base64_encode(decoded), &PICKLE_KEY, "account"),
+            Err(PickleError::Decryption(DecryptionError::Aead))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rekey() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody" });
+        let new_key = [1u8; 32];
+
+        let pickled = pickle(&value, &PICKLE_KEY, "Session");
+        let rekeyed = rekey_pickle(&pickled, &PICKLE_KEY, &new_key)?;
+
+        assert_eq!(unpickle::<Value>(&rekeyed, &new_key, "Session")?, value);
+        unpickle::<Value>(&rekeyed, &PICKLE_KEY, "Session")
+            .expect_err("The old key shouldn't be able to decrypt the pickle anymore");
+        unpickle::<Value>(&rekeyed, &new_key, "Account")
+            .expect_err("The pickle should still be bound to its type");
+
+        assert_matches!(
+            rekey_pickle(&pickled, &new_key, &PICKLE_KEY),
+            Err(PickleError::Decryption(DecryptionError::Aead))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_pickles_are_deterministic() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody" });
+        let seeded = |seed| {
+            pickle_with_rng(&value, &PICKLE_KEY, "Session", PickleEncoding::Json, &mut rng(seed))
+        };
+
+        assert_eq!(seeded(0), seeded(0));
+        assert_ne!(seeded(0), seeded(1));
+        assert_eq!(unpickle::<Value>(&seeded(0), &PICKLE_KEY, "Session")?, value);
+
+        let new_key = [1u8; 32];
+        let rekeyed = rekey_pickle_with_rng(&seeded(0), &PICKLE_KEY, &new_key, &mut rng(2))?;
+        assert_eq!(rekeyed, rekey_pickle_with_rng(&seeded(0), &PICKLE_KEY, &new_key, &mut rng(2))?);
+        assert_eq!(unpickle::<Value>(&rekeyed, &new_key, "Session")?, value);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rekey_legacy() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody" });
+        let plaintext = serde_json::to_vec(&value)?;
+        let legacy = base64_encode(Cipher::new_pickle(&PICKLE_KEY).encrypt_pickle(&plaintext));
+        let new_key = [1u8; 32];
+
+        let rekeyed = rekey_pickle(&legacy, &PICKLE_KEY, &new_key)?;
+
+        assert!(!base64_decode(&rekeyed)?.starts_with(PICKLE_MAGIC));
+        assert_eq!(unpickle::<Value>(&rekeyed, &new_key, "Account")?, value);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rekey_many() -> anyhow::Result<()> {
+        let new_key = [1u8; 32];
+        let pickles: Vec<_> =
+            (0..10).map(|i| pickle(&json!({ "index": i }), &PICKLE_KEY, "Session")).collect();
+
+        for (i, rekeyed) in rekey_pickles(&pickles, &PICKLE_KEY, &new_key).enumerate() {
+            assert_eq!(unpickle::<Value>(&rekeyed?, &new_key, "Session")?, json!({ "index": i }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn binary_encoding() -> anyhow::Result<()> {
+        let value = json!({ "secret": "It's a secret to everybody", "bytes": [0, 1, 255] });
+        let pickled = pickle_with_encoding(&value, &PICKLE_KEY, "Account", PickleEncoding::Binary);
+
+        assert_eq!(unpickle::<Value>(&pickled, &PICKLE_KEY, "Account")?, value);
+
+        let rekeyed = rekey_pickle(&pickled, &PICKLE_KEY, &[1u8; 32])?;
+        assert_eq!(unpickle::<Value>(&rekeyed, &[1u8; 32], "Account")?, value);
+
+        Ok(())
+    }
+}